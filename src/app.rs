@@ -1,138 +1,1986 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::any::Any;
 use std::hash::Hash;
+use std::mem;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use chrono::{DateTime, Local};
 use iced::futures::channel::mpsc;
 use iced::futures::lock::Mutex;
-use iced::{keyboard, widget, Element, Fill, Subscription, Task, Theme};
+use iced::{
+    clipboard, event, keyboard, mouse, time, widget, window, Element, Event, Fill, Point, Size,
+    Subscription, Task, Theme,
+};
 use iced::advanced::subscription::{from_recipe, EventStream, Hasher, Recipe};
 use iced::futures::stream::BoxStream;
-use iced::futures::StreamExt;
+use iced::futures::{FutureExt, SinkExt, Stream, StreamExt, future};
 use iced::keyboard::key;
-use tracing::{trace, warn};
-use crate::handlers::{handle_get_record, handle_key_text_changed, handle_p2p_event, handle_put_record, handle_value_text_changed};
+use iced::widget::{combo_box, text_editor};
+use iced::widget::scrollable::Viewport;
+use libp2p::{Multiaddr, PeerId, kad};
+use tracing::{error, trace, warn};
+use crate::format::ValueDisplay;
+use crate::handlers::{
+    Effect,
+    handle_block_peer,
+    handle_bootstrap,
+    handle_bootstrap_peer_input_changed,
+    handle_reconnect_bootstrap,
+    handle_cancel_direct,
+    handle_cancel_query,
+    handle_chat_input_changed,
+    handle_chat_topic_changed,
+    handle_compose_direct,
+    handle_direct_input_changed,
+    handle_disconnect_peer,
+    handle_dial,
+    handle_dial_address_changed,
+    handle_fetch_destination_picked,
+    handle_fetch_file,
+    handle_file_to_share_picked,
+    handle_get_providers,
+    handle_get_closest_peers,
+    handle_get_record,
+    handle_key_text_changed,
+    handle_clear_record_inputs,
+    handle_recent_key_selected,
+    handle_toggle_value_view,
+    handle_value_display_changed,
+    handle_show_whole_value,
+    handle_event_selected,
+    handle_close_event_detail,
+    handle_escape_pressed,
+    handle_dismiss_toast,
+    handle_toast_tick,
+    handle_detect_system_theme,
+    handle_window_moved,
+    handle_window_resized,
+    handle_window_maximized_checked,
+    handle_config_settled,
+    save_config,
+    derive_value_cipher,
+    handle_value_cipher_derived,
+    handle_shortcut,
+    handle_close_help,
+    handle_system_theme_detected,
+    handle_attach_value_file,
+    handle_value_file_picked,
+    handle_value_file_loaded,
+    handle_detach_value_file,
+    handle_save_value,
+    handle_value_destination_picked,
+    handle_find_focused_input,
+    handle_focus_input,
+    handle_listen_via_relay,
+    handle_p2p_event,
+    handle_publish_message,
+    handle_put_record,
+    handle_refresh_records, handle_remove_record,
+    handle_refresh_routing_table,
+    handle_toggle_bucket,
+    handle_send_direct,
+    handle_networking_restarted,
+    handle_networking_stopped,
+    handle_settings_changed,
+    handle_share_file,
+    handle_start_providing,
+    handle_stop_providing,
+    handle_subscribe_topic,
+    handle_tab_selected, handle_toggle_log_category, handle_log_search_changed, handle_clear_log, handle_copy_log, handle_log_scrolled,
+    handle_jump_to_latest,
+    handle_toggle_export_records,
+    handle_trace_line,
+    handle_toggle_diagnostics,
+    handle_trace_level_selected,
+    handle_copy_diagnostics,
+    handle_bench_count_changed,
+    handle_bench_value_size_changed,
+    handle_run_benchmark,
+    handle_cancel_benchmark,
+    handle_continue_benchmark,
+    handle_open_log_folder,
+    handle_export_log,
+    handle_export_destination_picked,
+    handle_export_finished,
+    handle_import_records,
+    handle_import_file_picked,
+    handle_records_imported,
+    handle_continue_import,
+    handle_dismiss_import,
+    handle_export_records,
+    handle_records_export_picked,
+    handle_tick,
+    handle_toggle_listen_addrs,
+    handle_toggle_relay_server,
+    handle_toggle_mdns,
+    handle_toggle_record_signing,
+    handle_toggle_inbound_approval,
+    handle_toggle_kad_server,
+    handle_toggle_hash_keys,
+    handle_accept_inbound,
+    handle_reject_inbound,
+    handle_unblock_peer,
+    handle_ttl_text_changed,
+    handle_unsubscribe_topic,
+    handle_value_edited,
+};
+use crate::bench::{Benchmark, BenchmarkReport};
+use crate::config::{AppConfig, KadMode, SettingsField, ThemePreference};
+use crate::diagnostics::{TraceLevel, TraceLine};
+use crate::export::{ImportedRecords, SkippedRow};
+use crate::inbound::InboundId;
+use crate::known_peers::KnownPeer;
 use crate::p2p;
-use crate::p2p::{P2pCommand, P2pEvent};
-use crate::widgets::{event_log, input_section, network_status};
+use crate::p2p::envelope::{self, RecordSignature, ValueCipher};
+use crate::p2p::security::SecurityProtocol;
+use crate::p2p::{
+    BucketInfo, ConnectionDirection, ConnectionTransport, FoundValue, NatStatus, NodeConfig, P2pCommand, P2pError, P2pEvent, QueryKind, RelayLimits, RequestId,
+};
+use crate::widgets::{benchmark_section, blocked_list, chat_view, closest_peers_list, config_section, diagnostics_panel, dial_section, event_detail, event_log, file_section, help_overlay, input_section, kad_mode_section, network_status, pending_inbound_list, peer_list, providing_list, query_history, record_lookup_view, records_table, remembered_list, routing_table, settings_view, stopped_banner, tab_bar, toast_layer, ValueInput};
 
 pub struct App {
-    p2p_control: mpsc::Sender<P2pCommand>,
-    p2p_events: Arc<Mutex<mpsc::Receiver<P2pEvent>>>,
+    p2p_control: Commands,
+    trace_lines: Arc<Mutex<tokio::sync::mpsc::Receiver<TraceLine>>>,
+    node_config: NodeConfig,
+    generation: u64,
+    restart_pending: bool,
+    closing: Option<window::Id>,
     state: State,
 }
 
+/// How long closing the window waits for the swarm to shut down before giving up on it.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// dark-light has no way to watch for changes, so the OS is asked again this often.
+const SYSTEM_THEME_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone)]
 pub enum Message {
     P2pEvent(P2pEvent),
     KeyTextChanged(String),
-    ValueTextChanged(String),
-    PutRecord(String, String),
+    ValueEdited(text_editor::Action),
+    TtlTextChanged(String),
+    ClearRecordInputs,
+    RecentKeySelected(String),
+    AttachValueFile,
+    ValueFilePicked(Option<PathBuf>),
+    ValueFileLoaded(Result<(String, Vec<u8>), P2pError>),
+    DetachValueFile,
+    SaveValue(Vec<u8>),
+    ValueDestinationPicked(Vec<u8>, Option<PathBuf>),
+    PutRecord(String, Vec<u8>, Option<Duration>),
     GetRecord(String),
+    GetProviders(String),
+    GetClosestPeers(String),
+    StartProviding(String),
+    StopProviding(String),
+    RemoveRecord(String),
+    RefreshRecords,
+    RefreshRoutingTable,
+    ToggleBucket(u32),
+    DialAddressChanged(String),
+    Dial(String),
+    ListenViaRelay(String),
+    Bootstrap,
+    BootstrapPeerInputChanged(String),
+    ReconnectBootstrap,
+    CopyToClipboard(String),
+    ToggleListenAddrs,
+    ToggleRelayServer(bool),
+    ToggleMdns(bool),
+    ToggleRecordSigning(bool),
+    ToggleInboundApproval(bool),
+    ToggleKadServer(bool),
+    ToggleHashKeys(bool),
+    AcceptInbound(InboundId),
+    RejectInbound(InboundId),
+    DisconnectPeer(PeerId),
+    BlockPeer(PeerId),
+    UnblockPeer(PeerId),
+    Tick(Instant),
+    LogClockTick,
+    CancelQuery(RequestId),
+    TabSelected(Tab),
+    ToggleLogCategory(LogCategory),
+    SettingsChanged(SettingsField),
+    ClearLog,
+    ToggleValueView(ValueSource, ValueDisplay),
+    ValueDisplayChanged(ValueSource, ValueDisplay),
+    ShowWholeValue(ValueSource),
+    EventSelected(usize),
+    CloseEventDetail,
+    DismissToast(u64),
+    ToastTick(Instant),
+    DetectSystemTheme,
+    WindowMoved(Point),
+    WindowResized(window::Id, Size),
+    WindowMaximizedChecked(Size, bool),
+    ConfigSettled(u64),
+    ValueCipherDerived(Arc<ValueCipher>),
+    ShortcutPressed(Shortcut, event::Status),
+    CloseHelp,
+    SystemThemeDetected(Option<Theme>),
+    LogScrolled(Viewport),
+    JumpToLatest,
+    CopyLog,
+    LogSearchChanged(String),
+    TraceLine(TraceLine),
+    ToggleDiagnostics,
+    TraceLevelSelected(TraceLevel),
+    CopyDiagnostics,
+    BenchCountChanged(String),
+    BenchValueSizeChanged(String),
+    RunBenchmark,
+    CancelBenchmark,
+    ContinueBenchmark,
+    OpenLogFolder,
+    ToggleExportRecords(bool),
+    ExportLog,
+    ExportDestinationPicked(Option<PathBuf>),
+    ExportFinished(Result<(), P2pError>),
+    ImportRecords,
+    ImportFilePicked(Option<PathBuf>),
+    RecordsImported(Result<ImportedRecords, P2pError>),
+    ContinueImport,
+    DismissImport,
+    ExportRecords,
+    RecordsExportPicked(Option<PathBuf>),
+    ChatTopicChanged(String),
+    ChatInputChanged(String),
+    SubscribeTopic(String),
+    UnsubscribeTopic(String),
+    SendChat(String, String),
+    ComposeDirect(PeerId),
+    DirectInputChanged(String),
+    SendDirect(PeerId, String),
+    CancelDirect,
+    ShareFile,
+    FileToSharePicked(Option<PathBuf>),
+    FetchFile(String),
+    FetchDestinationPicked(String, Option<PathBuf>),
     FocusNext,
-    ServerStarted,
-    Ignore,
+    FocusInput(InputField),
+    FindFocusedInput,
+    InputFocused(Option<InputField>),
+    EscapePressed,
+    RestartNetworking,
+    NetworkingExited(u64),
+    NetworkingStopped(String),
+    CloseRequested(window::Id),
+    ShutdownTimedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// When the event last happened.
+    pub at: SystemTime,
+    pub event: P2pEvent,
+    /// How many times in a row the event happened.
+    pub repeat: u32,
+    /// When the event happened before `at`, the latest [`MAX_KEPT_REPEATS`] of them.
+    pub earlier: VecDeque<SystemTime>,
+}
+
+impl LogEntry {
+    pub fn new(event: P2pEvent) -> Self {
+        Self {
+            at: SystemTime::now(),
+            event,
+            repeat: 1,
+            earlier: VecDeque::new(),
+        }
+    }
+
+    /// Whether `other` is the same event all over again. Everything the event carries has to
+    /// match, so two lookups of one key that found different values are kept apart.
+    fn repeats(&self, other: &LogEntry) -> bool {
+        mem::discriminant(&self.event) == mem::discriminant(&other.event)
+            && format!("{:?}", self.event) == format!("{:?}", other.event)
+    }
+
+    pub fn timestamp(&self) -> String {
+        clock_time(self.at)
+    }
+
+    pub fn full_timestamp(&self) -> String {
+        full_time(self.at)
+    }
+
+    /// Every kept time the event happened, latest first.
+    pub fn occurrences(&self) -> impl Iterator<Item = SystemTime> + '_ {
+        std::iter::once(self.at).chain(self.earlier.iter().rev().copied())
+    }
+}
+
+pub fn clock_time(at: SystemTime) -> String {
+    DateTime::<Local>::from(at).format("%H:%M:%S").to_string()
+}
+
+pub fn full_time(at: SystemTime) -> String {
+    DateTime::<Local>::from(at).format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.timestamp(), self.event)?;
+
+        if self.repeat > 1 {
+            write!(f, " ×{}", self.repeat)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub const DEFAULT_LOG_CAPACITY: usize = 1000;
+pub const MAX_KEPT_REPEATS: usize = 100;
+pub const TOAST_LIFETIME: Duration = Duration::from_secs(4);
+
+/// Keeps the most recent log entries, dropping the oldest once `capacity` is reached.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    discarded: usize,
+    /// Every entry that ever left, discarded or cleared, so numbers never get reused.
+    removed: usize,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_LOG_CAPACITY)
+    }
+}
+
+impl EventLog {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            discarded: 0,
+            removed: 0,
+        }
+    }
+
+    /// Adds `entry`, or counts it on the latest one when it's the same event again.
+    pub fn push(&mut self, entry: LogEntry) {
+        if let Some(last) = self.entries.back_mut()
+            && last.repeats(&entry)
+        {
+            if last.earlier.len() == MAX_KEPT_REPEATS {
+                last.earlier.pop_front();
+            }
+            last.earlier.push_back(last.at);
+            last.at = entry.at;
+            last.repeat += 1;
+            return;
+        }
+
+        self.entries.push_back(entry);
+        self.trim();
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.trim();
+        self.entries.shrink_to(capacity);
+    }
+
+    pub fn clear(&mut self) {
+        self.removed += self.entries.len();
+        self.entries.clear();
+        self.discarded = 0;
+    }
+
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    /// The entries with numbers that stay theirs as older ones are discarded.
+    pub fn numbered(&self) -> impl Iterator<Item = (usize, &LogEntry)> {
+        (self.removed..).zip(&self.entries)
+    }
+
+    /// The entry [`numbered`](Self::numbered) gave `number`, unless it has left the log since.
+    pub fn get(&self, number: usize) -> Option<&LogEntry> {
+        self.entries.get(number.checked_sub(self.removed)?)
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+            self.discarded += 1;
+            self.removed += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogCategory {
+    Peers,
+    Inbound,
+    Outbound,
+    Errors,
+    System,
+}
+
+impl LogCategory {
+    pub const ALL: [LogCategory; 5] = [
+        LogCategory::Peers,
+        LogCategory::Inbound,
+        LogCategory::Outbound,
+        LogCategory::Errors,
+        LogCategory::System,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            LogCategory::Peers => "Peers",
+            LogCategory::Inbound => "Inbound",
+            LogCategory::Outbound => "Outbound",
+            LogCategory::Errors => "Errors",
+            LogCategory::System => "System",
+        }
+    }
+
+    pub fn of(event: &P2pEvent) -> Self {
+        match event {
+            P2pEvent::PeerDiscovered(..)
+            | P2pEvent::PeerExpired(..)
+            | P2pEvent::Dialing(..)
+            | P2pEvent::DialingBootstrapPeer(..)
+            | P2pEvent::RememberedPeers(..)
+            | P2pEvent::PeerConnected { .. }
+            | P2pEvent::PeerDisconnected { .. }
+            | P2pEvent::PeerIdentified { .. }
+            | P2pEvent::DhtProtocolMissing { .. }
+            | P2pEvent::PingRtt(..)
+            | P2pEvent::PeerUnresponsive(..)
+            | P2pEvent::HolePunch { .. }
+            | P2pEvent::PeerBlocked(..)
+            | P2pEvent::PeerUnblocked(..)
+            | P2pEvent::RoutingUpdated { .. }
+            | P2pEvent::UnroutablePeer(..)
+            | P2pEvent::ConnectionDenied { .. } => LogCategory::Peers,
+            P2pEvent::Inbound(..)
+            | P2pEvent::InboundPending { .. }
+            | P2pEvent::InboundExpired(..)
+            | P2pEvent::InboundRejected { .. }
+            | P2pEvent::PeerRateLimited(..)
+            | P2pEvent::DirectMessageReceived(..)
+            | P2pEvent::GossipMessage { .. }
+            | P2pEvent::RelayReservationServed { .. }
+            | P2pEvent::RelayCircuitOpened { .. }
+            | P2pEvent::RelayCircuitClosed { .. } => LogCategory::Inbound,
+            P2pEvent::Outbound(..)
+            | P2pEvent::DirectMessageDelivered(..)
+            | P2pEvent::GossipPublished { .. }
+            | P2pEvent::FileTransferProgress { .. }
+            | P2pEvent::FileFetched { .. }
+            | P2pEvent::RelayReservationAccepted { .. } => LogCategory::Outbound,
+            P2pEvent::Error(..) => LogCategory::Errors,
+            _ => LogCategory::System,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    pub hidden: HashSet<LogCategory>,
+    pub search: String,
+}
+
+impl LogFilter {
+    pub fn matches(&self, event: &P2pEvent) -> bool {
+        if self.hidden.contains(&LogCategory::of(event)) {
+            return false;
+        }
+
+        self.search.is_empty()
+            || event
+                .to_string()
+                .to_lowercase()
+                .contains(&self.search.to_lowercase())
+    }
+}
+
+/// The single-line record inputs Enter submits from and Esc clears; the value editor handles
+/// both keys itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputField {
+    Key,
+    Ttl,
+}
+
+impl InputField {
+    pub const ALL: [InputField; 2] = [InputField::Key, InputField::Ttl];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shortcut {
+    FocusKey,
+    /// Puts the value, or gets the key when there's no value to put.
+    Submit,
+    ClearLog,
+    CopySelectedEvent,
+    SelectTab(Tab),
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    ToggleHelp,
+}
+
+impl Shortcut {
+    pub fn of(key: &keyboard::Key, modifiers: keyboard::Modifiers) -> Option<Self> {
+        match key.as_ref() {
+            keyboard::Key::Named(key::Named::F1) if modifiers.is_empty() => {
+                Some(Shortcut::ToggleHelp)
+            }
+            keyboard::Key::Named(key::Named::Enter) if modifiers.command() => {
+                Some(Shortcut::Submit)
+            }
+            keyboard::Key::Character(character) if modifiers.command() => match character {
+                "k" => Some(Shortcut::FocusKey),
+                "l" => Some(Shortcut::ClearLog),
+                "c" => Some(Shortcut::CopySelectedEvent),
+                "0" => Some(Shortcut::ResetZoom),
+                // Plus shares its key with equals, so either works with or without Shift
+                "+" | "=" => Some(Shortcut::ZoomIn),
+                "-" => Some(Shortcut::ZoomOut),
+                digit => digit
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|digit| Tab::ALL.get(digit.checked_sub(1)?))
+                    .map(|tab| Shortcut::SelectTab(*tab)),
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether the shortcut still works when a focused input has taken the key. Plain keys
+    /// belong to the input, and so do submitting and copying, which inputs do themselves.
+    pub fn overrides_inputs(self) -> bool {
+        !matches!(
+            self,
+            Shortcut::Submit | Shortcut::CopySelectedEvent | Shortcut::ToggleHelp
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tab {
+    #[default]
+    Dht,
+    Peers,
+    Log,
+    Settings,
+    Chat,
+    Routing,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 6] = [
+        Tab::Dht,
+        Tab::Peers,
+        Tab::Log,
+        Tab::Settings,
+        Tab::Chat,
+        Tab::Routing,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Tab::Dht => "DHT",
+            Tab::Peers => "Peers",
+            Tab::Log => "Log",
+            Tab::Settings => "Settings",
+            Tab::Chat => "Chat",
+            Tab::Routing => "Routing",
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct State {
-    pub event_log: Vec<P2pEvent>,
-    pub peer_count: usize,
+    pub active_tab: Tab,
+    pub event_log: EventLog,
+    pub log_filter: LogFilter,
+    pub log_paused: bool,
+    pub export_records: bool,
+    pub trace_log: VecDeque<TraceLine>,
+    pub trace_level: TraceLevel,
+    pub show_diagnostics: bool,
+    pub bench_count: String,
+    pub bench_value_size: String,
+    pub benchmark: Option<Benchmark>,
+    pub benchmark_report: Option<BenchmarkReport>,
+    pub log_file_dir: Option<PathBuf>,
+    pub local_peer_id: Option<PeerId>,
+    pub listen_addrs: Vec<Multiaddr>,
+    pub show_listen_addrs: bool,
+    pub peers: HashMap<PeerId, PeerInfo>,
+    pub connected_peers: HashSet<PeerId>,
+    pub remembered_peers: Vec<KnownPeer>,
+    pub blocked_peers: Vec<PeerId>,
+    pub routing_table: Vec<BucketInfo>,
+    pub expanded_buckets: HashSet<u32>,
+    pub changed_bucket: Option<u32>,
+    pub peer_grace: Duration,
     pub current_key: String,
-    pub current_value: String,
+    pub value_editor: text_editor::Content,
+    /// A file's contents put in place of the editor's text.
+    pub value_bytes: Option<Vec<u8>>,
+    pub value_file_name: String,
+    /// Open value detail views; values without one show just their preview.
+    pub value_views: HashMap<ValueSource, ValueView>,
+    pub current_ttl: String,
+    /// The key history dropdown's options, rebuilt from the config's recent keys.
+    pub recent_keys: combo_box::State<String>,
+    /// Tracked here because a text input drops its focus on Esc before the app hears the key.
+    pub focused_input: Option<InputField>,
+    /// The log entry whose details are open, by its number in the log.
+    pub selected_event: Option<usize>,
+    pub toasts: Vec<Toast>,
+    pub next_toast_id: u64,
+    pub provided_keys: Vec<String>,
+    pub closest_peers: Option<ClosestPeers>,
+    pub record_lookup: Option<RecordLookup>,
+    pub records: BTreeMap<String, RecordEntry>,
+    pub record_import: Option<RecordImport>,
+    pub hash_keys: bool,
+    pub key_labels: HashMap<kad::RecordKey, String>,
+    /// Derived from `config.value_passphrase` in the background; values aren't put until it is.
+    pub value_cipher: Option<Arc<ValueCipher>>,
+    pub inbound_approval: bool,
+    pub pending_inbound: Vec<PendingApproval>,
+    pub dial_address: String,
+    pub query_history: Vec<QueryRecord>,
+    pub pending_queries: Vec<PendingQuery>,
+    /// Queries held back until a peer connects, oldest first.
+    pub queued_queries: Vec<(PendingQuery, P2pCommand)>,
+    pub query_latencies: QueryLatencies,
+    pub bandwidth: Bandwidth,
+    pub query_timeout: Duration,
+    pub chat_topic: String,
+    pub chat_input: String,
+    pub subscribed_topics: Vec<String>,
+    pub chat_messages: Vec<ChatMessage>,
+    pub direct_target: Option<PeerId>,
+    pub direct_input: String,
+    pub file_transfers: Vec<FileTransfer>,
+    pub relay_server: bool,
+    pub mdns_enabled: bool,
+    pub relay_limits: RelayLimits,
+    pub active_circuits: usize,
+    /// Record lookups answered for other peers this session.
+    pub served_gets: usize,
+    /// Provider lookups answered for other peers this session.
+    pub served_provider_lookups: usize,
+    pub nat_status: NatStatus,
+    pub metrics_address: Option<SocketAddr>,
+    pub kad_mode: KadMode,
+    pub config: AppConfig,
+    pub running_config: AppConfig,
+    pub config_path: Option<PathBuf>,
+    pub settings_form: SettingsForm,
+    pub networking_stopped: Option<String>,
+    /// What the OS asks for, followed while the theme preference is [`ThemePreference::System`].
+    pub system_theme: Theme,
+    /// Bumped on every change to the config, which is saved once it stops changing.
+    pub config_changes: u64,
+    pub show_help: bool,
+}
+
+/// The editor's text as typed; `Content::text` always ends it with a newline.
+pub fn editor_text(content: &text_editor::Content) -> String {
+    content.lines().map(|line| line.to_owned()).collect::<Vec<_>>().join("\n")
+}
+
+#[derive(Debug, Clone)]
+pub struct FileTransfer {
+    pub key: String,
+    pub received: u64,
+    pub total: Option<u64>,
+    pub status: TransferStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+/// A value a detail view can be opened for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ValueSource {
+    Record(String),
+    /// Numbered as by [`EventLog::numbered`].
+    LogEntry(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueView {
+    pub display: ValueDisplay,
+    /// Shows all of a value past what the view cuts it at.
+    pub whole: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordEntry {
+    /// The key as stored, namespace and all.
+    pub key: kad::RecordKey,
+    /// The value as unwrapped from its envelope, if it came in one.
+    pub value: Vec<u8>,
+    pub publisher: Option<PeerId>,
+    pub expires: Option<Instant>,
+    pub signature: RecordSignature,
+}
+
+impl RecordEntry {
+    /// An entry for a value as it sits in the store, unwrapping a signed one.
+    pub fn open(
+        key: &kad::RecordKey,
+        stored: &[u8],
+        publisher: Option<PeerId>,
+        expires: Option<Instant>,
+    ) -> Self {
+        let (value, signature) = envelope::open(key, stored);
+
+        Self {
+            key: key.clone(),
+            value: value.to_vec(),
+            publisher,
+            expires,
+            signature,
+        }
+    }
+}
+
+/// Records from a file being put a few at a time.
+#[derive(Debug, Clone, Default)]
+pub struct RecordImport {
+    pub queued: VecDeque<ImportedRecord>,
+    pub in_flight: Vec<(RequestId, String)>,
+    pub total: usize,
+    pub done: usize,
+    /// Records the DHT wouldn't take, as `key: reason`.
+    pub failures: Vec<String>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+impl RecordImport {
+    pub fn is_finished(&self) -> bool {
+        self.queued.is_empty() && self.in_flight.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedRecord {
+    pub label: String,
+    pub key: kad::RecordKey,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Draft {
+    pub text: String,
+    pub error: Option<String>,
+}
+
+impl Draft {
+    fn new(text: String) -> Self {
+        Self { text, error: None }
+    }
+}
+
+/// The raw text of the free-form settings, kept while it doesn't validate.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsForm {
+    pub listen_addrs: Draft,
+    pub bootstrap_peer: Draft,
+    pub known_peer_max_age: Draft,
+    pub mdns_ttl: Draft,
+    pub mdns_query_interval: Draft,
+    pub dht_protocol: Draft,
+    pub replication_factor: Draft,
+    pub query_parallelism: Draft,
+    pub query_timeout: Draft,
+    pub record_republish: Draft,
+    pub provider_republish: Draft,
+    pub max_connections: Draft,
+    pub max_connections_per_peer: Draft,
+    pub max_pending_incoming: Draft,
+    pub max_pending_outgoing: Draft,
+    pub dial_concurrency: Draft,
+    pub idle_connection_timeout: Draft,
+    pub record_ttl: Draft,
+    pub put_quorum: Draft,
+    pub get_quorum: Draft,
+    pub value_passphrase: Draft,
+    pub key_namespace: Draft,
+    pub identity_path: Draft,
+    pub metrics_addr: Draft,
+    pub log_dir: Draft,
+    pub log_retention: Draft,
+    /// Why the last TCP or QUIC switch was refused.
+    pub transport_error: Option<String>,
+    pub save_error: Option<String>,
+}
+
+impl SettingsForm {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            listen_addrs: Draft::new(
+                config
+                    .listen_addrs
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            bootstrap_peer: Draft::default(),
+            known_peer_max_age: Draft::new(config.known_peer_max_age_days.to_string()),
+            mdns_ttl: Draft::new(config.mdns_ttl_secs.to_string()),
+            mdns_query_interval: Draft::new(config.mdns_query_interval_secs.to_string()),
+            dht_protocol: Draft::new(config.dht_protocol.clone()),
+            replication_factor: Draft::new(config.replication_factor.to_string()),
+            query_parallelism: Draft::new(config.query_parallelism.to_string()),
+            query_timeout: Draft::new(config.query_timeout_secs.to_string()),
+            record_republish: Draft::new(config.record_republish_secs.to_string()),
+            provider_republish: Draft::new(config.provider_republish_secs.to_string()),
+            max_connections: limit_draft(config.max_connections),
+            max_connections_per_peer: limit_draft(config.max_connections_per_peer),
+            max_pending_incoming: limit_draft(config.max_pending_incoming),
+            max_pending_outgoing: limit_draft(config.max_pending_outgoing),
+            dial_concurrency: Draft::new(config.dial_concurrency.to_string()),
+            idle_connection_timeout: Draft::new(config.idle_connection_timeout_secs.to_string()),
+            record_ttl: Draft::new(
+                config
+                    .record_ttl_secs
+                    .map(|secs| secs.to_string())
+                    .unwrap_or_default(),
+            ),
+            put_quorum: Draft::new(config.put_quorum.to_string()),
+            get_quorum: Draft::new(config.get_quorum.to_string()),
+            value_passphrase: Draft::new(config.value_passphrase.clone().unwrap_or_default()),
+            key_namespace: Draft::new(config.key_namespace.clone()),
+            identity_path: Draft::new(
+                config
+                    .identity_path
+                    .as_ref()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default(),
+            ),
+            metrics_addr: Draft::new(config.metrics_addr.to_string()),
+            log_dir: Draft::new(
+                config
+                    .log_dir
+                    .as_ref()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default(),
+            ),
+            log_retention: Draft::new(config.log_retention.to_string()),
+            transport_error: None,
+            save_error: None,
+        }
+    }
+
+    pub fn edit(&mut self, field: &SettingsField, result: Result<(), String>) {
+        let (draft, input) = match field {
+            SettingsField::ListenAddrs(input) => (&mut self.listen_addrs, input),
+            SettingsField::AddBootstrapPeer(input) => {
+                self.bootstrap_peer = match result {
+                    Ok(()) => Draft::default(),
+                    Err(error) => Draft {
+                        text: input.clone(),
+                        error: Some(error),
+                    },
+                };
+                return;
+            }
+            SettingsField::KnownPeerMaxAge(input) => (&mut self.known_peer_max_age, input),
+            SettingsField::MdnsTtl(input) => (&mut self.mdns_ttl, input),
+            SettingsField::MdnsQueryInterval(input) => (&mut self.mdns_query_interval, input),
+            SettingsField::DhtProtocol(input) => (&mut self.dht_protocol, input),
+            SettingsField::ReplicationFactor(input) => (&mut self.replication_factor, input),
+            SettingsField::QueryParallelism(input) => (&mut self.query_parallelism, input),
+            SettingsField::QueryTimeout(input) => (&mut self.query_timeout, input),
+            SettingsField::RecordRepublishInterval(input) => (&mut self.record_republish, input),
+            SettingsField::ProviderRepublishInterval(input) => {
+                (&mut self.provider_republish, input)
+            }
+            SettingsField::MaxConnections(input) => (&mut self.max_connections, input),
+            SettingsField::MaxConnectionsPerPeer(input) => {
+                (&mut self.max_connections_per_peer, input)
+            }
+            SettingsField::MaxPendingIncoming(input) => (&mut self.max_pending_incoming, input),
+            SettingsField::MaxPendingOutgoing(input) => (&mut self.max_pending_outgoing, input),
+            SettingsField::DialConcurrency(input) => (&mut self.dial_concurrency, input),
+            SettingsField::IdleConnectionTimeout(input) => {
+                (&mut self.idle_connection_timeout, input)
+            }
+            SettingsField::RecordTtl(input) => (&mut self.record_ttl, input),
+            SettingsField::PutQuorum(input) => (&mut self.put_quorum, input),
+            SettingsField::GetQuorum(input) => (&mut self.get_quorum, input),
+            SettingsField::ValuePassphrase(input) => (&mut self.value_passphrase, input),
+            SettingsField::KeyNamespace(input) => (&mut self.key_namespace, input),
+            SettingsField::IdentityPath(input) => (&mut self.identity_path, input),
+            SettingsField::MetricsAddr(input) => (&mut self.metrics_addr, input),
+            SettingsField::LogDir(input) => (&mut self.log_dir, input),
+            SettingsField::LogRetention(input) => (&mut self.log_retention, input),
+            SettingsField::EnableTcp(_) | SettingsField::EnableQuic(_) => {
+                self.transport_error = result.err();
+                return;
+            }
+            SettingsField::RemoveBootstrapPeer(_)
+            | SettingsField::EnableWebsocket(_)
+            | SettingsField::Security(_)
+            | SettingsField::MdnsEnabled(_)
+            | SettingsField::DisjointQueryPaths(_)
+            | SettingsField::CacheRecords(_)
+            | SettingsField::SignRecords(_)
+            | SettingsField::SavePassphrase(_)
+            | SettingsField::MetricsEnabled(_)
+            | SettingsField::LogToFile(_)
+            | SettingsField::KadMode(_)
+            | SettingsField::LogCapacity(_)
+            | SettingsField::Theme(_)
+            | SettingsField::UiScale(_)
+            | SettingsField::FontSize(_) => return,
+        };
+
+        draft.text = input.clone();
+        draft.error = result.err();
+    }
+}
+
+/// Unlimited connection counts are shown as an empty field.
+fn limit_draft(limit: Option<u32>) -> Draft {
+    Draft::new(limit.map(|limit| limit.to_string()).unwrap_or_default())
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingApproval {
+    pub id: InboundId,
+    pub source: PeerId,
+    pub key: String,
+    pub value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub topic: String,
+    pub source: Option<PeerId>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+    pub addresses: Vec<Multiaddr>,
+    pub agent: Option<String>,
+    pub protocols: Vec<String>,
+    pub rtt: Option<Duration>,
+    pub unresponsive: bool,
+    pub relayed: Option<bool>,
+    /// How the latest connection to the peer was made, `None` while disconnected.
+    pub connection: Option<(ConnectionTransport, ConnectionDirection)>,
+    pub security: Option<SecurityProtocol>,
+    pub discovered_at: Option<Instant>,
+    pub last_seen: Option<Instant>,
+    pub expired_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastKind {
+    Success,
+    Info,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Toast {
+    pub id: u64,
+    pub kind: ToastKind,
+    pub text: String,
+    /// When it was last shown, so a repeat keeps it up for another [`TOAST_LIFETIME`].
+    pub created: Instant,
+    /// How many identical toasts it stands for.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingQuery {
+    pub request_id: RequestId,
+    pub kind: QueryKind,
+    pub key: String,
+    pub started_at: Instant,
+}
+
+/// How long the latest record gets and puts took; timed out ones are kept apart, so they don't
+/// drag the median along.
+#[derive(Debug, Clone, Default)]
+pub struct QueryLatencies {
+    /// Oldest first, `None` for a query that timed out.
+    samples: VecDeque<Option<Duration>>,
+}
+
+impl QueryLatencies {
+    pub const WINDOW: usize = 50;
+
+    pub fn record(&mut self, latency: Option<Duration>) {
+        if self.samples.len() == Self::WINDOW {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(latency);
+    }
+
+    pub fn samples(&self) -> &VecDeque<Option<Duration>> {
+        &self.samples
+    }
+
+    pub fn median(&self) -> Option<Duration> {
+        let mut answered: Vec<_> = self.samples.iter().flatten().copied().collect();
+        answered.sort();
+
+        answered.get(answered.len().checked_sub(1)? / 2).copied()
+    }
+
+    pub fn worst(&self) -> Option<Duration> {
+        self.samples.iter().flatten().max().copied()
+    }
+
+    pub fn timed_out(&self) -> usize {
+        self.samples.iter().filter(|sample| sample.is_none()).count()
+    }
+}
+
+/// The latest traffic report of the networking task.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bandwidth {
+    pub inbound_bps: u64,
+    pub outbound_bps: u64,
+    pub total_in: u64,
+    pub total_out: u64,
+}
+
+/// The values the last record lookup came back with.
+#[derive(Debug, Clone)]
+pub struct RecordLookup {
+    pub key: String,
+    pub values: Vec<FoundValue>,
+    pub agree: bool,
+}
+
+/// The latest answer to "who is closest to this key?", ordered by XOR distance.
+#[derive(Debug, Clone)]
+pub struct ClosestPeers {
+    pub key: String,
+    pub record_key: kad::RecordKey,
+    pub peers: Vec<PeerId>,
+    pub complete: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryRecord {
+    pub request_id: RequestId,
+    pub kind: QueryKind,
+    pub key: String,
+    pub status: QueryStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryStatus {
+    /// Waiting for a peer to connect before it's sent.
+    Queued,
+    Pending,
+    Succeeded,
+    NotFound,
+    Failed,
+    TimedOut,
+    Cancelled,
 }
 
 impl App {
-    pub fn new() -> (Self, Task<Message>) {
-        let (command_sender, command_receiver) = mpsc::channel(100);
-        let (event_sender, event_receiver) = mpsc::channel(100);
+    pub fn new(
+        config: NodeConfig,
+        settings: AppConfig,
+        config_path: Option<PathBuf>,
+        peer_grace: Duration,
+        trace_lines: tokio::sync::mpsc::Receiver<TraceLine>,
+        log_file_dir: Option<PathBuf>,
+    ) -> (Self, Task<Message>) {
+        let maximize = match settings.window {
+            Some(window) if window.maximized => {
+                window::get_latest().and_then(|id| window::maximize(id, true))
+            }
+            _ => Task::none(),
+        };
 
-        (
-            Self {
-                p2p_control: command_sender,
-                p2p_events: Arc::new(Mutex::new(event_receiver)),
-                state: State::default(),
-            },
-            Task::batch([
-                Task::perform(p2p::run(command_receiver, event_sender), |_| {
-                    Message::ServerStarted
+        let app = Self {
+            p2p_control: Commands::default(),
+            trace_lines: Arc::new(Mutex::new(trace_lines)),
+            generation: 0,
+            restart_pending: false,
+            closing: None,
+            state: State {
+                query_timeout: config.query_timeout,
+                relay_server: config.relay_server,
+                mdns_enabled: config.mdns_enabled,
+                relay_limits: config.relay_limits,
+                inbound_approval: config.inbound_approval,
+                peer_grace,
+                log_file_dir,
+                event_log: EventLog::with_capacity(settings.log_capacity),
+                settings_form: SettingsForm::new(&settings),
+                kad_mode: settings.kad_mode,
+                recent_keys: combo_box::State::new(settings.recent_keys.clone().into()),
+                running_config: settings.clone(),
+                config: settings,
+                config_path,
+                system_theme: system_theme().unwrap_or_else(|| {
+                    warn!("Could not detect the system theme, using the dark one");
+                    Theme::Dark
                 }),
-                widget::focus_next(),
-            ]),
+                ..State::default()
+            },
+            node_config: config,
+        };
+        let derive = derive_value_cipher(&app.state);
+
+        (
+            app,
+            Task::batch([Task::done(Message::FocusInput(InputField::Key)), maximize, derive]),
         )
     }
 
+    /// Asks the running swarm to shut down; it is relaunched once its task has exited.
+    fn restart_networking(&mut self) -> Task<Message> {
+        if self.restart_pending {
+            return Task::none();
+        }
+
+        if self.p2p_control.is_closed() {
+            return self.relaunch_networking();
+        }
+
+        self.restart_pending = true;
+        self.p2p_control.send(vec![P2pCommand::Shutdown])
+    }
+
+    fn networking_exited(&mut self, generation: u64) -> Task<Message> {
+        if self.closing.is_some() {
+            return self.finish_closing();
+        }
+
+        if generation != self.generation || !self.restart_pending {
+            return Task::none();
+        }
+
+        self.restart_pending = false;
+        self.relaunch_networking()
+    }
+
+    fn networking_stopped(&mut self, reason: String) -> Task<Message> {
+        error!("Networking stopped: {reason}");
+
+        if self.closing.is_some() {
+            return self.finish_closing();
+        }
+
+        if self.restart_pending {
+            self.restart_pending = false;
+            return self.relaunch_networking();
+        }
+
+        handle_networking_stopped(&mut self.state, reason)
+    }
+
+    fn relaunch_networking(&mut self) -> Task<Message> {
+        let settings = &self.state.config;
+        self.node_config = NodeConfig {
+            listen_addrs: settings.listen_addrs(),
+            bootstrap_peers: settings.bootstrap_peers.clone(),
+            known_peer_max_age: settings.known_peer_max_age(),
+            mdns: settings.mdns_timing(),
+            mdns_enabled: self.state.mdns_enabled,
+            connection_limits: settings.connection_limits(),
+            kad_protocol: settings.dht_protocol(),
+            kad_tuning: settings.kad_tuning(),
+            query_timeout: settings.query_timeout(),
+            republish: settings.republish_intervals(),
+            tcp: settings.enable_tcp,
+            quic: settings.enable_quic,
+            websocket: settings.enable_websocket,
+            security: settings.security,
+            sign_records: settings.sign_records,
+            kad_mode: settings.kad_mode.into(),
+            identity_path: settings.identity_path.clone(),
+            metrics_addr: settings.metrics_addr(),
+            relay_server: self.state.relay_server,
+            inbound_approval: self.state.inbound_approval,
+            ..self.node_config.clone()
+        };
+        self.generation += 1;
+        self.p2p_control.sender = None;
+
+        handle_networking_restarted(&mut self.state);
+
+        Task::none()
+    }
+
+    /// Keeps the window open until the swarm has shut down or [`SHUTDOWN_TIMEOUT`] has passed.
+    fn close_requested(&mut self, id: window::Id) -> Task<Message> {
+        if self.closing.is_some() {
+            return Task::none();
+        }
+
+        self.closing = Some(id);
+        // Changes still waiting to settle would be lost otherwise
+        save_config(&mut self.state);
+
+        if self.p2p_control.is_closed() {
+            return self.finish_closing();
+        }
+
+        Task::batch([
+            self.p2p_control.send(vec![P2pCommand::Shutdown]),
+            Task::perform(tokio::time::sleep(SHUTDOWN_TIMEOUT), |_| {
+                Message::ShutdownTimedOut
+            }),
+        ])
+    }
+
+    fn finish_closing(&mut self) -> Task<Message> {
+        match self.closing.take() {
+            Some(id) => window::close(id),
+            None => Task::none(),
+        }
+    }
+
     pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
-            Message::P2pEvent(event) => handle_p2p_event(&mut self.state, event),
-            Message::ServerStarted => Task::none(),
-            Message::Ignore => Task::none(),
-            Message::FocusNext => widget::focus_next(),
+            Message::P2pEvent(P2pEvent::Ready(sender)) => self.p2p_control.ready(sender),
+            Message::P2pEvent(P2pEvent::ShutdownComplete) if self.closing.is_some() => {
+                self.finish_closing()
+            }
+            Message::P2pEvent(event) => {
+                self.p2p_control.perform(handle_p2p_event(&mut self.state, event))
+            }
+            Message::RestartNetworking => self.restart_networking(),
+            Message::NetworkingExited(generation) => self.networking_exited(generation),
+            Message::NetworkingStopped(reason) => self.networking_stopped(reason),
+            Message::CloseRequested(id) => self.close_requested(id),
+            Message::ShutdownTimedOut => {
+                if self.closing.is_some() {
+                    warn!("Networking did not shut down in time, closing anyway");
+                }
+
+                self.finish_closing()
+            }
+            Message::FocusNext => {
+                widget::focus_next().chain(handle_find_focused_input(&mut self.state))
+            }
+            Message::FocusInput(field) => handle_focus_input(&mut self.state, field),
+            Message::FindFocusedInput => handle_find_focused_input(&mut self.state),
+            Message::InputFocused(field) => {
+                self.state.focused_input = field;
+                Task::none()
+            }
+            Message::EscapePressed => handle_escape_pressed(&mut self.state),
             Message::KeyTextChanged(data) => handle_key_text_changed(&mut self.state, data),
-            Message::ValueTextChanged(data) => handle_value_text_changed(&mut self.state, data),
-            Message::PutRecord(key, value) => {
-                handle_put_record(&mut self.state, key, value, self.p2p_control.clone())
+            Message::ValueEdited(action) => handle_value_edited(&mut self.state, action),
+            Message::TtlTextChanged(data) => handle_ttl_text_changed(&mut self.state, data),
+            Message::ClearRecordInputs => handle_clear_record_inputs(&mut self.state),
+            Message::RecentKeySelected(key) => handle_recent_key_selected(&mut self.state, key),
+            Message::AttachValueFile => handle_attach_value_file(&mut self.state),
+            Message::ValueFilePicked(path) => handle_value_file_picked(
+                &mut self.state,
+                path,
+                self.node_config.inbound_limits.max_value_bytes,
+            ),
+            Message::ValueFileLoaded(result) => handle_value_file_loaded(&mut self.state, result),
+            Message::DetachValueFile => handle_detach_value_file(&mut self.state),
+            Message::SaveValue(value) => handle_save_value(&mut self.state, value),
+            Message::ValueDestinationPicked(value, path) => {
+                handle_value_destination_picked(&mut self.state, value, path)
+            }
+            Message::PutRecord(key, value, ttl) => {
+                self.p2p_control.perform(handle_put_record(&mut self.state, key, value, ttl))
             }
             Message::GetRecord(key) => {
-                handle_get_record(&mut self.state, key, self.p2p_control.clone())
+                self.p2p_control.perform(handle_get_record(&mut self.state, key))
+            }
+            Message::GetProviders(key) => {
+                self.p2p_control.perform(handle_get_providers(&mut self.state, key))
+            }
+            Message::GetClosestPeers(key) => {
+                self.p2p_control.perform(handle_get_closest_peers(&mut self.state, key))
+            }
+            Message::StartProviding(key) => {
+                self.p2p_control.perform(handle_start_providing(&mut self.state, key))
+            }
+            Message::StopProviding(key) => {
+                self.p2p_control.perform(handle_stop_providing(&mut self.state, key))
+            }
+            Message::RemoveRecord(key) => {
+                self.p2p_control.perform(handle_remove_record(&mut self.state, key))
+            }
+            Message::RefreshRoutingTable => {
+                self.p2p_control.perform(handle_refresh_routing_table(&mut self.state))
+            }
+            Message::ToggleBucket(index) => handle_toggle_bucket(&mut self.state, index),
+            Message::RefreshRecords => {
+                self.p2p_control.perform(handle_refresh_records(&mut self.state))
+            }
+            Message::DialAddressChanged(data) => handle_dial_address_changed(&mut self.state, data),
+            Message::CopyToClipboard(data) => clipboard::write(data),
+            Message::ToggleListenAddrs => handle_toggle_listen_addrs(&mut self.state),
+            Message::Tick(now) => handle_tick(&mut self.state, now),
+            Message::LogClockTick => Task::none(),
+            Message::CancelQuery(request_id) => {
+                self.p2p_control.perform(handle_cancel_query(&mut self.state, request_id))
+            }
+            Message::TabSelected(Tab::Routing) => Task::batch([
+                handle_tab_selected(&mut self.state, Tab::Routing),
+                self.p2p_control.perform(handle_refresh_routing_table(&mut self.state)),
+            ]),
+            Message::TabSelected(tab) => handle_tab_selected(&mut self.state, tab),
+            Message::ToggleLogCategory(category) => {
+                handle_toggle_log_category(&mut self.state, category)
+            }
+            Message::LogSearchChanged(data) => handle_log_search_changed(&mut self.state, data),
+            Message::ClearLog => handle_clear_log(&mut self.state),
+            Message::ToggleValueView(source, display) => {
+                handle_toggle_value_view(&mut self.state, source, display)
+            }
+            Message::ValueDisplayChanged(source, display) => {
+                handle_value_display_changed(&mut self.state, source, display)
+            }
+            Message::ShowWholeValue(source) => handle_show_whole_value(&mut self.state, source),
+            Message::EventSelected(number) => handle_event_selected(&mut self.state, number),
+            Message::CloseEventDetail => handle_close_event_detail(&mut self.state),
+            Message::DismissToast(id) => handle_dismiss_toast(&mut self.state, id),
+            Message::ToastTick(now) => handle_toast_tick(&mut self.state, now),
+            Message::DetectSystemTheme => handle_detect_system_theme(&mut self.state),
+            Message::WindowMoved(position) => handle_window_moved(&mut self.state, position),
+            Message::WindowResized(id, size) => handle_window_resized(&mut self.state, id, size),
+            Message::WindowMaximizedChecked(size, maximized) => {
+                handle_window_maximized_checked(&mut self.state, size, maximized)
+            }
+            Message::ConfigSettled(change) => handle_config_settled(&mut self.state, change),
+            Message::ValueCipherDerived(cipher) => {
+                handle_value_cipher_derived(&mut self.state, cipher)
+            }
+            Message::ShortcutPressed(shortcut, status) => handle_shortcut(
+                &mut self.state,
+                shortcut,
+                status,
+                self.node_config.inbound_limits.max_value_bytes,
+            ),
+            Message::CloseHelp => handle_close_help(&mut self.state),
+            Message::SystemThemeDetected(theme) => {
+                handle_system_theme_detected(&mut self.state, theme)
+            }
+            Message::LogScrolled(viewport) => handle_log_scrolled(&mut self.state, viewport),
+            Message::JumpToLatest => handle_jump_to_latest(&mut self.state),
+            Message::CopyLog => handle_copy_log(&mut self.state),
+            Message::TraceLine(line) => handle_trace_line(&mut self.state, line),
+            Message::ToggleDiagnostics => handle_toggle_diagnostics(&mut self.state),
+            Message::TraceLevelSelected(level) => {
+                handle_trace_level_selected(&mut self.state, level)
+            }
+            Message::CopyDiagnostics => handle_copy_diagnostics(&mut self.state),
+            Message::BenchCountChanged(input) => handle_bench_count_changed(&mut self.state, input),
+            Message::BenchValueSizeChanged(input) => {
+                handle_bench_value_size_changed(&mut self.state, input)
+            }
+            Message::RunBenchmark => {
+                self.p2p_control.perform(handle_run_benchmark(&mut self.state))
+            }
+            Message::CancelBenchmark => {
+                self.p2p_control.perform(handle_cancel_benchmark(&mut self.state))
+            }
+            Message::ContinueBenchmark => {
+                self.p2p_control.perform(handle_continue_benchmark(&mut self.state))
+            }
+            Message::OpenLogFolder => handle_open_log_folder(&mut self.state),
+            Message::SettingsChanged(field) => handle_settings_changed(&mut self.state, field),
+            Message::ToggleExportRecords(enabled) => {
+                handle_toggle_export_records(&mut self.state, enabled)
+            }
+            Message::ExportLog => handle_export_log(&mut self.state),
+            Message::ExportDestinationPicked(path) => {
+                handle_export_destination_picked(&mut self.state, path)
+            }
+            Message::ExportFinished(result) => handle_export_finished(&mut self.state, result),
+            Message::ImportRecords => handle_import_records(&mut self.state),
+            Message::ImportFilePicked(path) => handle_import_file_picked(&mut self.state, path),
+            Message::RecordsImported(result) => {
+                self.p2p_control.perform(handle_records_imported(&mut self.state, result))
+            }
+            Message::ContinueImport => {
+                self.p2p_control.perform(handle_continue_import(&mut self.state))
+            }
+            Message::DismissImport => handle_dismiss_import(&mut self.state),
+            Message::ExportRecords => handle_export_records(&mut self.state),
+            Message::RecordsExportPicked(path) => {
+                handle_records_export_picked(&mut self.state, path)
+            }
+            Message::ChatTopicChanged(data) => handle_chat_topic_changed(&mut self.state, data),
+            Message::ChatInputChanged(data) => handle_chat_input_changed(&mut self.state, data),
+            Message::SubscribeTopic(topic) => {
+                self.p2p_control.perform(handle_subscribe_topic(&mut self.state, topic))
+            }
+            Message::UnsubscribeTopic(topic) => {
+                self.p2p_control.perform(handle_unsubscribe_topic(&mut self.state, topic))
+            }
+            Message::SendChat(topic, text) => {
+                self.p2p_control.perform(handle_publish_message(&mut self.state, topic, text))
+            }
+            Message::ComposeDirect(peer_id) => handle_compose_direct(&mut self.state, peer_id),
+            Message::DirectInputChanged(data) => handle_direct_input_changed(&mut self.state, data),
+            Message::SendDirect(peer_id, text) => {
+                self.p2p_control.perform(handle_send_direct(&mut self.state, peer_id, text))
+            }
+            Message::CancelDirect => handle_cancel_direct(&mut self.state),
+            Message::ShareFile => handle_share_file(&mut self.state),
+            Message::FileToSharePicked(path) => {
+                self.p2p_control.perform(handle_file_to_share_picked(&mut self.state, path))
+            }
+            Message::FetchFile(key) => handle_fetch_file(&mut self.state, key),
+            Message::FetchDestinationPicked(key, path) => self
+                .p2p_control
+                .perform(handle_fetch_destination_picked(&mut self.state, key, path)),
+            Message::Bootstrap => self.p2p_control.perform(handle_bootstrap(&mut self.state)),
+            Message::BootstrapPeerInputChanged(data) => {
+                handle_bootstrap_peer_input_changed(&mut self.state, data)
+            }
+            Message::ReconnectBootstrap => {
+                self.p2p_control.perform(handle_reconnect_bootstrap(&mut self.state))
+            }
+            Message::Dial(address) => {
+                self.p2p_control.perform(handle_dial(&mut self.state, address))
+            }
+            Message::DisconnectPeer(peer_id) => {
+                self.p2p_control.perform(handle_disconnect_peer(&mut self.state, peer_id))
+            }
+            Message::BlockPeer(peer_id) => {
+                self.p2p_control.perform(handle_block_peer(&mut self.state, peer_id))
+            }
+            Message::UnblockPeer(peer_id) => {
+                self.p2p_control.perform(handle_unblock_peer(&mut self.state, peer_id))
+            }
+            Message::ToggleRelayServer(enabled) => {
+                self.p2p_control.perform(handle_toggle_relay_server(&mut self.state, enabled))
+            }
+            Message::ToggleMdns(enabled) => {
+                self.p2p_control.perform(handle_toggle_mdns(&mut self.state, enabled))
+            }
+            Message::ToggleRecordSigning(enabled) => {
+                self.p2p_control.perform(handle_toggle_record_signing(&mut self.state, enabled))
+            }
+            Message::ListenViaRelay(address) => {
+                self.p2p_control.perform(handle_listen_via_relay(&mut self.state, address))
+            }
+            Message::ToggleInboundApproval(enabled) => {
+                self.p2p_control.perform(handle_toggle_inbound_approval(&mut self.state, enabled))
+            }
+            Message::ToggleKadServer(enabled) => {
+                self.p2p_control.perform(handle_toggle_kad_server(&mut self.state, enabled))
+            }
+            Message::ToggleHashKeys(enabled) => handle_toggle_hash_keys(&mut self.state, enabled),
+            Message::AcceptInbound(id) => {
+                self.p2p_control.perform(handle_accept_inbound(&mut self.state, id))
+            }
+            Message::RejectInbound(id) => {
+                self.p2p_control.perform(handle_reject_inbound(&mut self.state, id))
             }
         }
     }
 
     pub fn subscription(&self) -> Subscription<Message> {
-        let p2p_sub = from_recipe(P2pSub(self.p2p_events.clone()));
+        let p2p_sub = Subscription::run_with_id(
+            self.generation,
+            networking(self.node_config.clone(), self.generation),
+        );
+        let trace_sub = from_recipe(TraceSub(self.trace_lines.clone()));
 
         let focus_sub = keyboard::on_key_release(|key, _modifiers| match key {
             keyboard::Key::Named(key::Named::Tab) => Some(Message::FocusNext),
             _ => None,
         });
 
-        Subscription::batch([p2p_sub, focus_sub])
+        let input_sub = event::listen_with(|event, status, window| match event {
+            Event::Keyboard(keyboard::Event::KeyPressed {
+                key: keyboard::Key::Named(key::Named::Escape),
+                ..
+            }) => Some(Message::EscapePressed),
+            // Not `keyboard::on_key_press`, which skips every key a focused input takes
+            Event::Keyboard(keyboard::Event::KeyPressed { key, modifiers, .. }) => {
+                Shortcut::of(&key, modifiers)
+                    .map(|shortcut| Message::ShortcutPressed(shortcut, status))
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(_)) => Some(Message::FindFocusedInput),
+            Event::Window(window::Event::Moved(position)) => Some(Message::WindowMoved(position)),
+            Event::Window(window::Event::Resized(size)) => {
+                Some(Message::WindowResized(window, size))
+            }
+            _ => None,
+        });
+
+        let tick_sub = if self.state.pending_queries.is_empty() && self.state.peers.is_empty() {
+            Subscription::none()
+        } else {
+            time::every(Duration::from_secs(1)).map(Message::Tick)
+        };
+
+        let log_clock_sub = if self.state.active_tab == Tab::Log && !self.state.event_log.is_empty() {
+            time::every(Duration::from_secs(1)).map(|_| Message::LogClockTick)
+        } else {
+            Subscription::none()
+        };
+
+        let toast_sub = if self.state.toasts.is_empty() {
+            Subscription::none()
+        } else {
+            time::every(Duration::from_millis(250)).map(Message::ToastTick)
+        };
+
+        let system_theme_sub = if self.state.config.theme == ThemePreference::System {
+            time::every(SYSTEM_THEME_POLL_INTERVAL).map(|_| Message::DetectSystemTheme)
+        } else {
+            Subscription::none()
+        };
+
+        let close_sub = window::close_requests().map(Message::CloseRequested);
+
+        Subscription::batch([
+            p2p_sub,
+            trace_sub,
+            focus_sub,
+            input_sub,
+            tick_sub,
+            log_clock_sub,
+            toast_sub,
+            system_theme_sub,
+            close_sub,
+        ])
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        f64::from(self.state.config.ui_scale) / 100.0
     }
 
     pub fn theme(&self) -> Theme {
-        match dark_light::detect().expect("Failed to detect system theme") {
-            dark_light::Mode::Light => {
-                trace!("Detected light system theme");
-                Theme::Light
+        match self.state.config.theme {
+            ThemePreference::Light => Theme::Light,
+            ThemePreference::Dark => Theme::Dark,
+            ThemePreference::System => self.state.system_theme.clone(),
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let network_status = network_status(
+            self.state.local_peer_id,
+            &self.state.listen_addrs,
+            self.state.show_listen_addrs,
+            (
+                self.state.connected_peers.len(),
+                self.state
+                    .peers
+                    .values()
+                    .filter(|peer| !peer.addresses.is_empty())
+                    .count(),
+            ),
+            self.state.relay_server.then_some(self.state.active_circuits),
+            (
+                &self.state.nat_status,
+                self.state.kad_mode,
+                self.state.mdns_enabled,
+                self.state.config.theme,
+            ),
+            (
+                self.state.served_gets,
+                self.state.served_provider_lookups,
+                &self.state.query_latencies,
+                self.state.bandwidth,
+            ),
+        );
+        let tab_bar = tab_bar(self.state.active_tab);
+
+        let content = match self.state.active_tab {
+            Tab::Dht => {
+                let value = match &self.state.value_bytes {
+                    Some(bytes) => ValueInput::File {
+                        name: &self.state.value_file_name,
+                        bytes,
+                    },
+                    None => ValueInput::Text(&self.state.value_editor),
+                };
+                let input_section = input_section(
+                    &self.state.current_key,
+                    value,
+                    &self.state.current_ttl,
+                    &self.state.recent_keys,
+                    &self.state.pending_queries,
+                    self.node_config.inbound_limits.max_value_bytes,
+                );
+                let file_section = file_section(&self.state.current_key, &self.state.file_transfers);
+                let pending_inbound = pending_inbound_list(&self.state.pending_inbound);
+                let records_table = records_table(
+                    &self.state.records,
+                    &self.state.value_views,
+                    self.state.record_import.as_ref(),
+                    Instant::now(),
+                );
+                let providing_list = providing_list(&self.state.provided_keys);
+                let record_lookup = record_lookup_view(self.state.record_lookup.as_ref());
+                let closest_peers = closest_peers_list(
+                    self.state.closest_peers.as_ref(),
+                    &self.state.connected_peers,
+                );
+                let query_history = query_history(&self.state.query_history);
+
+                iced::widget::column![
+                    input_section,
+                    file_section,
+                    pending_inbound,
+                    records_table,
+                    record_lookup,
+                    providing_list,
+                    closest_peers,
+                    query_history
+                ]
+                    .spacing(10)
+                    .into()
             }
-            dark_light::Mode::Dark => {
-                trace!("Detected dark system theme");
-                Theme::Dark
+            Tab::Peers => {
+                let dial_section = dial_section(&self.state.dial_address);
+                let peer_list = peer_list(
+                    &self.state.peers,
+                    &self.state.connected_peers,
+                    self.state.direct_target,
+                    &self.state.direct_input,
+                    Instant::now(),
+                );
+                let remembered_list = remembered_list(
+                    &self.state.remembered_peers,
+                    &self.state.peers,
+                    SystemTime::now(),
+                );
+                let blocked_list = blocked_list(&self.state.blocked_peers);
+
+                iced::widget::column![dial_section, peer_list, remembered_list, blocked_list]
+                    .spacing(10)
+                    .into()
             }
-            dark_light::Mode::Unspecified => {
-                warn!("System theme is not specified");
-                Theme::Dark
+            Tab::Log => iced::widget::column![
+                iced::widget::row![event_log(
+                    &self.state.event_log,
+                    &self.state.value_views,
+                    &self.state.log_filter,
+                    self.state.log_paused,
+                    self.state.export_records,
+                    self.state.selected_event,
+                    SystemTime::now(),
+                )]
+                .push_maybe(self.state.selected_event.map(|number| {
+                    event_detail(number, self.state.event_log.get(number))
+                }))
+                .spacing(10),
+                diagnostics_panel(
+                    &self.state.trace_log,
+                    self.state.trace_level,
+                    self.state.show_diagnostics,
+                ),
+            ]
+            .push_maybe(self.state.show_diagnostics.then(|| {
+                benchmark_section(
+                    &self.state.bench_count,
+                    &self.state.bench_value_size,
+                    self.state.benchmark.as_ref(),
+                    self.state.benchmark_report.as_ref(),
+                )
+            }))
+            .spacing(10)
+            .into(),
+            Tab::Settings => {
+                let settings_view = settings_view(
+                    self.state.relay_server,
+                    self.state.relay_limits,
+                    self.state.mdns_enabled,
+                    self.state.inbound_approval,
+                    self.state.hash_keys,
+                    self.state.peer_grace,
+                    self.state.query_timeout,
+                );
+                let kad_mode_section =
+                    kad_mode_section(self.state.kad_mode, self.state.provided_keys.len());
+                let config_section = config_section(
+                    &self.state.config,
+                    &self.state.settings_form,
+                    self.state.config.requires_restart(&self.state.running_config),
+                    self.state.metrics_address,
+                    self.state.log_file_dir.as_deref(),
+                );
+
+                widget::scrollable(iced::widget::column![
+                    settings_view,
+                    kad_mode_section,
+                    config_section
+                ])
+                .into()
             }
-        }
-    }
+            Tab::Chat => chat_view(
+                &self.state.chat_topic,
+                &self.state.chat_input,
+                &self.state.subscribed_topics,
+                &self.state.chat_messages,
+            ),
+            Tab::Routing => routing_table(
+                &self.state.routing_table,
+                &self.state.expanded_buckets,
+                self.state.changed_bucket,
+            ),
+        };
 
-    pub fn view(&self) -> Element<Message> {
-        let network_status = network_status(self.state.peer_count);
-        let input_section = input_section(&self.state.current_key, &self.state.current_value);
-        let event_log = event_log(&self.state.event_log);
+        let stopped_banner = self.state.networking_stopped.as_deref().map(stopped_banner);
 
-        iced::widget::column![network_status, input_section, event_log]
+        let main = iced::widget::column![network_status]
+            .push_maybe(stopped_banner)
+            .push(tab_bar)
+            .push(content)
             .height(Fill)
             .padding(20)
-            .spacing(10)
-            .into()
+            .spacing(10);
+
+        let mut layers = iced::widget::stack![main];
+        if self.state.show_help {
+            layers = layers.push(help_overlay());
+        }
+
+        layers.push(toast_layer(&self.state.toasts)).into()
+    }
+}
+
+/// The theme the OS asks for, if it can be found out; some Linux setups have no portal to ask.
+pub fn system_theme() -> Option<Theme> {
+    match dark_light::detect() {
+        Ok(dark_light::Mode::Light) => Some(Theme::Light),
+        Ok(dark_light::Mode::Dark) => Some(Theme::Dark),
+        Ok(dark_light::Mode::Unspecified) => {
+            trace!("System theme is not specified");
+            None
+        }
+        Err(err) => {
+            trace!("Failed to detect system theme: {err}");
+            None
+        }
+    }
+}
+
+/// Where commands for the node go; they wait here while a swarm is starting, until it hands
+/// out its sender.
+#[derive(Default)]
+struct Commands {
+    sender: Option<mpsc::Sender<P2pCommand>>,
+    queued: Vec<P2pCommand>,
+}
+
+impl Commands {
+    /// Whether the swarm the commands were for has stopped taking them.
+    fn is_closed(&self) -> bool {
+        self.sender.as_ref().is_some_and(mpsc::Sender::is_closed)
+    }
+
+    fn ready(&mut self, sender: mpsc::Sender<P2pCommand>) -> Task<Message> {
+        self.sender = Some(sender);
+        let queued = mem::take(&mut self.queued);
+
+        self.send(queued)
+    }
+
+    /// Runs what a handler asked for, sending its commands to the node in order.
+    fn perform(&mut self, effect: Effect) -> Task<Message> {
+        let Effect { commands, task } = effect;
+
+        Task::batch([task, self.send(commands)])
     }
+
+    /// Hands the commands to the node without waiting, reporting the ones its full channel
+    /// turned away. A closed channel is left to the subscription to report.
+    fn send(&mut self, commands: Vec<P2pCommand>) -> Task<Message> {
+        let Some(sender) = self.sender.as_mut() else {
+            self.queued.extend(commands);
+            return Task::none();
+        };
+
+        let rejected = commands.into_iter().filter_map(|cmd| match sender.try_send(cmd) {
+            Err(err) if err.is_full() => {
+                let id = err.into_inner().request_id();
+                Some(Task::done(Message::P2pEvent(P2pEvent::Error(id, P2pError::NodeBusy))))
+            }
+            _ => None,
+        });
+
+        Task::batch(rejected)
+    }
+}
+
+/// Runs one generation of the swarm for as long as its subscription lives: hands out the
+/// sender for its commands first, forwards its events and reports how it ended last.
+fn networking(config: NodeConfig, generation: u64) -> impl Stream<Item = Message> {
+    iced::stream::channel(100, move |mut output| async move {
+        let (command_sender, command_receiver) = mpsc::channel(100);
+        let (event_sender, mut event_receiver) = mpsc::channel(100);
+        output.send(Message::P2pEvent(P2pEvent::Ready(command_sender))).await.ok();
+
+        let run = AssertUnwindSafe(p2p::run(command_receiver, event_sender, config)).catch_unwind();
+        let forward = async {
+            while let Some(event) = event_receiver.next().await {
+                output.send(Message::P2pEvent(event)).await.ok();
+            }
+        };
+        let (result, ()) = future::join(run, forward).await;
+
+        let exited = match result {
+            Ok(Ok(())) => Message::NetworkingExited(generation),
+            Ok(Err(reason)) => Message::NetworkingStopped(reason),
+            Err(panic) => Message::NetworkingStopped(panic_reason(panic)),
+        };
+        output.send(exited).await.ok();
+    })
+}
+
+fn panic_reason(panic: Box<dyn Any + Send>) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_owned());
+
+    format!("the p2p task panicked: {message}")
 }
 
-struct P2pSub(Arc<Mutex<mpsc::Receiver<P2pEvent>>>);
+/// The lines the tracing layer forwards; there is only ever one receiver, so the hash is fixed.
+struct TraceSub(Arc<Mutex<tokio::sync::mpsc::Receiver<TraceLine>>>);
 
-impl Recipe for P2pSub {
+impl Recipe for TraceSub {
     type Output = Message;
 
     fn hash(&self, state: &mut Hasher) {
-        std::any::TypeId::of::<Self>().hash(state)
+        std::any::TypeId::of::<Self>().hash(state);
     }
 
     fn stream(self: Box<Self>, _: EventStream) -> BoxStream<'static, Self::Output> {
         Box::pin(async_stream::stream! {
             let mut receiver = self.0.lock().await;
 
-            while let Some(event) = receiver.next().await {
-                yield Message::P2pEvent(event)
+            while let Some(line) = receiver.recv().await {
+                yield Message::TraceLine(line)
             }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::{FoundValue, P2pOutboundEvent};
+    use crate::p2p::envelope::RecordSignature;
+
+    #[test]
+    fn commands_wait_for_the_swarm_to_hand_out_its_sender() {
+        let mut commands = Commands::default();
+
+        let _ = commands.perform(Effect::send(P2pCommand::Bootstrap));
+        assert!(matches!(commands.queued.as_slice(), [P2pCommand::Bootstrap]));
+        assert!(!commands.is_closed());
+
+        let (sender, receiver) = mpsc::channel(10);
+        let _ = commands.ready(sender);
+        assert!(commands.queued.is_empty());
+
+        drop(receiver);
+        assert!(commands.is_closed());
+    }
+
+    #[test]
+    fn log_entries_keep_their_numbers_as_older_ones_leave() {
+        let mut log = EventLog::with_capacity(2);
+        let push = |log: &mut EventLog| {
+            log.push(LogEntry::new(P2pEvent::LocalIdentity(PeerId::random())))
+        };
+
+        for _ in 0..3 {
+            push(&mut log);
+        }
+        let numbers: Vec<_> = log.numbered().map(|(number, _)| number).collect();
+
+        assert_eq!(numbers, [1, 2]);
+        assert!(log.get(0).is_none());
+        assert!(std::ptr::eq(log.get(2).unwrap(), log.iter().last().unwrap()));
+
+        log.clear();
+        push(&mut log);
+
+        assert!(log.get(2).is_none());
+        assert_eq!(log.numbered().next().unwrap().0, 3);
+        assert_eq!(log.discarded(), 0);
+    }
+
+    #[test]
+    fn repeated_events_collapse_unless_anything_differs() {
+        let mut log = EventLog::default();
+        let resolved = |value: &[u8]| {
+            LogEntry::new(P2pEvent::Outbound(None, P2pOutboundEvent::RecordResolved {
+                key: kad::RecordKey::new(&"key"),
+                values: vec![FoundValue {
+                    peer: None,
+                    value: value.to_vec(),
+                    signature: RecordSignature::default(),
+                }],
+                agree: true,
+            }))
+        };
+
+        for _ in 0..3 {
+            log.push(resolved(b"a"));
+        }
+        log.push(resolved(b"b"));
+        log.push(resolved(b"a"));
+
+        let repeats: Vec<_> = log.iter().map(|entry| entry.repeat).collect();
+        assert_eq!(repeats, [3, 1, 1]);
+
+        let first = log.iter().next().unwrap();
+        assert_eq!(first.occurrences().count(), 3);
+        assert!(first.to_string().ends_with(" ×3"));
+    }
+
+    #[test]
+    fn editor_text_is_the_value_as_typed() {
+        let json = "{\n  \"a\": 1\n}";
+
+        assert_eq!(editor_text(&text_editor::Content::new()), "");
+        assert_eq!(editor_text(&text_editor::Content::with_text(json)), json);
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Formatter;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use libp2p::kad;
+use rand::RngCore;
+use crate::inbound::DEFAULT_MAX_VALUE_BYTES;
+use crate::p2p::{P2pCommand, P2pEvent, P2pLocalEvent, P2pOutboundEvent, RequestId};
+
+pub const DEFAULT_BENCH_COUNT: usize = 50;
+pub const DEFAULT_BENCH_VALUE_SIZE: usize = 256;
+const MAX_BENCH_COUNT: usize = 10_000;
+/// Operations kept in flight at once, well under the queries Kademlia runs side by side.
+const BENCH_CONCURRENCY: usize = 4;
+/// We can only remove our own copies, so the ones at other peers are put with a short life.
+const BENCH_RECORD_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Reads the record count and value size typed for a benchmark; blank inputs take the defaults.
+pub fn parse_params(count: &str, value_size: &str) -> Result<(usize, usize), String> {
+    let count = match count.trim() {
+        "" => DEFAULT_BENCH_COUNT,
+        input => match input.parse() {
+            Ok(count @ 1..=MAX_BENCH_COUNT) => count,
+            _ => return Err(format!("Enter a record count from 1 to {MAX_BENCH_COUNT}")),
+        },
+    };
+    let value_size = match value_size.trim() {
+        "" => DEFAULT_BENCH_VALUE_SIZE,
+        input => match input.parse() {
+            Ok(size @ 1..=DEFAULT_MAX_VALUE_BYTES) => size,
+            _ => {
+                let max = DEFAULT_MAX_VALUE_BYTES;
+                return Err(format!("Enter a value size from 1 to {max} bytes"));
+            }
+        },
+    };
+
+    Ok((count, value_size))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchPhase {
+    Put,
+    Get,
+    Remove,
+}
+
+/// Puts random records and gets them back through the normal command channel, timing every
+/// operation from its command going out to the event answering its request.
+///
+/// The owner feeds it events with [`Benchmark::handle`] and sends whatever
+/// [`Benchmark::take_commands`] hands back, which ends with removing the records again. Events
+/// about its records are claimed too, so they don't flood the log.
+#[derive(Debug)]
+pub struct Benchmark {
+    /// Starts every key of this run.
+    prefix: String,
+    records: Vec<(kad::RecordKey, Vec<u8>)>,
+    value_size: usize,
+    put_quorum: kad::Quorum,
+    get_quorum: NonZeroUsize,
+    phase: BenchPhase,
+    /// Operations of the current phase sent so far.
+    started: usize,
+    in_flight: HashMap<RequestId, Instant>,
+    /// Queries given up on by cancelling, whose answers are dropped.
+    abandoned: HashSet<RequestId>,
+    removing: HashSet<kad::RecordKey>,
+    phase_started_at: Instant,
+    puts: Timings,
+    gets: Timings,
+    cancelled: bool,
+    outbox: Vec<P2pCommand>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Timings {
+    latencies: Vec<Duration>,
+    failed: usize,
+    elapsed: Duration,
+}
+
+impl Timings {
+    fn done(&self) -> usize {
+        self.latencies.len() + self.failed
+    }
+
+    fn stats(&self) -> OpStats {
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+
+        let quantile = |q: f64| {
+            let rank = (sorted.len() as f64 * q).ceil() as usize;
+            sorted.get(rank.saturating_sub(1)).copied().unwrap_or_default()
+        };
+
+        OpStats {
+            succeeded: sorted.len(),
+            failed: self.failed,
+            min: sorted.first().copied().unwrap_or_default(),
+            median: quantile(0.5),
+            p95: quantile(0.95),
+            elapsed: self.elapsed,
+        }
+    }
+}
+
+impl Benchmark {
+    pub fn new(
+        count: usize,
+        value_size: usize,
+        put_quorum: kad::Quorum,
+        get_quorum: NonZeroUsize,
+    ) -> Self {
+        let mut rng = rand::thread_rng();
+        let prefix = format!("bench-{:016x}-", rng.next_u64());
+        let records = (0..count)
+            .map(|i| {
+                let mut value = vec![0; value_size];
+                rng.fill_bytes(&mut value);
+                (kad::RecordKey::new(&format!("{prefix}{i}")), value)
+            })
+            .collect();
+
+        let mut benchmark = Self {
+            prefix,
+            records,
+            value_size,
+            put_quorum,
+            get_quorum,
+            phase: BenchPhase::Put,
+            started: 0,
+            in_flight: HashMap::new(),
+            abandoned: HashSet::new(),
+            removing: HashSet::new(),
+            phase_started_at: Instant::now(),
+            puts: Timings::default(),
+            gets: Timings::default(),
+            cancelled: false,
+            outbox: Vec::new(),
+        };
+        benchmark.fill();
+        benchmark.advance();
+        benchmark
+    }
+
+    /// The phase running and how many of its operations are done, out of how many.
+    pub fn progress(&self) -> (BenchPhase, usize, usize) {
+        match self.phase {
+            BenchPhase::Put => (self.phase, self.puts.done(), self.records.len()),
+            BenchPhase::Get => (self.phase, self.gets.done(), self.records.len()),
+            BenchPhase::Remove => (self.phase, self.started - self.removing.len(), self.started),
+        }
+    }
+
+    /// Whether it's done and its records are gone again.
+    pub fn is_finished(&self) -> bool {
+        self.phase == BenchPhase::Remove && self.removing.is_empty()
+    }
+
+    /// Commands to send next, in order.
+    pub fn take_commands(&mut self) -> Vec<P2pCommand> {
+        mem::take(&mut self.outbox)
+    }
+
+    /// Times the operation `event` answers, returning whether it was about the benchmark.
+    pub fn handle(&mut self, event: &P2pEvent) -> bool {
+        let (request_id, succeeded) = match event {
+            P2pEvent::Local(P2pLocalEvent::RecordStored(record)) => {
+                return self.owns(&record.key);
+            }
+            P2pEvent::Local(
+                P2pLocalEvent::RecordRemoved(key) | P2pLocalEvent::RecordNotInStore(key),
+            ) => return self.removing.remove(key),
+            P2pEvent::Outbound(Some(id), P2pOutboundEvent::RecordNotFound(_)) => (id, false),
+            P2pEvent::Outbound(Some(id), _) => (id, true),
+            P2pEvent::Error(Some(id), _) => (id, false),
+            _ => return false,
+        };
+
+        if self.abandoned.remove(request_id) {
+            return true;
+        }
+
+        let Some(sent_at) = self.in_flight.remove(request_id) else {
+            return false;
+        };
+
+        let timings = match self.phase {
+            BenchPhase::Put => &mut self.puts,
+            BenchPhase::Get | BenchPhase::Remove => &mut self.gets,
+        };
+
+        if succeeded {
+            timings.latencies.push(sent_at.elapsed());
+        } else {
+            timings.failed += 1;
+        }
+
+        self.fill();
+        self.advance();
+
+        true
+    }
+
+    /// Gives up on the operations in flight and cleans up what was put so far.
+    pub fn cancel(&mut self) {
+        if self.phase == BenchPhase::Remove {
+            return;
+        }
+
+        self.abandoned = self.in_flight.drain().map(|(request_id, _)| request_id).collect();
+        self.outbox.extend(self.abandoned.iter().copied().map(P2pCommand::CancelQuery));
+        self.cancelled = true;
+        self.finish();
+    }
+
+    /// The results, once it's finished or cancelled.
+    pub fn report(&self) -> Option<BenchmarkReport> {
+        self.is_finished().then(|| BenchmarkReport {
+            count: self.records.len(),
+            value_size: self.value_size,
+            puts: self.puts.stats(),
+            gets: self.gets.stats(),
+            cancelled: self.cancelled,
+        })
+    }
+
+    fn fill(&mut self) {
+        while self.in_flight.len() < BENCH_CONCURRENCY && self.started < self.records.len() {
+            let (key, value) = &self.records[self.started];
+            let request_id = RequestId::next();
+            let cmd = match self.phase {
+                BenchPhase::Put => P2pCommand::PutRecord(
+                    request_id,
+                    key.clone(),
+                    value.clone(),
+                    Some(BENCH_RECORD_TTL),
+                    self.put_quorum,
+                ),
+                BenchPhase::Get => P2pCommand::GetRecord(request_id, key.clone(), self.get_quorum),
+                BenchPhase::Remove => return,
+            };
+
+            self.in_flight.insert(request_id, Instant::now());
+            self.outbox.push(cmd);
+            self.started += 1;
+        }
+    }
+
+    fn advance(&mut self) {
+        while self.in_flight.is_empty() {
+            match self.phase {
+                BenchPhase::Put => {
+                    self.puts.elapsed = self.phase_started_at.elapsed();
+                    self.phase = BenchPhase::Get;
+                    self.started = 0;
+                    self.phase_started_at = Instant::now();
+                    self.fill();
+                }
+                BenchPhase::Get => self.finish(),
+                BenchPhase::Remove => return,
+            }
+        }
+    }
+
+    /// Removes the records put so far; `started` counts them from here on.
+    fn finish(&mut self) {
+        match self.phase {
+            BenchPhase::Put => self.puts.elapsed = self.phase_started_at.elapsed(),
+            BenchPhase::Get => {
+                self.gets.elapsed = self.phase_started_at.elapsed();
+                self.started = self.records.len();
+            }
+            BenchPhase::Remove => return,
+        }
+
+        self.phase = BenchPhase::Remove;
+        self.removing = self.records[..self.started].iter().map(|(key, _)| key.clone()).collect();
+        self.outbox.extend(self.removing.iter().cloned().map(P2pCommand::RemoveRecord));
+    }
+
+    fn owns(&self, key: &kad::RecordKey) -> bool {
+        key.as_ref().starts_with(self.prefix.as_bytes())
+    }
+}
+
+/// Latencies of one kind of operation; the quantiles only count the ones that succeeded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    /// From the first operation going out to the last one answered.
+    pub elapsed: Duration,
+}
+
+impl OpStats {
+    pub fn ops_per_sec(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+
+        self.succeeded as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for OpStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ok, {} failed", self.succeeded, self.failed)?;
+
+        if self.succeeded > 0 {
+            write!(
+                f,
+                ", min {}ms, median {}ms, p95 {}ms, {:.1} ops/s",
+                self.min.as_millis(),
+                self.median.as_millis(),
+                self.p95.as_millis(),
+                self.ops_per_sec()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchmarkReport {
+    pub count: usize,
+    pub value_size: usize,
+    pub puts: OpStats,
+    pub gets: OpStats,
+    pub cancelled: bool,
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Benchmark of {} records of {} bytes{}: puts {}; gets {}",
+            self.count,
+            self.value_size,
+            if self.cancelled { " (cancelled)" } else { "" },
+            self.puts,
+            self.gets
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers the commands the way the node would, with every lookup coming up empty.
+    fn answer(benchmark: &mut Benchmark, commands: &[P2pCommand]) {
+        for cmd in commands {
+            let event = match cmd {
+                P2pCommand::PutRecord(request_id, key, ..) => {
+                    P2pEvent::Outbound(Some(*request_id), P2pOutboundEvent::RecordPut(key.clone()))
+                }
+                P2pCommand::GetRecord(request_id, key, _) => P2pEvent::Outbound(
+                    Some(*request_id),
+                    P2pOutboundEvent::RecordNotFound(key.clone()),
+                ),
+                P2pCommand::RemoveRecord(key) => {
+                    P2pEvent::Local(P2pLocalEvent::RecordRemoved(key.clone()))
+                }
+                _ => continue,
+            };
+
+            assert!(benchmark.handle(&event));
+        }
+    }
+
+    #[test]
+    fn runs_puts_then_gets_a_few_at_a_time_and_removes_the_records() {
+        let mut benchmark = Benchmark::new(6, 32, kad::Quorum::One, NonZeroUsize::MIN);
+        let mut removed = 0;
+
+        while !benchmark.is_finished() {
+            let commands = benchmark.take_commands();
+            let (removes, queries): (Vec<_>, Vec<_>) = commands
+                .iter()
+                .partition(|cmd| matches!(cmd, P2pCommand::RemoveRecord(_)));
+
+            assert!(!commands.is_empty());
+            assert!(queries.len() <= BENCH_CONCURRENCY);
+            removed += removes.len();
+            answer(&mut benchmark, &commands);
+        }
+
+        let report = benchmark.report().unwrap();
+        assert_eq!(removed, 6);
+        assert_eq!((report.puts.succeeded, report.puts.failed), (6, 0));
+        assert_eq!((report.gets.succeeded, report.gets.failed), (0, 6));
+        assert!(report.puts.min <= report.puts.median && report.puts.median <= report.puts.p95);
+        assert!(!report.to_string().contains("cancelled"));
+
+        let stranger = P2pEvent::Outbound(
+            Some(RequestId::next()),
+            P2pOutboundEvent::RecordPut(kad::RecordKey::new(&"greeting")),
+        );
+        assert!(!benchmark.handle(&stranger));
+    }
+
+    #[test]
+    fn cancelling_stops_the_queries_and_removes_only_what_was_put() {
+        let mut benchmark = Benchmark::new(10, 32, kad::Quorum::One, NonZeroUsize::MIN);
+        let first = benchmark.take_commands();
+        answer(&mut benchmark, &first[..1]);
+        benchmark.take_commands();
+
+        benchmark.cancel();
+        let commands = benchmark.take_commands();
+        let cancelled: Vec<_> = commands
+            .iter()
+            .filter_map(|cmd| match cmd {
+                P2pCommand::CancelQuery(request_id) => Some(*request_id),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(cancelled.len(), BENCH_CONCURRENCY);
+        assert_eq!(commands.len() - cancelled.len(), BENCH_CONCURRENCY + 1);
+        assert!(benchmark.report().is_none());
+
+        let late = P2pEvent::Outbound(
+            Some(cancelled[0]),
+            P2pOutboundEvent::QueryCancelled(kad::RecordKey::new(&"greeting")),
+        );
+        assert!(benchmark.handle(&late));
+
+        answer(&mut benchmark, &commands);
+        assert!(benchmark.report().unwrap().cancelled);
+    }
+
+    #[test]
+    fn blank_parameters_take_the_defaults() {
+        assert_eq!(parse_params("", " "), Ok((DEFAULT_BENCH_COUNT, DEFAULT_BENCH_VALUE_SIZE)));
+        assert_eq!(parse_params("5", "1024"), Ok((5, 1024)));
+        assert!(parse_params("0", "").is_err());
+        assert!(parse_params("", "1000000").is_err());
+    }
+}
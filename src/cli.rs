@@ -0,0 +1,192 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use clap::Parser;
+use libp2p::Multiaddr;
+use crate::config::AppConfig;
+use crate::p2p;
+
+/// A libp2p playground: Kademlia records, gossip chat, file sharing and relays.
+///
+/// Options given here win over the settings file for this run.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Settings file to read and save instead of the default one
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Identity key file to load or create
+    #[arg(long, value_name = "PATH")]
+    pub identity: Option<PathBuf>,
+    /// Address to listen on; repeat for several
+    #[arg(long = "listen", value_name = "MULTIADDR")]
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Only listen on IPv4 when no --listen address is given
+    #[arg(long)]
+    pub no_ipv6: bool,
+    /// Console log filter in `RUST_LOG` syntax, overriding the environment
+    #[arg(long, value_name = "FILTER")]
+    pub log: Option<String>,
+    /// Don't discover peers on the local network
+    #[arg(long)]
+    pub no_mdns: bool,
+    /// Use a throwaway identity instead of the saved one
+    #[arg(long)]
+    pub fresh_identity: bool,
+    /// Don't remember or redial peers from earlier sessions
+    #[arg(long)]
+    pub forget_peers: bool,
+    /// Keep records on disk in the data directory
+    #[arg(long)]
+    pub persistent_store: bool,
+    /// Keep records on disk in this file
+    #[arg(long, value_name = "PATH")]
+    pub store_path: Option<PathBuf>,
+    /// Seconds a DHT query may run before it is given up
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub query_timeout: Option<u64>,
+    /// Seconds expired peers stay listed
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub peer_grace: u64,
+    /// Act as a relay for other peers
+    #[arg(long)]
+    pub relay_server: bool,
+    #[arg(long, value_name = "COUNT")]
+    pub relay_max_reservations: Option<usize>,
+    #[arg(long, value_name = "COUNT")]
+    pub relay_max_circuits: Option<usize>,
+    /// Largest record value accepted from other peers, in bytes
+    #[arg(long, value_name = "BYTES")]
+    pub max_record_size: Option<usize>,
+    #[arg(long, value_name = "COUNT")]
+    pub max_records_per_peer: Option<usize>,
+    /// Store requests one peer may send per minute before the rest are dropped
+    #[arg(long, value_name = "COUNT")]
+    pub max_stores_per_minute: Option<u32>,
+    /// Only accept record values that are valid UTF-8
+    #[arg(long)]
+    pub require_utf8: bool,
+    /// Run without a window, reading commands from stdin
+    #[arg(long)]
+    pub headless: bool,
+    /// Print events and read commands as JSON lines (headless only)
+    #[arg(long, requires = "headless")]
+    pub json: bool,
+    /// Serve the HTTP control API on this address (headless only)
+    #[arg(long, value_name = "ADDR", requires = "headless")]
+    pub api: Option<SocketAddr>,
+}
+
+impl Cli {
+    /// Parses the process arguments, exiting with usage, help or version text when asked.
+    pub fn parse_or_exit() -> Self {
+        match Cli::try_parse() {
+            Ok(cli) => cli,
+            Err(err) => exit(err),
+        }
+    }
+
+    /// Overrides the settings loaded from the config file.
+    pub fn apply(&self, settings: &mut AppConfig) {
+        if !self.listen_addrs.is_empty() {
+            settings.listen_addrs = self.listen_addrs.clone();
+        } else if self.no_ipv6 {
+            settings.listen_addrs = p2p::default_listen_addrs(false);
+        }
+
+        if let Some(path) = &self.identity {
+            settings.identity_path = Some(path.clone());
+        }
+
+        if self.no_mdns {
+            settings.mdns_enabled = false;
+        }
+
+        if let Some(secs) = self.query_timeout {
+            settings.query_timeout_secs = secs;
+        }
+    }
+}
+
+/// The release build has no console, so the text goes into a message box there.
+#[cfg(all(windows, not(debug_assertions)))]
+fn exit(err: clap::Error) -> ! {
+    rfd::MessageDialog::new()
+        .set_title("iced-libp2p-sample")
+        .set_description(err.render().to_string())
+        .show();
+    std::process::exit(err.exit_code())
+}
+
+#[cfg(not(all(windows, not(debug_assertions))))]
+fn exit(err: clap::Error) -> ! {
+    err.exit()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(args)
+    }
+
+    #[test]
+    fn listen_args_accept_both_forms_and_reject_garbage() {
+        let cli = parse(&[
+            "app",
+            "--listen",
+            "/ip4/0.0.0.0/tcp/4001",
+            "--listen=/ip4/0.0.0.0/udp/4001/quic-v1",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.listen_addrs.len(), 2);
+        assert!(parse(&["app", "--listen", "garbage"]).is_err());
+        assert!(parse(&["app", "--listen"]).is_err());
+    }
+
+    #[test]
+    fn api_arg_accepts_both_forms_and_rejects_garbage() {
+        let expected: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+
+        assert_eq!(parse(&["app"]).unwrap().api, None);
+        assert_eq!(
+            parse(&["app", "--headless", "--api", "127.0.0.1:8080"]).unwrap().api,
+            Some(expected)
+        );
+        assert_eq!(
+            parse(&["app", "--headless", "--api=127.0.0.1:8080"]).unwrap().api,
+            Some(expected)
+        );
+        assert!(parse(&["app", "--headless", "--api", "localhost"]).is_err());
+        assert!(parse(&["app", "--headless", "--api"]).is_err());
+    }
+
+    #[test]
+    fn cli_options_win_over_the_config_file() {
+        let mut settings = AppConfig::default();
+        parse(&["app", "--identity", "/tmp/id.key", "--no-mdns", "--query-timeout", "5"])
+            .unwrap()
+            .apply(&mut settings);
+
+        assert_eq!(settings.identity_path, Some("/tmp/id.key".into()));
+        assert!(!settings.mdns_enabled);
+        assert_eq!(settings.query_timeout(), Duration::from_secs(5));
+        assert!(parse(&["app", "--query-timeout", "0"]).is_err());
+        assert_eq!(settings.listen_addrs, AppConfig::default().listen_addrs);
+    }
+
+    #[test]
+    fn ipv6_is_listened_on_unless_turned_off() {
+        let is_ipv6 = |address: &Multiaddr| {
+            matches!(address.iter().next(), Some(libp2p::multiaddr::Protocol::Ip6(_)))
+        };
+        let mut settings = AppConfig::default();
+        assert_eq!(settings.listen_addrs.iter().filter(|address| is_ipv6(address)).count(), 2);
+
+        parse(&["app", "--no-ipv6"]).unwrap().apply(&mut settings);
+        assert_eq!(settings.listen_addrs.len(), 2);
+        assert!(!settings.listen_addrs.iter().any(is_ipv6));
+    }
+}
@@ -0,0 +1,976 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::num::{NonZeroU8, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use directories::ProjectDirs;
+use iced::{Point, Size};
+use libp2p::{Multiaddr, StreamProtocol};
+use libp2p::kad::{self, Mode};
+use serde::{Deserialize, Serialize};
+use crate::app::DEFAULT_LOG_CAPACITY;
+use crate::file_log;
+use crate::p2p;
+use crate::p2p::{ConnectionLimits, KadTuning, MdnsTiming, RepublishIntervals};
+use crate::p2p::security::Security;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+pub const MIN_LOG_CAPACITY: usize = 100;
+const RECENT_KEYS_CAPACITY: usize = 20;
+pub const MAX_LOG_CAPACITY: usize = 10_000;
+/// The UI scale is kept in percent, so the config stays comparable.
+pub const MIN_UI_SCALE: u32 = 75;
+pub const MAX_UI_SCALE: u32 = 200;
+pub const DEFAULT_UI_SCALE: u32 = 100;
+pub const UI_SCALE_STEP: u32 = 5;
+pub const MIN_FONT_SIZE: u16 = 10;
+pub const MAX_FONT_SIZE: u16 = 24;
+/// What iced uses unless told otherwise.
+const DEFAULT_FONT_SIZE: u16 = 16;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+const DEFAULT_METRICS_PORT: u16 = 9464;
+const MAX_REPLICATION_FACTOR: usize = 100;
+const MAX_QUERY_PARALLELISM: usize = 20;
+/// A few answers are enough to see whether peers disagree without waiting for all of them.
+const DEFAULT_GET_QUORUM: NonZeroUsize = NonZeroUsize::new(3).unwrap();
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{err}"),
+            ConfigError::Parse(err) => write!(f, "config file is invalid: {err}"),
+            ConfigError::Serialize(err) => write!(f, "config can't be written: {err}"),
+            ConfigError::Invalid(reason) => write!(f, "config file is invalid: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(err: toml::ser::Error) -> Self {
+        ConfigError::Serialize(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KadMode {
+    Client,
+    #[default]
+    Server,
+}
+
+impl KadMode {
+    pub const ALL: [KadMode; 2] = [KadMode::Client, KadMode::Server];
+}
+
+impl From<KadMode> for Mode {
+    fn from(mode: KadMode) -> Self {
+        match mode {
+            KadMode::Client => Mode::Client,
+            KadMode::Server => Mode::Server,
+        }
+    }
+}
+
+impl From<Mode> for KadMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Client => KadMode::Client,
+            Mode::Server => KadMode::Server,
+        }
+    }
+}
+
+impl fmt::Display for KadMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            KadMode::Client => write!(f, "Client"),
+            KadMode::Server => write!(f, "Server"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreference {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl ThemePreference {
+    pub const ALL: [ThemePreference; 3] = [
+        ThemePreference::System,
+        ThemePreference::Light,
+        ThemePreference::Dark,
+    ];
+
+    /// The one after this in [`ALL`](Self::ALL), wrapping around.
+    pub fn next(self) -> Self {
+        match self {
+            ThemePreference::System => ThemePreference::Light,
+            ThemePreference::Light => ThemePreference::Dark,
+            ThemePreference::Dark => ThemePreference::System,
+        }
+    }
+}
+
+impl fmt::Display for ThemePreference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemePreference::System => write!(f, "System"),
+            ThemePreference::Light => write!(f, "Light"),
+            ThemePreference::Dark => write!(f, "Dark"),
+        }
+    }
+}
+
+/// The window's last size and place, in the OS's logical pixels, to open it the same way again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub width: u32,
+    pub height: u32,
+    /// Missing where windows aren't told where they are, as on Wayland.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<(i32, i32)>,
+    pub maximized: bool,
+}
+
+impl Default for WindowGeometry {
+    fn default() -> Self {
+        let size = iced::window::Settings::default().size;
+
+        Self {
+            width: size.width as u32,
+            height: size.height as u32,
+            position: None,
+            maximized: false,
+        }
+    }
+}
+
+impl WindowGeometry {
+    pub fn size(&self) -> Size {
+        Size::new(self.width as f32, self.height as f32)
+    }
+
+    /// `position` moved just enough for a `window` to fit on a `monitor`, so that a window last
+    /// seen on a screen that has since been unplugged doesn't open where nobody can see it.
+    pub fn clamp_position(position: Point, window: Size, monitor: Size) -> Point {
+        let clamp = |at: f32, length: f32, room: f32| at.clamp(0.0, (room - length).max(0.0));
+
+        Point::new(
+            clamp(position.x, window.width, monitor.width),
+            clamp(position.y, window.height, monitor.height),
+        )
+    }
+}
+
+/// User settings, read from `config.toml` at startup and written back whenever they change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub listen_addrs: Vec<Multiaddr>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bootstrap_peers: Vec<Multiaddr>,
+    /// Switching one off forces connections over the other, for testing; one has to stay on.
+    pub enable_tcp: bool,
+    pub enable_quic: bool,
+    /// Also accepts and dials `/ws` addresses, for browsers and networks that only let web
+    /// traffic through.
+    pub enable_websocket: bool,
+    /// What TCP, WebSocket and relayed connections are secured with; QUIC always uses TLS.
+    pub security: Security,
+    pub known_peer_max_age_days: u64,
+    pub mdns_enabled: bool,
+    pub mdns_ttl_secs: u64,
+    pub mdns_query_interval_secs: u64,
+    pub kad_mode: KadMode,
+    pub dht_protocol: String,
+    pub replication_factor: NonZeroUsize,
+    pub query_parallelism: NonZeroUsize,
+    pub query_timeout_secs: u64,
+    pub disjoint_query_paths: bool,
+    pub cache_records: bool,
+    /// How often our own records and provider announcements are put again; real nodes wait
+    /// hours, shorter intervals make it visible in a demo.
+    pub record_republish_secs: u64,
+    pub provider_republish_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections_per_peer: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pending_incoming: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pending_outgoing: Option<u32>,
+    pub dial_concurrency: NonZeroU8,
+    pub idle_connection_timeout_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record_ttl_secs: Option<u64>,
+    pub put_quorum: NonZeroUsize,
+    /// Values a lookup collects before it stops.
+    pub get_quorum: NonZeroUsize,
+    pub sign_records: bool,
+    /// Encrypts the values we put and decrypts the ones we fetch; kept out of the config file
+    /// unless `save_passphrase` is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_passphrase: Option<String>,
+    pub save_passphrase: bool,
+    /// Prefixed to every key we put or look up, as `<namespace>/<key>`, so instances sharing a
+    /// DHT don't step on each other's records.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_namespace: String,
+    pub log_capacity: usize,
+    pub log_to_file: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<PathBuf>,
+    pub log_retention: usize,
+    pub theme: ThemePreference,
+    pub ui_scale: u32,
+    /// Only read at startup; iced has no way to change the default text size of a running app.
+    pub font_size: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity_path: Option<PathBuf>,
+    pub metrics_enabled: bool,
+    pub metrics_addr: SocketAddr,
+    /// Keys of the latest successful puts, gets and provides, newest first.
+    #[serde(skip_serializing_if = "VecDeque::is_empty")]
+    pub recent_keys: VecDeque<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<WindowGeometry>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        let mdns = MdnsTiming::default();
+        let connections = ConnectionLimits::default();
+        let kad_tuning = KadTuning::default();
+        let republish = RepublishIntervals::default();
+
+        Self {
+            listen_addrs: p2p::default_listen_addrs(true),
+            bootstrap_peers: Vec::new(),
+            enable_tcp: true,
+            enable_quic: true,
+            enable_websocket: false,
+            security: Security::default(),
+            known_peer_max_age_days: p2p::DEFAULT_KNOWN_PEER_MAX_AGE.as_secs() / SECS_PER_DAY,
+            mdns_enabled: true,
+            mdns_ttl_secs: mdns.ttl.as_secs(),
+            mdns_query_interval_secs: mdns.query_interval.as_secs(),
+            kad_mode: KadMode::default(),
+            dht_protocol: kad::PROTOCOL_NAME.to_string(),
+            replication_factor: kad_tuning.replication_factor,
+            query_parallelism: kad_tuning.parallelism,
+            query_timeout_secs: p2p::DEFAULT_QUERY_TIMEOUT.as_secs(),
+            disjoint_query_paths: kad_tuning.disjoint_query_paths,
+            cache_records: kad_tuning.cache_records,
+            record_republish_secs: republish.records.as_secs(),
+            provider_republish_secs: republish.providers.as_secs(),
+            max_connections: connections.max_established,
+            max_connections_per_peer: connections.max_established_per_peer,
+            max_pending_incoming: connections.max_pending_incoming,
+            max_pending_outgoing: connections.max_pending_outgoing,
+            dial_concurrency: connections.dial_concurrency,
+            idle_connection_timeout_secs: connections.idle_timeout.as_secs(),
+            record_ttl_secs: None,
+            put_quorum: NonZeroUsize::MIN,
+            get_quorum: DEFAULT_GET_QUORUM,
+            sign_records: false,
+            value_passphrase: None,
+            save_passphrase: false,
+            key_namespace: String::new(),
+            log_capacity: DEFAULT_LOG_CAPACITY,
+            log_to_file: !cfg!(debug_assertions),
+            log_dir: None,
+            log_retention: file_log::DEFAULT_LOG_RETENTION,
+            theme: ThemePreference::default(),
+            ui_scale: DEFAULT_UI_SCALE,
+            font_size: DEFAULT_FONT_SIZE,
+            identity_path: None,
+            metrics_enabled: false,
+            metrics_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, DEFAULT_METRICS_PORT)),
+            recent_keys: VecDeque::new(),
+            window: None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Moves `key` to the front of the recent keys, dropping the oldest past the cap.
+    pub fn remember_key(&mut self, key: &str) {
+        if key.is_empty() {
+            return;
+        }
+
+        self.recent_keys.retain(|recent| recent != key);
+        self.recent_keys.push_front(key.to_owned());
+        self.recent_keys.truncate(RECENT_KEYS_CAPACITY);
+    }
+
+    /// The configured listen addresses, plus a WebSocket one when that transport is on and none
+    /// of them uses it yet.
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        let mut addrs = self.listen_addrs.clone();
+
+        if self.enable_websocket && !addrs.iter().any(p2p::is_websocket) {
+            addrs.push(p2p::default_websocket_listen_addr());
+        }
+
+        addrs
+    }
+
+    pub fn mdns_timing(&self) -> MdnsTiming {
+        MdnsTiming {
+            ttl: Duration::from_secs(self.mdns_ttl_secs),
+            query_interval: Duration::from_secs(self.mdns_query_interval_secs),
+        }
+    }
+
+    /// The Kademlia protocol name; only peers using the same one share a DHT with us.
+    pub fn dht_protocol(&self) -> StreamProtocol {
+        parse_dht_protocol(&self.dht_protocol).unwrap_or(kad::PROTOCOL_NAME)
+    }
+
+    pub fn kad_tuning(&self) -> KadTuning {
+        KadTuning {
+            replication_factor: self.replication_factor,
+            parallelism: self.query_parallelism,
+            disjoint_query_paths: self.disjoint_query_paths,
+            cache_records: self.cache_records,
+        }
+    }
+
+    pub fn republish_intervals(&self) -> RepublishIntervals {
+        RepublishIntervals {
+            records: Duration::from_secs(self.record_republish_secs),
+            providers: Duration::from_secs(self.provider_republish_secs),
+        }
+    }
+
+    pub fn query_timeout(&self) -> Duration {
+        Duration::from_secs(self.query_timeout_secs)
+    }
+
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            max_established: self.max_connections,
+            max_established_per_peer: self.max_connections_per_peer,
+            max_pending_incoming: self.max_pending_incoming,
+            max_pending_outgoing: self.max_pending_outgoing,
+            dial_concurrency: self.dial_concurrency,
+            idle_timeout: Duration::from_secs(self.idle_connection_timeout_secs),
+        }
+    }
+
+    pub fn known_peer_max_age(&self) -> Duration {
+        Duration::from_secs(self.known_peer_max_age_days * SECS_PER_DAY)
+    }
+
+    /// The folder for log files, if writing them is enabled and a folder is known.
+    pub fn log_dir(&self) -> Option<PathBuf> {
+        if !self.log_to_file {
+            return None;
+        }
+
+        self.log_dir.clone().or_else(file_log::default_dir)
+    }
+
+    /// Where to serve `/metrics` from, if serving it is enabled.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_enabled.then_some(self.metrics_addr)
+    }
+
+    pub fn record_ttl(&self) -> Option<Duration> {
+        self.record_ttl_secs.map(Duration::from_secs)
+    }
+
+    pub fn quorum(&self) -> kad::Quorum {
+        match self.put_quorum.get() {
+            1 => kad::Quorum::One,
+            _ => kad::Quorum::N(self.put_quorum),
+        }
+    }
+
+    /// Whether the running swarm was built from settings that differ from these.
+    pub fn requires_restart(&self, running: &AppConfig) -> bool {
+        self.listen_addrs != running.listen_addrs
+            || self.enable_tcp != running.enable_tcp
+            || self.enable_quic != running.enable_quic
+            || self.enable_websocket != running.enable_websocket
+            || self.security != running.security
+            || self.mdns_ttl_secs != running.mdns_ttl_secs
+            || self.mdns_query_interval_secs != running.mdns_query_interval_secs
+            || self.kad_mode != running.kad_mode
+            || self.dht_protocol != running.dht_protocol
+            || self.kad_tuning() != running.kad_tuning()
+            || self.query_timeout_secs != running.query_timeout_secs
+            || self.republish_intervals() != running.republish_intervals()
+            || self.connection_limits() != running.connection_limits()
+            || self.identity_path != running.identity_path
+            || self.metrics_addr() != running.metrics_addr()
+    }
+
+    /// Validates `field` and stores it, leaving the config untouched when the input is invalid.
+    pub fn apply(&mut self, field: &SettingsField) -> Result<(), String> {
+        match field {
+            SettingsField::ListenAddrs(input) => {
+                let addrs = input
+                    .split([',', '\n'])
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| {
+                        value
+                            .parse::<Multiaddr>()
+                            .map_err(|err| format!("{value} is not a multiaddr: {err}"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if addrs.is_empty() {
+                    return Err("At least one listen address is required".to_owned());
+                }
+
+                self.listen_addrs = addrs;
+            }
+            SettingsField::AddBootstrapPeer(input) => {
+                let address = input
+                    .trim()
+                    .parse::<Multiaddr>()
+                    .map_err(|err| format!("Not a multiaddr: {err}"))?;
+
+                if self.bootstrap_peers.contains(&address) {
+                    return Err(format!("{address} is already a bootstrap peer"));
+                }
+
+                self.bootstrap_peers.push(address);
+            }
+            SettingsField::RemoveBootstrapPeer(address) => {
+                self.bootstrap_peers.retain(|known| known != address);
+            }
+            SettingsField::EnableTcp(enabled) => {
+                check_transports(*enabled, self.enable_quic)?;
+                self.enable_tcp = *enabled;
+            }
+            SettingsField::EnableQuic(enabled) => {
+                check_transports(self.enable_tcp, *enabled)?;
+                self.enable_quic = *enabled;
+            }
+            SettingsField::EnableWebsocket(enabled) => self.enable_websocket = *enabled,
+            SettingsField::Security(security) => self.security = *security,
+            SettingsField::KnownPeerMaxAge(input) => {
+                self.known_peer_max_age_days = match input.trim().parse() {
+                    Ok(0) | Err(_) => return Err("Enter a whole number of days above zero".to_owned()),
+                    Ok(days) => days,
+                };
+            }
+            SettingsField::MdnsEnabled(enabled) => self.mdns_enabled = *enabled,
+            SettingsField::MdnsTtl(input) => self.mdns_ttl_secs = parse_secs(input)?,
+            SettingsField::MdnsQueryInterval(input) => {
+                self.mdns_query_interval_secs = parse_secs(input)?;
+            }
+            SettingsField::KadMode(mode) => self.kad_mode = *mode,
+            SettingsField::DhtProtocol(input) => {
+                self.dht_protocol = parse_dht_protocol(input)?.to_string();
+            }
+            SettingsField::ReplicationFactor(input) => {
+                self.replication_factor = parse_bounded(input, MAX_REPLICATION_FACTOR)?;
+            }
+            SettingsField::QueryParallelism(input) => {
+                self.query_parallelism = parse_bounded(input, MAX_QUERY_PARALLELISM)?;
+            }
+            SettingsField::QueryTimeout(input) => self.query_timeout_secs = parse_secs(input)?,
+            SettingsField::DisjointQueryPaths(enabled) => self.disjoint_query_paths = *enabled,
+            SettingsField::CacheRecords(enabled) => self.cache_records = *enabled,
+            SettingsField::RecordRepublishInterval(input) => {
+                self.record_republish_secs = parse_secs(input)?;
+            }
+            SettingsField::ProviderRepublishInterval(input) => {
+                self.provider_republish_secs = parse_secs(input)?;
+            }
+            SettingsField::MaxConnections(input) => self.max_connections = parse_limit(input)?,
+            SettingsField::MaxConnectionsPerPeer(input) => {
+                self.max_connections_per_peer = parse_limit(input)?;
+            }
+            SettingsField::MaxPendingIncoming(input) => {
+                self.max_pending_incoming = parse_limit(input)?;
+            }
+            SettingsField::MaxPendingOutgoing(input) => {
+                self.max_pending_outgoing = parse_limit(input)?;
+            }
+            SettingsField::DialConcurrency(input) => {
+                self.dial_concurrency = input
+                    .trim()
+                    .parse()
+                    .map_err(|_| "Enter a number of dials from 1 to 255".to_owned())?;
+            }
+            SettingsField::IdleConnectionTimeout(input) => {
+                self.idle_connection_timeout_secs = parse_secs(input)?;
+            }
+            SettingsField::RecordTtl(input) if input.trim().is_empty() => {
+                self.record_ttl_secs = None;
+            }
+            SettingsField::RecordTtl(input) => self.record_ttl_secs = Some(parse_secs(input)?),
+            SettingsField::PutQuorum(input) => {
+                self.put_quorum = input
+                    .trim()
+                    .parse()
+                    .map_err(|_| "Quorum must be a positive number of peers".to_owned())?;
+            }
+            SettingsField::GetQuorum(input) => {
+                self.get_quorum = input
+                    .trim()
+                    .parse()
+                    .map_err(|_| "Quorum must be a positive number of peers".to_owned())?;
+            }
+            SettingsField::SignRecords(enabled) => self.sign_records = *enabled,
+            SettingsField::ValuePassphrase(input) => {
+                self.value_passphrase = (!input.is_empty()).then(|| input.clone());
+            }
+            SettingsField::SavePassphrase(enabled) => self.save_passphrase = *enabled,
+            SettingsField::KeyNamespace(input) => {
+                self.key_namespace = input.trim().trim_end_matches('/').to_owned();
+            }
+            SettingsField::LogCapacity(capacity) => {
+                self.log_capacity = (*capacity).clamp(MIN_LOG_CAPACITY, MAX_LOG_CAPACITY);
+            }
+            SettingsField::LogToFile(enabled) => self.log_to_file = *enabled,
+            SettingsField::LogDir(input) if input.trim().is_empty() => self.log_dir = None,
+            SettingsField::LogDir(input) => self.log_dir = Some(input.trim().into()),
+            SettingsField::LogRetention(input) => {
+                self.log_retention = match input.trim().parse() {
+                    Ok(0) | Err(_) => return Err("Keep at least one log file".to_owned()),
+                    Ok(count) => count,
+                };
+            }
+            SettingsField::Theme(theme) => self.theme = *theme,
+            SettingsField::UiScale(scale) => {
+                self.ui_scale = (*scale).clamp(MIN_UI_SCALE, MAX_UI_SCALE);
+            }
+            SettingsField::FontSize(size) => {
+                self.font_size = (*size).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+            }
+            SettingsField::IdentityPath(input) if input.trim().is_empty() => {
+                self.identity_path = None;
+            }
+            SettingsField::IdentityPath(input) => self.identity_path = Some(input.trim().into()),
+            SettingsField::MetricsEnabled(enabled) => self.metrics_enabled = *enabled,
+            SettingsField::MetricsAddr(input) => {
+                self.metrics_addr = input
+                    .trim()
+                    .parse()
+                    .map_err(|_| "Enter an address like 127.0.0.1:9464".to_owned())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An edit made in the Settings tab; free-form fields carry the raw input for validation.
+#[derive(Debug, Clone)]
+pub enum SettingsField {
+    ListenAddrs(String),
+    AddBootstrapPeer(String),
+    RemoveBootstrapPeer(Multiaddr),
+    EnableTcp(bool),
+    EnableQuic(bool),
+    EnableWebsocket(bool),
+    Security(Security),
+    KnownPeerMaxAge(String),
+    MdnsEnabled(bool),
+    MdnsTtl(String),
+    MdnsQueryInterval(String),
+    KadMode(KadMode),
+    DhtProtocol(String),
+    ReplicationFactor(String),
+    QueryParallelism(String),
+    QueryTimeout(String),
+    DisjointQueryPaths(bool),
+    CacheRecords(bool),
+    RecordRepublishInterval(String),
+    ProviderRepublishInterval(String),
+    MaxConnections(String),
+    MaxConnectionsPerPeer(String),
+    MaxPendingIncoming(String),
+    MaxPendingOutgoing(String),
+    DialConcurrency(String),
+    IdleConnectionTimeout(String),
+    RecordTtl(String),
+    PutQuorum(String),
+    GetQuorum(String),
+    SignRecords(bool),
+    ValuePassphrase(String),
+    SavePassphrase(bool),
+    KeyNamespace(String),
+    LogCapacity(usize),
+    LogToFile(bool),
+    LogDir(String),
+    LogRetention(String),
+    Theme(ThemePreference),
+    UiScale(u32),
+    FontSize(u16),
+    IdentityPath(String),
+    MetricsEnabled(bool),
+    MetricsAddr(String),
+}
+
+fn check_transports(tcp: bool, quic: bool) -> Result<(), String> {
+    match tcp || quic {
+        true => Ok(()),
+        false => Err("Keep TCP or QUIC enabled".to_owned()),
+    }
+}
+
+fn parse_dht_protocol(input: &str) -> Result<StreamProtocol, String> {
+    StreamProtocol::try_from_owned(input.trim().to_owned())
+        .map_err(|_| format!("DHT protocol {} must start with /", input.trim()))
+}
+
+fn parse_bounded(input: &str, max: usize) -> Result<NonZeroUsize, String> {
+    match input.trim().parse() {
+        Ok(value) if NonZeroUsize::get(value) <= max => Ok(value),
+        _ => Err(format!("Enter a number from 1 to {max}")),
+    }
+}
+
+/// An empty input lifts the limit.
+fn parse_limit(input: &str) -> Result<Option<u32>, String> {
+    if input.trim().is_empty() {
+        return Ok(None);
+    }
+
+    match input.trim().parse() {
+        Ok(0) | Err(_) => Err("Enter a number above zero, or nothing for no limit".to_owned()),
+        Ok(limit) => Ok(Some(limit)),
+    }
+}
+
+fn parse_secs(input: &str) -> Result<u64, String> {
+    match input.trim().parse() {
+        Ok(0) | Err(_) => Err("Enter a whole number of seconds above zero".to_owned()),
+        Ok(secs) => Ok(secs),
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "iced-libp2p-sample")
+        .map(|dirs| dirs.config_dir().join(CONFIG_FILE_NAME))
+}
+
+pub fn load(path: &Path) -> Result<AppConfig, ConfigError> {
+    let text = fs::read_to_string(path)?;
+    let config: AppConfig = toml::from_str(&text)?;
+
+    parse_dht_protocol(&config.dht_protocol).map_err(ConfigError::Invalid)?;
+    check_transports(config.enable_tcp, config.enable_quic).map_err(ConfigError::Invalid)?;
+
+    Ok(config)
+}
+
+pub fn save(path: &Path, config: &AppConfig) -> Result<(), ConfigError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let text = if config.save_passphrase {
+        toml::to_string_pretty(config)?
+    } else {
+        toml::to_string_pretty(&AppConfig {
+            value_passphrase: None,
+            ..config.clone()
+        })?
+    };
+
+    // A crash halfway through writing leaves the old file in place, not a truncated one
+    let temp_path = path.with_extension("toml.tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    // It may hold the value passphrase, so only the owner gets to read it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(&temp_path)?.write_all(text.as_bytes())?;
+    fs::rename(temp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn recent_keys_are_deduped_and_capped() {
+        let mut config = AppConfig::default();
+
+        for i in 0..RECENT_KEYS_CAPACITY + 5 {
+            config.remember_key(&format!("key-{i}"));
+        }
+        config.remember_key("key-10");
+        config.remember_key("");
+
+        assert_eq!(config.recent_keys.len(), RECENT_KEYS_CAPACITY);
+        assert_eq!(config.recent_keys[0], "key-10");
+        assert_eq!(config.recent_keys.iter().filter(|key| *key == "key-10").count(), 1);
+        assert!(!config.recent_keys.contains(&"key-4".to_owned()));
+    }
+
+    #[test]
+    fn saved_config_loads_back() {
+        let path = temp_path("config-roundtrip").join(CONFIG_FILE_NAME);
+        let mut config = AppConfig::default();
+        config.apply(&SettingsField::RecordTtl("60".to_owned())).unwrap();
+        config.apply(&SettingsField::Theme(ThemePreference::Dark)).unwrap();
+        config
+            .apply(&SettingsField::IdentityPath("/tmp/identity.key".to_owned()))
+            .unwrap();
+        config.remember_key("greeting");
+
+        save(&path, &config).unwrap();
+
+        assert_eq!(load(&path).unwrap(), config);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn passphrase_is_only_saved_when_asked_to() {
+        let path = temp_path("passphrase").join(CONFIG_FILE_NAME);
+        let mut config = AppConfig::default();
+        config.apply(&SettingsField::ValuePassphrase("correct horse".to_owned())).unwrap();
+
+        save(&path, &config).unwrap();
+
+        assert!(!fs::read_to_string(&path).unwrap().contains("correct horse"));
+        assert_eq!(load(&path).unwrap().value_passphrase, None);
+
+        config.apply(&SettingsField::SavePassphrase(true)).unwrap();
+        save(&path, &config).unwrap();
+
+        assert_eq!(load(&path).unwrap(), config);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_fields_fall_back_to_defaults() {
+        let config: AppConfig = toml::from_str("theme = \"light\"").unwrap();
+
+        assert_eq!(config.theme, ThemePreference::Light);
+        assert_eq!(config.listen_addrs, AppConfig::default().listen_addrs);
+    }
+
+    #[test]
+    fn invalid_input_leaves_config_untouched() {
+        let mut config = AppConfig::default();
+
+        assert!(config.apply(&SettingsField::ListenAddrs("nonsense".to_owned())).is_err());
+        assert!(config.apply(&SettingsField::MdnsTtl("0".to_owned())).is_err());
+        assert!(config.apply(&SettingsField::PutQuorum("-1".to_owned())).is_err());
+        assert_eq!(config, AppConfig::default());
+
+        config
+            .apply(&SettingsField::ListenAddrs("/ip4/0.0.0.0/tcp/4001".to_owned()))
+            .unwrap();
+
+        assert!(config.requires_restart(&AppConfig::default()));
+    }
+
+    #[test]
+    fn bootstrap_peers_reject_invalid_and_duplicate_entries() {
+        let mut config = AppConfig::default();
+        let address = "/ip4/10.0.0.1/tcp/4001";
+
+        config.apply(&SettingsField::AddBootstrapPeer(address.to_owned())).unwrap();
+
+        assert!(config.apply(&SettingsField::AddBootstrapPeer(address.to_owned())).is_err());
+        assert!(config.apply(&SettingsField::AddBootstrapPeer("nonsense".to_owned())).is_err());
+        assert_eq!(config.bootstrap_peers.len(), 1);
+        assert!(!config.requires_restart(&AppConfig::default()));
+
+        config
+            .apply(&SettingsField::RemoveBootstrapPeer(address.parse().unwrap()))
+            .unwrap();
+
+        assert!(config.bootstrap_peers.is_empty());
+    }
+
+    #[test]
+    fn one_transport_has_to_stay_enabled() {
+        let mut config = AppConfig::default();
+
+        config.apply(&SettingsField::EnableQuic(false)).unwrap();
+
+        assert!(config.apply(&SettingsField::EnableTcp(false)).is_err());
+        assert!(config.enable_tcp);
+        assert!(config.requires_restart(&AppConfig::default()));
+
+        config.apply(&SettingsField::EnableQuic(true)).unwrap();
+        config.apply(&SettingsField::EnableTcp(false)).unwrap();
+
+        assert!(!config.enable_tcp);
+        assert!(config.enable_quic);
+    }
+
+    #[test]
+    fn websocket_adds_a_listen_address_unless_one_is_configured() {
+        let mut config = AppConfig::default();
+        assert_eq!(config.listen_addrs(), config.listen_addrs);
+
+        config.apply(&SettingsField::EnableWebsocket(true)).unwrap();
+        assert!(config.requires_restart(&AppConfig::default()));
+        assert_eq!(config.listen_addrs().last(), Some(&p2p::default_websocket_listen_addr()));
+
+        let configured = "/ip4/0.0.0.0/tcp/8080/ws";
+        config.apply(&SettingsField::ListenAddrs(configured.to_owned())).unwrap();
+        assert_eq!(config.listen_addrs(), [configured.parse().unwrap()]);
+    }
+
+    #[test]
+    fn security_defaults_to_noise_and_needs_a_restart() {
+        assert_eq!(AppConfig::default().security, Security::Noise);
+
+        let config: AppConfig = toml::from_str("security = \"both\"").unwrap();
+        assert_eq!(config.security, Security::Both);
+        assert!(config.requires_restart(&AppConfig::default()));
+    }
+
+    #[test]
+    fn dht_protocols_without_a_leading_slash_are_rejected_on_load() {
+        let path = temp_path("config.toml");
+        fs::write(&path, "dht_protocol = \"my-app/kad/1.0.0\"").unwrap();
+
+        assert!(matches!(load(&path), Err(ConfigError::Invalid(_))));
+
+        fs::write(&path, "dht_protocol = \"/my-app/kad/1.0.0\"").unwrap();
+
+        assert_eq!(load(&path).unwrap().dht_protocol().as_ref(), "/my-app/kad/1.0.0");
+        assert!(AppConfig::default().apply(&SettingsField::DhtProtocol("kad".into())).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn kad_tuning_is_bounded_and_needs_a_restart() {
+        let mut config = AppConfig::default();
+
+        assert!(config.apply(&SettingsField::ReplicationFactor("0".to_owned())).is_err());
+        assert!(config.apply(&SettingsField::ReplicationFactor("101".to_owned())).is_err());
+        assert!(config.apply(&SettingsField::QueryParallelism("many".to_owned())).is_err());
+        assert_eq!(config, AppConfig::default());
+
+        config.apply(&SettingsField::ReplicationFactor("3".to_owned())).unwrap();
+        config.apply(&SettingsField::DisjointQueryPaths(true)).unwrap();
+
+        assert_eq!(config.kad_tuning().replication_factor.get(), 3);
+        assert!(config.kad_tuning().disjoint_query_paths);
+        assert!(config.requires_restart(&AppConfig::default()));
+    }
+
+    #[test]
+    fn connection_limits_are_unlimited_until_set() {
+        let mut config = AppConfig::default();
+
+        assert_eq!(config.connection_limits(), ConnectionLimits::default());
+        assert!(config.apply(&SettingsField::MaxConnections("0".to_owned())).is_err());
+        assert!(config.apply(&SettingsField::DialConcurrency("300".to_owned())).is_err());
+
+        config.apply(&SettingsField::MaxConnectionsPerPeer("2".to_owned())).unwrap();
+
+        assert_eq!(config.connection_limits().max_established_per_peer, Some(2));
+        assert!(config.requires_restart(&AppConfig::default()));
+
+        config.apply(&SettingsField::MaxConnectionsPerPeer(" ".to_owned())).unwrap();
+
+        assert!(!config.requires_restart(&AppConfig::default()));
+    }
+
+    #[test]
+    fn metrics_address_only_matters_while_enabled() {
+        let mut config = AppConfig::default();
+
+        assert!(config.apply(&SettingsField::MetricsAddr("localhost".to_owned())).is_err());
+
+        config
+            .apply(&SettingsField::MetricsAddr("0.0.0.0:9000".to_owned()))
+            .unwrap();
+
+        assert_eq!(config.metrics_addr(), None);
+        assert!(!config.requires_restart(&AppConfig::default()));
+
+        config.apply(&SettingsField::MetricsEnabled(true)).unwrap();
+
+        assert_eq!(config.metrics_addr(), Some("0.0.0.0:9000".parse().unwrap()));
+        assert!(config.requires_restart(&AppConfig::default()));
+    }
+
+    #[test]
+    fn windows_are_pulled_back_onto_the_screen() {
+        let window = Size::new(800.0, 600.0);
+        let monitor = Size::new(1920.0, 1080.0);
+        let clamp = |x, y| WindowGeometry::clamp_position(Point::new(x, y), window, monitor);
+
+        assert_eq!(clamp(100.0, 50.0), Point::new(100.0, 50.0));
+        assert_eq!(clamp(3000.0, -200.0), Point::new(1120.0, 0.0));
+
+        let too_large = Size::new(2560.0, 1440.0);
+        let position = WindowGeometry::clamp_position(Point::new(50.0, 50.0), too_large, monitor);
+        assert_eq!(position, Point::ORIGIN);
+    }
+
+    #[test]
+    fn window_geometry_survives_the_config_file() {
+        let config = AppConfig {
+            window: Some(WindowGeometry {
+                width: 1280,
+                height: 720,
+                position: Some((-40, 200)),
+                maximized: true,
+            }),
+            ..AppConfig::default()
+        };
+
+        let text = toml::to_string(&config).unwrap();
+
+        assert_eq!(toml::from_str::<AppConfig>(&text).unwrap(), config);
+    }
+}
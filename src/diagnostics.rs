@@ -0,0 +1,163 @@
+use std::fmt;
+use std::fmt::{Formatter, Write};
+use std::time::SystemTime;
+use chrono::{DateTime, Local};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+
+/// How many lines the Diagnostics panel keeps.
+pub const TRACE_LOG_CAPACITY: usize = 2000;
+/// How many lines may wait for the app before new ones are dropped.
+pub const TRACE_CHANNEL_CAPACITY: usize = 500;
+/// How many of the latest lines "Copy diagnostics" puts on the clipboard.
+pub const COPIED_TRACE_LINES: usize = 200;
+
+/// One formatted tracing event.
+#[derive(Debug, Clone)]
+pub struct TraceLine {
+    pub at: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl TraceLine {
+    pub fn timestamp(&self) -> String {
+        DateTime::<Local>::from(self.at).format("%H:%M:%S%.3f").to_string()
+    }
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:>5} {}: {}", self.timestamp(), self.level, self.target, self.message)
+    }
+}
+
+/// The most verbose level the Diagnostics panel shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl TraceLevel {
+    pub const ALL: [TraceLevel; 4] = [
+        TraceLevel::Error,
+        TraceLevel::Warn,
+        TraceLevel::Info,
+        TraceLevel::Debug,
+    ];
+
+    pub fn includes(self, level: &Level) -> bool {
+        let most_verbose = match self {
+            TraceLevel::Error => Level::ERROR,
+            TraceLevel::Warn => Level::WARN,
+            TraceLevel::Info => Level::INFO,
+            TraceLevel::Debug => Level::DEBUG,
+        };
+
+        *level <= most_verbose
+    }
+}
+
+impl fmt::Display for TraceLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceLevel::Error => write!(f, "Error"),
+            TraceLevel::Warn => write!(f, "Warn"),
+            TraceLevel::Info => write!(f, "Info"),
+            TraceLevel::Debug => write!(f, "Debug"),
+        }
+    }
+}
+
+/// A tracing layer that hands every event to the app as a [`TraceLine`].
+///
+/// It never waits: when the app falls behind, lines are dropped instead of stalling whichever
+/// thread is logging, the swarm included.
+pub struct ChannelLayer {
+    sender: mpsc::Sender<TraceLine>,
+}
+
+pub fn channel_layer(capacity: usize) -> (ChannelLayer, mpsc::Receiver<TraceLine>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+
+    (ChannelLayer { sender }, receiver)
+}
+
+impl<S: Subscriber> Layer<S> for ChannelLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        if self.sender.is_closed() {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let line = TraceLine {
+            at: SystemTime::now(),
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_owned(),
+            message: visitor.message,
+        };
+
+        self.sender.try_send(line).ok();
+    }
+}
+
+/// Collects the `message` field followed by the other fields as `name=value`.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.message.is_empty() {
+            self.message.push(' ');
+        }
+
+        if field.name() == "message" {
+            write!(self.message, "{value:?}").ok();
+        } else {
+            write!(self.message, "{}={value:?}", field.name()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{debug, info};
+    use tracing_subscriber::layer::SubscriberExt;
+    use super::*;
+
+    #[test]
+    fn lines_are_dropped_once_the_channel_is_full() {
+        let (layer, mut receiver) = channel_layer(3);
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            info!(peers = 2, "Connected");
+
+            for n in 0..10 {
+                debug!("Line {n}");
+            }
+        });
+
+        let first = receiver.try_recv().unwrap();
+
+        assert_eq!(first.level, Level::INFO);
+        assert_eq!(first.message, "Connected peers=2");
+        assert_eq!(receiver.try_recv().unwrap().message, "Line 0");
+        assert_eq!(receiver.try_recv().unwrap().message, "Line 1");
+        assert!(receiver.try_recv().is_err());
+        assert!(!TraceLevel::Info.includes(&Level::DEBUG));
+        assert!(TraceLevel::Warn.includes(&Level::ERROR));
+    }
+}
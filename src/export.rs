@@ -0,0 +1,727 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::mem;
+use std::path::Path;
+use std::time::{Instant, SystemTime};
+use chrono::{DateTime, Local};
+use libp2p::kad;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use crate::app::{LogCategory, LogEntry, RecordEntry};
+use crate::p2p::{self, P2pError, P2pEvent, P2pInboundEvent, P2pLocalEvent, P2pOutboundEvent};
+
+const CSV_HEADER: &str = "timestamp,category,peer_id,address,key,message";
+const RECORDS_CSV_HEADER: &str = "key,label,value,publisher,expires_at";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+/// A log entry with its peer, address and key pulled out as strings, so it can be filtered
+/// outside the app.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedEvent {
+    pub timestamp: String,
+    pub category: String,
+    pub peer_id: Option<String>,
+    pub address: Option<String>,
+    pub key: Option<String>,
+    pub message: String,
+    /// How many times in a row the event happened, when it did more than once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<u32>,
+}
+
+impl ExportedEvent {
+    pub fn new(entry: &LogEntry) -> Self {
+        let (peer_id, address, key) = subjects(&entry.event);
+
+        Self {
+            timestamp: DateTime::<Local>::from(entry.at).to_rfc3339(),
+            category: LogCategory::of(&entry.event).title().to_owned(),
+            peer_id: peer_id.map(ToString::to_string),
+            address: address.map(ToString::to_string),
+            key: key.map(p2p::format_key),
+            message: entry.event.to_string(),
+            repeat: (entry.repeat > 1).then_some(entry.repeat),
+        }
+    }
+}
+
+/// One line of the headless node's `--json` output; `value` carries record and message payloads
+/// in the [`p2p::format_bytes`] encoding.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonEvent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub event: ExportedEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl JsonEvent {
+    pub fn new(entry: &LogEntry) -> Self {
+        Self {
+            kind: event_kind(&entry.event).to_owned(),
+            event: ExportedEvent::new(entry),
+            value: event_value(&entry.event).map(p2p::format_bytes),
+        }
+    }
+}
+
+/// A record as shown in the records table. `key` and `value` use the [`p2p::format_bytes`]
+/// encoding so they survive a round trip; `label` keeps the human name of hashed keys.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedRecord {
+    pub key: String,
+    pub label: Option<String>,
+    pub value: String,
+    pub publisher: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+impl ExportedRecord {
+    pub fn new(
+        label: &str,
+        key: &kad::RecordKey,
+        entry: &RecordEntry,
+        (now, system_now): (Instant, SystemTime),
+    ) -> Self {
+        let key = p2p::format_key(key);
+
+        Self {
+            label: (label != key).then(|| label.to_owned()),
+            key,
+            value: p2p::format_bytes(&entry.value),
+            publisher: entry.publisher.map(|publisher| publisher.to_string()),
+            expires_at: entry.expires.map(|expires| {
+                let at = system_now + expires.saturating_duration_since(now);
+                DateTime::<Local>::from(at).to_rfc3339()
+            }),
+        }
+    }
+
+    /// Exported records name their exact key next to a label; a key without one was typed by
+    /// hand, so it goes in `namespace` like any other typed key.
+    pub fn record_key(&self, namespace: &str) -> kad::RecordKey {
+        match self.label {
+            Some(_) => p2p::parse_raw_key(&self.key),
+            None => p2p::parse_key(&self.key, namespace, false),
+        }
+    }
+
+    pub fn value_bytes(&self) -> Vec<u8> {
+        p2p::parse_bytes(&self.value)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Export {
+    pub events: Vec<ExportedEvent>,
+    #[serde(default)]
+    pub records: Vec<ExportedRecord>,
+}
+
+impl Export {
+    /// Records only fit the JSON layout, a CSV export contains the events alone.
+    pub fn render(&self, format: ExportFormat) -> Vec<u8> {
+        match format {
+            ExportFormat::Json => {
+                serde_json::to_vec_pretty(self).expect("exports are always serializable")
+            }
+            ExportFormat::Csv => {
+                let mut csv = String::from(CSV_HEADER);
+
+                for event in &self.events {
+                    let fields = [
+                        event.timestamp.as_str(),
+                        event.category.as_str(),
+                        event.peer_id.as_deref().unwrap_or_default(),
+                        event.address.as_deref().unwrap_or_default(),
+                        event.key.as_deref().unwrap_or_default(),
+                        event.message.as_str(),
+                    ];
+
+                    csv.push('\n');
+                    csv.push_str(&fields.map(csv_field).join(","));
+                }
+
+                csv.push('\n');
+                csv.into_bytes()
+            }
+        }
+    }
+}
+
+/// Records read from a file, and the rows that had to be left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportedRecords {
+    pub records: Vec<ExportedRecord>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRow {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedRow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// The records part of a JSON export; the events are of no use when importing.
+#[derive(Deserialize)]
+struct RecordsOnly<'a> {
+    #[serde(borrow, default)]
+    records: Vec<&'a RawValue>,
+}
+
+/// Writes records in the layout [`parse_records`] reads: a JSON array, or CSV with a header.
+pub fn render_records(records: &[ExportedRecord], format: ExportFormat) -> Vec<u8> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_vec_pretty(records).expect("records are always serializable")
+        }
+        ExportFormat::Csv => {
+            let mut csv = String::from(RECORDS_CSV_HEADER);
+
+            for record in records {
+                let fields = [
+                    record.key.as_str(),
+                    record.label.as_deref().unwrap_or_default(),
+                    record.value.as_str(),
+                    record.publisher.as_deref().unwrap_or_default(),
+                    record.expires_at.as_deref().unwrap_or_default(),
+                ];
+
+                csv.push('\n');
+                csv.push_str(&fields.map(csv_field).join(","));
+            }
+
+            csv.push('\n');
+            csv.into_bytes()
+        }
+    }
+}
+
+/// Reads records from a JSON array, a JSON export, or CSV with `key` and `value` columns.
+///
+/// Rows that don't make a record are skipped with their line number; only a file that can't be
+/// read as a whole is an error.
+pub fn parse_records(bytes: &[u8], format: ExportFormat) -> Result<ImportedRecords, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "the file is not UTF-8".to_owned())?;
+    let rows = match format {
+        ExportFormat::Json => json_records(text)?,
+        ExportFormat::Csv => csv_records(text)?,
+    };
+
+    let mut imported = ImportedRecords::default();
+
+    for (line, row) in rows {
+        match row {
+            Ok(record) if record.key.is_empty() => imported.skipped.push(SkippedRow {
+                line,
+                reason: "the key is empty".to_owned(),
+            }),
+            Ok(record) => imported.records.push(record),
+            Err(reason) => imported.skipped.push(SkippedRow { line, reason }),
+        }
+    }
+
+    Ok(imported)
+}
+
+type Row = (usize, Result<ExportedRecord, String>);
+
+fn json_records(text: &str) -> Result<Vec<Row>, String> {
+    let values = if text.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<&RawValue>>(text)
+    } else {
+        serde_json::from_str::<RecordsOnly>(text).map(|export| export.records)
+    }
+    .map_err(|err| err.to_string())?;
+
+    let rows = values
+        .into_iter()
+        .map(|value| {
+            let offset = value.get().as_ptr() as usize - text.as_ptr() as usize;
+            let line = text[..offset].matches('\n').count() + 1;
+            let record = serde_json::from_str(value.get()).map_err(|err| err.to_string());
+
+            (line, record)
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+fn csv_records(text: &str) -> Result<Vec<Row>, String> {
+    let mut rows = csv_rows(text).into_iter();
+    let header = match rows.next() {
+        Some((_, Ok(header))) => header,
+        _ => return Err("the first line must name the columns".to_owned()),
+    };
+    let column = |name: &str| header.iter().position(|column| column.trim() == name);
+    let (Some(key), Some(value)) = (column("key"), column("value")) else {
+        return Err("the header must have key and value columns".to_owned());
+    };
+    let (label, publisher) = (column("label"), column("publisher"));
+    let expires_at = column("expires_at");
+
+    let rows = rows
+        .map(|(line, fields)| {
+            let record = fields.and_then(|mut fields| {
+                if fields.len() != header.len() {
+                    return Err(format!(
+                        "expected {} fields, found {}",
+                        header.len(),
+                        fields.len()
+                    ));
+                }
+
+                let mut optional = |column: Option<usize>| {
+                    column
+                        .map(|column| mem::take(&mut fields[column]))
+                        .filter(|field| !field.is_empty())
+                };
+
+                Ok(ExportedRecord {
+                    label: optional(label),
+                    publisher: optional(publisher),
+                    expires_at: optional(expires_at),
+                    key: mem::take(&mut fields[key]),
+                    value: mem::take(&mut fields[value]),
+                })
+            });
+
+            (line, record)
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+/// Splits CSV text into rows of fields, each with the line it starts on. Quoted fields may span
+/// lines, and blank lines are left out.
+fn csv_rows(text: &str) -> Vec<(usize, Result<Vec<String>, String>)> {
+    let mut rows = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+
+        loop {
+            match chars.next() {
+                None => break,
+                Some('"') if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                Some('"') if quoted => quoted = false,
+                Some('"') if field.is_empty() => quoted = true,
+                Some('\n') if !quoted => {
+                    line += 1;
+                    break;
+                }
+                Some('\n') => {
+                    line += 1;
+                    field.push('\n');
+                }
+                Some('\r') if !quoted && chars.peek() == Some(&'\n') => {}
+                Some(',') if !quoted => fields.push(mem::take(&mut field)),
+                Some(other) => field.push(other),
+            }
+        }
+
+        if quoted {
+            rows.push((start, Err("a quoted field is never closed".to_owned())));
+        } else if !fields.is_empty() || !field.is_empty() {
+            fields.push(field);
+            rows.push((start, Ok(fields)));
+        }
+    }
+
+    rows
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+type Subjects<'a> = (
+    Option<&'a PeerId>,
+    Option<&'a Multiaddr>,
+    Option<&'a kad::RecordKey>,
+);
+
+pub fn subjects(event: &P2pEvent) -> Subjects<'_> {
+    match event {
+        P2pEvent::LocalIdentity(peer_id)
+        | P2pEvent::PingRtt(peer_id, _)
+        | P2pEvent::PeerUnresponsive(peer_id)
+        | P2pEvent::DirectMessageReceived(peer_id, _)
+        | P2pEvent::DirectMessageDelivered(peer_id)
+        | P2pEvent::PeerBlocked(peer_id)
+        | P2pEvent::PeerUnblocked(peer_id)
+        | P2pEvent::PeerRateLimited(peer_id)
+        | P2pEvent::UnroutablePeer(peer_id)
+        | P2pEvent::RoutingUpdated { peer_id, .. }
+        | P2pEvent::PeerDisconnected { peer_id, .. }
+        | P2pEvent::PeerIdentified { peer_id, .. }
+        | P2pEvent::DhtProtocolMissing { peer_id, .. }
+        | P2pEvent::RelayReservationAccepted { relay: peer_id, .. }
+        | P2pEvent::HolePunch {
+            remote: peer_id, ..
+        }
+        | P2pEvent::RelayReservationServed { peer_id, .. }
+        | P2pEvent::RelayCircuitOpened { src: peer_id, .. }
+        | P2pEvent::RelayCircuitClosed { src: peer_id, .. } => (Some(peer_id), None, None),
+        P2pEvent::PeerDiscovered(peer_id, address)
+        | P2pEvent::PeerExpired(peer_id, address)
+        | P2pEvent::PeerConnected {
+            peer_id, address, ..
+        } => (Some(peer_id), Some(address), None),
+        P2pEvent::Bootstrapped(address)
+        | P2pEvent::ListenAddrExpired(address)
+        | P2pEvent::Dialing(address)
+        | P2pEvent::DialingBootstrapPeer(address)
+        | P2pEvent::NatStatus(p2p::NatStatus::Public(address)) => (None, Some(address), None),
+        P2pEvent::GossipMessage { source, .. } => (source.as_ref(), None, None),
+        P2pEvent::ConnectionDenied { peer, address, .. } => (peer.as_ref(), address.as_ref(), None),
+        P2pEvent::InboundPending { source, key, .. }
+        | P2pEvent::InboundRejected { source, key, .. }
+        | P2pEvent::Inbound(P2pInboundEvent::RecordStored(source, key, ..)) => {
+            (Some(source), None, Some(key))
+        }
+        P2pEvent::Inbound(P2pInboundEvent::ProviderAdded(key))
+        | P2pEvent::Local(
+            P2pLocalEvent::RecordRemoved(key)
+            | P2pLocalEvent::RecordNotInStore(key)
+            | P2pLocalEvent::RecordExpired(key),
+        ) => (None, None, Some(key)),
+        P2pEvent::Local(P2pLocalEvent::RecordStored(record)) => (None, None, Some(&record.key)),
+        P2pEvent::Outbound(_, event) => (None, None, outbound_key(event)),
+        P2pEvent::Error(_, error) => error_subjects(error),
+        _ => (None, None, None),
+    }
+}
+
+pub fn event_kind(event: &P2pEvent) -> &'static str {
+    match event {
+        P2pEvent::LocalIdentity(_) => "local_identity",
+        P2pEvent::Bootstrapped(_) => "listening",
+        P2pEvent::ListenAddrExpired(_) => "listen_addr_expired",
+        P2pEvent::PeerDiscovered(..) => "peer_discovered",
+        P2pEvent::PeerExpired(..) => "peer_expired",
+        P2pEvent::Dialing(_) => "dialing",
+        P2pEvent::DialingBootstrapPeer(_) => "dialing_bootstrap_peer",
+        P2pEvent::RememberedPeers(_) => "remembered_peers",
+        P2pEvent::PeerConnected { .. } => "peer_connected",
+        P2pEvent::PeerDisconnected { .. } => "peer_disconnected",
+        P2pEvent::PeerIdentified { .. } => "peer_identified",
+        P2pEvent::DhtProtocolMissing { .. } => "dht_protocol_missing",
+        P2pEvent::PingRtt(..) => "ping",
+        P2pEvent::PeerUnresponsive(_) => "peer_unresponsive",
+        P2pEvent::TopicSubscribed(_) => "topic_subscribed",
+        P2pEvent::TopicUnsubscribed(_) => "topic_unsubscribed",
+        P2pEvent::GossipMessage { .. } => "gossip_message",
+        P2pEvent::GossipPublished { .. } => "gossip_published",
+        P2pEvent::DirectMessageReceived(..) => "direct_message_received",
+        P2pEvent::DirectMessageDelivered(_) => "direct_message_delivered",
+        P2pEvent::FileTransferProgress { .. } => "file_transfer_progress",
+        P2pEvent::FileFetched { .. } => "file_fetched",
+        P2pEvent::RelayReservationAccepted { .. } => "relay_reservation_accepted",
+        P2pEvent::RelayServerToggled(_) => "relay_server_toggled",
+        P2pEvent::MdnsToggled(_) => "mdns_toggled",
+        P2pEvent::ConnectionDenied { .. } => "connection_denied",
+        P2pEvent::MetricsServing(_) => "metrics_serving",
+        P2pEvent::HolePunch { .. } => "hole_punch",
+        P2pEvent::NatStatus(_) => "nat_status",
+        P2pEvent::KadModeChanged(_) => "kad_mode_changed",
+        P2pEvent::RoutingTable(_) => "routing_table",
+        P2pEvent::RoutingUpdated { .. } => "routing_updated",
+        P2pEvent::UnroutablePeer(_) => "unroutable_peer",
+        P2pEvent::PeerBlocked(_) => "peer_blocked",
+        P2pEvent::PeerUnblocked(_) => "peer_unblocked",
+        P2pEvent::RelayReservationServed { .. } => "relay_reservation_served",
+        P2pEvent::RelayCircuitOpened { .. } => "relay_circuit_opened",
+        P2pEvent::RelayCircuitClosed { .. } => "relay_circuit_closed",
+        P2pEvent::LocalRecords(_) => "local_records",
+        P2pEvent::StoreRestored { .. } => "store_restored",
+        P2pEvent::Republished { .. } => "republished",
+        P2pEvent::BenchmarkFinished(_) => "benchmark_finished",
+        P2pEvent::Bandwidth { .. } => "bandwidth",
+        P2pEvent::InboundApprovalToggled(_) => "inbound_approval_toggled",
+        P2pEvent::InboundPending { .. } => "inbound_pending",
+        P2pEvent::InboundExpired(_) => "inbound_expired",
+        P2pEvent::InboundRejected { .. } => "inbound_rejected",
+        P2pEvent::PeerRateLimited(_) => "peer_rate_limited",
+        P2pEvent::Outbound(_, event) => match event {
+            P2pOutboundEvent::RecordResolved { .. } => "record_resolved",
+            P2pOutboundEvent::ProvidersFound(..) => "providers_found",
+            P2pOutboundEvent::ClosestPeers(..) => "closest_peers",
+            P2pOutboundEvent::RecordPut(_) => "record_put",
+            P2pOutboundEvent::ProviderPut(_) => "provider_put",
+            P2pOutboundEvent::ProviderStopped(_) => "provider_stopped",
+            P2pOutboundEvent::RecordCached { .. } => "record_cached",
+            P2pOutboundEvent::BootstrapProgress { .. } => "bootstrap_progress",
+            P2pOutboundEvent::BootstrapFinished => "bootstrap_finished",
+            P2pOutboundEvent::NoKnownPeers => "no_known_peers",
+            P2pOutboundEvent::NotProviding(_) => "not_providing",
+            P2pOutboundEvent::RecordNotFound(_) => "record_not_found",
+            P2pOutboundEvent::QueryCancelled(_) => "query_cancelled",
+        },
+        P2pEvent::Inbound(P2pInboundEvent::ProviderAdded(_)) => "inbound_provider_added",
+        P2pEvent::Inbound(P2pInboundEvent::RecordsRequested { .. }) => "inbound_records_requested",
+        P2pEvent::Inbound(P2pInboundEvent::ProvidersRequested(_)) => "inbound_providers_requested",
+        P2pEvent::Inbound(P2pInboundEvent::RecordStored(..)) => "inbound_record_stored",
+        P2pEvent::Local(event) => match event {
+            P2pLocalEvent::RecordStored(_) => "local_record_stored",
+            P2pLocalEvent::RecordRemoved(_) => "local_record_removed",
+            P2pLocalEvent::RecordNotInStore(_) => "record_not_in_store",
+            P2pLocalEvent::RecordExpired(_) => "record_expired",
+        },
+        P2pEvent::Error(..) => "error",
+        P2pEvent::Ready(_) => "ready",
+        P2pEvent::ShutdownComplete => "shutdown_complete",
+    }
+}
+
+fn event_value(event: &P2pEvent) -> Option<&[u8]> {
+    match event {
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordResolved { values, .. }) => {
+            values.first().map(|found| found.value.as_slice())
+        }
+        P2pEvent::Inbound(P2pInboundEvent::RecordStored(_, _, value, ..))
+        | P2pEvent::InboundPending {
+            value: Some(value), ..
+        }
+        | P2pEvent::GossipMessage { data: value, .. }
+        | P2pEvent::GossipPublished { data: value, .. } => Some(value),
+        P2pEvent::Local(P2pLocalEvent::RecordStored(record)) => Some(&record.value),
+        _ => None,
+    }
+}
+
+fn outbound_key(event: &P2pOutboundEvent) -> Option<&kad::RecordKey> {
+    match event {
+        P2pOutboundEvent::RecordResolved { key, .. }
+        | P2pOutboundEvent::ProvidersFound(key, _)
+        | P2pOutboundEvent::ClosestPeers(key, _)
+        | P2pOutboundEvent::RecordPut(key)
+        | P2pOutboundEvent::ProviderPut(key)
+        | P2pOutboundEvent::ProviderStopped(key)
+        | P2pOutboundEvent::RecordCached { key, .. }
+        | P2pOutboundEvent::NotProviding(key)
+        | P2pOutboundEvent::RecordNotFound(key)
+        | P2pOutboundEvent::QueryCancelled(key) => Some(key),
+        P2pOutboundEvent::BootstrapProgress { .. }
+        | P2pOutboundEvent::BootstrapFinished
+        | P2pOutboundEvent::NoKnownPeers => None,
+    }
+}
+
+fn error_subjects(error: &P2pError) -> Subjects<'_> {
+    match error {
+        P2pError::GetRecordQuorumFailed { key, .. }
+        | P2pError::GetRecordTimeout { key }
+        | P2pError::PutRecordQuorumFailed { key, .. }
+        | P2pError::PutRecordTimeout { key, .. }
+        | P2pError::GetProvidersTimeout { key }
+        | P2pError::GetClosestPeersTimeout { key, .. }
+        | P2pError::StartProvidingTimeout { key }
+        | P2pError::QueryTimedOut { key }
+        | P2pError::Store { key, .. } => (None, None, Some(key)),
+        P2pError::DirectMessageUnsupported { peer_id }
+        | P2pError::DirectMessageDialFailed { peer_id }
+        | P2pError::DirectMessageTimeout { peer_id }
+        | P2pError::DirectMessageConnectionClosed { peer_id }
+        | P2pError::DirectMessageIo { peer_id, .. }
+        | P2pError::NotConnected { peer_id } => (Some(peer_id), None, None),
+        P2pError::ConnectionFailed { peer_id, .. } => (peer_id.as_ref(), None, None),
+        P2pError::RelayReservationFailed { relay: address, .. }
+        | P2pError::ListenFailed { address, .. }
+        | P2pError::DialFailed { address, .. }
+        | P2pError::ResolveFailed { address, .. } => (None, Some(address), None),
+        _ => (None, None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::p2p::FoundValue;
+    use crate::p2p::envelope::RecordSignature;
+    use super::*;
+
+    #[test]
+    fn exported_records_round_trip() {
+        let now = (Instant::now(), SystemTime::now());
+        let binary = kad::RecordKey::from(vec![0xde, 0xad, 0x00]);
+        let entry = RecordEntry {
+            key: binary.clone(),
+            value: vec![0xff, 0x00, 0x01],
+            publisher: Some(PeerId::random()),
+            expires: None,
+            signature: RecordSignature::default(),
+        };
+        let export = Export {
+            events: Vec::new(),
+            records: vec![
+                ExportedRecord::new("0xdead00", &binary, &entry, now),
+                ExportedRecord::new("my key", &kad::RecordKey::new(&"hashed"), &entry, now),
+            ],
+        };
+
+        let records = parse_records(&export.render(ExportFormat::Json), ExportFormat::Json)
+            .unwrap()
+            .records;
+
+        assert_eq!(records, export.records);
+        assert_eq!(records[0].record_key("app"), binary);
+        assert_eq!(records[0].label, None);
+        assert_eq!(records[0].value_bytes(), entry.value);
+        assert_eq!(records[1].label.as_deref(), Some("my key"));
+    }
+
+    #[test]
+    fn typed_keys_are_imported_into_the_namespace() {
+        let csv = "key,value,label\ngreeting,hi,\nns/farewell,bye,farewell\n";
+        let imported = parse_records(csv.as_bytes(), ExportFormat::Csv).unwrap();
+        let keys: Vec<_> = imported.records.iter().map(|record| record.record_key("ns")).collect();
+
+        assert_eq!(
+            keys,
+            [kad::RecordKey::new(&"ns/greeting"), kad::RecordKey::new(&"ns/farewell")]
+        );
+    }
+
+    #[test]
+    fn records_round_trip_through_both_formats() {
+        let now = (Instant::now(), SystemTime::now());
+        let key = kad::RecordKey::new(&"ns/greeting");
+        let entry = RecordEntry {
+            key: key.clone(),
+            value: b"hello, \"world\"\nbye".to_vec(),
+            publisher: Some(PeerId::random()),
+            expires: None,
+            signature: RecordSignature::default(),
+        };
+        let records = vec![ExportedRecord::new("greeting", &key, &entry, now)];
+
+        for format in [ExportFormat::Json, ExportFormat::Csv] {
+            let imported = parse_records(&render_records(&records, format), format).unwrap();
+
+            assert_eq!(imported.records, records);
+            assert!(imported.skipped.is_empty());
+        }
+    }
+
+    #[test]
+    fn malformed_rows_are_skipped_with_their_line() {
+        let csv = "value,key\nhi,greeting\n\nonly one field\n\"multi\nline\",farewell\n\
+                   bye,\n\"open,quote\n";
+        let imported = parse_records(csv.as_bytes(), ExportFormat::Csv).unwrap();
+        let keys: Vec<_> = imported.records.iter().map(|record| &record.key).collect();
+        let lines: Vec<_> = imported.skipped.iter().map(|row| row.line).collect();
+
+        assert_eq!(keys, ["greeting", "farewell"]);
+        assert_eq!(imported.records[1].value, "multi\nline");
+        assert_eq!(lines, [4, 7, 8]);
+        assert_eq!(imported.skipped[0].to_string(), "line 4: expected 2 fields, found 1");
+
+        let json = "[\n  {\"key\": \"a\", \"value\": \"1\"},\n  {\"key\": \"b\"},\n  \
+                    {\"key\": \"\", \"value\": \"3\"}\n]";
+        let imported = parse_records(json.as_bytes(), ExportFormat::Json).unwrap();
+        let lines: Vec<_> = imported.skipped.iter().map(|row| row.line).collect();
+
+        assert_eq!(imported.records.len(), 1);
+        assert_eq!(lines, [3, 4]);
+
+        assert!(parse_records(b"key,label\na,b", ExportFormat::Csv).is_err());
+        assert!(parse_records(b"{\"records\": 1}", ExportFormat::Json).is_err());
+    }
+
+    #[test]
+    fn json_events_keep_binary_values_intact() {
+        let key = kad::RecordKey::from(vec![0x00, 0xff]);
+        let events = [
+            P2pEvent::Outbound(None, P2pOutboundEvent::RecordResolved {
+                key: key.clone(),
+                values: vec![FoundValue {
+                    peer: None,
+                    value: vec![0xc3, 0x28],
+                    signature: RecordSignature::default(),
+                }],
+                agree: true,
+            }),
+            P2pEvent::Inbound(P2pInboundEvent::RecordStored(
+                PeerId::random(),
+                key.clone(),
+                vec![0x00, 0x0a],
+                None,
+                RecordSignature::default(),
+            )),
+            P2pEvent::Local(P2pLocalEvent::RecordStored(kad::Record::new(key, vec![0x80]))),
+            P2pEvent::GossipMessage {
+                topic: "chat".to_owned(),
+                source: None,
+                data: "line\nbreak".as_bytes().to_vec(),
+            },
+            P2pEvent::ShutdownComplete,
+        ];
+
+        for event in events {
+            let expected = event_value(&event).map(<[u8]>::to_vec);
+            let line = serde_json::to_string(&JsonEvent::new(&LogEntry::new(event)))
+            .unwrap();
+            let parsed: JsonEvent = serde_json::from_str(&line).unwrap();
+
+            assert!(!line.contains('\n'));
+            assert_eq!(parsed.value.as_deref().map(p2p::parse_bytes), expected);
+        }
+    }
+
+    #[test]
+    fn csv_export_quotes_messages() {
+        let entry = LogEntry::new(P2pEvent::Error(
+            None,
+            P2pError::InvalidAddress {
+                input: "a,\"b\"".to_owned(),
+                reason: "bad".to_owned(),
+            },
+        ));
+        let export = Export {
+            events: vec![ExportedEvent::new(&entry)],
+            records: Vec::new(),
+        };
+
+        let csv = String::from_utf8(export.render(ExportFormat::Csv)).unwrap();
+        let row = csv.lines().nth(1).unwrap();
+
+        assert!(row.ends_with(",Errors,,,,\"Something went wrong: Invalid address a,\"\"b\"\": bad\""));
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use directories::ProjectDirs;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+const LOG_DIR_NAME: &str = "logs";
+const LOG_FILE_PREFIX: &str = "iced-libp2p-sample";
+const LOG_FILE_SUFFIX: &str = "log";
+pub const DEFAULT_LOG_RETENTION: usize = 7;
+/// Log file directives; independent of `RUST_LOG`, which only steers the console.
+pub const FILE_LOG_FILTER: &str = "iced_libp2p_sample=debug,info";
+
+pub fn default_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "iced-libp2p-sample").map(|dirs| dirs.data_dir().join(LOG_DIR_NAME))
+}
+
+/// The file tracing output is written to today; the appender starts a new one at midnight UTC.
+pub fn current_file(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "{LOG_FILE_PREFIX}.{}.{LOG_FILE_SUFFIX}",
+        Utc::now().format("%Y-%m-%d")
+    ))
+}
+
+/// Opens a daily log in `dir`, deleting all but the newest `retention` files right away.
+///
+/// Lines are written on a background thread; keep the guard alive until exit so they get flushed.
+pub fn open(dir: &Path, retention: usize) -> Result<(NonBlocking, WorkerGuard), String> {
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix(LOG_FILE_SUFFIX)
+        .max_log_files(retention.max(1))
+        .build(dir)
+        .map_err(|err| format!("Failed to open a log file in {}: {err}", dir.display()))?;
+
+    Ok(tracing_appender::non_blocking(appender))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn opening_prunes_files_beyond_the_retention_count() {
+        let dir = temp_path("logs");
+        fs::create_dir_all(&dir).unwrap();
+
+        for day in 1..=4 {
+            let name = format!("{LOG_FILE_PREFIX}.2020-01-0{day}.{LOG_FILE_SUFFIX}");
+            fs::write(dir.join(name), "old").unwrap();
+        }
+
+        let (writer, guard) = open(&dir, 2).unwrap();
+        drop((writer, guard));
+
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[1], current_file(&dir).file_name().unwrap().to_str().unwrap());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use iced::futures::FutureExt;
+use iced::futures::future::BoxFuture;
+use iced::futures::stream::FuturesUnordered;
+use libp2p::PeerId;
+use libp2p::kad::QueryId;
+use libp2p::request_response::{OutboundRequestId, ResponseChannel};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::error;
+use crate::p2p::RequestId;
+
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// SHA-256 of a whole file.
+pub type FileHash = [u8; 32];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRequest {
+    pub key: String,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FileResponse {
+    /// `total` and `hash` describe the whole file, so that chunks of different files shared
+    /// under the same key can't be mixed up.
+    Chunk { data: Vec<u8>, total: u64, hash: FileHash },
+    NotFound,
+}
+
+/// A file we provide, along with what it held when it was shared.
+#[derive(Debug, Clone)]
+pub struct SharedFile {
+    path: PathBuf,
+    total: u64,
+    hash: FileHash,
+}
+
+impl SharedFile {
+    /// Reads the whole file once to hash it; blocks until it's done.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let mut hasher = Sha256::new();
+        let total = io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+
+        Ok(Self {
+            path,
+            total,
+            hash: hasher.finalize().into(),
+        })
+    }
+
+    /// Blocks on reading the chunk at `offset`, never reading past what was hashed.
+    pub fn read_chunk(&self, offset: u64) -> io::Result<FileResponse> {
+        let mut file = fs::File::open(&self.path)?;
+        let mut data = Vec::new();
+
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(CHUNK_SIZE.min(self.total.saturating_sub(offset)))
+            .read_to_end(&mut data)?;
+
+        Ok(FileResponse::Chunk {
+            data,
+            total: self.total,
+            hash: self.hash,
+        })
+    }
+}
+
+/// Why a chunk couldn't be written.
+#[derive(Debug)]
+pub enum ChunkError {
+    /// The provider sent nothing before the end, or a chunk of some other file.
+    WrongFile,
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub struct Download {
+    destination: PathBuf,
+    file: fs::File,
+    providers: VecDeque<PeerId>,
+    tried: HashSet<PeerId>,
+    pub current: Option<PeerId>,
+    pub received: u64,
+    pub total: Option<u64>,
+    /// What the first chunk announced; every later one has to announce the same.
+    hash: Option<FileHash>,
+    hasher: Sha256,
+    pub lookup_finished: bool,
+}
+
+impl Download {
+    pub fn new(destination: PathBuf) -> io::Result<Self> {
+        let file = fs::File::create(&destination)?;
+
+        Ok(Self {
+            destination,
+            file,
+            providers: VecDeque::new(),
+            tried: HashSet::new(),
+            current: None,
+            received: 0,
+            total: None,
+            hash: None,
+            hasher: Sha256::new(),
+            lookup_finished: false,
+        })
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    pub fn add_providers(&mut self, providers: impl IntoIterator<Item = PeerId>) {
+        for provider in providers {
+            if !self.tried.contains(&provider) && !self.providers.contains(&provider) {
+                self.providers.push_back(provider);
+            }
+        }
+    }
+
+    pub fn next_provider(&mut self) -> Option<PeerId> {
+        let provider = self.providers.pop_front()?;
+
+        self.tried.insert(provider);
+        self.current = Some(provider);
+
+        Some(provider)
+    }
+
+    pub fn write_chunk(
+        &mut self,
+        data: &[u8],
+        total: u64,
+        hash: FileHash,
+    ) -> Result<(), ChunkError> {
+        let end = self.received + data.len() as u64;
+        let stalled = data.is_empty() && self.received < total;
+        let changed = self.total.is_some_and(|known| known != total)
+            || self.hash.is_some_and(|known| known != hash);
+
+        if stalled || changed || end > total {
+            return Err(ChunkError::WrongFile);
+        }
+
+        self.file.write_all(data).map_err(ChunkError::Io)?;
+        self.hasher.update(data);
+        self.received = end;
+        self.total = Some(total);
+        self.hash = Some(hash);
+
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total.is_some_and(|total| self.received >= total)
+    }
+
+    /// Whether what was written hashes to what the providers announced.
+    pub fn is_intact(&self) -> bool {
+        self.hash
+            .is_some_and(|hash| self.hasher.clone().finalize().as_slice() == hash)
+    }
+
+    pub fn discard(self) {
+        drop(self.file);
+        let _ = fs::remove_file(&self.destination);
+    }
+}
+
+/// Disk work done off the swarm loop, handed back to it when finished.
+pub enum FileJob {
+    /// A file was hashed so it can be shared.
+    Hashed {
+        request_id: RequestId,
+        key: String,
+        path: PathBuf,
+        result: io::Result<SharedFile>,
+    },
+    /// A chunk a peer asked for was read.
+    Read {
+        peer: PeerId,
+        channel: ResponseChannel<FileResponse>,
+        response: FileResponse,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct FileTransfers {
+    pub shared: HashMap<String, SharedFile>,
+    pub lookups: HashMap<QueryId, String>,
+    pub downloads: HashMap<String, Download>,
+    pub requests: HashMap<OutboundRequestId, String>,
+    pub jobs: FuturesUnordered<BoxFuture<'static, FileJob>>,
+}
+
+impl FileTransfers {
+    pub fn share(&mut self, request_id: RequestId, key: String, path: PathBuf) {
+        self.spawn(move || FileJob::Hashed {
+            request_id,
+            key,
+            result: SharedFile::open(path.clone()),
+            path,
+        });
+    }
+
+    pub fn serve(
+        &mut self,
+        shared: SharedFile,
+        offset: u64,
+        peer: PeerId,
+        channel: ResponseChannel<FileResponse>,
+    ) {
+        self.spawn(move || {
+            let response = shared.read_chunk(offset).unwrap_or_else(|err| {
+                error!("Failed to read shared file {}: {err}", shared.path.display());
+                FileResponse::NotFound
+            });
+
+            FileJob::Read {
+                peer,
+                channel,
+                response,
+            }
+        });
+    }
+
+    fn spawn(&mut self, job: impl FnOnce() -> FileJob + Send + 'static) {
+        let job = tokio::task::spawn_blocking(job).map(|done| done.expect("file job panicked"));
+
+        self.jobs.push(job.boxed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn chunk(shared: &SharedFile, offset: u64) -> (Vec<u8>, u64, FileHash) {
+        let FileResponse::Chunk { data, total, hash } = shared.read_chunk(offset).unwrap() else {
+            panic!("shared file should be readable");
+        };
+
+        (data, total, hash)
+    }
+
+    #[test]
+    fn chunks_reassemble_into_original_file() {
+        let source = temp_path("source");
+        let destination = temp_path("destination");
+        let content: Vec<u8> = (0..CHUNK_SIZE * 2 + 17).map(|i| i as u8).collect();
+        fs::write(&source, &content).unwrap();
+
+        let mut download = Download::new(destination.clone()).unwrap();
+
+        let shared = SharedFile::open(source.clone()).unwrap();
+
+        while !download.is_complete() {
+            let (data, total, hash) = chunk(&shared, download.received);
+            download.write_chunk(&data, total, hash).unwrap();
+        }
+
+        assert!(download.is_intact());
+        assert_eq!(fs::read(&destination).unwrap(), content);
+
+        fs::remove_file(source).unwrap();
+        fs::remove_file(destination).unwrap();
+    }
+
+    #[test]
+    fn failed_providers_are_not_retried() {
+        let destination = temp_path("retry");
+        let first = PeerId::random();
+        let second = PeerId::random();
+
+        let mut download = Download::new(destination).unwrap();
+        download.add_providers([first, second]);
+
+        assert_eq!(download.next_provider(), Some(first));
+        download.add_providers([first]);
+        assert_eq!(download.next_provider(), Some(second));
+        assert_eq!(download.next_provider(), None);
+
+        download.discard();
+    }
+
+    #[test]
+    fn chunks_that_dont_continue_the_file_are_turned_down() {
+        let source = temp_path("changing");
+        fs::write(&source, vec![1; CHUNK_SIZE as usize * 2]).unwrap();
+        let original = SharedFile::open(source.clone()).unwrap();
+
+        let mut download = Download::new(temp_path("changing-copy")).unwrap();
+        let (data, total, hash) = chunk(&original, 0);
+        download.write_chunk(&data, total, hash).unwrap();
+
+        // The provider's file shrank after it was shared
+        fs::write(&source, b"short").unwrap();
+        let (data, total, hash) = chunk(&original, download.received);
+        assert!(data.is_empty());
+        assert!(matches!(download.write_chunk(&data, total, hash), Err(ChunkError::WrongFile)));
+
+        // Another provider shares a different file under the same key
+        fs::write(&source, vec![2; CHUNK_SIZE as usize * 3]).unwrap();
+        let other = SharedFile::open(source.clone()).unwrap();
+        let (data, total, hash) = chunk(&other, download.received);
+        assert!(matches!(download.write_chunk(&data, total, hash), Err(ChunkError::WrongFile)));
+
+        assert_eq!(download.received, CHUNK_SIZE);
+
+        fs::remove_file(source).unwrap();
+        download.discard();
+    }
+
+    #[test]
+    fn downloads_must_hash_to_what_was_announced() {
+        let mut download = Download::new(temp_path("tampered")).unwrap();
+
+        download.write_chunk(b"abc", 3, [0; 32]).unwrap();
+
+        assert!(download.is_complete());
+        assert!(!download.is_intact());
+
+        download.discard();
+    }
+}
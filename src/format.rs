@@ -0,0 +1,176 @@
+use std::fmt;
+use std::fmt::Formatter;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+const HEX_ROW_BYTES: usize = 16;
+/// How much of a value a detail view shows until asked for all of it.
+const MAX_SHOWN_BYTES: usize = 4 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueDisplay {
+    Text,
+    Hex,
+    Base64,
+}
+
+impl ValueDisplay {
+    pub const ALL: [ValueDisplay; 3] =
+        [ValueDisplay::Text, ValueDisplay::Hex, ValueDisplay::Base64];
+
+    /// Text for valid UTF-8, a hex dump for anything else.
+    pub fn detect(value: &[u8]) -> Self {
+        match std::str::from_utf8(value) {
+            Ok(_) => ValueDisplay::Text,
+            Err(_) => ValueDisplay::Hex,
+        }
+    }
+}
+
+impl fmt::Display for ValueDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueDisplay::Text => write!(f, "Text"),
+            ValueDisplay::Hex => write!(f, "Hex"),
+            ValueDisplay::Base64 => write!(f, "Base64"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub text: String,
+    /// Whether the value went on past what `text` shows.
+    pub truncated: bool,
+}
+
+/// Renders `value` the way `display` asks, cut at [`MAX_SHOWN_BYTES`] unless `whole` is set.
+pub fn render(value: &[u8], display: ValueDisplay, whole: bool) -> Rendered {
+    let shown = if whole {
+        value
+    } else {
+        &value[..value.len().min(MAX_SHOWN_BYTES)]
+    };
+
+    let text = match display {
+        ValueDisplay::Text => text(shown),
+        ValueDisplay::Hex => hex_dump(shown),
+        ValueDisplay::Base64 => STANDARD.encode(shown),
+    };
+
+    Rendered {
+        text,
+        truncated: shown.len() < value.len(),
+    }
+}
+
+/// Rows of [`HEX_ROW_BYTES`] bytes, each with its offset and an ASCII gutter.
+pub fn hex_dump(value: &[u8]) -> String {
+    value
+        .chunks(HEX_ROW_BYTES)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<_> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&byte| match byte {
+                    b' ' | b'!'..=b'~' => byte as char,
+                    _ => '.',
+                })
+                .collect();
+
+            format!(
+                "{:08x}  {:<width$}  |{ascii}|",
+                row * HEX_ROW_BYTES,
+                hex.join(" "),
+                width = HEX_ROW_BYTES * 3 - 1
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Text with invalid sequences replaced, except for one the cut split at the end.
+fn text(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) => text.to_owned(),
+        Err(err) if err.error_len().is_none() => {
+            String::from_utf8_lossy(&value[..err.valid_up_to()]).into_owned()
+        }
+        Err(_) => String::from_utf8_lossy(value).into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_values_render_as_nothing() {
+        for display in ValueDisplay::ALL {
+            assert_eq!(render(&[], display, false), Rendered {
+                text: String::new(),
+                truncated: false,
+            });
+        }
+    }
+
+    #[test]
+    fn a_full_row_fits_on_one_line() {
+        let value: Vec<u8> = (b'a'..b'a' + HEX_ROW_BYTES as u8).collect();
+
+        assert_eq!(
+            hex_dump(&value),
+            "00000000  61 62 63 64 65 66 67 68 69 6a 6b 6c 6d 6e 6f 70  |abcdefghijklmnop|"
+        );
+    }
+
+    #[test]
+    fn short_rows_keep_the_gutter_aligned() {
+        let mut value = vec![b'a'; HEX_ROW_BYTES];
+        value.extend([0x00, 0xff, b' ']);
+
+        let dump = hex_dump(&value);
+        let rows: Vec<_> = dump.lines().collect();
+
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].starts_with("00000010  00 ff 20 "));
+        assert!(rows[1].ends_with("|.. |"));
+        assert_eq!(rows[0].find('|'), rows[1].find('|'));
+    }
+
+    #[test]
+    fn large_values_are_cut_until_shown_whole() {
+        let value = vec![b'x'; MAX_SHOWN_BYTES * 3];
+
+        let cut = render(&value, ValueDisplay::Text, false);
+        assert!(cut.truncated);
+        assert_eq!(cut.text.len(), MAX_SHOWN_BYTES);
+
+        let whole = render(&value, ValueDisplay::Text, true);
+        assert!(!whole.truncated);
+        assert_eq!(whole.text.len(), value.len());
+
+        let hex = render(&value, ValueDisplay::Hex, false);
+        assert_eq!(hex.text.lines().count(), MAX_SHOWN_BYTES / HEX_ROW_BYTES);
+    }
+
+    #[test]
+    fn cutting_through_a_character_drops_it() {
+        let mut value = vec![b'x'; MAX_SHOWN_BYTES - 1];
+        value.extend("é".as_bytes());
+
+        let cut = render(&value, ValueDisplay::Text, false);
+
+        assert!(cut.truncated);
+        assert!(!cut.text.contains('\u{fffd}'));
+        assert_eq!(cut.text.len(), MAX_SHOWN_BYTES - 1);
+    }
+
+    #[test]
+    fn binary_values_are_shown_as_hex_and_encode_as_base64() {
+        assert_eq!(ValueDisplay::detect("hello, мир".as_bytes()), ValueDisplay::Text);
+        assert_eq!(ValueDisplay::detect(&[0xff, 0x00]), ValueDisplay::Hex);
+        assert_eq!(render(b"hello", ValueDisplay::Base64, false).text, "aGVsbG8=");
+    }
+}
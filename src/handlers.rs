@@ -1,56 +1,3344 @@
-use crate::p2p::{P2pCommand, P2pEvent};
-use iced::Task;
-use iced::futures::SinkExt;
-use iced::futures::channel::mpsc;
-use crate::app::{Message, State};
+use crate::bench::{self, Benchmark};
+use crate::config;
+use crate::config::{KadMode, SettingsField};
+use crate::diagnostics::{COPIED_TRACE_LINES, TRACE_LOG_CAPACITY, TraceLevel, TraceLine};
+use crate::export;
+use crate::format::ValueDisplay;
+use crate::export::{Export, ExportFormat, ExportedEvent, ExportedRecord, ImportedRecords};
+use crate::inbound::InboundId;
+use crate::widgets::{get_action, input_id, log_scroll_id, put_action};
+use crate::p2p;
+use crate::p2p::envelope::{self, ValueCipher};
+use crate::p2p::{
+    FoundValue, NatStatus, P2pCommand, P2pError, P2pEvent, P2pInboundEvent, P2pLocalEvent,
+    P2pOutboundEvent, QueryKind, RequestId,
+};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use iced::advanced::widget::{self, operate, operation::focusable};
+use iced::widget::scrollable::{self, RelativeOffset, Viewport};
+use iced::widget::{combo_box, text_editor, text_input};
+use iced::{Point, Size, Task, Theme, clipboard, event, window};
+use libp2p::{Multiaddr, PeerId, kad};
+use tracing::{error, info};
+use crate::app::{
+    ChatMessage, ClosestPeers, Draft, FileTransfer, ImportedRecord, InputField, LogCategory, LogEntry, Message, PeerInfo, PendingQuery, QueryRecord, QueryStatus, RecordEntry,
+    Bandwidth, PendingApproval, QueryLatencies, RecordImport, RecordLookup, State, Tab, TransferStatus, ValueSource, ValueView,
+    TOAST_LIFETIME, Shortcut, Toast, ToastKind, editor_text, system_theme,
+};
 
-pub fn handle_p2p_event(state: &mut State, event: P2pEvent) -> Task<Message> {
-    state.event_log.push(event.clone());
-    
-    if let P2pEvent::PeerDiscovered(..) = event {
-        state.peer_count += 1;
-    } else if let P2pEvent::PeerExpired(..) = event {
-        state.peer_count -= 1;
+/// Imported records put at once, well under the queries Kademlia runs side by side.
+const IMPORT_CONCURRENCY: usize = 4;
+/// Typing a setting, moving or resizing the window all change the config in a stream; it is
+/// written once they stop.
+const CONFIG_SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// What a handler asks for besides the changes it made to the state. Handlers never talk to the
+/// node themselves; the app sends their commands for them.
+#[must_use]
+pub struct Effect {
+    pub commands: Vec<P2pCommand>,
+    pub task: Task<Message>,
+}
+
+impl From<Task<Message>> for Effect {
+    fn from(task: Task<Message>) -> Self {
+        Self {
+            commands: Vec::new(),
+            task,
+        }
+    }
+}
+
+impl Effect {
+    pub fn none() -> Self {
+        Self::send_all(Vec::new())
+    }
+
+    pub fn send(cmd: P2pCommand) -> Self {
+        Self::send_all(vec![cmd])
+    }
+
+    pub fn send_all(commands: Vec<P2pCommand>) -> Self {
+        Self {
+            commands,
+            task: Task::none(),
+        }
+    }
+
+    pub fn with_task(self, task: Task<Message>) -> Self {
+        Self {
+            task: Task::batch([self.task, task]),
+            ..self
+        }
+    }
+}
+
+/// Applies an event, and sends the queries held back for want of peers once one connects.
+pub fn handle_p2p_event(state: &mut State, event: P2pEvent) -> Effect {
+    let task = apply_p2p_event(state, event);
+
+    if state.connected_peers.is_empty() || state.queued_queries.is_empty() {
+        return task.into();
+    }
+
+    let commands = mem::take(&mut state.queued_queries)
+        .into_iter()
+        .map(|(mut query, cmd)| {
+            query.started_at = Instant::now();
+            set_query_status(state, query.request_id, QueryStatus::Pending);
+            state.pending_queries.push(query);
+            cmd
+        })
+        .collect();
+
+    Effect::send_all(commands).with_task(task)
+}
+
+fn apply_p2p_event(state: &mut State, event: P2pEvent) -> Task<Message> {
+    // Comes every second, so it only updates the status bar.
+    if let P2pEvent::Bandwidth {
+        inbound_bps,
+        outbound_bps,
+        total_in,
+        total_out,
+    } = event
+    {
+        state.bandwidth = Bandwidth {
+            inbound_bps,
+            outbound_bps,
+            total_in,
+            total_out,
+        };
+        return Task::none();
+    }
+
+    // Benchmark operations are summed up once it's done instead of logged one by one.
+    if let Some(benchmark) = &mut state.benchmark
+        && benchmark.handle(&event)
+    {
+        return Task::done(Message::ContinueBenchmark);
+    }
+
+    let event = decrypt_values(state, event);
+    let logged = is_logged(&event);
+
+    if logged {
+        log_event(state, event.clone());
+    }
+
+    if let Some((kind, text)) = toast_for(&event) {
+        show_toast(state, kind, text, Instant::now());
+    }
+
+    let import_advanced = match &event {
+        P2pEvent::Outbound(Some(request_id), _) => finish_imported_put(state, *request_id, None),
+        P2pEvent::Error(Some(request_id), err) => {
+            finish_imported_put(state, *request_id, Some(err.to_string()))
+        }
+        _ => false,
+    };
+
+    match event {
+        P2pEvent::LocalIdentity(peer_id) => state.local_peer_id = Some(peer_id),
+        P2pEvent::Bootstrapped(address) if !state.listen_addrs.contains(&address) => {
+            state.listen_addrs.push(address);
+        }
+        P2pEvent::ListenAddrExpired(address) => {
+            state.listen_addrs.retain(|known| known != &address);
+        }
+        P2pEvent::PeerDiscovered(peer_id, address) => {
+            let peer = seen_peer(state, peer_id);
+
+            if !peer.addresses.contains(&address) {
+                peer.addresses.push(address);
+            }
+        }
+        P2pEvent::PeerExpired(peer_id, address) => {
+            if let Some(peer) = state.peers.get_mut(&peer_id) {
+                peer.addresses.retain(|known| known != &address);
+
+                if peer.addresses.is_empty() && !state.connected_peers.contains(&peer_id) {
+                    forget_peer(state, peer_id);
+                }
+            }
+        }
+        P2pEvent::PeerIdentified {
+            peer_id,
+            agent,
+            protocols,
+        } => {
+            let peer = seen_peer(state, peer_id);
+            peer.agent = Some(agent);
+            peer.protocols = protocols;
+        }
+        P2pEvent::PingRtt(peer_id, rtt) => {
+            let peer = seen_peer(state, peer_id);
+            peer.rtt = Some(rtt);
+            peer.unresponsive = false;
+        }
+        P2pEvent::TopicSubscribed(topic) if !state.subscribed_topics.contains(&topic) => {
+            state.subscribed_topics.push(topic);
+        }
+        P2pEvent::TopicUnsubscribed(topic) => {
+            state.subscribed_topics.retain(|known| known != &topic);
+        }
+        P2pEvent::GossipMessage {
+            topic,
+            source,
+            data,
+        } => state.chat_messages.push(ChatMessage {
+            topic,
+            source,
+            text: String::from_utf8_lossy(&data).into_owned(),
+        }),
+        P2pEvent::GossipPublished { topic, data } => state.chat_messages.push(ChatMessage {
+            topic,
+            source: state.local_peer_id,
+            text: String::from_utf8_lossy(&data).into_owned(),
+        }),
+        P2pEvent::PeerUnresponsive(peer_id) => {
+            if let Some(peer) = state.peers.get_mut(&peer_id) {
+                peer.unresponsive = true;
+            }
+        }
+        P2pEvent::RememberedPeers(peers) => state.remembered_peers = peers,
+        P2pEvent::MetricsServing(address) => state.metrics_address = Some(address),
+        P2pEvent::PeerConnected {
+            peer_id,
+            relayed,
+            transport,
+            direction,
+            security,
+            ..
+        } => {
+            state.connected_peers.insert(peer_id);
+
+            if let Some(known) = state
+                .remembered_peers
+                .iter_mut()
+                .find(|known| known.peer_id == peer_id)
+            {
+                known.last_seen = SystemTime::now();
+            }
+
+            let peer = seen_peer(state, peer_id);
+
+            peer.connection = Some((transport, direction));
+            peer.security = security;
+
+            if peer.relayed != Some(false) {
+                peer.relayed = Some(relayed);
+            }
+        }
+        P2pEvent::PeerDisconnected {
+            peer_id,
+            num_established: 0,
+        } => {
+            state.connected_peers.remove(&peer_id);
+
+            if let Some(peer) = state.peers.get_mut(&peer_id) {
+                peer.relayed = None;
+                peer.connection = None;
+                peer.security = None;
+
+                if peer.addresses.is_empty() {
+                    forget_peer(state, peer_id);
+                }
+            }
+        }
+        P2pEvent::HolePunch {
+            remote,
+            success: true,
+        } => seen_peer(state, remote).relayed = Some(false),
+        P2pEvent::NatStatus(status) => state.nat_status = status,
+        P2pEvent::KadModeChanged(mode) => state.kad_mode = mode.into(),
+        P2pEvent::RoutingTable(buckets) => state.routing_table = buckets,
+        P2pEvent::RoutingUpdated { bucket, .. } => state.changed_bucket = Some(bucket),
+        P2pEvent::PeerBlocked(peer_id) => {
+            state.peers.remove(&peer_id);
+
+            if !state.blocked_peers.contains(&peer_id) {
+                state.blocked_peers.push(peer_id);
+            }
+        }
+        P2pEvent::PeerUnblocked(peer_id) => {
+            state.blocked_peers.retain(|blocked| blocked != &peer_id);
+        }
+        P2pEvent::RelayServerToggled(enabled) => {
+            state.relay_server = enabled;
+
+            if !enabled {
+                state.active_circuits = 0;
+            }
+        }
+        P2pEvent::MdnsToggled(enabled) => state.mdns_enabled = enabled,
+        P2pEvent::Error(None, P2pError::MdnsUnavailable { .. }) => state.mdns_enabled = false,
+        P2pEvent::RelayCircuitOpened { .. } => state.active_circuits += 1,
+        P2pEvent::RelayCircuitClosed { .. } => {
+            state.active_circuits = state.active_circuits.saturating_sub(1);
+        }
+        P2pEvent::FileTransferProgress {
+            key,
+            received,
+            total,
+        } => {
+            if let Some(transfer) = find_transfer(state, &key) {
+                transfer.received = received;
+                transfer.total = Some(total);
+            }
+        }
+        P2pEvent::FileFetched { key, .. } => {
+            if let Some(transfer) = find_transfer(state, &key) {
+                transfer.status = TransferStatus::Done;
+            }
+        }
+        P2pEvent::Error(None, P2pError::FileUnavailable { key } | P2pError::FileIo { key, .. }) => {
+            if let Some(transfer) = find_transfer(state, &key) {
+                transfer.status = TransferStatus::Failed;
+            }
+        }
+        P2pEvent::StoreRestored { providers } => {
+            for key in providers {
+                let key = key_label(state, &key);
+
+                if !state.provided_keys.contains(&key) {
+                    state.provided_keys.push(key);
+                }
+            }
+        }
+        P2pEvent::InboundApprovalToggled(enabled) => state.inbound_approval = enabled,
+        P2pEvent::InboundPending {
+            id,
+            source,
+            key,
+            value,
+        } => state.pending_inbound.push(PendingApproval {
+            id,
+            source,
+            key: key_label(state, &key),
+            value,
+        }),
+        P2pEvent::InboundExpired(id) => state.pending_inbound.retain(|pending| pending.id != id),
+        P2pEvent::LocalRecords(records) => {
+            let mut previous = std::mem::take(&mut state.records);
+
+            for (record_key, value, expires) in records {
+                let key = key_label(state, &record_key);
+                let publisher = previous.remove(&key).and_then(|entry| entry.publisher);
+                let mut entry = RecordEntry::open(&record_key, &value, publisher, expires);
+                entry.value = decrypt_value(state, entry.value);
+
+                state.records.insert(key, entry);
+            }
+        }
+        P2pEvent::Local(P2pLocalEvent::RecordStored(record)) => {
+            let kad::Record { key, value, publisher, expires } = record;
+            let mut entry = RecordEntry::open(&key, &value, publisher, expires);
+            entry.value = decrypt_value(state, entry.value);
+            state.records.insert(key_label(state, &key), entry);
+        }
+        P2pEvent::Inbound(P2pInboundEvent::RecordsRequested { found, missing }) => {
+            state.served_gets += found + missing;
+        }
+        P2pEvent::Inbound(P2pInboundEvent::ProvidersRequested(count)) => {
+            state.served_provider_lookups += count;
+        }
+        P2pEvent::Inbound(P2pInboundEvent::RecordStored(
+            source,
+            key,
+            value,
+            expires,
+            signature,
+        )) => {
+            state.records.insert(key_label(state, &key), RecordEntry {
+                key,
+                value,
+                publisher: Some(source),
+                expires,
+                signature,
+            });
+        }
+        P2pEvent::Local(
+            P2pLocalEvent::RecordRemoved(key)
+            | P2pLocalEvent::RecordNotInStore(key)
+            | P2pLocalEvent::RecordExpired(key),
+        ) => {
+            let key = key_label(state, &key);
+            state.records.remove(&key);
+        }
+        P2pEvent::Outbound(
+            request_id,
+            P2pOutboundEvent::RecordResolved {
+                ref key,
+                ref values,
+                agree,
+            },
+        ) => {
+            if let Some(request_id) = request_id {
+                update_query_status(state, request_id, QueryStatus::Succeeded);
+            }
+
+            state.record_lookup = Some(RecordLookup {
+                key: key_label(state, key),
+                values: values.clone(),
+                agree,
+            });
+        }
+        P2pEvent::Outbound(request_id, P2pOutboundEvent::ClosestPeers(key, peers)) => {
+            if let Some(request_id) = request_id {
+                update_query_status(state, request_id, QueryStatus::Succeeded);
+            }
+
+            state.closest_peers = Some(ClosestPeers {
+                key: key_label(state, &key),
+                record_key: key,
+                peers,
+                complete: true,
+            });
+        }
+        P2pEvent::Error(request_id, P2pError::GetClosestPeersTimeout { key, peers }) => {
+            if let Some(request_id) = request_id {
+                update_query_status(state, request_id, QueryStatus::TimedOut);
+            }
+
+            state.closest_peers = Some(ClosestPeers {
+                key: key_label(state, &key),
+                record_key: key,
+                peers,
+                complete: false,
+            });
+        }
+        P2pEvent::Outbound(Some(request_id), event) => {
+            let status = match event {
+                P2pOutboundEvent::RecordNotFound(..) => QueryStatus::NotFound,
+                P2pOutboundEvent::QueryCancelled(..) => QueryStatus::Cancelled,
+                _ => QueryStatus::Succeeded,
+            };
+            update_query_status(state, request_id, status);
+        }
+        P2pEvent::Error(Some(request_id), P2pError::QueryTimedOut { .. }) => {
+            update_query_status(state, request_id, QueryStatus::TimedOut);
+        }
+        P2pEvent::Error(Some(request_id), _) => {
+            update_query_status(state, request_id, QueryStatus::Failed);
+        }
+        _ => {}
+    }
+
+    let snap = if logged && !state.log_paused {
+        scrollable::snap_to(log_scroll_id(), RelativeOffset::END)
+    } else {
+        Task::none()
+    };
+
+    if import_advanced {
+        Task::batch([snap, Task::done(Message::ContinueImport)])
+    } else {
+        snap
+    }
+}
+
+/// Swaps values encrypted under our passphrase for their plaintext before anything shows them.
+fn decrypt_values(state: &mut State, event: P2pEvent) -> P2pEvent {
+    match event {
+        P2pEvent::Outbound(request_id, P2pOutboundEvent::RecordResolved { key, values, .. }) => {
+            let values: Vec<_> = values
+                .into_iter()
+                .map(|found| FoundValue {
+                    value: decrypt_value(state, found.value),
+                    ..found
+                })
+                .collect();
+            let agree = p2p::value_variants(&values).len() <= 1;
+
+            P2pEvent::Outbound(request_id, P2pOutboundEvent::RecordResolved { key, values, agree })
+        }
+        P2pEvent::Inbound(P2pInboundEvent::RecordStored(source, key, value, expires, signature)) =>
+        {
+            let value = decrypt_value(state, value);
+            P2pEvent::Inbound(P2pInboundEvent::RecordStored(source, key, value, expires, signature))
+        }
+        event => event,
+    }
+}
+
+/// Leaves plain values, and those encrypted under another passphrase, as they are.
+fn decrypt_value(state: &State, value: Vec<u8>) -> Vec<u8> {
+    if !envelope::is_encrypted(&value) {
+        return value;
+    }
+
+    value_cipher(state)
+        .and_then(|cipher| cipher.decrypt(&value))
+        .unwrap_or(value)
+}
+
+/// `None` while the key for the passphrase is still being derived, so that nothing goes out in
+/// the clear that was meant to be encrypted.
+fn encrypt_value(state: &State, value: Vec<u8>) -> Option<Vec<u8>> {
+    if state.config.value_passphrase.is_none() || envelope::is_encrypted(&value) {
+        return Some(value);
+    }
+
+    value_cipher(state).map(|cipher| cipher.encrypt(&value))
+}
+
+/// The cipher for the current passphrase, once its key has been derived.
+fn value_cipher(state: &State) -> Option<&ValueCipher> {
+    let passphrase = state.config.value_passphrase.as_deref()?;
+
+    state
+        .value_cipher
+        .as_deref()
+        .filter(|cipher| cipher.is_for(passphrase))
+}
+
+/// Derives the key for a new passphrase. Argon2id is slow on purpose, so it runs off the UI
+/// thread.
+pub fn derive_value_cipher(state: &State) -> Task<Message> {
+    let Some(passphrase) = state.config.value_passphrase.clone() else {
+        return Task::none();
+    };
+
+    if value_cipher(state).is_some() {
+        return Task::none();
+    }
+
+    let derive = async move {
+        tokio::task::spawn_blocking(move || ValueCipher::new(&passphrase)).await.ok()
+    };
+
+    Task::future(derive)
+        .and_then(|cipher| Task::done(Message::ValueCipherDerived(Arc::new(cipher))))
+}
+
+/// Keeps the cipher unless the passphrase changed again while its key was being derived.
+pub fn handle_value_cipher_derived(state: &mut State, cipher: Arc<ValueCipher>) -> Task<Message> {
+    if state
+        .config
+        .value_passphrase
+        .as_deref()
+        .is_some_and(|passphrase| cipher.is_for(passphrase))
+    {
+        state.value_cipher = Some(cipher);
     }
 
     Task::none()
 }
 
-pub fn handle_key_text_changed(state: &mut State, data: String) -> Task<Message> {
-    state.current_key = data;
+fn show_key_not_ready(state: &mut State) {
+    let text = "The passphrase's key is still being derived, try again in a moment".to_owned();
+
+    show_toast(state, ToastKind::Info, text, Instant::now());
+}
+
+fn seen_peer(state: &mut State, peer_id: PeerId) -> &mut PeerInfo {
+    let now = Instant::now();
+    let peer = state.peers.entry(peer_id).or_default();
+
+    peer.discovered_at.get_or_insert(now);
+    peer.last_seen = Some(now);
+    peer.expired_at = None;
+
+    peer
+}
+
+fn forget_peer(state: &mut State, peer_id: PeerId) {
+    if state.peer_grace.is_zero() {
+        state.peers.remove(&peer_id);
+    } else if let Some(peer) = state.peers.get_mut(&peer_id) {
+        peer.expired_at = Some(Instant::now());
+    }
+}
+
+/// Maps user input to a record key, reusing the key a human label was last resolved to so a
+/// hashed record stays reachable under its name even after the hashing setting changes.
+fn resolve_key(state: &mut State, input: &str) -> kad::RecordKey {
+    let namespace = &state.config.key_namespace;
+    let label = p2p::namespaced(input, namespace);
+
+    if let Some((key, _)) = state.key_labels.iter().find(|(_, known)| **known == label) {
+        return key.clone();
+    }
+
+    let key = p2p::parse_key(input, namespace, state.hash_keys);
+
+    if p2p::format_key(&key) != label {
+        state.key_labels.insert(key.clone(), label);
+    }
+
+    key
+}
+
+fn key_label(state: &State, key: &kad::RecordKey) -> String {
+    let label = state
+        .key_labels
+        .get(key)
+        .cloned()
+        .unwrap_or_else(|| p2p::format_key(key));
+
+    match label.strip_prefix(&state.config.key_namespace) {
+        Some(logical) if !state.config.key_namespace.is_empty() => {
+            logical.strip_prefix('/').unwrap_or(&label).to_owned()
+        }
+        _ => label,
+    }
+}
+
+/// Shows records and provided keys relative to a new namespace; the keys themselves stay put.
+fn relabel_keys(state: &mut State, provided: Vec<kad::RecordKey>) {
+    let records = mem::take(&mut state.records);
+    state.records = records
+        .into_values()
+        .map(|entry| (key_label(state, &entry.key), entry))
+        .collect();
+    state.provided_keys = provided.iter().map(|key| key_label(state, key)).collect();
+}
+
+const LOG_BOTTOM_THRESHOLD: f32 = 0.98;
+
+fn log_event(state: &mut State, event: P2pEvent) {
+    state.event_log.push(LogEntry::new(event));
+}
+
+pub fn is_logged(event: &P2pEvent) -> bool {
+    match event {
+        P2pEvent::LocalRecords(..)
+        | P2pEvent::RoutingTable(..)
+        | P2pEvent::PingRtt(..)
+        | P2pEvent::PeerUnresponsive(..)
+        | P2pEvent::GossipMessage { .. }
+        | P2pEvent::FileTransferProgress { .. }
+        | P2pEvent::Bandwidth { .. } => false,
+        P2pEvent::PeerConnected {
+            num_established, ..
+        } => *num_established == 1,
+        P2pEvent::PeerDisconnected {
+            num_established, ..
+        } => *num_established == 0,
+        _ => true,
+    }
+}
+
+/// What finishing a query or failing at something has to say, outside the log.
+fn toast_for(event: &P2pEvent) -> Option<(ToastKind, String)> {
+    let toast = match event {
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordPut(_)) => {
+            (ToastKind::Success, "Record put".to_owned())
+        }
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordResolved { .. }) => {
+            (ToastKind::Success, "Record found".to_owned())
+        }
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordNotFound(_)) => {
+            (ToastKind::Info, "No record found".to_owned())
+        }
+        P2pEvent::Outbound(_, P2pOutboundEvent::ProviderPut(_)) => {
+            (ToastKind::Success, "Providing the key".to_owned())
+        }
+        P2pEvent::Outbound(_, P2pOutboundEvent::ProvidersFound(_, providers)) => {
+            match providers.len() {
+                0 => (ToastKind::Info, "No providers found".to_owned()),
+                1 => (ToastKind::Success, "Found 1 provider".to_owned()),
+                count => (ToastKind::Success, format!("Found {count} providers")),
+            }
+        }
+        P2pEvent::Error(_, error) => (ToastKind::Error, error.to_string()),
+        _ => return None,
+    };
+
+    Some(toast)
+}
+
+/// Shows a toast, or counts it on one already showing the same thing.
+fn show_toast(state: &mut State, kind: ToastKind, text: String, now: Instant) {
+    if let Some(toast) = state
+        .toasts
+        .iter_mut()
+        .find(|toast| toast.kind == kind && toast.text == text)
+    {
+        toast.count += 1;
+        toast.created = now;
+        return;
+    }
+
+    state.toasts.push(Toast {
+        id: state.next_toast_id,
+        kind,
+        text,
+        created: now,
+        count: 1,
+    });
+    state.next_toast_id += 1;
+}
+
+pub fn handle_dismiss_toast(state: &mut State, id: u64) -> Task<Message> {
+    state.toasts.retain(|toast| toast.id != id);
 
     Task::none()
 }
 
-pub fn handle_value_text_changed(state: &mut State, data: String) -> Task<Message> {
-    state.current_value = data;
+pub fn handle_toast_tick(state: &mut State, now: Instant) -> Task<Message> {
+    state
+        .toasts
+        .retain(|toast| now.duration_since(toast.created) < TOAST_LIFETIME);
 
     Task::none()
 }
 
-pub fn handle_put_record(
+fn find_transfer<'a>(state: &'a mut State, key: &str) -> Option<&'a mut FileTransfer> {
+    state
+        .file_transfers
+        .iter_mut()
+        .find(|transfer| transfer.key == key && transfer.status == TransferStatus::Running)
+}
+
+fn update_query_status(state: &mut State, request_id: RequestId, status: QueryStatus) {
+    if let Some(index) = state
+        .pending_queries
+        .iter()
+        .position(|query| query.request_id == request_id)
+    {
+        let query = state.pending_queries.remove(index);
+        record_latency(state, &query, status);
+
+        let recalled = matches!(
+            query.kind,
+            QueryKind::GetRecord | QueryKind::PutRecord | QueryKind::StartProviding
+        );
+        if recalled && status == QueryStatus::Succeeded {
+            remember_key(state, &query.key);
+        }
+
+        unlist_failed_provide(state, &query, status);
+    }
+
+    if let Some(query) = state.query_history.iter_mut().find(|query| {
+        query.request_id == request_id
+            && matches!(query.status, QueryStatus::Pending | QueryStatus::TimedOut)
+    }) {
+        query.status = status;
+    }
+}
+
+/// The key was listed as provided when asked for; it only stays if that worked.
+fn unlist_failed_provide(state: &mut State, query: &PendingQuery, status: QueryStatus) {
+    if query.kind == QueryKind::StartProviding && status != QueryStatus::Succeeded {
+        state.provided_keys.retain(|provided| *provided != query.key);
+    }
+}
+
+fn remember_key(state: &mut State, key: &str) {
+    state.config.remember_key(key);
+    state.recent_keys = combo_box::State::new(state.config.recent_keys.iter().cloned().collect());
+
+    save_config(state);
+}
+
+/// Times record gets and puts; cancelled ones say nothing about the DHT.
+fn record_latency(state: &mut State, query: &PendingQuery, status: QueryStatus) {
+    if !matches!(query.kind, QueryKind::GetRecord | QueryKind::PutRecord) {
+        return;
+    }
+
+    match status {
+        QueryStatus::Cancelled | QueryStatus::Pending => {}
+        QueryStatus::TimedOut => state.query_latencies.record(None),
+        _ => state.query_latencies.record(Some(query.started_at.elapsed())),
+    }
+}
+
+pub fn handle_toggle_listen_addrs(state: &mut State) -> Task<Message> {
+    state.show_listen_addrs = !state.show_listen_addrs;
+
+    Task::none()
+}
+
+pub fn handle_disconnect_peer(_: &mut State, peer_id: PeerId) -> Effect {
+    let cmd = P2pCommand::DisconnectPeer(peer_id);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_block_peer(_: &mut State, peer_id: PeerId) -> Effect {
+    let cmd = P2pCommand::BlockPeer(peer_id);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_unblock_peer(_: &mut State, peer_id: PeerId) -> Effect {
+    let cmd = P2pCommand::UnblockPeer(peer_id);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_toggle_relay_server(state: &mut State, enabled: bool) -> Effect {
+    state.relay_server = enabled;
+
+    let cmd = P2pCommand::SetRelayServer(enabled.then_some(state.relay_limits));
+
+    Effect::send(cmd)
+}
+
+/// Switches local discovery on the running node and remembers the choice in the settings file.
+pub fn handle_toggle_mdns(state: &mut State, enabled: bool) -> Effect {
+    state.mdns_enabled = enabled;
+    state.running_config.mdns_enabled = enabled;
+    let saved = handle_settings_changed(state, SettingsField::MdnsEnabled(enabled));
+
+    let cmd = P2pCommand::SetMdnsEnabled(enabled);
+
+    Effect::send(cmd).with_task(saved)
+}
+
+pub fn handle_toggle_record_signing(state: &mut State, enabled: bool) -> Effect {
+    state.running_config.sign_records = enabled;
+    let saved = handle_settings_changed(state, SettingsField::SignRecords(enabled));
+
+    let cmd = P2pCommand::SetRecordSigning(enabled);
+
+    Effect::send(cmd).with_task(saved)
+}
+
+pub fn handle_toggle_kad_server(state: &mut State, enabled: bool) -> Effect {
+    state.kad_mode = if enabled { KadMode::Server } else { KadMode::Client };
+
+    let cmd = P2pCommand::SetKadMode(state.kad_mode.into());
+
+    Effect::send(cmd)
+}
+
+pub fn handle_toggle_inbound_approval(state: &mut State, enabled: bool) -> Effect {
+    state.inbound_approval = enabled;
+
+    let cmd = P2pCommand::SetInboundApproval(enabled);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_accept_inbound(state: &mut State, id: InboundId) -> Effect {
+    state.pending_inbound.retain(|pending| pending.id != id);
+
+    let cmd = P2pCommand::AcceptInbound(id);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_reject_inbound(state: &mut State, id: InboundId) -> Effect {
+    state.pending_inbound.retain(|pending| pending.id != id);
+
+    let cmd = P2pCommand::RejectInbound(id);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_toggle_hash_keys(state: &mut State, enabled: bool) -> Task<Message> {
+    state.hash_keys = enabled;
+
+    Task::none()
+}
+
+pub fn handle_toggle_log_category(state: &mut State, category: LogCategory) -> Task<Message> {
+    let hidden = &mut state.log_filter.hidden;
+
+    if !hidden.remove(&category) {
+        hidden.insert(category);
+    }
+
+    Task::none()
+}
+
+/// Pauses following new entries once the user scrolls away from the bottom of the log.
+pub fn handle_log_scrolled(state: &mut State, viewport: Viewport) -> Task<Message> {
+    let fits = viewport.content_bounds().height <= viewport.bounds().height;
+    state.log_paused = !fits && viewport.relative_offset().y < LOG_BOTTOM_THRESHOLD;
+
+    Task::none()
+}
+
+pub fn handle_jump_to_latest(state: &mut State) -> Task<Message> {
+    state.log_paused = false;
+
+    scrollable::snap_to(log_scroll_id(), RelativeOffset::END)
+}
+
+pub fn handle_clear_log(state: &mut State) -> Task<Message> {
+    state.event_log.clear();
+    // Cleared entries never come back, so neither would their views
+    state.value_views.retain(|source, _| !matches!(source, ValueSource::LogEntry(_)));
+    state.selected_event = None;
+
+    Task::none()
+}
+
+pub fn handle_event_selected(state: &mut State, number: usize) -> Task<Message> {
+    state.selected_event = Some(number);
+
+    Task::none()
+}
+
+pub fn handle_close_event_detail(state: &mut State) -> Task<Message> {
+    state.selected_event = None;
+
+    Task::none()
+}
+
+/// Opens a value's detail view showing it as `display`, or closes an open one.
+pub fn handle_toggle_value_view(
     state: &mut State,
-    key: String,
-    value: String,
-    mut sender: mpsc::Sender<P2pCommand>,
+    source: ValueSource,
+    display: ValueDisplay,
 ) -> Task<Message> {
-    state.current_value = "".to_owned();
-
-    let cmd = P2pCommand::PutRecord(key, value.into_bytes());
+    if state.value_views.remove(&source).is_none() {
+        state.value_views.insert(source, ValueView {
+            display,
+            whole: false,
+        });
+    }
 
-    Task::perform(async move { sender.send(cmd).await.ok() }, |_| {
-        Message::Ignore
-    })
+    Task::none()
 }
 
-pub fn handle_get_record(
-    _: &mut State,
-    key: String,
-    mut sender: mpsc::Sender<P2pCommand>,
+pub fn handle_value_display_changed(
+    state: &mut State,
+    source: ValueSource,
+    display: ValueDisplay,
 ) -> Task<Message> {
-    let cmd = P2pCommand::GetRecord(key);
+    if let Some(view) = state.value_views.get_mut(&source) {
+        view.display = display;
+    }
 
-    Task::perform(async move { sender.send(cmd).await.ok() }, |_| {
-        Message::Ignore
-    })
+    Task::none()
+}
+
+pub fn handle_show_whole_value(state: &mut State, source: ValueSource) -> Task<Message> {
+    if let Some(view) = state.value_views.get_mut(&source) {
+        view.whole = true;
+    }
+
+    Task::none()
+}
+
+pub fn handle_copy_log(state: &mut State) -> Task<Message> {
+    let text = state
+        .event_log
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    clipboard::write(text)
+}
+
+pub fn handle_trace_line(state: &mut State, line: TraceLine) -> Task<Message> {
+    if state.trace_log.len() == TRACE_LOG_CAPACITY {
+        state.trace_log.pop_front();
+    }
+
+    state.trace_log.push_back(line);
+
+    Task::none()
+}
+
+pub fn handle_toggle_diagnostics(state: &mut State) -> Task<Message> {
+    state.show_diagnostics = !state.show_diagnostics;
+
+    Task::none()
+}
+
+pub fn handle_trace_level_selected(state: &mut State, level: TraceLevel) -> Task<Message> {
+    state.trace_level = level;
+
+    Task::none()
+}
+
+/// Copies the latest lines the Diagnostics panel shows, oldest first.
+pub fn handle_copy_diagnostics(state: &mut State) -> Task<Message> {
+    let mut lines: Vec<String> = state
+        .trace_log
+        .iter()
+        .rev()
+        .filter(|line| state.trace_level.includes(&line.level))
+        .take(COPIED_TRACE_LINES)
+        .map(ToString::to_string)
+        .collect();
+    lines.reverse();
+
+    clipboard::write(lines.join("\n"))
+}
+
+pub fn handle_bench_count_changed(state: &mut State, input: String) -> Task<Message> {
+    state.bench_count = input;
+
+    Task::none()
+}
+
+pub fn handle_bench_value_size_changed(state: &mut State, input: String) -> Task<Message> {
+    state.bench_value_size = input;
+
+    Task::none()
+}
+
+pub fn handle_run_benchmark(state: &mut State) -> Effect {
+    if state.benchmark.is_some() {
+        return Effect::none();
+    }
+
+    let Ok((count, value_size)) = bench::parse_params(&state.bench_count, &state.bench_value_size)
+    else {
+        return Effect::none();
+    };
+
+    info!("Benchmarking {count} records of {value_size} bytes");
+    state.benchmark_report = None;
+    state.benchmark = Some(Benchmark::new(
+        count,
+        value_size,
+        state.config.quorum(),
+        state.config.get_quorum,
+    ));
+
+    handle_continue_benchmark(state)
+}
+
+pub fn handle_cancel_benchmark(state: &mut State) -> Effect {
+    if let Some(benchmark) = &mut state.benchmark {
+        benchmark.cancel();
+    }
+
+    handle_continue_benchmark(state)
+}
+
+/// Sends what the benchmark wants to run next, and reports it once it's done.
+pub fn handle_continue_benchmark(state: &mut State) -> Effect {
+    let Some(benchmark) = &mut state.benchmark else {
+        return Effect::none();
+    };
+
+    let commands = benchmark.take_commands();
+
+    if let Some(report) = benchmark.report() {
+        state.benchmark = None;
+        state.benchmark_report = Some(report.clone());
+        log_event(state, P2pEvent::BenchmarkFinished(report));
+    }
+
+    Effect::send_all(commands)
+}
+
+pub fn handle_open_log_folder(state: &mut State) -> Task<Message> {
+    if let Some(dir) = &state.log_file_dir
+        && let Err(err) = open::that_detached(dir)
+    {
+        error!("Failed to open {}: {err}", dir.display());
+    }
+
+    Task::none()
+}
+
+pub fn handle_toggle_export_records(state: &mut State, enabled: bool) -> Task<Message> {
+    state.export_records = enabled;
+
+    Task::none()
+}
+
+pub fn handle_export_log(_: &mut State) -> Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Export event log")
+        .add_filter("JSON", &["json"])
+        .add_filter("CSV", &["csv"])
+        .set_file_name("event-log.json")
+        .save_file();
+
+    Task::perform(dialog, |file| {
+        Message::ExportDestinationPicked(file.map(|file| file.path().to_owned()))
+    })
+}
+
+pub fn handle_export_destination_picked(state: &mut State, path: Option<PathBuf>) -> Task<Message> {
+    let Some(path) = path else {
+        return Task::none();
+    };
+
+    let now = (Instant::now(), SystemTime::now());
+    let export = Export {
+        events: state.event_log.iter().map(ExportedEvent::new).collect(),
+        records: if state.export_records {
+            state
+                .records
+                .iter()
+                .map(|(label, entry)| ExportedRecord::new(label, &entry.key, entry, now))
+                .collect()
+        } else {
+            Vec::new()
+        },
+    };
+    let contents = export.render(ExportFormat::from_path(&path));
+
+    Task::perform(
+        async move {
+            tokio::fs::write(&path, contents)
+                .await
+                .map_err(|err| P2pError::ExportFailed {
+                    path,
+                    reason: err.to_string(),
+                })
+        },
+        Message::ExportFinished,
+    )
+}
+
+pub fn handle_export_finished(state: &mut State, result: Result<(), P2pError>) -> Task<Message> {
+    if let Err(err) = result {
+        log_event(state, P2pEvent::Error(None, err));
+    }
+
+    Task::none()
+}
+
+pub fn handle_export_records(_: &mut State) -> Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Export records")
+        .add_filter("JSON", &["json"])
+        .add_filter("CSV", &["csv"])
+        .set_file_name("records.json")
+        .save_file();
+
+    Task::perform(dialog, |file| {
+        Message::RecordsExportPicked(file.map(|file| file.path().to_owned()))
+    })
+}
+
+pub fn handle_records_export_picked(state: &mut State, path: Option<PathBuf>) -> Task<Message> {
+    let Some(path) = path else {
+        return Task::none();
+    };
+
+    let now = (Instant::now(), SystemTime::now());
+    let records: Vec<_> = state
+        .records
+        .iter()
+        .map(|(label, entry)| ExportedRecord::new(label, &entry.key, entry, now))
+        .collect();
+    let contents = export::render_records(&records, ExportFormat::from_path(&path));
+
+    Task::perform(
+        async move {
+            tokio::fs::write(&path, contents)
+                .await
+                .map_err(|err| P2pError::ExportFailed {
+                    path,
+                    reason: err.to_string(),
+                })
+        },
+        Message::ExportFinished,
+    )
+}
+
+pub fn handle_import_records(_: &mut State) -> Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Import records")
+        .add_filter("JSON", &["json"])
+        .add_filter("CSV", &["csv"])
+        .pick_file();
+
+    Task::perform(dialog, |file| {
+        Message::ImportFilePicked(file.map(|file| file.path().to_owned()))
+    })
+}
+
+pub fn handle_import_file_picked(_: &mut State, path: Option<PathBuf>) -> Task<Message> {
+    let Some(path) = path else {
+        return Task::none();
+    };
+
+    Task::perform(
+        async move {
+            let bytes = tokio::fs::read(&path).await.map_err(|err| err.to_string());
+
+            bytes
+                .and_then(|bytes| export::parse_records(&bytes, ExportFormat::from_path(&path)))
+                .map_err(|reason| P2pError::ImportFailed { path, reason })
+        },
+        Message::RecordsImported,
+    )
+}
+
+/// Queues the records of an import, joining one that's still running.
+pub fn handle_records_imported(
+    state: &mut State,
+    result: Result<ImportedRecords, P2pError>,
+) -> Effect {
+    let imported = match result {
+        Ok(imported) => imported,
+        Err(err) => {
+            log_event(state, P2pEvent::Error(None, err));
+            return Effect::none();
+        }
+    };
+
+    if encrypt_value(state, Vec::new()).is_none() {
+        show_key_not_ready(state);
+        return Effect::none();
+    }
+
+    let queued: Vec<_> = imported
+        .records
+        .into_iter()
+        .map(|record| {
+            let key = record.record_key(&state.config.key_namespace);
+            let label = record.label.clone().unwrap_or_else(|| record.key.clone());
+
+            if label != record.key {
+                state.key_labels.insert(key.clone(), label.clone());
+            }
+
+            ImportedRecord {
+                label,
+                key,
+                value: encrypt_value(state, record.value_bytes()).expect("the key is ready"),
+            }
+        })
+        .collect();
+
+    let import = match state.record_import.take() {
+        Some(import) if !import.is_finished() => import,
+        _ => RecordImport::default(),
+    };
+    let import = state.record_import.insert(import);
+    import.total += queued.len();
+    import.queued.extend(queued);
+    import.skipped.extend(imported.skipped);
+
+    handle_continue_import(state)
+}
+
+/// Tops the imported puts in flight back up to [`IMPORT_CONCURRENCY`].
+pub fn handle_continue_import(state: &mut State) -> Effect {
+    let Some(import) = &mut state.record_import else {
+        return Effect::none();
+    };
+
+    let free = IMPORT_CONCURRENCY.saturating_sub(import.in_flight.len());
+    let next: Vec<_> = import.queued.drain(..free.min(import.queued.len())).collect();
+    let mut started = Vec::with_capacity(next.len());
+
+    let commands: Vec<_> = next
+        .into_iter()
+        .map(|record| {
+            let request_id = track_query(state, QueryKind::PutRecord, &record.label);
+            started.push((request_id, record.label));
+
+            P2pCommand::PutRecord(
+                request_id,
+                record.key,
+                record.value,
+                state.config.record_ttl(),
+                state.config.quorum(),
+            )
+        })
+        .collect();
+
+    if let Some(import) = &mut state.record_import {
+        import.in_flight.extend(started);
+    }
+
+    Effect::send_all(commands)
+}
+
+pub fn handle_dismiss_import(state: &mut State) -> Task<Message> {
+    state.record_import = None;
+
+    Task::none()
+}
+
+/// Counts an imported put as done, returning whether `request_id` was one.
+fn finish_imported_put(state: &mut State, request_id: RequestId, failure: Option<String>) -> bool {
+    let Some(import) = &mut state.record_import else {
+        return false;
+    };
+    let Some(index) = import.in_flight.iter().position(|(id, _)| *id == request_id) else {
+        return false;
+    };
+
+    let (_, label) = import.in_flight.remove(index);
+    import.done += 1;
+
+    if let Some(reason) = failure {
+        import.failures.push(format!("{label}: {reason}"));
+    }
+
+    true
+}
+
+pub fn handle_networking_stopped(state: &mut State, reason: String) -> Task<Message> {
+    state.networking_stopped = Some(reason);
+
+    Task::none()
+}
+
+/// Forgets everything that belonged to the previous swarm. Records and provided keys come back
+/// through the new swarm's store events.
+pub fn handle_networking_restarted(state: &mut State) {
+    state.running_config = state.config.clone();
+    state.networking_stopped = None;
+    state.local_peer_id = None;
+    state.listen_addrs.clear();
+    state.peers.clear();
+    state.connected_peers.clear();
+    state.blocked_peers.clear();
+    state.routing_table.clear();
+    state.changed_bucket = None;
+    state.records.clear();
+    state.record_import = None;
+    state.benchmark = None;
+    state.query_latencies = QueryLatencies::default();
+    state.bandwidth = Bandwidth::default();
+    state.record_lookup = None;
+    state.provided_keys.clear();
+    state.pending_inbound.clear();
+    state.subscribed_topics.clear();
+    state.direct_target = None;
+    state.active_circuits = 0;
+    state.nat_status = NatStatus::default();
+    state.metrics_address = None;
+    state.kad_mode = state.config.kad_mode;
+    state.query_timeout = state.config.query_timeout();
+
+    let queued = state.queued_queries.drain(..).map(|(query, _)| query);
+
+    for query in state.pending_queries.drain(..).chain(queued).collect::<Vec<_>>() {
+        set_query_status(state, query.request_id, QueryStatus::Cancelled);
+    }
+
+    for transfer in &mut state.file_transfers {
+        if transfer.status == TransferStatus::Running {
+            transfer.status = TransferStatus::Failed;
+        }
+    }
+}
+
+pub fn handle_settings_changed(state: &mut State, field: SettingsField) -> Task<Message> {
+    let provided = match field {
+        SettingsField::KeyNamespace(_) => mem::take(&mut state.provided_keys)
+            .iter()
+            .map(|key| resolve_key(state, key))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let result = state.config.apply(&field);
+    let applied = result.is_ok();
+    state.settings_form.edit(&field, result);
+
+    match field {
+        SettingsField::KeyNamespace(_) => relabel_keys(state, provided),
+        _ if !applied => return Task::none(),
+        SettingsField::LogCapacity(_) => state.event_log.set_capacity(state.config.log_capacity),
+        _ => {}
+    }
+
+    config_changed(state)
+}
+
+/// Zooms the interface in or out by `steps` of [`config::UI_SCALE_STEP`].
+pub fn handle_step_ui_scale(state: &mut State, steps: i32) -> Task<Message> {
+    let step = steps.saturating_mul(config::UI_SCALE_STEP as i32);
+    let scale = state.config.ui_scale.saturating_add_signed(step);
+
+    handle_settings_changed(state, SettingsField::UiScale(scale))
+}
+
+pub fn handle_window_moved(state: &mut State, position: Point) -> Task<Message> {
+    let scale = ui_scale(state);
+    let window = state.config.window.get_or_insert_default();
+
+    // A maximized window sits in the corner; keep where it goes back to
+    if window.maximized {
+        return Task::none();
+    }
+
+    let scaled = |at: f32| (at * scale).round() as i32;
+    window.position = Some((scaled(position.x), scaled(position.y)));
+
+    config_changed(state)
+}
+
+/// Maximizing only shows as a resize, so asks whether that's what it was.
+pub fn handle_window_resized(_: &mut State, id: window::Id, size: Size) -> Task<Message> {
+    window::get_maximized(id).map(move |maximized| Message::WindowMaximizedChecked(size, maximized))
+}
+
+pub fn handle_window_maximized_checked(
+    state: &mut State,
+    size: Size,
+    maximized: bool,
+) -> Task<Message> {
+    let scale = ui_scale(state);
+    let window = state.config.window.get_or_insert_default();
+    window.maximized = maximized;
+
+    // Minimizing shrinks some windows to nothing, and maximizing keeps the size to go back to
+    if !maximized && size.width > 0.0 && size.height > 0.0 {
+        window.width = (size.width * scale).round() as u32;
+        window.height = (size.height * scale).round() as u32;
+    }
+
+    config_changed(state)
+}
+
+/// Saves the config once `change` turns out to be the last one for a while.
+pub fn handle_config_settled(state: &mut State, change: u64) -> Task<Message> {
+    if change != state.config_changes {
+        return Task::none();
+    }
+
+    save_config(state);
+
+    // Typing a passphrase would otherwise derive a key for every prefix of it
+    derive_value_cipher(state)
+}
+
+fn config_changed(state: &mut State) -> Task<Message> {
+    state.config_changes += 1;
+    let change = state.config_changes;
+
+    Task::perform(
+        async { tokio::time::sleep(CONFIG_SAVE_DELAY).await },
+        move |()| Message::ConfigSettled(change),
+    )
+}
+
+/// Window events come in the app's own pixels, which the UI scale makes larger than the OS's.
+fn ui_scale(state: &State) -> f32 {
+    state.config.ui_scale as f32 / 100.0
+}
+
+pub fn handle_detect_system_theme(_: &mut State) -> Task<Message> {
+    // Asking can take a D-Bus round trip, so it stays off the UI thread
+    Task::perform(tokio::task::spawn_blocking(system_theme), |theme| {
+        Message::SystemThemeDetected(theme.ok().flatten())
+    })
+}
+
+/// Follows the OS theme, keeping the last one known while it can't be found out.
+pub fn handle_system_theme_detected(state: &mut State, theme: Option<Theme>) -> Task<Message> {
+    if let Some(theme) = theme
+        && theme != state.system_theme
+    {
+        info!("System theme changed to {theme}");
+        state.system_theme = theme;
+    }
+
+    Task::none()
+}
+
+pub fn save_config(state: &mut State) {
+    if let Some(path) = &state.config_path {
+        state.settings_form.save_error = match config::save(path, &state.config) {
+            Ok(()) => None,
+            Err(err) => {
+                error!("Failed to save settings to {}: {err}", path.display());
+                Some(err.to_string())
+            }
+        };
+    }
+}
+
+pub fn handle_log_search_changed(state: &mut State, data: String) -> Task<Message> {
+    state.log_filter.search = data;
+
+    Task::none()
+}
+
+pub fn handle_tab_selected(state: &mut State, tab: Tab) -> Task<Message> {
+    state.active_tab = tab;
+
+    Task::none()
+}
+
+pub fn handle_chat_topic_changed(state: &mut State, data: String) -> Task<Message> {
+    state.chat_topic = data;
+
+    Task::none()
+}
+
+pub fn handle_chat_input_changed(state: &mut State, data: String) -> Task<Message> {
+    state.chat_input = data;
+
+    Task::none()
+}
+
+pub fn handle_subscribe_topic(_: &mut State, topic: String) -> Effect {
+    let cmd = P2pCommand::SubscribeTopic(topic);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_unsubscribe_topic(_: &mut State, topic: String) -> Effect {
+    let cmd = P2pCommand::UnsubscribeTopic(topic);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_publish_message(state: &mut State, topic: String, text: String) -> Effect {
+    state.chat_input = "".to_owned();
+
+    let cmd = P2pCommand::PublishMessage(topic, text.into_bytes());
+
+    Effect::send(cmd)
+}
+
+pub fn handle_compose_direct(state: &mut State, peer_id: PeerId) -> Task<Message> {
+    if state.direct_target != Some(peer_id) {
+        state.direct_input = "".to_owned();
+    }
+    state.direct_target = Some(peer_id);
+
+    Task::none()
+}
+
+pub fn handle_direct_input_changed(state: &mut State, data: String) -> Task<Message> {
+    state.direct_input = data;
+
+    Task::none()
+}
+
+pub fn handle_send_direct(state: &mut State, peer_id: PeerId, text: String) -> Effect {
+    state.direct_target = None;
+    state.direct_input = "".to_owned();
+
+    let cmd = P2pCommand::SendDirect(peer_id, text);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_cancel_direct(state: &mut State) -> Task<Message> {
+    state.direct_target = None;
+    state.direct_input = "".to_owned();
+
+    Task::none()
+}
+
+pub fn handle_share_file(_: &mut State) -> Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Share file")
+        .pick_file();
+
+    Task::perform(dialog, |file| {
+        Message::FileToSharePicked(file.map(|file| file.path().to_owned()))
+    })
+}
+
+pub fn handle_file_to_share_picked(state: &mut State, path: Option<PathBuf>) -> Effect {
+    let Some(path) = path else {
+        return Effect::none();
+    };
+    let Some(key) = path.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+        return Effect::none();
+    };
+
+    if !state.provided_keys.contains(&key) {
+        state.provided_keys.push(key.clone());
+    }
+
+    let request_id = track_query(state, QueryKind::StartProviding, &key);
+    let cmd = P2pCommand::ShareFile(request_id, key, path);
+
+    send_query(state, cmd)
+}
+
+pub fn handle_fetch_file(_: &mut State, key: String) -> Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Save fetched file")
+        .set_file_name(&key)
+        .save_file();
+
+    Task::perform(dialog, move |file| {
+        Message::FetchDestinationPicked(key.clone(), file.map(|file| file.path().to_owned()))
+    })
+}
+
+pub fn handle_fetch_destination_picked(
+    state: &mut State,
+    key: String,
+    path: Option<PathBuf>,
+) -> Effect {
+    let Some(path) = path else {
+        return Effect::none();
+    };
+
+    if find_transfer(state, &key).is_some() {
+        return Effect::none();
+    }
+
+    state.file_transfers.push(FileTransfer {
+        key: key.clone(),
+        received: 0,
+        total: None,
+        status: TransferStatus::Running,
+    });
+
+    let cmd = P2pCommand::FetchFile(key, path);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_cancel_query(state: &mut State, request_id: RequestId) -> Effect {
+    if let Some(index) = state
+        .queued_queries
+        .iter()
+        .position(|(query, _)| query.request_id == request_id)
+    {
+        let (query, _) = state.queued_queries.remove(index);
+        unlist_failed_provide(state, &query, QueryStatus::Cancelled);
+        set_query_status(state, request_id, QueryStatus::Cancelled);
+        return Effect::none();
+    }
+
+    let cmd = P2pCommand::CancelQuery(request_id);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_tick(state: &mut State, now: Instant) -> Task<Message> {
+    let timeout = state.query_timeout;
+    let (timed_out, pending): (Vec<_>, Vec<_>) = state
+        .pending_queries
+        .drain(..)
+        .partition(|query| now.duration_since(query.started_at) >= timeout);
+
+    state.pending_queries = pending;
+
+    let grace = state.peer_grace;
+    state.peers.retain(|_, peer| {
+        peer.expired_at
+            .is_none_or(|expired_at| now.duration_since(expired_at) < grace)
+    });
+
+    let mut import_advanced = false;
+
+    for query in timed_out {
+        record_latency(state, &query, QueryStatus::TimedOut);
+        unlist_failed_provide(state, &query, QueryStatus::TimedOut);
+
+        if let Some(record) = state
+            .query_history
+            .iter_mut()
+            .find(|record| record.request_id == query.request_id)
+        {
+            record.status = QueryStatus::TimedOut;
+        }
+
+        let failure = Some("timed out".to_owned());
+        import_advanced |= finish_imported_put(state, query.request_id, failure);
+    }
+
+    if import_advanced {
+        Task::done(Message::ContinueImport)
+    } else {
+        Task::none()
+    }
+}
+
+/// Holds a query back while no peer is connected, where the DHT would only fail it.
+fn send_query(state: &mut State, cmd: P2pCommand) -> Effect {
+    if !state.connected_peers.is_empty() {
+        return Effect::send(cmd);
+    }
+
+    let Some(index) = state
+        .pending_queries
+        .iter()
+        .position(|query| Some(query.request_id) == cmd.request_id())
+    else {
+        return Effect::send(cmd);
+    };
+
+    let query = state.pending_queries.remove(index);
+    set_query_status(state, query.request_id, QueryStatus::Queued);
+    state.queued_queries.push((query, cmd));
+
+    Effect::none()
+}
+
+fn set_query_status(state: &mut State, request_id: RequestId, status: QueryStatus) {
+    if let Some(record) = state
+        .query_history
+        .iter_mut()
+        .find(|record| record.request_id == request_id)
+    {
+        record.status = status;
+    }
+}
+
+fn track_query(state: &mut State, kind: QueryKind, key: &str) -> RequestId {
+    let request_id = RequestId::next();
+
+    state.query_history.push(QueryRecord {
+        request_id,
+        kind,
+        key: key.to_owned(),
+        status: QueryStatus::Pending,
+    });
+    state.pending_queries.push(PendingQuery {
+        request_id,
+        kind,
+        key: key.to_owned(),
+        started_at: Instant::now(),
+    });
+
+    request_id
+}
+
+pub fn handle_key_text_changed(state: &mut State, data: String) -> Task<Message> {
+    state.current_key = data;
+
+    Task::none()
+}
+
+pub fn handle_clear_record_inputs(state: &mut State) -> Task<Message> {
+    state.current_key.clear();
+    state.value_editor = text_editor::Content::new();
+    state.value_bytes = None;
+
+    Task::none()
+}
+
+pub fn handle_attach_value_file(_: &mut State) -> Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new().set_title("Put a file's contents").pick_file();
+
+    Task::perform(dialog, |file| {
+        Message::ValueFilePicked(file.map(|file| file.path().to_owned()))
+    })
+}
+
+pub fn handle_value_file_picked(
+    _: &mut State,
+    path: Option<PathBuf>,
+    max_value_bytes: usize,
+) -> Task<Message> {
+    let Some(path) = path else {
+        return Task::none();
+    };
+
+    Task::perform(read_value_file(path, max_value_bytes), Message::ValueFileLoaded)
+}
+
+/// Reads a file to put as a value, turning it down without reading it when it's larger than
+/// a record can hold.
+async fn read_value_file(
+    path: PathBuf,
+    max_value_bytes: usize,
+) -> Result<(String, Vec<u8>), P2pError> {
+    let read = async {
+        let size = tokio::fs::metadata(&path).await.map_err(|err| err.to_string())?.len();
+        if size > max_value_bytes as u64 {
+            return Err(format!("it is {size} bytes, over the {max_value_bytes} a record can hold"));
+        }
+
+        tokio::fs::read(&path).await.map_err(|err| err.to_string())
+    };
+
+    match read.await {
+        Ok(bytes) => {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            Ok((name.into_owned(), bytes))
+        }
+        Err(reason) => Err(P2pError::ReadFailed { path, reason }),
+    }
+}
+
+pub fn handle_value_file_loaded(
+    state: &mut State,
+    result: Result<(String, Vec<u8>), P2pError>,
+) -> Task<Message> {
+    match result {
+        Ok((name, bytes)) => {
+            state.value_file_name = name;
+            state.value_bytes = Some(bytes);
+        }
+        Err(err) => log_event(state, P2pEvent::Error(None, err)),
+    }
+
+    Task::none()
+}
+
+pub fn handle_detach_value_file(state: &mut State) -> Task<Message> {
+    state.value_bytes = None;
+
+    Task::none()
+}
+
+pub fn handle_save_value(_: &mut State, value: Vec<u8>) -> Task<Message> {
+    let dialog = rfd::AsyncFileDialog::new().set_title("Save value").save_file();
+
+    Task::perform(dialog, move |file| {
+        Message::ValueDestinationPicked(value.clone(), file.map(|file| file.path().to_owned()))
+    })
+}
+
+pub fn handle_value_destination_picked(
+    _: &mut State,
+    value: Vec<u8>,
+    path: Option<PathBuf>,
+) -> Task<Message> {
+    let Some(path) = path else {
+        return Task::none();
+    };
+
+    Task::perform(
+        async move {
+            tokio::fs::write(&path, value)
+                .await
+                .map_err(|err| P2pError::ExportFailed {
+                    path,
+                    reason: err.to_string(),
+                })
+        },
+        Message::ExportFinished,
+    )
+}
+
+pub fn handle_recent_key_selected(state: &mut State, key: String) -> Task<Message> {
+    state.current_key = key;
+
+    handle_focus_input(state, InputField::Key)
+}
+
+pub fn handle_focus_input(state: &mut State, field: InputField) -> Task<Message> {
+    state.focused_input = Some(field);
+
+    text_input::focus(input_id(field))
+}
+
+/// Looks up which record input has focus after something that may have moved it.
+pub fn handle_find_focused_input(state: &mut State) -> Task<Message> {
+    // The lookup finds nothing when no input with an id has focus
+    state.focused_input = None;
+
+    operate(focusable::find_focused()).map(|id| {
+        let field = InputField::ALL
+            .into_iter()
+            .find(|field| widget::Id::from(input_id(*field)) == id);
+
+        Message::InputFocused(field)
+    })
+}
+
+/// Clears the focused input, or closes the help or else the event details when no input has
+/// focus.
+pub fn handle_escape_pressed(state: &mut State) -> Task<Message> {
+    match state.focused_input.take() {
+        Some(InputField::Key) => state.current_key.clear(),
+        Some(InputField::Ttl) => state.current_ttl.clear(),
+        None if state.show_help => state.show_help = false,
+        None => state.selected_event = None,
+    }
+
+    Task::none()
+}
+
+/// Runs `shortcut`, unless a focused input took a key the shortcut leaves to it.
+pub fn handle_shortcut(
+    state: &mut State,
+    shortcut: Shortcut,
+    status: event::Status,
+    max_value_bytes: usize,
+) -> Task<Message> {
+    if status == event::Status::Captured && !shortcut.overrides_inputs() {
+        return Task::none();
+    }
+
+    match shortcut {
+        Shortcut::FocusKey => handle_focus_input(state, InputField::Key),
+        Shortcut::Submit if state.active_tab == Tab::Dht => submit_record(state, max_value_bytes),
+        Shortcut::Submit => Task::none(),
+        Shortcut::ClearLog => handle_clear_log(state),
+        Shortcut::CopySelectedEvent if state.active_tab == Tab::Log => state
+            .selected_event
+            .and_then(|number| state.event_log.get(number))
+            .map_or_else(Task::none, |entry| clipboard::write(entry.to_string())),
+        Shortcut::CopySelectedEvent => Task::none(),
+        Shortcut::SelectTab(tab) => handle_tab_selected(state, tab),
+        Shortcut::ZoomIn => handle_step_ui_scale(state, 1),
+        Shortcut::ZoomOut => handle_step_ui_scale(state, -1),
+        Shortcut::ResetZoom => {
+            handle_settings_changed(state, SettingsField::UiScale(config::DEFAULT_UI_SCALE))
+        }
+        Shortcut::ToggleHelp => {
+            state.show_help = !state.show_help;
+
+            Task::none()
+        }
+    }
+}
+
+/// Does what the Put button would, or Get when there's no value, saying why when neither can.
+fn submit_record(state: &mut State, max_value_bytes: usize) -> Task<Message> {
+    let value = match &state.value_bytes {
+        Some(bytes) => bytes.clone(),
+        None => editor_text(&state.value_editor).into_bytes(),
+    };
+    let action = if value.is_empty() {
+        get_action(&state.current_key, &state.pending_queries)
+    } else {
+        put_action(
+            &state.current_key,
+            &value,
+            &state.current_ttl,
+            max_value_bytes,
+            &state.pending_queries,
+        )
+    };
+
+    match action {
+        Ok(message) => Task::done(message),
+        Err(reason) => {
+            show_toast(state, ToastKind::Info, reason.to_owned(), Instant::now());
+
+            Task::none()
+        }
+    }
+}
+
+pub fn handle_close_help(state: &mut State) -> Task<Message> {
+    state.show_help = false;
+
+    Task::none()
+}
+
+pub fn handle_value_edited(state: &mut State, action: text_editor::Action) -> Task<Message> {
+    state.value_editor.perform(action);
+
+    Task::none()
+}
+
+pub fn handle_ttl_text_changed(state: &mut State, data: String) -> Task<Message> {
+    state.current_ttl = data;
+
+    Task::none()
+}
+
+pub fn handle_put_record(
+    state: &mut State,
+    key: String,
+    value: Vec<u8>,
+    ttl: Option<Duration>,
+) -> Effect {
+    let Some(value) = encrypt_value(state, value) else {
+        show_key_not_ready(state);
+        return Effect::none();
+    };
+
+    state.value_editor = text_editor::Content::new();
+    state.value_bytes = None;
+
+    let request_id = track_query(state, QueryKind::PutRecord, &key);
+    let cmd = P2pCommand::PutRecord(
+        request_id,
+        resolve_key(state, &key),
+        value,
+        ttl.or(state.config.record_ttl()),
+        state.config.quorum(),
+    );
+
+    send_query(state, cmd)
+}
+
+pub fn handle_get_record(state: &mut State, key: String) -> Effect {
+    let request_id = track_query(state, QueryKind::GetRecord, &key);
+    let key = resolve_key(state, &key);
+    let cmd = P2pCommand::GetRecord(request_id, key, state.config.get_quorum);
+
+    send_query(state, cmd)
+}
+
+pub fn handle_get_providers(state: &mut State, key: String) -> Effect {
+    let request_id = track_query(state, QueryKind::GetProviders, &key);
+    let cmd = P2pCommand::GetProviders(request_id, resolve_key(state, &key));
+
+    send_query(state, cmd)
+}
+
+pub fn handle_get_closest_peers(state: &mut State, key: String) -> Effect {
+    let request_id = track_query(state, QueryKind::GetClosestPeers, &key);
+    let cmd = P2pCommand::GetClosestPeers(request_id, resolve_key(state, &key));
+
+    send_query(state, cmd)
+}
+
+pub fn handle_start_providing(state: &mut State, key: String) -> Effect {
+    if !state.provided_keys.contains(&key) {
+        state.provided_keys.push(key.clone());
+    }
+
+    let request_id = track_query(state, QueryKind::StartProviding, &key);
+    let cmd = P2pCommand::PutProvider(request_id, resolve_key(state, &key));
+
+    send_query(state, cmd)
+}
+
+pub fn handle_stop_providing(state: &mut State, key: String) -> Effect {
+    state.provided_keys.retain(|provided| provided != &key);
+
+    let cmd = P2pCommand::StopProviding(resolve_key(state, &key));
+
+    Effect::send(cmd)
+}
+
+pub fn handle_remove_record(state: &mut State, key: String) -> Effect {
+    let key = match state.records.get(&key) {
+        Some(entry) => entry.key.clone(),
+        None => resolve_key(state, &key),
+    };
+    let cmd = P2pCommand::RemoveRecord(key);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_refresh_records(_: &mut State) -> Effect {
+    Effect::send(P2pCommand::ListRecords)
+}
+
+pub fn handle_refresh_routing_table(_: &mut State) -> Effect {
+    Effect::send(P2pCommand::DumpRoutingTable)
+}
+
+pub fn handle_toggle_bucket(state: &mut State, index: u32) -> Task<Message> {
+    if !state.expanded_buckets.remove(&index) {
+        state.expanded_buckets.insert(index);
+    }
+
+    Task::none()
+}
+
+pub fn handle_dial_address_changed(state: &mut State, data: String) -> Task<Message> {
+    state.dial_address = data;
+
+    Task::none()
+}
+
+pub fn handle_dial(state: &mut State, address: String) -> Effect {
+    let Some(address) = parse_address(state, address) else {
+        return Effect::none();
+    };
+
+    state.dial_address = "".to_owned();
+
+    let cmd = P2pCommand::Dial(address);
+
+    Effect::send(cmd)
+}
+
+pub fn handle_listen_via_relay(state: &mut State, address: String) -> Effect {
+    let Some(address) = parse_address(state, address) else {
+        return Effect::none();
+    };
+
+    state.dial_address = "".to_owned();
+
+    let cmd = P2pCommand::ListenViaRelay(address);
+
+    Effect::send(cmd)
+}
+
+fn parse_address(state: &mut State, input: String) -> Option<Multiaddr> {
+    match input.trim().parse::<Multiaddr>() {
+        Ok(address) => Some(address),
+        Err(err) => {
+            log_event(
+                state,
+                P2pEvent::Error(
+                    None,
+                    P2pError::InvalidAddress {
+                        input,
+                        reason: err.to_string(),
+                    },
+                ),
+            );
+            None
+        }
+    }
+}
+
+pub fn handle_bootstrap(_: &mut State) -> Effect {
+    Effect::send(P2pCommand::Bootstrap)
+}
+
+pub fn handle_bootstrap_peer_input_changed(state: &mut State, data: String) -> Task<Message> {
+    state.settings_form.bootstrap_peer = Draft {
+        text: data,
+        error: None,
+    };
+
+    Task::none()
+}
+
+pub fn handle_reconnect_bootstrap(state: &mut State) -> Effect {
+    let cmd = P2pCommand::ConnectBootstrapPeers(state.config.bootstrap_peers.clone());
+
+    Effect::send(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::DEFAULT_LOG_CAPACITY;
+    use crate::test_util::temp_path;
+    use crate::p2p::{BucketInfo, ConnectionDirection, ConnectionTransport};
+    use crate::p2p::security::SecurityProtocol;
+    use libp2p::identity;
+    use crate::p2p::envelope::RecordSignature;
+
+    fn address(port: u16) -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap()
+    }
+
+    fn connected(peer_id: PeerId, relayed: bool, num_established: u32) -> P2pEvent {
+        P2pEvent::PeerConnected {
+            peer_id,
+            address: address(1),
+            relayed,
+            transport: ConnectionTransport::Tcp,
+            direction: ConnectionDirection::Outbound,
+            security: Some(SecurityProtocol::Noise),
+            num_established,
+        }
+    }
+
+    /// A state with a peer connected, so queries are sent rather than queued.
+    fn online() -> State {
+        State {
+            connected_peers: [PeerId::random()].into(),
+            ..State::default()
+        }
+    }
+
+    fn disconnected(peer_id: PeerId, num_established: u32) -> P2pEvent {
+        P2pEvent::PeerDisconnected {
+            peer_id,
+            num_established,
+        }
+    }
+
+    #[test]
+    fn concurrent_gets_are_resolved_independently() {
+        let mut state = online();
+
+        let _ = handle_get_record(&mut state, "first".to_owned());
+        let _ = handle_get_record(&mut state, "second".to_owned());
+        let first = state.query_history[0].request_id;
+        let second = state.query_history[1].request_id;
+
+        let found = |value: &[u8]| FoundValue {
+            peer: Some(PeerId::random()),
+            value: value.to_vec(),
+            signature: RecordSignature::default(),
+        };
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(Some(second), P2pOutboundEvent::RecordResolved {
+                key: kad::RecordKey::new(&"second"),
+                values: vec![found(b"2"), found(b"two"), found(b"2")],
+                agree: false,
+            }),
+        );
+
+        assert_eq!(state.query_history[0].status, QueryStatus::Pending);
+        assert_eq!(state.query_history[1].status, QueryStatus::Succeeded);
+
+        let lookup = state.record_lookup.as_ref().unwrap();
+        assert_eq!(lookup.key, "second");
+        assert!(!lookup.agree);
+        assert_eq!(p2p::value_variants(&lookup.values).len(), 2);
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(
+                Some(first),
+                P2pOutboundEvent::RecordNotFound(kad::RecordKey::new(&"first")),
+            ),
+        );
+
+        assert_eq!(state.query_history[0].status, QueryStatus::NotFound);
+        assert_eq!(state.query_history[1].status, QueryStatus::Succeeded);
+    }
+
+    #[test]
+    fn result_clears_pending_query() {
+        let mut state = online();
+
+        let _ = handle_put_record(&mut state, "key".to_owned(), b"value".to_vec(), None);
+        let request_id = state.pending_queries[0].request_id;
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(
+                Some(request_id),
+                P2pOutboundEvent::RecordPut(kad::RecordKey::new(&"key")),
+            ),
+        );
+
+        assert!(state.pending_queries.is_empty());
+        assert_eq!(state.query_history[0].status, QueryStatus::Succeeded);
+    }
+
+    #[test]
+    fn stale_pending_query_times_out() {
+        let mut state = State {
+            query_timeout: Duration::from_secs(5),
+            ..online()
+        };
+
+        let _ = handle_get_record(&mut state, "key".to_owned());
+        let started_at = state.pending_queries[0].started_at;
+
+        let _ = handle_tick(&mut state, started_at + Duration::from_secs(1));
+        assert_eq!(state.pending_queries.len(), 1);
+
+        let _ = handle_tick(&mut state, started_at + Duration::from_secs(5));
+        assert!(state.pending_queries.is_empty());
+        assert_eq!(state.query_history[0].status, QueryStatus::TimedOut);
+    }
+
+    #[test]
+    fn timed_out_queries_are_counted_apart_from_the_latencies() {
+        let mut state = State {
+            query_timeout: Duration::from_secs(5),
+            ..online()
+        };
+
+        let _ = handle_get_record(&mut state, "slow".to_owned());
+        let started_at = state.pending_queries[0].started_at;
+        let _ = handle_tick(&mut state, started_at + Duration::from_secs(5));
+        assert_eq!(state.query_latencies.timed_out(), 1);
+        assert_eq!(state.query_latencies.median(), None);
+
+        for _ in 0..QueryLatencies::WINDOW {
+            let _ = handle_get_record(&mut state, "key".to_owned());
+            let request_id = state.pending_queries[0].request_id;
+            let key = kad::RecordKey::new(&"key");
+            let _ = handle_p2p_event(
+                &mut state,
+                P2pEvent::Outbound(Some(request_id), P2pOutboundEvent::RecordNotFound(key)),
+            );
+        }
+
+        let _ = handle_get_closest_peers(&mut state, "key".to_owned());
+        let request_id = state.pending_queries[0].request_id;
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(
+                Some(request_id),
+                P2pOutboundEvent::ClosestPeers(kad::RecordKey::new(&"key"), Vec::new()),
+            ),
+        );
+
+        let latencies = &state.query_latencies;
+        assert_eq!(latencies.samples().len(), QueryLatencies::WINDOW);
+        assert_eq!(latencies.timed_out(), 0);
+        assert!(latencies.worst().unwrap() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn bandwidth_reports_update_the_status_without_being_logged() {
+        let mut state = State::default();
+        let report = P2pEvent::Bandwidth {
+            inbound_bps: 2048,
+            outbound_bps: 512,
+            total_in: 10_000,
+            total_out: 4_000,
+        };
+
+        let _ = handle_p2p_event(&mut state, report);
+
+        assert_eq!(state.bandwidth.inbound_bps, 2048);
+        assert_eq!(state.bandwidth.total_out, 4_000);
+        assert_eq!(state.event_log.len(), 0);
+    }
+
+    #[test]
+    fn expired_listen_addr_is_removed() {
+        let mut state = State::default();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::Bootstrapped(address(1)));
+        let _ = handle_p2p_event(&mut state, P2pEvent::Bootstrapped(address(2)));
+        let _ = handle_p2p_event(&mut state, P2pEvent::ListenAddrExpired(address(1)));
+
+        assert_eq!(state.listen_addrs, vec![address(2)]);
+    }
+
+    #[test]
+    fn ping_updates_peer_without_logging() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerUnresponsive(peer_id));
+        assert!(state.peers[&peer_id].unresponsive);
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::PingRtt(peer_id, Duration::from_millis(12)),
+        );
+
+        assert_eq!(state.peers[&peer_id].rtt, Some(Duration::from_millis(12)));
+        assert!(!state.peers[&peer_id].unresponsive);
+        assert_eq!(state.event_log.len(), 1);
+    }
+
+    #[test]
+    fn gossip_messages_go_to_chat_not_log() {
+        let mut state = State::default();
+        let source = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::TopicSubscribed("lobby".to_owned()));
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::GossipMessage {
+                topic: "lobby".to_owned(),
+                source: Some(source),
+                data: b"hi".to_vec(),
+            },
+        );
+
+        assert_eq!(state.subscribed_topics, vec!["lobby".to_owned()]);
+        assert_eq!(state.chat_messages.len(), 1);
+        assert_eq!(state.chat_messages[0].source, Some(source));
+        assert_eq!(state.chat_messages[0].text, "hi");
+        assert_eq!(state.event_log.len(), 1);
+    }
+
+    #[test]
+    fn record_table_follows_store_updates() {
+        let mut state = State::default();
+        let source = PeerId::random();
+        let key = kad::RecordKey::new(&"key");
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Inbound(P2pInboundEvent::RecordStored(
+                source,
+                key.clone(),
+                vec![0xff, 0x00],
+                None,
+                RecordSignature::default(),
+            )),
+        );
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::LocalRecords(vec![(key.clone(), b"updated".to_vec(), None)]),
+        );
+
+        assert_eq!(state.records["key"].value, b"updated");
+        assert_eq!(state.records["key"].publisher, Some(source));
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::Local(P2pLocalEvent::RecordRemoved(key)));
+
+        assert!(state.records.is_empty());
+    }
+
+    #[test]
+    fn signed_records_are_listed_unwrapped_with_their_signer() {
+        let mut state = State::default();
+        let keypair = identity::Keypair::generate_ed25519();
+        let key = kad::RecordKey::new(&"key");
+        let sealed = p2p::envelope::seal(&keypair, &key, b"hello").unwrap();
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::LocalRecords(vec![(key, sealed, None)]),
+        );
+
+        let entry = &state.records["key"];
+        assert_eq!(entry.value, b"hello");
+        assert_eq!(entry.signature.signer, Some(keypair.public().to_peer_id()));
+        assert!(entry.signature.verified);
+    }
+
+    #[test]
+    fn values_only_decrypt_with_the_same_passphrase() {
+        let passphrase_state = |passphrase: &str| {
+            let mut state = State::default();
+            state.config.value_passphrase = Some(passphrase.to_owned());
+            state.value_cipher = Some(Arc::new(ValueCipher::new(passphrase)));
+            state
+        };
+        let mut state = passphrase_state("correct horse");
+        let mut stranger = passphrase_state("battery staple");
+        let encrypted = encrypt_value(&state, b"secret".to_vec()).unwrap();
+
+        for state in [&mut state, &mut stranger] {
+            let _ = handle_p2p_event(
+                state,
+                P2pEvent::Inbound(P2pInboundEvent::RecordStored(
+                    PeerId::random(),
+                    kad::RecordKey::new(&"key"),
+                    encrypted.clone(),
+                    None,
+                    RecordSignature::default(),
+                )),
+            );
+        }
+
+        assert_eq!(state.records["key"].value, b"secret");
+        assert!(state.event_log.iter().any(|entry| entry.to_string().contains("secret")));
+        assert_eq!(stranger.records["key"].value, encrypted);
+        assert_eq!(p2p::format_record_value(&encrypted), "encrypted (wrong passphrase?)");
+    }
+
+    #[test]
+    fn imports_keep_a_few_puts_in_flight_and_collect_failures() {
+        let mut state = State::default();
+        let csv = "key,value\na,1\nb,2\nc,3\nd,4\ne,5\nf\n";
+        let imported = export::parse_records(csv.as_bytes(), ExportFormat::Csv).unwrap();
+        let _ = handle_records_imported(&mut state, Ok(imported));
+
+        let import = state.record_import.as_ref().unwrap();
+        assert_eq!((import.total, import.in_flight.len(), import.queued.len()), (5, 4, 1));
+        assert_eq!(import.skipped[0].line, 7);
+
+        let (first, _) = import.in_flight[0];
+        let (second, _) = import.in_flight[1];
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(Some(first), P2pOutboundEvent::RecordPut(kad::RecordKey::new(&"a"))),
+        );
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Error(Some(second), P2pError::QueryTimedOut {
+                key: kad::RecordKey::new(&"b"),
+            }),
+        );
+        let _ = handle_continue_import(&mut state);
+
+        let import = state.record_import.as_ref().unwrap();
+        assert_eq!((import.done, import.in_flight.len(), import.queued.len()), (2, 3, 0));
+        assert!(import.failures[0].starts_with("b: "));
+
+        let key = kad::RecordKey::new(&"c");
+
+        for (request_id, _) in import.in_flight.clone() {
+            let _ = handle_p2p_event(
+                &mut state,
+                P2pEvent::Outbound(Some(request_id), P2pOutboundEvent::RecordPut(key.clone())),
+            );
+        }
+
+        assert!(state.record_import.as_ref().unwrap().is_finished());
+        assert_eq!(state.record_import.as_ref().unwrap().done, 5);
+    }
+
+    #[test]
+    fn namespaced_keys_are_listed_as_typed_and_survive_a_namespace_change() {
+        let mut state = State::default();
+        let _ = handle_settings_changed(&mut state, SettingsField::KeyNamespace("app/".into()));
+        let key = resolve_key(&mut state, "greeting");
+
+        assert_eq!(key, kad::RecordKey::new(&"app/greeting"));
+        assert_eq!(resolve_key(&mut state, "0x6869"), kad::RecordKey::from(b"hi".to_vec()));
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::LocalRecords(vec![(key.clone(), b"hi".to_vec(), None)]),
+        );
+        state.provided_keys.push("greeting".to_owned());
+
+        assert_eq!(state.records["greeting"].key, key);
+
+        let _ = handle_settings_changed(&mut state, SettingsField::KeyNamespace("other".into()));
+
+        assert_eq!(state.records["app/greeting"].key, key);
+        assert_eq!(state.provided_keys, ["app/greeting"]);
+        assert_eq!(resolve_key(&mut state, "greeting"), kad::RecordKey::new(&"other/greeting"));
+    }
+
+    #[test]
+    fn hashed_records_are_listed_under_their_human_key() {
+        let mut state = State {
+            hash_keys: true,
+            ..State::default()
+        };
+
+        let key = resolve_key(&mut state, "greeting");
+        let _ = handle_toggle_hash_keys(&mut state, false);
+
+        assert_eq!(resolve_key(&mut state, "greeting"), key);
+        assert_eq!(resolve_key(&mut state, &p2p::format_key(&key)), key);
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::LocalRecords(vec![(key, b"hi".to_vec(), None)]),
+        );
+
+        assert!(state.records.contains_key("greeting"));
+    }
+
+    #[test]
+    fn log_filter_hides_categories_without_dropping_history() {
+        let mut state = State::default();
+
+        let _ = handle_p2p_event(&mut state, connected(PeerId::random(), false, 1));
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(None, P2pOutboundEvent::RecordPut(kad::RecordKey::new(&"key"))),
+        );
+        let _ = handle_toggle_log_category(&mut state, LogCategory::Peers);
+
+        let visible = |state: &State| {
+            state
+                .event_log
+                .iter()
+                .filter(|entry| state.log_filter.matches(&entry.event))
+                .count()
+        };
+
+        assert_eq!(visible(&state), 1);
+
+        let _ = handle_log_search_changed(&mut state, "nothing like this".to_owned());
+        assert_eq!(visible(&state), 0);
+
+        let _ = handle_log_search_changed(&mut state, "".to_owned());
+        let _ = handle_toggle_log_category(&mut state, LogCategory::Peers);
+        assert_eq!(visible(&state), 2);
+        assert_eq!(state.event_log.len(), 2);
+    }
+
+    #[test]
+    fn event_log_stays_bounded_under_load() {
+        let mut state = State::default();
+
+        // Alternating, so that no event repeats the one before and gets collapsed into it
+        for port in (0..100_000u32).map(|n| (n % 2) as u16) {
+            let _ = handle_p2p_event(&mut state, P2pEvent::Dialing(address(port)));
+        }
+
+        assert_eq!(state.event_log.len(), DEFAULT_LOG_CAPACITY);
+        assert_eq!(state.event_log.discarded(), 100_000 - DEFAULT_LOG_CAPACITY);
+
+        let started = Instant::now();
+        let now = SystemTime::now();
+        let _ = crate::widgets::event_log(
+            &state.event_log,
+            &state.value_views,
+            &state.log_filter,
+            false,
+            false,
+            None,
+            now,
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let _ = handle_settings_changed(&mut state, SettingsField::LogCapacity(100));
+        assert_eq!(state.event_log.len(), 100);
+    }
+
+    #[test]
+    fn clearing_log_keeps_peers() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+        let _ = handle_clear_log(&mut state);
+
+        assert!(state.event_log.is_empty());
+        assert!(state.peers.contains_key(&peer_id));
+    }
+
+    #[test]
+    fn jumping_to_latest_resumes_auto_scroll() {
+        let mut state = State {
+            log_paused: true,
+            ..State::default()
+        };
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::Dialing(address(1)));
+        assert!(state.log_paused);
+
+        let _ = handle_jump_to_latest(&mut state);
+        assert!(!state.log_paused);
+    }
+
+    #[test]
+    fn invalid_setting_keeps_draft_without_applying() {
+        let mut state = State::default();
+
+        let _ = handle_settings_changed(&mut state, SettingsField::PutQuorum("three".to_owned()));
+
+        assert_eq!(state.settings_form.put_quorum.text, "three");
+        assert!(state.settings_form.put_quorum.error.is_some());
+        assert_eq!(state.config.quorum(), kad::Quorum::One);
+
+        let _ = handle_settings_changed(&mut state, SettingsField::PutQuorum("3".to_owned()));
+
+        assert!(state.settings_form.put_quorum.error.is_none());
+        assert_eq!(state.config.quorum(), kad::Quorum::N(3.try_into().unwrap()));
+        assert!(!state.config.requires_restart(&state.running_config));
+    }
+
+    #[test]
+    fn restarting_networking_forgets_peers_and_cancels_queries() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, connected(peer_id, false, 1));
+        let _ = handle_get_record(&mut state, "key".to_owned());
+        let _ = handle_settings_changed(&mut state, SettingsField::KadMode(KadMode::Client));
+
+        assert!(state.config.requires_restart(&state.running_config));
+
+        handle_networking_restarted(&mut state);
+
+        assert!(state.peers.is_empty());
+        assert!(state.connected_peers.is_empty());
+        assert!(state.pending_queries.is_empty());
+        assert_eq!(state.query_history[0].status, QueryStatus::Cancelled);
+        assert!(!state.config.requires_restart(&state.running_config));
+    }
+
+    #[test]
+    fn routing_updates_highlight_bucket_without_collapsing_it() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_toggle_bucket(&mut state, 250);
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::RoutingUpdated {
+                peer_id,
+                bucket: 250,
+                is_new_peer: true,
+                evicted: None,
+            },
+        );
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::RoutingTable(vec![BucketInfo {
+                index: 250,
+                entries: vec![(peer_id, vec![address(1)], kad::NodeStatus::Connected)],
+            }]),
+        );
+
+        assert_eq!(state.changed_bucket, Some(250));
+        assert_eq!(state.routing_table[0].entries.len(), 1);
+        assert!(state.expanded_buckets.contains(&250));
+        assert_eq!(state.event_log.len(), 1);
+    }
+
+    #[test]
+    fn timed_out_closest_peers_keep_partial_result() {
+        let mut state = online();
+        let peers = vec![PeerId::random(), PeerId::random()];
+
+        let _ = handle_get_closest_peers(&mut state, "key".to_owned());
+        let request_id = state.query_history[0].request_id;
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Error(Some(request_id), P2pError::GetClosestPeersTimeout {
+                key: kad::RecordKey::new(&"key"),
+                peers: peers.clone(),
+            }),
+        );
+
+        let closest = state.closest_peers.as_ref().unwrap();
+        assert_eq!(closest.key, "key");
+        assert_eq!(closest.peers, peers);
+        assert!(!closest.complete);
+        assert_eq!(state.query_history[0].status, QueryStatus::TimedOut);
+        assert!(state.pending_queries.is_empty());
+    }
+
+    #[test]
+    fn selected_tab_survives_incoming_events() {
+        let mut state = State::default();
+
+        let _ = handle_tab_selected(&mut state, Tab::Log);
+        let _ = handle_p2p_event(&mut state, connected(PeerId::random(), false, 1));
+
+        assert_eq!(state.active_tab, Tab::Log);
+    }
+
+    #[test]
+    fn sending_direct_message_closes_composer() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_compose_direct(&mut state, peer_id);
+        let _ = handle_direct_input_changed(&mut state, "hello".to_owned());
+        let _ = handle_send_direct(&mut state, peer_id, "hello".to_owned());
+
+        assert_eq!(state.direct_target, None);
+        assert!(state.direct_input.is_empty());
+    }
+
+    #[test]
+    fn file_transfer_progress_and_failure_update_transfer() {
+        let mut state = State::default();
+        let path = PathBuf::from("notes.txt");
+
+        let _ = handle_fetch_destination_picked(&mut state, "notes.txt".to_owned(), Some(path));
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::FileTransferProgress {
+                key: "notes.txt".to_owned(),
+                received: 10,
+                total: 40,
+            },
+        );
+
+        assert_eq!(state.file_transfers[0].received, 10);
+        assert_eq!(state.file_transfers[0].total, Some(40));
+        assert!(state.event_log.is_empty());
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Error(None, P2pError::FileUnavailable {
+                key: "notes.txt".to_owned(),
+            }),
+        );
+
+        assert_eq!(state.file_transfers[0].status, TransferStatus::Failed);
+    }
+
+    #[test]
+    fn invalid_relay_address_is_logged() {
+        let mut state = State {
+            dial_address: "not an address".to_owned(),
+            ..State::default()
+        };
+
+        let _ = handle_listen_via_relay(&mut state, "not an address".to_owned());
+
+        assert_eq!(state.dial_address, "not an address");
+        assert!(matches!(
+            state.event_log.iter().collect::<Vec<_>>().as_slice(),
+            [LogEntry {
+                event: P2pEvent::Error(None, P2pError::InvalidAddress { .. }),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn mdns_switches_without_a_restart() {
+        let mut state = State::default();
+
+        let _ = handle_toggle_mdns(&mut state, false);
+
+        assert!(!state.config.mdns_enabled);
+        assert!(!state.config.requires_restart(&state.running_config));
+
+        let _ = handle_toggle_mdns(&mut state, true);
+        let unavailable = P2pError::MdnsUnavailable { reason: "no multicast".to_owned() };
+        let _ = handle_p2p_event(&mut state, P2pEvent::Error(None, unavailable));
+
+        assert!(!state.mdns_enabled);
+    }
+
+    #[test]
+    fn disabling_relay_server_resets_circuit_count() {
+        let mut state = State::default();
+        let circuit = || P2pEvent::RelayCircuitOpened {
+            src: PeerId::random(),
+            dst: PeerId::random(),
+        };
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::RelayServerToggled(true));
+        let _ = handle_p2p_event(&mut state, circuit());
+        let _ = handle_p2p_event(&mut state, circuit());
+
+        assert_eq!(state.active_circuits, 2);
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::RelayServerToggled(false));
+
+        assert!(!state.relay_server);
+        assert_eq!(state.active_circuits, 0);
+    }
+
+    #[test]
+    fn failed_hole_punch_keeps_peer_relayed() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, connected(peer_id, true, 1));
+        let _ = handle_p2p_event(&mut state, P2pEvent::HolePunch {
+            remote: peer_id,
+            success: false,
+        });
+
+        assert_eq!(state.peers[&peer_id].relayed, Some(true));
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::HolePunch {
+            remote: peer_id,
+            success: true,
+        });
+        let _ = handle_p2p_event(&mut state, connected(peer_id, true, 2));
+
+        assert_eq!(state.peers[&peer_id].relayed, Some(false));
+    }
+
+    #[test]
+    fn closing_last_connection_leaves_peer_discovered() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+        let _ = handle_p2p_event(&mut state, connected(peer_id, false, 1));
+        let _ = handle_p2p_event(&mut state, connected(peer_id, false, 2));
+        let _ = handle_p2p_event(&mut state, disconnected(peer_id, 1));
+
+        assert!(state.connected_peers.contains(&peer_id));
+
+        let _ = handle_p2p_event(&mut state, disconnected(peer_id, 0));
+
+        assert!(state.connected_peers.is_empty());
+        assert!(state.peers.contains_key(&peer_id));
+        assert_eq!(state.event_log.len(), 3);
+    }
+
+    #[test]
+    fn expired_peer_is_kept_for_grace_period() {
+        let mut state = State {
+            peer_grace: Duration::from_secs(30),
+            ..State::default()
+        };
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerExpired(peer_id, address(1)));
+
+        let expired_at = state.peers[&peer_id].expired_at.expect("peer should be expired");
+
+        let _ = handle_tick(&mut state, expired_at + Duration::from_secs(10));
+        assert!(state.peers.contains_key(&peer_id));
+
+        let _ = handle_tick(&mut state, expired_at + Duration::from_secs(30));
+        assert!(state.peers.is_empty());
+    }
+
+    #[test]
+    fn rediscovered_peer_is_no_longer_expired() {
+        let mut state = State {
+            peer_grace: Duration::from_secs(30),
+            ..State::default()
+        };
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+        let discovered_at = state.peers[&peer_id].discovered_at;
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerExpired(peer_id, address(1)));
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+
+        assert_eq!(state.peers[&peer_id].expired_at, None);
+        assert_eq!(state.peers[&peer_id].discovered_at, discovered_at);
+    }
+
+    #[test]
+    fn duplicate_discovery_counts_peer_once() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+
+        assert_eq!(state.peers.len(), 1);
+        assert_eq!(state.peers[&peer_id].addresses, vec![address(1)]);
+    }
+
+    #[test]
+    fn expiry_of_unknown_peer_is_ignored() {
+        let mut state = State::default();
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::PeerExpired(PeerId::random(), address(1)),
+        );
+
+        assert!(state.peers.is_empty());
+        assert_eq!(state.event_log.len(), 1);
+    }
+
+    #[test]
+    fn peer_with_multiple_addresses_expires_after_last_one() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(1)));
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerDiscovered(peer_id, address(2)));
+        assert_eq!(state.peers.len(), 1);
+        assert_eq!(state.peers[&peer_id].addresses.len(), 2);
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerExpired(peer_id, address(1)));
+        assert_eq!(state.peers[&peer_id].addresses, vec![address(2)]);
+
+        let _ = handle_p2p_event(&mut state, P2pEvent::PeerExpired(peer_id, address(2)));
+        assert!(state.peers.is_empty());
+    }
+
+    #[test]
+    fn put_clears_the_value_and_sends_the_record() {
+        let mut state = State {
+            current_key: "key".to_owned(),
+            value_editor: text_editor::Content::with_text("value"),
+            ..online()
+        };
+
+        let effect = handle_put_record(&mut state, "key".to_owned(), b"value".to_vec(), None);
+        let request_id = state.pending_queries[0].request_id;
+
+        assert_eq!(state.current_key, "key");
+        assert!(editor_text(&state.value_editor).is_empty());
+        assert!(matches!(
+            effect.commands.as_slice(),
+            [P2pCommand::PutRecord(id, key, value, None, kad::Quorum::One)]
+                if *id == request_id && *key == kad::RecordKey::new(&"key") && value == b"value"
+        ));
+    }
+
+    #[test]
+    fn get_sends_a_lookup_and_leaves_the_inputs_alone() {
+        let mut state = State {
+            current_key: "key".to_owned(),
+            value_editor: text_editor::Content::with_text("value"),
+            ..online()
+        };
+
+        let effect = handle_get_record(&mut state, "key".to_owned());
+        let request_id = state.pending_queries[0].request_id;
+
+        assert_eq!(state.current_key, "key");
+        assert_eq!(editor_text(&state.value_editor), "value");
+        assert!(state.event_log.is_empty());
+        assert!(matches!(
+            effect.commands.as_slice(),
+            [P2pCommand::GetRecord(id, key, _)]
+                if *id == request_id && *key == kad::RecordKey::new(&"key")
+        ));
+    }
+
+    #[test]
+    fn handlers_without_node_work_send_nothing() {
+        let mut state = State::default();
+
+        let effect = handle_dial(&mut state, "not an address".to_owned());
+        assert!(effect.commands.is_empty());
+
+        let effect = handle_continue_import(&mut state);
+        assert!(effect.commands.is_empty());
+    }
+
+    #[test]
+    fn connections_update_the_peer_sets_and_the_log() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, connected(peer_id, false, 1));
+
+        assert!(state.connected_peers.contains(&peer_id));
+        assert_eq!(state.peers[&peer_id].relayed, Some(false));
+        assert_eq!(state.event_log.len(), 1);
+
+        let _ = handle_p2p_event(&mut state, disconnected(peer_id, 0));
+
+        assert!(state.connected_peers.is_empty());
+        assert!(state.peers.is_empty());
+        assert_eq!(state.event_log.len(), 2);
+    }
+
+    #[test]
+    fn repeated_and_unknown_connection_events_keep_counts_straight() {
+        let mut state = State::default();
+        let peer_id = PeerId::random();
+
+        let _ = handle_p2p_event(&mut state, connected(peer_id, false, 1));
+        let _ = handle_p2p_event(&mut state, connected(peer_id, false, 2));
+        let _ = handle_p2p_event(&mut state, connected(peer_id, false, 1));
+
+        assert_eq!(state.connected_peers.len(), 1);
+        assert_eq!(state.peers.len(), 1);
+
+        let _ = handle_p2p_event(&mut state, disconnected(PeerId::random(), 0));
+        let _ = handle_p2p_event(&mut state, disconnected(peer_id, 1));
+
+        assert_eq!(state.connected_peers.len(), 1);
+        assert_eq!(state.peers.len(), 1);
+    }
+
+    #[test]
+    fn queries_wait_for_the_first_peer() {
+        let mut state = State::default();
+
+        let effect = handle_get_record(&mut state, "key".to_owned());
+        let request_id = state.query_history[0].request_id;
+
+        assert!(effect.commands.is_empty());
+        assert!(state.pending_queries.is_empty());
+        assert_eq!(state.queued_queries.len(), 1);
+        assert_eq!(state.query_history[0].status, QueryStatus::Queued);
+
+        let effect = handle_p2p_event(&mut state, connected(PeerId::random(), false, 1));
+
+        assert!(matches!(
+            effect.commands.as_slice(),
+            [P2pCommand::GetRecord(id, ..)] if *id == request_id
+        ));
+        assert!(state.queued_queries.is_empty());
+        assert_eq!(state.pending_queries[0].request_id, request_id);
+        assert_eq!(state.query_history[0].status, QueryStatus::Pending);
+    }
+
+    #[test]
+    fn cancelling_a_queued_query_sends_nothing() {
+        let mut state = State::default();
+
+        let _ = handle_put_record(&mut state, "key".to_owned(), b"value".to_vec(), None);
+        let request_id = state.query_history[0].request_id;
+        let effect = handle_cancel_query(&mut state, request_id);
+
+        assert!(effect.commands.is_empty());
+        assert!(state.queued_queries.is_empty());
+        assert_eq!(state.query_history[0].status, QueryStatus::Cancelled);
+
+        let effect = handle_p2p_event(&mut state, connected(PeerId::random(), false, 1));
+
+        assert!(effect.commands.is_empty());
+    }
+
+    #[test]
+    fn escape_clears_only_the_focused_input() {
+        let mut state = State {
+            current_key: "key".to_owned(),
+            current_ttl: "60".to_owned(),
+            focused_input: Some(InputField::Ttl),
+            selected_event: Some(3),
+            ..State::default()
+        };
+
+        let _ = handle_escape_pressed(&mut state);
+
+        assert_eq!(state.current_key, "key");
+        assert!(state.current_ttl.is_empty());
+        assert_eq!(state.focused_input, None);
+        assert_eq!(state.selected_event, Some(3));
+
+        state.current_ttl = "60".to_owned();
+        let _ = handle_escape_pressed(&mut state);
+
+        assert_eq!(state.current_ttl, "60");
+        assert_eq!(state.selected_event, None);
+    }
+
+    #[test]
+    fn identical_toasts_coalesce_and_expire() {
+        let mut state = State::default();
+        let key = kad::RecordKey::new(&"key");
+        let put = || P2pEvent::Outbound(None, P2pOutboundEvent::RecordPut(key.clone()));
+
+        for _ in 0..3 {
+            let _ = handle_p2p_event(&mut state, put());
+        }
+        let _ = handle_p2p_event(&mut state, P2pEvent::Error(None, P2pError::NodeBusy));
+
+        assert_eq!(state.toasts.len(), 2);
+        assert_eq!((state.toasts[0].kind, state.toasts[0].count), (ToastKind::Success, 3));
+        assert_eq!(state.toasts[1].kind, ToastKind::Error);
+
+        let shown = state.toasts[0].created;
+        let _ = handle_toast_tick(&mut state, shown + TOAST_LIFETIME / 2);
+        assert_eq!(state.toasts.len(), 2);
+
+        let error = state.toasts[1].id;
+        let _ = handle_dismiss_toast(&mut state, error);
+        let _ = handle_toast_tick(&mut state, shown + TOAST_LIFETIME);
+        assert!(state.toasts.is_empty());
+    }
+
+    #[test]
+    fn the_last_known_system_theme_is_kept_while_detection_fails() {
+        let mut state = State {
+            system_theme: Theme::Dark,
+            ..State::default()
+        };
+
+        let _ = handle_system_theme_detected(&mut state, None);
+        assert_eq!(state.system_theme, Theme::Dark);
+
+        let _ = handle_system_theme_detected(&mut state, Some(Theme::Light));
+        assert_eq!(state.system_theme, Theme::Light);
+    }
+
+    #[test]
+    fn zooming_stays_within_the_scale_limits() {
+        let mut state = State::default();
+
+        let _ = handle_step_ui_scale(&mut state, 2);
+        assert_eq!(state.config.ui_scale, config::DEFAULT_UI_SCALE + 2 * config::UI_SCALE_STEP);
+
+        let _ = handle_step_ui_scale(&mut state, -100);
+        assert_eq!(state.config.ui_scale, config::MIN_UI_SCALE);
+
+        let _ = handle_step_ui_scale(&mut state, 100);
+        assert_eq!(state.config.ui_scale, config::MAX_UI_SCALE);
+    }
+
+    #[test]
+    fn maximizing_keeps_the_size_and_place_to_go_back_to() {
+        let mut state = State::default();
+
+        let _ = handle_window_maximized_checked(&mut state, Size::new(900.0, 700.0), false);
+        let _ = handle_window_moved(&mut state, Point::new(120.0, 80.0));
+        let _ = handle_window_maximized_checked(&mut state, Size::new(1920.0, 1080.0), true);
+        let _ = handle_window_moved(&mut state, Point::ORIGIN);
+
+        assert_eq!(state.config.window, Some(config::WindowGeometry {
+            width: 900,
+            height: 700,
+            position: Some((120, 80)),
+            maximized: true,
+        }));
+        assert_eq!(state.config_changes, 3);
+    }
+
+    #[test]
+    fn plain_shortcuts_leave_focused_inputs_alone() {
+        let mut state = State::default();
+        let (captured, ignored) = (event::Status::Captured, event::Status::Ignored);
+
+        let _ = handle_shortcut(&mut state, Shortcut::ToggleHelp, captured, 1024);
+        assert!(!state.show_help);
+        let _ = handle_shortcut(&mut state, Shortcut::ToggleHelp, ignored, 1024);
+        assert!(state.show_help);
+
+        state.current_key = "key".to_owned();
+        let _ = handle_shortcut(&mut state, Shortcut::FocusKey, captured, 1024);
+        assert_eq!(state.focused_input, Some(InputField::Key));
+        let _ = handle_shortcut(&mut state, Shortcut::SelectTab(Tab::Log), captured, 1024);
+        assert_eq!(state.active_tab, Tab::Log);
+
+        // Escape clears what's being typed before it closes the help
+        let _ = handle_escape_pressed(&mut state);
+        assert!(state.current_key.is_empty());
+        assert!(state.show_help);
+        let _ = handle_escape_pressed(&mut state);
+        assert!(!state.show_help);
+    }
+
+    #[test]
+    fn submitting_by_shortcut_says_why_it_cannot() {
+        let mut state = State::default();
+
+        let _ = handle_shortcut(&mut state, Shortcut::Submit, event::Status::Ignored, 1024);
+
+        assert_eq!(state.toasts.len(), 1);
+        assert_eq!(state.toasts[0].text, "Enter a key to get");
+    }
+
+    #[test]
+    fn settings_are_saved_once_they_stop_changing() {
+        let path = temp_path("settings").join("config.toml");
+        let mut state = State {
+            config_path: Some(path.clone()),
+            ..State::default()
+        };
+
+        let _ = handle_settings_changed(&mut state, SettingsField::PutQuorum("1".to_owned()));
+        let _ = handle_settings_changed(&mut state, SettingsField::PutQuorum("12".to_owned()));
+        let _ = handle_config_settled(&mut state, 1);
+        assert!(!path.exists());
+
+        let _ = handle_config_settled(&mut state, 2);
+        assert_eq!(config::load(&path).unwrap().put_quorum.get(), 12);
+        assert!(!path.with_extension("toml.tmp").exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keys_whose_provide_failed_are_not_listed_as_provided() {
+        let mut state = online();
+
+        let _ = handle_start_providing(&mut state, "kept".to_owned());
+        let kept = state.pending_queries[0].request_id;
+        let _ = handle_start_providing(&mut state, "lost".to_owned());
+        let lost = state.pending_queries[1].request_id;
+
+        let put = P2pOutboundEvent::ProviderPut(kad::RecordKey::new(&"kept"));
+        let _ = handle_p2p_event(&mut state, P2pEvent::Outbound(Some(kept), put));
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Error(Some(lost), P2pError::StartProvidingTimeout {
+                key: kad::RecordKey::new(&"lost"),
+            }),
+        );
+
+        assert_eq!(state.provided_keys, ["kept"]);
+    }
+
+    #[test]
+    fn keys_whose_provide_timed_out_are_not_listed_as_provided() {
+        let mut state = State {
+            query_timeout: Duration::from_secs(5),
+            ..online()
+        };
+
+        let _ = handle_start_providing(&mut state, "slow".to_owned());
+        let started_at = state.pending_queries[0].started_at;
+        let _ = handle_tick(&mut state, started_at + Duration::from_secs(5));
+
+        assert_eq!(state.query_history[0].status, QueryStatus::TimedOut);
+        assert!(state.provided_keys.is_empty());
+    }
+
+    #[test]
+    fn keys_whose_provide_was_cancelled_are_not_listed_as_provided() {
+        let mut state = online();
+
+        let _ = handle_start_providing(&mut state, "sent".to_owned());
+        let sent = state.pending_queries[0].request_id;
+        let effect = handle_cancel_query(&mut state, sent);
+        assert!(matches!(
+            effect.commands.as_slice(),
+            [P2pCommand::CancelQuery(id)] if *id == sent
+        ));
+
+        let cancelled = P2pOutboundEvent::QueryCancelled(kad::RecordKey::new(&"sent"));
+        let _ = handle_p2p_event(&mut state, P2pEvent::Outbound(Some(sent), cancelled));
+        assert!(state.provided_keys.is_empty());
+
+        let mut state = State::default();
+
+        let _ = handle_start_providing(&mut state, "queued".to_owned());
+        let queued = state.query_history[0].request_id;
+        let _ = handle_cancel_query(&mut state, queued);
+
+        assert!(state.provided_keys.is_empty());
+    }
+
+    #[test]
+    fn missing_records_are_not_reported_as_failures() {
+        let mut state = online();
+
+        let _ = handle_get_record(&mut state, "missing".to_owned());
+        let request_id = state.pending_queries[0].request_id;
+        let missing = P2pOutboundEvent::RecordNotFound(kad::RecordKey::new(&"missing"));
+        let missing = P2pEvent::Outbound(Some(request_id), missing);
+
+        assert!(!missing.to_string().contains("Something went wrong"));
+        let _ = handle_p2p_event(&mut state, missing);
+
+        assert_eq!(state.query_history[0].status, QueryStatus::NotFound);
+        assert_eq!(state.toasts[0].kind, ToastKind::Info);
+    }
+
+    #[test]
+    fn only_successful_queries_are_remembered() {
+        let mut state = online();
+
+        let _ = handle_get_record(&mut state, "missing".to_owned());
+        let missing = state.pending_queries[0].request_id;
+        let _ = handle_get_record(&mut state, "found".to_owned());
+        let found = state.pending_queries[1].request_id;
+
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(
+                Some(missing),
+                P2pOutboundEvent::RecordNotFound(kad::RecordKey::new(&"missing")),
+            ),
+        );
+        let _ = handle_p2p_event(
+            &mut state,
+            P2pEvent::Outbound(Some(found), P2pOutboundEvent::RecordResolved {
+                key: kad::RecordKey::new(&"found"),
+                values: Vec::new(),
+                agree: true,
+            }),
+        );
+
+        assert_eq!(state.config.recent_keys, ["found"]);
+    }
+
+    #[tokio::test]
+    async fn files_over_the_record_size_are_not_read() {
+        let path = temp_path("value.bin");
+        let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        tokio::fs::write(&path, [0xff; 8]).await.unwrap();
+
+        let loaded = read_value_file(path.clone(), 8).await.unwrap();
+        let rejected = read_value_file(path.clone(), 7).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(loaded, (name, vec![0xff; 8]));
+        assert!(matches!(
+            rejected,
+            Err(P2pError::ReadFailed { reason, .. }) if reason.contains("8 bytes")
+        ));
+    }
+
+    #[test]
+    fn an_attached_file_is_put_in_place_of_the_typed_value() {
+        let mut state = online();
+
+        let _ = handle_value_file_loaded(&mut state, Ok(("blob.bin".to_owned(), vec![0xff, 0])));
+        assert_eq!(state.value_file_name, "blob.bin");
+        assert_eq!(state.value_bytes.as_deref(), Some(&[0xff, 0][..]));
+
+        let effect = handle_put_record(&mut state, "key".to_owned(), vec![0xff, 0], None);
+
+        assert!(state.value_bytes.is_none());
+        assert!(matches!(
+            effect.commands.as_slice(),
+            [P2pCommand::PutRecord(_, _, value, ..)] if value == &[0xff, 0]
+        ));
+    }
+
+    #[test]
+    fn values_are_not_put_until_the_passphrase_key_is_ready() {
+        let mut state = online();
+        state.config.value_passphrase = Some("correct horse".to_owned());
+
+        let effect = handle_put_record(&mut state, "key".to_owned(), b"secret".to_vec(), None);
+        assert!(effect.commands.is_empty());
+        assert_eq!(state.toasts.len(), 1);
+
+        // A key derived for a passphrase that has since changed is no use
+        let _ = handle_value_cipher_derived(&mut state, Arc::new(ValueCipher::new("old")));
+        assert!(value_cipher(&state).is_none());
+
+        let cipher = Arc::new(ValueCipher::new("correct horse"));
+        let _ = handle_value_cipher_derived(&mut state, cipher);
+        let effect = handle_put_record(&mut state, "key".to_owned(), b"secret".to_vec(), None);
+
+        assert!(matches!(
+            effect.commands.as_slice(),
+            [P2pCommand::PutRecord(_, _, value, ..)] if envelope::is_encrypted(value)
+        ));
+    }
+
+    #[test]
+    fn value_views_open_as_detected_and_close_with_the_log() {
+        let mut state = State::default();
+        let record = ValueSource::Record("key".to_owned());
+        let entry = ValueSource::LogEntry(0);
+
+        let _ = handle_toggle_value_view(&mut state, record.clone(), ValueDisplay::Hex);
+        let _ = handle_toggle_value_view(&mut state, entry.clone(), ValueDisplay::Text);
+        let _ = handle_value_display_changed(&mut state, record.clone(), ValueDisplay::Base64);
+        let _ = handle_show_whole_value(&mut state, record.clone());
+
+        assert_eq!(state.value_views[&record], ValueView {
+            display: ValueDisplay::Base64,
+            whole: true,
+        });
+        assert!(!state.value_views[&entry].whole);
+
+        let _ = handle_clear_log(&mut state);
+        assert!(!state.value_views.contains_key(&entry));
+
+        let _ = handle_toggle_value_view(&mut state, record.clone(), ValueDisplay::Hex);
+        assert!(state.value_views.is_empty());
+    }
 }
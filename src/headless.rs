@@ -0,0 +1,580 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use iced::futures::channel::{mpsc, oneshot};
+use iced::futures::{SinkExt, StreamExt, select};
+use libp2p::{Multiaddr, PeerId, kad};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{error, info};
+use crate::app::LogEntry;
+use crate::bench::{self, Benchmark};
+use crate::config::AppConfig;
+use crate::export::JsonEvent;
+use crate::handlers::is_logged;
+use crate::p2p::{self, NodeConfig, P2pCommand, P2pEvent, RequestId};
+use self::api::Api;
+
+mod api;
+
+const API_TIMEOUT_MARGIN: Duration = Duration::from_secs(5);
+
+const COMMANDS: [(&str, &str, &str); 9] = [
+    ("HELP", "HELP", "show this list"),
+    ("PEERS", "PEERS", "show discovered and connected peers"),
+    ("LIST", "LIST", "show the records in the local store"),
+    ("GET", "GET <key>", "look up a record"),
+    ("PUT", "PUT <key> <value>", "publish a record, the value is the rest of the line"),
+    ("PROVIDERS", "PROVIDERS <key>", "look up the providers of a key"),
+    ("PROVIDE", "PROVIDE <key>", "announce this node as a provider of a key"),
+    ("STOP_PROVIDE", "STOP_PROVIDE <key>", "stop providing a key"),
+    ("BENCH", "BENCH <n> <size>", "time putting and getting n random records of size bytes"),
+];
+
+/// A console command; with `--json` it can also be written as e.g.
+/// `{"command": "put", "key": "greeting", "value": "hello"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum ReplCommand {
+    Help,
+    Peers,
+    List,
+    Get { key: String },
+    Put { key: String, value: String },
+    Providers { key: String },
+    Provide { key: String },
+    StopProvide { key: String },
+    Bench { count: usize, size: usize },
+}
+
+/// Query results the HTTP API is waiting for, keyed by the request that started the query.
+type Waiters = Arc<Mutex<HashMap<RequestId, oneshot::Sender<P2pEvent>>>>;
+
+/// Runs the node without a window, printing its events to stdout and reading commands from
+/// stdin until Ctrl+C is pressed. `json` switches both to newline-delimited JSON, `api` also
+/// serves the HTTP API on that address.
+pub fn run(
+    config: NodeConfig,
+    settings: AppConfig,
+    json: bool,
+    api: Option<SocketAddr>,
+) -> Result<(), String> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| format!("Failed to start the runtime: {err}"))?
+        .block_on(serve(config, settings, json, api))
+}
+
+async fn serve(
+    config: NodeConfig,
+    settings: AppConfig,
+    json: bool,
+    api_address: Option<SocketAddr>,
+) -> Result<(), String> {
+    let api_listener = match api_address {
+        Some(address) => Some(
+            tokio::net::TcpListener::bind(address)
+                .await
+                .map_err(|err| format!("Failed to start the HTTP API on {address}: {err}"))?,
+        ),
+        None => None,
+    };
+
+    let (command_sender, command_receiver) = mpsc::channel(100);
+    let (event_sender, mut event_receiver) = mpsc::channel(100);
+    let api_timeout = config.query_timeout + API_TIMEOUT_MARGIN;
+    let metrics = config.metrics.clone();
+    let node = tokio::spawn(p2p::run(command_receiver, event_sender, config));
+    let mut console = Console::new(command_sender, settings, json);
+
+    if let Some(listener) = api_listener {
+        let api = Api {
+            commands: console.commands.clone(),
+            node: console.node.clone(),
+            waiters: console.waiters.clone(),
+            settings: console.settings.clone(),
+            timeout: api_timeout,
+            metrics,
+        };
+
+        tokio::spawn(async move {
+            if let Err(err) = api::serve(listener, api).await {
+                error!("The HTTP API stopped: {err}");
+            }
+        });
+    }
+
+    let mut shutdown = console.commands.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Interrupted, shutting down");
+            shutdown.send(P2pCommand::Shutdown).await.ok();
+        }
+    });
+
+    let mut lines = Box::pin(async_stream::stream! {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            yield line;
+        }
+    })
+    .fuse();
+
+    loop {
+        select! {
+            event = event_receiver.next() => match event {
+                Some(event) => console.show(event).await,
+                None => break,
+            },
+            line = lines.next() => {
+                if let Some(line) = line {
+                    console.execute(&line).await;
+                }
+            }
+        }
+    }
+
+    node.await.map_err(|err| format!("the p2p task panicked: {err}"))?
+}
+
+/// What the console and the HTTP API know about the node, built up from its events.
+#[derive(Debug, Default)]
+struct NodeView {
+    local_peer_id: Option<PeerId>,
+    listen_addrs: Vec<Multiaddr>,
+    peers: BTreeMap<PeerId, Vec<Multiaddr>>,
+    connected: HashSet<PeerId>,
+}
+
+impl NodeView {
+    fn apply(&mut self, event: &P2pEvent) {
+        match event {
+            P2pEvent::LocalIdentity(peer_id) => self.local_peer_id = Some(*peer_id),
+            P2pEvent::Bootstrapped(address) => self.listen_addrs.push(address.clone()),
+            P2pEvent::ListenAddrExpired(address) => {
+                self.listen_addrs.retain(|known| known != address);
+            }
+            P2pEvent::PeerDiscovered(peer_id, address)
+            | P2pEvent::PeerConnected {
+                peer_id, address, ..
+            } => {
+                let addresses = self.peers.entry(*peer_id).or_default();
+
+                if !addresses.contains(address) {
+                    addresses.push(address.clone());
+                }
+
+                if matches!(event, P2pEvent::PeerConnected { .. }) {
+                    self.connected.insert(*peer_id);
+                }
+            }
+            P2pEvent::PeerExpired(peer_id, address) => {
+                if let Some(addresses) = self.peers.get_mut(peer_id) {
+                    addresses.retain(|known| known != address);
+                }
+            }
+            P2pEvent::PeerDisconnected {
+                peer_id,
+                num_established: 0,
+            } => {
+                self.connected.remove(peer_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn peers_json(&self) -> serde_json::Value {
+        self.peers
+            .iter()
+            .map(|(peer_id, addresses)| {
+                json!({
+                    "peer_id": peer_id.to_string(),
+                    "connected": self.connected.contains(peer_id),
+                    "addresses": addresses.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Reads commands from stdin and prints events, keeping the state `PEERS` and `LIST` need.
+struct Console {
+    commands: mpsc::Sender<P2pCommand>,
+    settings: AppConfig,
+    json: bool,
+    node: Arc<Mutex<NodeView>>,
+    waiters: Waiters,
+    listing: bool,
+    benchmark: Option<Benchmark>,
+}
+
+impl Console {
+    fn new(commands: mpsc::Sender<P2pCommand>, settings: AppConfig, json: bool) -> Self {
+        Self {
+            commands,
+            settings,
+            json,
+            node: Arc::default(),
+            waiters: Arc::default(),
+            listing: false,
+            benchmark: None,
+        }
+    }
+
+    async fn show(&mut self, event: P2pEvent) {
+        if let Some(benchmark) = &mut self.benchmark
+            && benchmark.handle(&event)
+        {
+            self.run_benchmark().await;
+            return;
+        }
+
+        self.node.lock().unwrap().apply(&event);
+
+        let request_id = match &event {
+            P2pEvent::Outbound(request_id, _) | P2pEvent::Error(request_id, _) => *request_id,
+            _ => None,
+        };
+
+        if let Some(waiter) = request_id.and_then(|id| self.waiters.lock().unwrap().remove(&id)) {
+            waiter.send(event.clone()).ok();
+        }
+
+        match &event {
+            P2pEvent::LocalRecords(records) if self.listing => {
+                self.listing = false;
+
+                if self.json {
+                    let records: Vec<_> = records
+                        .iter()
+                        .map(|(key, value, _)| {
+                            json!({
+                                "key": p2p::format_key(key),
+                                "value": p2p::format_bytes(value),
+                            })
+                        })
+                        .collect();
+                    println!("{}", json!({ "type": "records", "records": records }));
+                    return;
+                }
+
+                if records.is_empty() {
+                    println!("The local store is empty");
+                }
+
+                for (key, value, _) in records {
+                    println!("{} = {}", p2p::format_key(key), p2p::format_record_value(value));
+                }
+
+                return;
+            }
+            _ => {}
+        }
+
+        if is_logged(&event) {
+            self.print(event);
+        }
+    }
+
+    fn print(&self, event: P2pEvent) {
+        if self.json {
+            let entry = LogEntry::new(event);
+            let line = serde_json::to_string(&JsonEvent::new(&entry))
+                .expect("events are always serializable");
+            println!("{line}");
+        } else {
+            println!("{event}");
+        }
+    }
+
+    async fn execute(&mut self, line: &str) {
+        let parsed = match line.trim_start().starts_with('{') && self.json {
+            true => serde_json::from_str(line).map(Some).map_err(|err| err.to_string()),
+            false => parse_line(line),
+        };
+        let command = match parsed {
+            Ok(Some(command)) => command,
+            Ok(None) => return,
+            Err(message) if self.json => {
+                println!("{}", json!({ "type": "error", "message": message }));
+                return;
+            }
+            Err(usage) => {
+                println!("{usage}");
+                return;
+            }
+        };
+
+        let command = match command {
+            ReplCommand::Help if self.json => {
+                let commands: Vec<_> = COMMANDS.iter().map(|(_, usage, _)| usage).collect();
+                println!("{}", json!({ "type": "help", "commands": commands }));
+                return;
+            }
+            ReplCommand::Help => {
+                for (_, usage, description) in COMMANDS {
+                    println!("{usage:<22}{description}");
+                }
+                return;
+            }
+            ReplCommand::Peers => {
+                self.print_peers();
+                return;
+            }
+            ReplCommand::List => {
+                self.listing = true;
+                P2pCommand::ListRecords
+            }
+            ReplCommand::Get { key } => {
+                let key = self.parse_key(&key);
+                P2pCommand::GetRecord(RequestId::next(), key, self.settings.get_quorum)
+            }
+            ReplCommand::Put { key, value } => P2pCommand::PutRecord(
+                RequestId::next(),
+                self.parse_key(&key),
+                value.into_bytes(),
+                self.settings.record_ttl(),
+                self.settings.quorum(),
+            ),
+            ReplCommand::Providers { key } => {
+                P2pCommand::GetProviders(RequestId::next(), self.parse_key(&key))
+            }
+            ReplCommand::Provide { key } => {
+                P2pCommand::PutProvider(RequestId::next(), self.parse_key(&key))
+            }
+            ReplCommand::StopProvide { key } => {
+                P2pCommand::StopProviding(self.parse_key(&key))
+            }
+            ReplCommand::Bench { .. } if self.benchmark.is_some() => {
+                println!("A benchmark is already running");
+                return;
+            }
+            ReplCommand::Bench { count, size } => {
+                if let Err(message) = bench::parse_params(&count.to_string(), &size.to_string()) {
+                    println!("{message}");
+                    return;
+                }
+
+                self.benchmark = Some(Benchmark::new(
+                    count,
+                    size,
+                    self.settings.quorum(),
+                    self.settings.get_quorum,
+                ));
+                self.run_benchmark().await;
+                return;
+            }
+        };
+
+        self.commands.send(command).await.ok();
+    }
+
+    /// Sends what the benchmark wants to run next, printing its report once it's done.
+    async fn run_benchmark(&mut self) {
+        let Some(benchmark) = &mut self.benchmark else {
+            return;
+        };
+
+        let commands = benchmark.take_commands();
+        let report = benchmark.report();
+
+        for cmd in commands {
+            self.commands.send(cmd).await.ok();
+        }
+
+        if let Some(report) = report {
+            self.benchmark = None;
+            self.print(P2pEvent::BenchmarkFinished(report));
+        }
+    }
+
+    fn parse_key(&self, input: &str) -> kad::RecordKey {
+        p2p::parse_key(input, &self.settings.key_namespace, false)
+    }
+
+    fn print_peers(&self) {
+        let node = self.node.lock().unwrap();
+
+        if self.json {
+            println!("{}", json!({ "type": "peers", "peers": node.peers_json() }));
+            return;
+        }
+
+        if node.peers.is_empty() {
+            println!("No peers known yet");
+        }
+
+        for (peer_id, addresses) in &node.peers {
+            let status = match node.connected.contains(peer_id) {
+                true => "connected",
+                false => "discovered",
+            };
+            let addresses = addresses
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            println!("{peer_id} {status} {addresses}");
+        }
+    }
+}
+
+fn usage(name: &str) -> String {
+    COMMANDS
+        .iter()
+        .find(|(command, ..)| *command == name)
+        .map(|(_, usage, _)| format!("Usage: {usage}"))
+        .unwrap_or_default()
+}
+
+/// Splits off the next word, treating a double quoted run as one word even if it has spaces.
+fn next_word(input: &str) -> Option<(String, &str)> {
+    let input = input.trim_start();
+
+    if let Some(quoted) = input.strip_prefix('"') {
+        return Some(match quoted.split_once('"') {
+            Some((word, rest)) => (word.to_owned(), rest),
+            None => (quoted.to_owned(), ""),
+        });
+    }
+
+    match input.split_once(char::is_whitespace) {
+        _ if input.is_empty() => None,
+        Some((word, rest)) => Some((word.to_owned(), rest)),
+        None => Some((input.to_owned(), "")),
+    }
+}
+
+fn next_number(input: &str) -> Option<(usize, &str)> {
+    let (word, rest) = next_word(input)?;
+
+    Some((word.parse().ok()?, rest))
+}
+
+fn parse_line(line: &str) -> Result<Option<ReplCommand>, String> {
+    let Some((name, rest)) = next_word(line) else {
+        return Ok(None);
+    };
+    let name = name.to_ascii_uppercase();
+    let key = || match next_word(rest) {
+        Some((key, rest)) if !key.is_empty() && rest.trim().is_empty() => Ok(key),
+        _ => Err(usage(&name)),
+    };
+
+    let command = match name.as_str() {
+        "HELP" => ReplCommand::Help,
+        "PEERS" => ReplCommand::Peers,
+        "LIST" => ReplCommand::List,
+        "GET" => ReplCommand::Get { key: key()? },
+        "PROVIDERS" => ReplCommand::Providers { key: key()? },
+        "PROVIDE" => ReplCommand::Provide { key: key()? },
+        "STOP_PROVIDE" => ReplCommand::StopProvide { key: key()? },
+        "BENCH" => {
+            let (count, rest) = next_number(rest).ok_or_else(|| usage(&name))?;
+            let (size, rest) = next_number(rest).ok_or_else(|| usage(&name))?;
+
+            if !rest.trim().is_empty() {
+                return Err(usage(&name));
+            }
+
+            ReplCommand::Bench { count, size }
+        }
+        "PUT" => {
+            let (key, value) = next_word(rest).ok_or_else(|| usage(&name))?;
+            let value = value.trim();
+            let value = match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+                Some(unquoted) => unquoted,
+                None => value,
+            };
+
+            if key.is_empty() || value.is_empty() {
+                return Err(usage(&name));
+            }
+
+            ReplCommand::Put {
+                key,
+                value: value.to_owned(),
+            }
+        }
+        _ => return Err(format!("Unknown command {name}, type HELP for the list")),
+    };
+
+    Ok(Some(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_keeps_spaces_and_strips_quotes() {
+        assert_eq!(
+            parse_line("PUT greeting hello world"),
+            Ok(Some(ReplCommand::Put {
+                key: "greeting".to_owned(),
+                value: "hello world".to_owned(),
+            }))
+        );
+        assert_eq!(
+            parse_line("put \"my key\" \"  padded value \"  "),
+            Ok(Some(ReplCommand::Put {
+                key: "my key".to_owned(),
+                value: "  padded value ".to_owned(),
+            }))
+        );
+    }
+
+    #[test]
+    fn blank_lines_are_ignored_and_trailing_whitespace_is_trimmed() {
+        assert_eq!(parse_line(""), Ok(None));
+        assert_eq!(parse_line("   \t"), Ok(None));
+        assert_eq!(
+            parse_line("GET key   "),
+            Ok(Some(ReplCommand::Get {
+                key: "key".to_owned()
+            }))
+        );
+        assert_eq!(parse_line("peers\n"), Ok(Some(ReplCommand::Peers)));
+    }
+
+    #[test]
+    fn missing_arguments_print_usage_of_that_command() {
+        assert_eq!(parse_line("GET"), Err("Usage: GET <key>".to_owned()));
+        assert_eq!(parse_line("PUT key"), Err("Usage: PUT <key> <value>".to_owned()));
+        assert_eq!(parse_line("PROVIDE a b"), Err("Usage: PROVIDE <key>".to_owned()));
+        assert!(parse_line("FROB").is_err());
+    }
+
+    #[test]
+    fn bench_takes_a_count_and_a_value_size() {
+        assert_eq!(
+            parse_line("bench 100 256"),
+            Ok(Some(ReplCommand::Bench {
+                count: 100,
+                size: 256
+            }))
+        );
+        assert_eq!(parse_line("BENCH 100"), Err("Usage: BENCH <n> <size>".to_owned()));
+        assert!(parse_line("BENCH many 256").is_err());
+        assert!(parse_line("BENCH 1 2 3").is_err());
+    }
+
+    #[test]
+    fn json_commands_use_the_same_names() {
+        let command: ReplCommand =
+            serde_json::from_str(r#"{"command": "stop_provide", "key": "greeting"}"#).unwrap();
+
+        assert_eq!(
+            command,
+            ReplCommand::StopProvide {
+                key: "greeting".to_owned()
+            }
+        );
+        assert!(serde_json::from_str::<ReplCommand>(r#"{"command": "put", "key": "k"}"#).is_err());
+    }
+}
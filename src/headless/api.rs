@@ -0,0 +1,246 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use iced::futures::SinkExt;
+use iced::futures::channel::{mpsc, oneshot};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tracing::info;
+use crate::config::AppConfig;
+use crate::p2p::metrics::{self, Metrics};
+use crate::p2p::{self, P2pCommand, P2pEvent, P2pOutboundEvent, RequestId};
+use super::{NodeView, Waiters};
+
+/// Everything the HTTP handlers share: they drive the node through the same command channel as
+/// the console and read peers from the same view of it.
+#[derive(Clone)]
+pub(super) struct Api {
+    pub(super) commands: mpsc::Sender<P2pCommand>,
+    pub(super) node: Arc<Mutex<NodeView>>,
+    pub(super) waiters: Waiters,
+    pub(super) settings: AppConfig,
+    pub(super) timeout: Duration,
+    pub(super) metrics: Option<Arc<Metrics>>,
+}
+
+impl Api {
+    /// Sends the command built by `command` and waits for the event answering it.
+    async fn query(
+        &self,
+        command: impl FnOnce(RequestId) -> P2pCommand,
+    ) -> Result<P2pEvent, Response> {
+        let request_id = RequestId::next();
+        let (sender, receiver) = oneshot::channel();
+        self.waiters.lock().unwrap().insert(request_id, sender);
+
+        if self.commands.clone().send(command(request_id)).await.is_err() {
+            self.waiters.lock().unwrap().remove(&request_id);
+            return Err(error(StatusCode::SERVICE_UNAVAILABLE, "The node has stopped"));
+        }
+
+        match tokio::time::timeout(self.timeout, receiver).await {
+            Ok(Ok(event)) => Ok(event),
+            Ok(Err(_)) => Err(error(StatusCode::SERVICE_UNAVAILABLE, "The node has stopped")),
+            Err(_) => {
+                self.waiters.lock().unwrap().remove(&request_id);
+                Err(error(StatusCode::GATEWAY_TIMEOUT, "The query did not finish in time"))
+            }
+        }
+    }
+}
+
+pub(super) async fn serve(listener: TcpListener, api: Api) -> std::io::Result<()> {
+    if let Ok(address) = listener.local_addr() {
+        info!("HTTP API listening on http://{address}");
+    }
+
+    axum::serve(listener, router(api)).await
+}
+
+fn router(api: Api) -> Router {
+    let metrics = api.metrics.clone();
+    let router = Router::new()
+        .route("/records/{key}", get(get_record).put(put_record))
+        .route("/providers/{key}", get(get_providers).post(start_providing))
+        .route("/peers", get(peers))
+        .route("/status", get(status))
+        .with_state(api);
+
+    match metrics {
+        Some(metrics) => router.merge(metrics::router(metrics)),
+        None => router,
+    }
+}
+
+async fn get_record(State(api): State<Api>, Path(key): Path<String>) -> Response {
+    let key = p2p::parse_key(&key, &api.settings.key_namespace, false);
+
+    let quorum = api.settings.get_quorum;
+
+    match api.query(|id| P2pCommand::GetRecord(id, key, quorum)).await {
+        Ok(P2pEvent::Outbound(_, P2pOutboundEvent::RecordResolved { mut values, .. }))
+            if !values.is_empty() =>
+        {
+            let value = values.swap_remove(0).value;
+            ([(header::CONTENT_TYPE, "application/octet-stream")], value).into_response()
+        }
+        Ok(event) => failure(event),
+        Err(response) => response,
+    }
+}
+
+async fn put_record(
+    State(api): State<Api>,
+    Path(key): Path<String>,
+    value: Bytes,
+) -> Response {
+    let key = p2p::parse_key(&key, &api.settings.key_namespace, false);
+    let (ttl, quorum) = (api.settings.record_ttl(), api.settings.quorum());
+
+    match api.query(|id| P2pCommand::PutRecord(id, key, value.to_vec(), ttl, quorum)).await {
+        Ok(P2pEvent::Outbound(_, P2pOutboundEvent::RecordPut(_))) => {
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(event) => failure(event),
+        Err(response) => response,
+    }
+}
+
+async fn get_providers(State(api): State<Api>, Path(key): Path<String>) -> Response {
+    let key = p2p::parse_key(&key, &api.settings.key_namespace, false);
+
+    match api.query(|id| P2pCommand::GetProviders(id, key)).await {
+        Ok(P2pEvent::Outbound(_, P2pOutboundEvent::ProvidersFound(_, providers))) => {
+            let providers: Vec<_> = providers.iter().map(ToString::to_string).collect();
+            axum::Json(providers).into_response()
+        }
+        Ok(event) => failure(event),
+        Err(response) => response,
+    }
+}
+
+async fn start_providing(State(api): State<Api>, Path(key): Path<String>) -> Response {
+    let key = p2p::parse_key(&key, &api.settings.key_namespace, false);
+
+    match api.query(|id| P2pCommand::PutProvider(id, key)).await {
+        Ok(P2pEvent::Outbound(_, P2pOutboundEvent::ProviderPut(_))) => {
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(event) => failure(event),
+        Err(response) => response,
+    }
+}
+
+async fn peers(State(api): State<Api>) -> Response {
+    axum::Json(api.node.lock().unwrap().peers_json()).into_response()
+}
+
+async fn status(State(api): State<Api>) -> Response {
+    let node = api.node.lock().unwrap();
+
+    axum::Json(json!({
+        "peer_id": node.local_peer_id.map(|peer_id| peer_id.to_string()),
+        "listen_addrs": node.listen_addrs.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        "known_peers": node.peers.len(),
+        "connected_peers": node.connected.len(),
+    }))
+    .into_response()
+}
+
+/// Maps an event that didn't answer the query the way the handler hoped to a status code.
+fn failure(event: P2pEvent) -> Response {
+    match event {
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordNotFound(_)) => {
+            error(StatusCode::NOT_FOUND, "No value stored for this key")
+        }
+        P2pEvent::Error(_, err) if err.is_timeout() => {
+            error(StatusCode::GATEWAY_TIMEOUT, &err.to_string())
+        }
+        P2pEvent::Error(_, err) => error(StatusCode::BAD_GATEWAY, &err.to_string()),
+        event => error(StatusCode::BAD_GATEWAY, &event.to_string()),
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, axum::Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use iced::futures::StreamExt;
+    use libp2p::kad;
+    use crate::p2p::FoundValue;
+    use crate::p2p::envelope::RecordSignature;
+    use super::*;
+
+    fn api(timeout: Duration) -> (Api, mpsc::Receiver<P2pCommand>) {
+        let (commands, receiver) = mpsc::channel(10);
+        let api = Api {
+            commands,
+            node: Arc::default(),
+            waiters: Arc::default(),
+            settings: AppConfig::default(),
+            timeout,
+            metrics: None,
+        };
+
+        (api, receiver)
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    #[tokio::test]
+    async fn found_records_are_returned_as_raw_bytes() {
+        let (api, mut commands) = api(Duration::from_secs(5));
+        let waiters = api.waiters.clone();
+
+        tokio::spawn(async move {
+            let Some(P2pCommand::GetRecord(id, key, _)) = commands.next().await else {
+                panic!("expected a GetRecord command");
+            };
+            let values = vec![FoundValue {
+                peer: None,
+                value: vec![0, 159, 146, 150],
+                signature: RecordSignature::default(),
+            }];
+            let event = P2pEvent::Outbound(Some(id), P2pOutboundEvent::RecordResolved {
+                key,
+                values,
+                agree: true,
+            });
+            waiters.lock().unwrap().remove(&id).unwrap().send(event).ok();
+        });
+
+        let response = get_record(State(api), Path("key".to_owned())).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/octet-stream");
+        assert_eq!(body(response).await, vec![0, 159, 146, 150]);
+    }
+
+    #[tokio::test]
+    async fn unanswered_queries_time_out_with_504() {
+        let (api, _commands) = api(Duration::from_millis(10));
+        let waiters = api.waiters.clone();
+
+        let response = start_providing(State(api), Path("key".to_owned())).await;
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(waiters.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_records_map_to_404() {
+        let key = kad::RecordKey::new(&"key");
+        let not_found = P2pEvent::Outbound(None, P2pOutboundEvent::RecordNotFound(key));
+
+        assert_eq!(failure(not_found).status(), StatusCode::NOT_FOUND);
+    }
+}
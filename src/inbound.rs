@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use libp2p::kad;
+use libp2p::kad::store::RecordStore;
+use crate::p2p::envelope;
+
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_VALUE_BYTES: usize = 64 * 1024;
+pub const DEFAULT_MAX_RECORDS_PER_PEER: usize = 256;
+pub const DEFAULT_MAX_STORES_PER_MINUTE: u32 = 600;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+const MAX_PENDING: usize = 100;
+/// So one chatty peer can't fill the whole approval queue and crowd everyone else out.
+const MAX_PENDING_PER_PEER: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InboundId(u64);
+
+impl fmt::Display for InboundId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum InboundRecord {
+    Record(kad::Record),
+    Provider(kad::ProviderRecord),
+}
+
+impl InboundRecord {
+    pub fn key(&self) -> &kad::RecordKey {
+        match self {
+            InboundRecord::Record(record) => &record.key,
+            InboundRecord::Provider(record) => &record.key,
+        }
+    }
+
+    pub fn value(&self) -> Option<&[u8]> {
+        match self {
+            InboundRecord::Record(record) => Some(&record.value),
+            InboundRecord::Provider(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RejectReason {
+    ValueTooLarge { size: usize, max: usize },
+    TooManyRecords { source: PeerId, max: usize },
+    NotUtf8,
+    Store(kad::store::Error),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::ValueTooLarge { size, max } => {
+                write!(f, "value is {size} bytes, limit is {max}")
+            }
+            RejectReason::TooManyRecords { source, max } => {
+                write!(f, "{source} already has {max} records stored here")
+            }
+            RejectReason::NotUtf8 => write!(f, "value is not valid UTF-8"),
+            RejectReason::Store(error) => write!(f, "store refused it: {error}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InboundLimits {
+    pub max_value_bytes: usize,
+    pub max_records_per_peer: usize,
+    pub require_utf8: bool,
+    /// Store requests one peer may send per minute before the rest are dropped.
+    pub max_stores_per_minute: u32,
+}
+
+impl Default for InboundLimits {
+    fn default() -> Self {
+        Self {
+            max_value_bytes: DEFAULT_MAX_VALUE_BYTES,
+            max_records_per_peer: DEFAULT_MAX_RECORDS_PER_PEER,
+            require_utf8: false,
+            max_stores_per_minute: DEFAULT_MAX_STORES_PER_MINUTE,
+        }
+    }
+}
+
+impl InboundLimits {
+    pub fn check(
+        &self,
+        source: PeerId,
+        record: &InboundRecord,
+        store: &impl RecordStore,
+        uploads: &Uploads,
+    ) -> Result<(), RejectReason> {
+        let InboundRecord::Record(record) = record else {
+            return Ok(());
+        };
+
+        if record.value.len() > self.max_value_bytes {
+            return Err(RejectReason::ValueTooLarge {
+                size: record.value.len(),
+                max: self.max_value_bytes,
+            });
+        }
+
+        let (value, _) = envelope::open(&record.key, &record.value);
+
+        if self.require_utf8 && std::str::from_utf8(value).is_err() {
+            return Err(RejectReason::NotUtf8);
+        }
+
+        if uploads.count(source, &record.key, store) >= self.max_records_per_peer {
+            return Err(RejectReason::TooManyRecords {
+                source,
+                max: self.max_records_per_peer,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Which peer sent each record we store for others. Quotas count these rather than the
+/// publisher a record names, which is whatever its sender put there.
+#[derive(Debug, Default)]
+pub struct Uploads {
+    senders: HashMap<kad::RecordKey, PeerId>,
+}
+
+impl Uploads {
+    pub fn record(&mut self, source: PeerId, key: kad::RecordKey) {
+        self.senders.insert(key, source);
+    }
+
+    /// Records other than `key` that `source` has stored here and the store still holds.
+    fn count(&self, source: PeerId, key: &kad::RecordKey, store: &impl RecordStore) -> usize {
+        self.senders
+            .iter()
+            .filter(|(stored, sender)| {
+                **sender == source && *stored != key && store.get(stored).is_some()
+            })
+            .count()
+    }
+
+    /// Forgets records the store no longer holds.
+    pub fn prune(&mut self, store: &impl RecordStore) {
+        self.senders.retain(|key, _| store.get(key).is_some());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throttle {
+    Allowed,
+    /// The first request dropped since the peer went over its limit.
+    Limited,
+    StillLimited,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    limited: bool,
+}
+
+/// A token bucket per peer: each store request takes a token, and a full minute's worth of
+/// tokens trickles back in over a minute.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<PeerId, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn check(&mut self, peer: PeerId, per_minute: u32, now: Instant) -> Throttle {
+        let capacity = f64::from(per_minute);
+        let bucket = self.buckets.entry(peer).or_insert(Bucket {
+            tokens: capacity,
+            refilled_at: now,
+            limited: false,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        let refill = capacity * elapsed.as_secs_f64() / RATE_WINDOW.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.limited = false;
+            Throttle::Allowed
+        } else if bucket.limited {
+            Throttle::StillLimited
+        } else {
+            bucket.limited = true;
+            Throttle::Limited
+        }
+    }
+
+    /// Forgets peers that have been quiet long enough for their bucket to fill up again.
+    pub fn prune(&mut self, now: Instant) {
+        self.buckets
+            .retain(|_, bucket| now.saturating_duration_since(bucket.refilled_at) < RATE_WINDOW);
+    }
+}
+
+#[derive(Debug)]
+pub struct PendingInbound {
+    pub id: InboundId,
+    pub source: PeerId,
+    pub record: InboundRecord,
+    received_at: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct InboundQueue {
+    pub approval_required: bool,
+    pub limits: InboundLimits,
+    pub served: ServedRequests,
+    pub rate: RateLimiter,
+    pub uploads: Uploads,
+    pending: Vec<PendingInbound>,
+    next_id: u64,
+}
+
+/// Reads answered for other peers since the last report, counted instead of logged one by one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServedRequests {
+    pub records_found: usize,
+    pub records_missing: usize,
+    pub provider_lookups: usize,
+}
+
+impl ServedRequests {
+    pub fn record(&mut self, present_locally: bool) {
+        if present_locally {
+            self.records_found += 1;
+        } else {
+            self.records_missing += 1;
+        }
+    }
+
+    /// The counts gathered so far, if there are any, starting over from zero.
+    pub fn take(&mut self) -> Option<ServedRequests> {
+        let served = std::mem::take(self);
+
+        (served != ServedRequests::default()).then_some(served)
+    }
+}
+
+impl InboundQueue {
+    /// Parks a request until it is accepted, rejected or expires; returns `None` when the queue,
+    /// or the sender's share of it, is full.
+    pub fn park(&mut self, source: PeerId, record: InboundRecord, now: Instant) -> Option<InboundId> {
+        let from_source = self.pending.iter().filter(|pending| pending.source == source).count();
+
+        if self.pending.len() >= MAX_PENDING || from_source >= MAX_PENDING_PER_PEER {
+            return None;
+        }
+
+        self.next_id += 1;
+        let id = InboundId(self.next_id);
+
+        self.pending.push(PendingInbound {
+            id,
+            source,
+            record,
+            received_at: now,
+        });
+
+        Some(id)
+    }
+
+    pub fn take(&mut self, id: InboundId) -> Option<PendingInbound> {
+        let index = self.pending.iter().position(|pending| pending.id == id)?;
+
+        Some(self.pending.remove(index))
+    }
+
+    pub fn expire(&mut self, now: Instant) -> Vec<InboundId> {
+        let (expired, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|pending| now.duration_since(pending.received_at) >= APPROVAL_TIMEOUT);
+
+        self.pending = pending;
+
+        expired.into_iter().map(|pending: PendingInbound| pending.id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> InboundRecord {
+        InboundRecord::Record(kad::Record::new(kad::RecordKey::new(&"key"), b"value".to_vec()))
+    }
+
+    #[test]
+    fn pending_requests_expire_after_timeout() {
+        let mut queue = InboundQueue::default();
+        let now = Instant::now();
+
+        let old = queue.park(PeerId::random(), record(), now).unwrap();
+        let fresh = queue.park(PeerId::random(), record(), now + APPROVAL_TIMEOUT / 2).unwrap();
+
+        assert_eq!(queue.expire(now + APPROVAL_TIMEOUT), vec![old]);
+        assert!(queue.take(old).is_none());
+        assert!(queue.take(fresh).is_some());
+    }
+
+    #[test]
+    fn peer_record_quota_is_enforced() {
+        let source = PeerId::random();
+        let mut store = kad::store::MemoryStore::new(PeerId::random());
+        let mut uploads = Uploads::default();
+        let limits = InboundLimits {
+            max_records_per_peer: 1,
+            ..InboundLimits::default()
+        };
+
+        let stored = kad::Record::new(kad::RecordKey::new(&"first"), b"value".to_vec());
+        store.put(stored.clone()).unwrap();
+        uploads.record(source, stored.key.clone());
+
+        // Naming someone else as the publisher doesn't get around the quota
+        let mut second = kad::Record::new(kad::RecordKey::new(&"second"), b"value".to_vec());
+        second.publisher = Some(PeerId::random());
+        let second = InboundRecord::Record(second);
+
+        assert!(matches!(
+            limits.check(source, &second, &store, &uploads),
+            Err(RejectReason::TooManyRecords { max: 1, .. })
+        ));
+        assert!(limits.check(source, &InboundRecord::Record(stored), &store, &uploads).is_ok());
+        assert!(limits.check(PeerId::random(), &second, &store, &uploads).is_ok());
+
+        store.remove(&kad::RecordKey::new(&"first"));
+        uploads.prune(&store);
+
+        assert!(limits.check(source, &second, &store, &uploads).is_ok());
+        assert!(uploads.senders.is_empty());
+    }
+
+    #[test]
+    fn chatty_peers_are_limited_until_tokens_trickle_back() {
+        let mut limiter = RateLimiter::default();
+        let (chatty, quiet) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(chatty, 3, now), Throttle::Allowed);
+        }
+
+        assert_eq!(limiter.check(chatty, 3, now), Throttle::Limited);
+        assert_eq!(limiter.check(chatty, 3, now), Throttle::StillLimited);
+        assert_eq!(limiter.check(quiet, 3, now), Throttle::Allowed);
+
+        let later = now + RATE_WINDOW / 3;
+
+        assert_eq!(limiter.check(chatty, 3, later), Throttle::Allowed);
+        assert_eq!(limiter.check(chatty, 3, later), Throttle::Limited);
+
+        limiter.prune(later + RATE_WINDOW);
+
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn served_requests_are_reported_once() {
+        let mut served = ServedRequests::default();
+
+        assert_eq!(served.take(), None);
+
+        served.record(true);
+        served.record(true);
+        served.record(false);
+        served.provider_lookups += 1;
+
+        assert_eq!(
+            served.take(),
+            Some(ServedRequests {
+                records_found: 2,
+                records_missing: 1,
+                provider_lookups: 1,
+            })
+        );
+        assert_eq!(served.take(), None);
+    }
+
+    #[test]
+    fn full_queue_refuses_new_requests() {
+        let mut queue = InboundQueue::default();
+        let now = Instant::now();
+
+        for _ in 0..MAX_PENDING {
+            assert!(queue.park(PeerId::random(), record(), now).is_some());
+        }
+
+        assert!(queue.park(PeerId::random(), record(), now).is_none());
+    }
+
+    #[test]
+    fn one_peer_cant_fill_the_queue() {
+        let mut queue = InboundQueue::default();
+        let (chatty, other) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        for _ in 0..MAX_PENDING_PER_PEER {
+            assert!(queue.park(chatty, record(), now).is_some());
+        }
+
+        assert!(queue.park(chatty, record(), now).is_none());
+        let parked = queue.park(other, record(), now).unwrap();
+
+        queue.take(parked);
+        assert!(queue.park(chatty, record(), now).is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use directories::ProjectDirs;
+use libp2p::identity::{DecodingError, Keypair};
+
+const KEYPAIR_FILE_NAME: &str = "identity.key";
+
+#[derive(Debug)]
+pub enum KeypairError {
+    Io(io::Error),
+    Decode(DecodingError),
+}
+
+impl fmt::Display for KeypairError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            KeypairError::Io(err) => write!(f, "{err}"),
+            KeypairError::Decode(err) => write!(f, "key file is corrupted: {err}"),
+        }
+    }
+}
+
+impl From<io::Error> for KeypairError {
+    fn from(err: io::Error) -> Self {
+        KeypairError::Io(err)
+    }
+}
+
+impl From<DecodingError> for KeypairError {
+    fn from(err: DecodingError) -> Self {
+        KeypairError::Decode(err)
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "iced-libp2p-sample")
+        .map(|dirs| dirs.data_dir().join(KEYPAIR_FILE_NAME))
+}
+
+pub fn load(path: &Path) -> Result<Keypair, KeypairError> {
+    let bytes = fs::read(path)?;
+
+    Ok(Keypair::from_protobuf_encoding(&bytes)?)
+}
+
+pub fn save(path: &Path, keypair: &Keypair) -> Result<(), KeypairError> {
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .expect("Ed25519 keypairs are always encodable");
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(&bytes)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn saved_keypair_loads_with_same_peer_id() {
+        let path = temp_path("roundtrip").join(KEYPAIR_FILE_NAME);
+        let keypair = Keypair::generate_ed25519();
+
+        save(&path, &keypair).unwrap();
+        let loaded = load(&path).unwrap();
+
+        assert_eq!(loaded.public().to_peer_id(), keypair.public().to_peer_id());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupted_key_file_is_a_decode_error() {
+        let path = temp_path("corrupted").join(KEYPAIR_FILE_NAME);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"definitely not a keypair").unwrap();
+
+        assert!(matches!(load(&path), Err(KeypairError::Decode(_))));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
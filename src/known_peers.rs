@@ -0,0 +1,197 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use directories::ProjectDirs;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+const KNOWN_PEERS_FILE_NAME: &str = "peers.json";
+
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "iced-libp2p-sample")
+        .map(|dirs| dirs.data_dir().join(KNOWN_PEERS_FILE_NAME))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownPeer {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    pub last_seen: SystemTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredPeer {
+    peer_id: String,
+    addresses: Vec<String>,
+    last_seen: u64,
+}
+
+/// Peers this node has been connected to, mirrored into a JSON file so they can be dialed again
+/// after a restart. Without a path nothing is remembered.
+#[derive(Debug, Default)]
+pub struct KnownPeers {
+    path: Option<PathBuf>,
+    peers: HashMap<PeerId, KnownPeer>,
+}
+
+impl KnownPeers {
+    /// Loads the file at `path`, dropping peers that were last seen more than `max_age` ago.
+    pub fn open(path: Option<PathBuf>, max_age: Duration) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+
+        let cutoff = SystemTime::now().checked_sub(max_age).unwrap_or(UNIX_EPOCH);
+        let peers = match load(&path) {
+            Ok(stored) => stored
+                .into_iter()
+                .filter_map(StoredPeer::restore)
+                .filter(|peer| peer.last_seen >= cutoff)
+                .map(|peer| (peer.peer_id, peer))
+                .collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                error!("Ignoring unreadable peer list {}: {err}", path.display());
+                HashMap::new()
+            }
+        };
+
+        info!("Remembered {} peers from {}", peers.len(), path.display());
+
+        let known = Self {
+            path: Some(path),
+            peers,
+        };
+        known.save();
+        known
+    }
+
+    /// The `limit` peers seen most recently, newest first.
+    pub fn most_recent(&self, limit: usize) -> Vec<KnownPeer> {
+        let mut peers: Vec<_> = self.peers.values().cloned().collect();
+        peers.sort_by_key(|peer| Reverse(peer.last_seen));
+        peers.truncate(limit);
+        peers
+    }
+
+    pub fn all(&self) -> Vec<KnownPeer> {
+        self.most_recent(self.peers.len())
+    }
+
+    /// Marks `peer_id` as seen now, adding `address` to its known addresses.
+    pub fn seen(&mut self, peer_id: PeerId, address: Option<Multiaddr>) {
+        let peer = self.touch(peer_id);
+
+        if let Some(address) = address.filter(|address| !peer.addresses.contains(address)) {
+            peer.addresses.push(address);
+        }
+
+        self.save();
+    }
+
+    /// Replaces the known addresses of `peer_id` with the ones it announced itself.
+    pub fn identified(&mut self, peer_id: PeerId, listen_addrs: Vec<Multiaddr>) {
+        if listen_addrs.is_empty() {
+            return;
+        }
+
+        self.touch(peer_id).addresses = listen_addrs;
+        self.save();
+    }
+
+    fn touch(&mut self, peer_id: PeerId) -> &mut KnownPeer {
+        let peer = self.peers.entry(peer_id).or_insert_with(|| KnownPeer {
+            peer_id,
+            addresses: Vec::new(),
+            last_seen: SystemTime::now(),
+        });
+        peer.last_seen = SystemTime::now();
+        peer
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let stored: Vec<_> = self.peers.values().map(StoredPeer::new).collect();
+
+        if let Err(err) = save(path, &stored) {
+            error!("Failed to save known peers to {}: {err}", path.display());
+        }
+    }
+}
+
+impl StoredPeer {
+    fn new(peer: &KnownPeer) -> Self {
+        Self {
+            peer_id: peer.peer_id.to_string(),
+            addresses: peer.addresses.iter().map(ToString::to_string).collect(),
+            last_seen: peer
+                .last_seen
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+
+    fn restore(self) -> Option<KnownPeer> {
+        Some(KnownPeer {
+            peer_id: self.peer_id.parse().ok()?,
+            addresses: self
+                .addresses
+                .iter()
+                .filter_map(|address| address.parse().ok())
+                .collect(),
+            last_seen: UNIX_EPOCH + Duration::from_secs(self.last_seen),
+        })
+    }
+}
+
+fn load(path: &Path) -> io::Result<Vec<StoredPeer>> {
+    let bytes = fs::read(path)?;
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+fn save(path: &Path, peers: &[StoredPeer]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec(peers)?)?;
+    fs::rename(temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn known_peers_survive_reopening_until_they_go_stale() {
+        let path = temp_path("known-peers").join(KNOWN_PEERS_FILE_NAME);
+        let fresh = PeerId::random();
+        let stale = PeerId::random();
+        let address: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+
+        let mut known = KnownPeers::open(Some(path.clone()), Duration::from_secs(3600));
+        known.seen(fresh, Some(address.clone()));
+        known.seen(stale, None);
+        known.peers.get_mut(&stale).unwrap().last_seen -= Duration::from_secs(7200);
+        known.save();
+
+        let reopened = KnownPeers::open(Some(path.clone()), Duration::from_secs(3600));
+
+        assert_eq!(reopened.all().len(), 1);
+        assert_eq!(reopened.most_recent(1)[0].peer_id, fresh);
+        assert_eq!(reopened.most_recent(1)[0].addresses, vec![address]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -2,30 +2,190 @@
 #![forbid(unsafe_code)]
 
 use crate::app::App;
+use crate::cli::Cli;
+use crate::config::{AppConfig, WindowGeometry};
+use crate::diagnostics::TRACE_CHANNEL_CAPACITY;
+use crate::inbound::InboundLimits;
+use crate::p2p::metrics::Metrics;
+use crate::p2p::{NodeConfig, RelayLimits};
+use crate::store::StoreConfig;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use iced::Point;
 use iced::window::Position;
+use tracing_subscriber::{EnvFilter, Layer};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+mod bench;
+mod cli;
+mod config;
+mod diagnostics;
+mod export;
+mod file_log;
+mod file_transfer;
+mod format;
 mod handlers;
+mod headless;
+mod inbound;
+mod keypair;
+mod known_peers;
 mod p2p;
+mod store;
+#[cfg(test)]
+mod test_util;
 mod widgets;
 mod app;
 
+/// Where to restore the window, for the position callback, which can't capture anything.
+static RESTORED_POSITION: OnceLock<Point> = OnceLock::new();
+
 fn main() -> iced::Result {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                "iced-libp2p-sample=debug,wgpu_core=info".into()
+    let cli = Cli::parse_or_exit();
+    let defaults = RelayLimits::default();
+    let inbound_defaults = InboundLimits::default();
+
+    let config_path = cli.config.clone().or_else(config::default_path);
+    let mut settings = match &config_path {
+        Some(path) if path.exists() => config::load(path).unwrap_or_else(|err| {
+            eprintln!("Ignoring settings in {}: {err}", path.display());
+            AppConfig::default()
+        }),
+        _ => AppConfig::default(),
+    };
+    cli.apply(&mut settings);
+
+    let record_store = match &cli.store_path {
+        Some(path) => StoreConfig::Persistent(path.clone()),
+        None if cli.persistent_store => store::default_path()
+            .map(StoreConfig::Persistent)
+            .unwrap_or_else(|| {
+                eprintln!("No data directory available, keeping records in memory");
+                StoreConfig::Memory
             }),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        None => StoreConfig::Memory,
+    };
+
+    let peer_grace = Duration::from_secs(cli.peer_grace);
+
+    let mut config = NodeConfig {
+        listen_addrs: settings.listen_addrs(),
+        fresh_identity: cli.fresh_identity,
+        relay_server: cli.relay_server,
+        relay_limits: RelayLimits {
+            max_reservations: cli.relay_max_reservations.unwrap_or(defaults.max_reservations),
+            max_circuits: cli.relay_max_circuits.unwrap_or(defaults.max_circuits),
+        },
+        inbound_limits: InboundLimits {
+            max_value_bytes: cli.max_record_size.unwrap_or(inbound_defaults.max_value_bytes),
+            max_records_per_peer: cli
+                .max_records_per_peer
+                .unwrap_or(inbound_defaults.max_records_per_peer),
+            require_utf8: cli.require_utf8,
+            max_stores_per_minute: cli
+                .max_stores_per_minute
+                .unwrap_or(inbound_defaults.max_stores_per_minute),
+        },
+        record_store,
+        known_peers: if cli.forget_peers {
+            None
+        } else {
+            known_peers::default_path()
+        },
+        known_peer_max_age: settings.known_peer_max_age(),
+        bootstrap_peers: settings.bootstrap_peers.clone(),
+        mdns: settings.mdns_timing(),
+        mdns_enabled: settings.mdns_enabled,
+        connection_limits: settings.connection_limits(),
+        kad_protocol: settings.dht_protocol(),
+        kad_tuning: settings.kad_tuning(),
+        query_timeout: settings.query_timeout(),
+        republish: settings.republish_intervals(),
+        tcp: settings.enable_tcp,
+        quic: settings.enable_quic,
+        websocket: settings.enable_websocket,
+        security: settings.security,
+        sign_records: settings.sign_records,
+        kad_mode: settings.kad_mode.into(),
+        identity_path: settings.identity_path.clone(),
+        metrics: Some(Arc::new(Metrics::default())),
+        metrics_addr: settings.metrics_addr(),
+        ..NodeConfig::default()
+    };
+
+    let (diagnostics_layer, trace_lines) = diagnostics::channel_layer(TRACE_CHANNEL_CAPACITY);
+
+    let console_filter = || match &cli.log {
+        Some(directives) => EnvFilter::new(directives),
+        None => EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| "iced-libp2p-sample=debug,wgpu_core=info".into()),
+    };
+
+    let file_log = settings
+        .log_dir()
+        .map(|dir| file_log::open(&dir, settings.log_retention).map(|log| (dir, log)));
+    let (log_dir, file_writer, _file_log_guard) = match file_log {
+        Some(Ok((dir, (writer, guard)))) => (Some(dir), Some(writer), Some(guard)),
+        Some(Err(err)) => {
+            eprintln!("{err}");
+            (None, None, None)
+        }
+        None => (None, None, None),
+    };
+    let file_layer = file_writer.map(|writer| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false)
+            .with_filter(EnvFilter::new(file_log::FILE_LOG_FILTER))
+    });
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(console_filter()))
+        .with(diagnostics_layer.with_filter(console_filter()))
+        .with(file_layer)
         .try_init()
         .expect("Failed to set up logger");
 
+    if cli.headless {
+        if cli.api.is_some() {
+            // The API listener serves /metrics as well.
+            config.metrics_addr = None;
+        }
+
+        if let Err(err) = headless::run(config, settings, cli.json, cli.api) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    let font_size = settings.font_size;
+    let geometry = settings.window.unwrap_or_default();
+    let position = match geometry.position {
+        Some((x, y)) => {
+            RESTORED_POSITION.get_or_init(|| Point::new(x as f32, y as f32));
+            Position::SpecificWith(|window, monitor| {
+                let position = RESTORED_POSITION.get().copied().unwrap_or_default();
+                WindowGeometry::clamp_position(position, window, monitor)
+            })
+        }
+        None => Position::Centered,
+    };
+
     iced::application("P2P Iced", App::update, App::view)
+        .settings(iced::Settings {
+            default_text_size: f32::from(font_size).into(),
+            ..iced::Settings::default()
+        })
         .subscription(App::subscription)
         .theme(App::theme)
-        .position(Position::Centered)
-        .run_with(App::new)
+        .scale_factor(App::scale_factor)
+        .window_size(geometry.size())
+        .position(position)
+        .exit_on_close_request(false)
+        .run_with(move || {
+            App::new(config, settings, config_path, peer_grace, trace_lines, log_dir)
+        })
 }
 
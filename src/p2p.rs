@@ -1,204 +1,2857 @@
+use std::error::Error;
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Formatter;
-use std::time::Duration;
+use std::iter;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::{NonZeroU8, NonZeroUsize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use iced::futures::channel::mpsc;
+use iced::futures::stream::FusedStream;
 use iced::futures::{SinkExt, select};
 use libp2p::futures::StreamExt;
-use libp2p::kad::store::{MemoryStore, RecordStore};
-use libp2p::kad::{InboundRequest, Mode, QueryResult, StoreInserts};
-use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
-use libp2p::{Multiaddr, PeerId, Swarm, SwarmBuilder, kad, mdns, noise, tcp, yamux};
-use tracing::{debug, error, info};
+use libp2p::kad::store::RecordStore;
+use libp2p::kad::{InboundRequest, Mode, QueryId, QueryResult};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{DialError, ListenError, SwarmEvent};
+use libp2p::core::ConnectedPoint;
+use libp2p::core::transport::{ListenerId, TransportError};
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::{self, OutboundFailure};
+use libp2p::{Multiaddr, PeerId, StreamProtocol, Swarm, autonat, connection_limits, dcutr, gossipsub, identify, identity, kad, mdns, ping, relay};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, trace, warn};
+use crate::bench::BenchmarkReport;
+use crate::file_transfer::{ChunkError, Download, FileJob, FileRequest, FileResponse, FileTransfers};
+use crate::inbound::{InboundId, InboundLimits, InboundQueue, InboundRecord, RejectReason, Throttle, Uploads};
+use crate::keypair;
+use crate::known_peers::{KnownPeer, KnownPeers};
+use crate::store::StoreConfig;
+use envelope::RecordSignature;
+use metrics::Metrics;
+use security::{NegotiatedSecurity, Security, SecurityProtocol};
+use swarm::{CustomBehaviour, CustomBehaviourEvent, SwarmConfig, build_measured_swarm, mdns_behaviour, relay_server};
+
+pub mod envelope;
+#[cfg(test)]
+pub(crate) mod harness;
+pub mod metrics;
+pub mod security;
+mod swarm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    pub fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        RequestId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryKind {
+    GetRecord,
+    GetProviders,
+    PutRecord,
+    StartProviding,
+    GetClosestPeers,
+}
+
+impl fmt::Display for QueryKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryKind::GetRecord => write!(f, "Get"),
+            QueryKind::GetProviders => write!(f, "Providers"),
+            QueryKind::PutRecord => write!(f, "Put"),
+            QueryKind::StartProviding => write!(f, "Provide"),
+            QueryKind::GetClosestPeers => write!(f, "Closest"),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum P2pCommand {
-    GetRecord(String),
-    GetProviders(String),
-    PutRecord(String, Vec<u8>),
-    PutProvider(String),
+    /// Stops looking once this many peers have returned a value.
+    GetRecord(RequestId, kad::RecordKey, NonZeroUsize),
+    GetProviders(RequestId, kad::RecordKey),
+    GetClosestPeers(RequestId, kad::RecordKey),
+    PutRecord(RequestId, kad::RecordKey, Vec<u8>, Option<Duration>, kad::Quorum),
+    PutProvider(RequestId, kad::RecordKey),
+    StopProviding(kad::RecordKey),
+    RemoveRecord(kad::RecordKey),
+    ListRecords,
+    Dial(Multiaddr),
+    Bootstrap,
+    ConnectBootstrapPeers(Vec<Multiaddr>),
+    CancelQuery(RequestId),
+    SubscribeTopic(String),
+    UnsubscribeTopic(String),
+    PublishMessage(String, Vec<u8>),
+    SendDirect(PeerId, String),
+    ShareFile(RequestId, String, PathBuf),
+    FetchFile(String, PathBuf),
+    ListenViaRelay(Multiaddr),
+    SetRelayServer(Option<RelayLimits>),
+    SetMdnsEnabled(bool),
+    SetRecordSigning(bool),
+    DisconnectPeer(PeerId),
+    BlockPeer(PeerId),
+    UnblockPeer(PeerId),
+    SetInboundApproval(bool),
+    AcceptInbound(InboundId),
+    RejectInbound(InboundId),
+    SetKadMode(Mode),
+    DumpRoutingTable,
+    Shutdown,
+}
+
+impl P2pCommand {
+    /// The query this command starts, if it starts one.
+    pub fn request_id(&self) -> Option<RequestId> {
+        match self {
+            P2pCommand::GetRecord(request_id, ..)
+            | P2pCommand::GetProviders(request_id, ..)
+            | P2pCommand::GetClosestPeers(request_id, ..)
+            | P2pCommand::PutRecord(request_id, ..)
+            | P2pCommand::PutProvider(request_id, ..)
+            | P2pCommand::ShareFile(request_id, ..) => Some(*request_id),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum P2pEvent {
+    /// The node is starting and takes commands through this sender; comes before anything else.
+    Ready(mpsc::Sender<P2pCommand>),
+    LocalIdentity(PeerId),
     Bootstrapped(Multiaddr),
+    ListenAddrExpired(Multiaddr),
     PeerDiscovered(PeerId, Multiaddr),
     PeerExpired(PeerId, Multiaddr),
-    Outbound(P2pOutboundEvent),
+    Dialing(Multiaddr),
+    DialingBootstrapPeer(Multiaddr),
+    RememberedPeers(Vec<KnownPeer>),
+    PeerConnected {
+        peer_id: PeerId,
+        address: Multiaddr,
+        relayed: bool,
+        transport: ConnectionTransport,
+        direction: ConnectionDirection,
+        security: Option<SecurityProtocol>,
+        num_established: u32,
+    },
+    PeerDisconnected {
+        peer_id: PeerId,
+        num_established: u32,
+    },
+    PeerIdentified {
+        peer_id: PeerId,
+        agent: String,
+        protocols: Vec<String>,
+    },
+    /// The peer doesn't serve our DHT protocol, so it stays out of the routing table.
+    DhtProtocolMissing {
+        peer_id: PeerId,
+        protocol: String,
+    },
+    PingRtt(PeerId, Duration),
+    PeerUnresponsive(PeerId),
+    TopicSubscribed(String),
+    TopicUnsubscribed(String),
+    GossipMessage {
+        topic: String,
+        source: Option<PeerId>,
+        data: Vec<u8>,
+    },
+    GossipPublished {
+        topic: String,
+        data: Vec<u8>,
+    },
+    DirectMessageReceived(PeerId, String),
+    DirectMessageDelivered(PeerId),
+    FileTransferProgress {
+        key: String,
+        received: u64,
+        total: u64,
+    },
+    FileFetched {
+        key: String,
+        path: PathBuf,
+    },
+    RelayReservationAccepted {
+        relay: PeerId,
+        renewal: bool,
+    },
+    RelayServerToggled(bool),
+    MdnsToggled(bool),
+    ConnectionDenied {
+        peer: Option<PeerId>,
+        address: Option<Multiaddr>,
+        reason: String,
+    },
+    MetricsServing(SocketAddr),
+    HolePunch {
+        remote: PeerId,
+        success: bool,
+    },
+    NatStatus(NatStatus),
+    KadModeChanged(Mode),
+    RoutingTable(Vec<BucketInfo>),
+    RoutingUpdated {
+        peer_id: PeerId,
+        bucket: u32,
+        is_new_peer: bool,
+        evicted: Option<PeerId>,
+    },
+    UnroutablePeer(PeerId),
+    PeerBlocked(PeerId),
+    PeerUnblocked(PeerId),
+    RelayReservationServed {
+        peer_id: PeerId,
+        renewed: bool,
+    },
+    RelayCircuitOpened {
+        src: PeerId,
+        dst: PeerId,
+    },
+    RelayCircuitClosed {
+        src: PeerId,
+        dst: PeerId,
+    },
+    LocalRecords(Vec<(kad::RecordKey, Vec<u8>, Option<Instant>)>),
+    StoreRestored {
+        providers: Vec<kad::RecordKey>,
+    },
+    /// Our own records and provider announcements were put to the DHT again.
+    Republished {
+        records: usize,
+        providers: usize,
+    },
+    BenchmarkFinished(BenchmarkReport),
+    /// Traffic over our TCP and QUIC connections: rates since the last report and totals since
+    /// the node started.
+    Bandwidth {
+        inbound_bps: u64,
+        outbound_bps: u64,
+        total_in: u64,
+        total_out: u64,
+    },
+    InboundApprovalToggled(bool),
+    /// Store requests from this peer are dropped until it slows down.
+    PeerRateLimited(PeerId),
+    InboundPending {
+        id: InboundId,
+        source: PeerId,
+        key: kad::RecordKey,
+        value: Option<Vec<u8>>,
+    },
+    InboundExpired(InboundId),
+    InboundRejected {
+        source: PeerId,
+        key: kad::RecordKey,
+        reason: RejectReason,
+    },
+    Outbound(Option<RequestId>, P2pOutboundEvent),
     Inbound(P2pInboundEvent),
-    Error(String),
+    Local(P2pLocalEvent),
+    Error(Option<RequestId>, P2pError),
+    ShutdownComplete,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NatStatus {
+    Public(Multiaddr),
+    Private,
+    #[default]
+    Unknown,
+}
+
+impl From<autonat::NatStatus> for NatStatus {
+    fn from(status: autonat::NatStatus) -> Self {
+        match status {
+            autonat::NatStatus::Public(address) => NatStatus::Public(address),
+            autonat::NatStatus::Private => NatStatus::Private,
+            autonat::NatStatus::Unknown => NatStatus::Unknown,
+        }
+    }
+}
+
+/// The transport a connection runs over, read from its remote address; relayed connections
+/// count as the one they reach the relay with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionTransport {
+    Tcp,
+    Quic,
+    WebSocket,
+    Other,
+}
+
+impl ConnectionTransport {
+    pub fn of(address: &Multiaddr) -> Self {
+        if is_websocket(address) {
+            return ConnectionTransport::WebSocket;
+        }
+
+        address
+            .iter()
+            .find_map(|protocol| match protocol {
+                Protocol::Tcp(_) => Some(ConnectionTransport::Tcp),
+                Protocol::QuicV1 => Some(ConnectionTransport::Quic),
+                _ => None,
+            })
+            .unwrap_or(ConnectionTransport::Other)
+    }
+}
+
+pub fn is_websocket(address: &Multiaddr) -> bool {
+    address
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::Ws(_) | Protocol::Wss(_)))
+}
+
+impl fmt::Display for ConnectionTransport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionTransport::Tcp => write!(f, "tcp"),
+            ConnectionTransport::Quic => write!(f, "quic-v1"),
+            ConnectionTransport::WebSocket => write!(f, "ws"),
+            ConnectionTransport::Other => write!(f, "other"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionDirection {
+    Inbound,
+    Outbound,
+}
+
+impl From<&ConnectedPoint> for ConnectionDirection {
+    fn from(endpoint: &ConnectedPoint) -> Self {
+        match endpoint {
+            ConnectedPoint::Dialer { .. } => ConnectionDirection::Outbound,
+            ConnectedPoint::Listener { .. } => ConnectionDirection::Inbound,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionDirection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionDirection::Inbound => write!(f, "inbound"),
+            ConnectionDirection::Outbound => write!(f, "outbound"),
+        }
+    }
+}
+
+/// One non-empty k-bucket; `index` is the log2 of the XOR distance its peers share.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BucketInfo {
+    pub index: u32,
+    pub entries: Vec<(PeerId, Vec<Multiaddr>, kad::NodeStatus)>,
+}
+
+/// A value one peer returned for a record lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundValue {
+    /// `None` when the value came from our own store.
+    pub peer: Option<PeerId>,
+    /// The value as unwrapped from its envelope, if it came in one.
+    pub value: Vec<u8>,
+    pub signature: RecordSignature,
+}
+
+/// Groups the values a lookup returned by their bytes, in the order they first came in.
+pub fn value_variants(values: &[FoundValue]) -> Vec<Vec<&FoundValue>> {
+    let mut variants: Vec<Vec<&FoundValue>> = Vec::new();
+
+    for found in values {
+        match variants.iter_mut().find(|variant| variant[0].value == found.value) {
+            Some(variant) => variant.push(found),
+            None => variants.push(vec![found]),
+        }
+    }
+
+    variants
 }
 
 #[derive(Debug, Clone)]
 pub enum P2pOutboundEvent {
-    RecordFound(kad::RecordKey, Vec<u8>),
+    /// Everything a record lookup found once it's done; `agree` is false when peers returned
+    /// different values.
+    RecordResolved {
+        key: kad::RecordKey,
+        values: Vec<FoundValue>,
+        agree: bool,
+    },
     ProvidersFound(kad::RecordKey, Vec<PeerId>),
+    ClosestPeers(kad::RecordKey, Vec<PeerId>),
     RecordPut(kad::RecordKey),
     ProviderPut(kad::RecordKey),
+    ProviderStopped(kad::RecordKey),
+    /// A record we looked up was stored at this many of the close peers that lacked it.
+    RecordCached { key: kad::RecordKey, peers: usize },
+    BootstrapProgress { num_remaining: u32 },
+    BootstrapFinished,
+    NoKnownPeers,
+    NotProviding(kad::RecordKey),
+    RecordNotFound(kad::RecordKey),
+    QueryCancelled(kad::RecordKey),
 }
 
+/// Events are moved through the channel once and dropped, so the stored record stays inline.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 pub enum P2pInboundEvent {
     ProviderAdded(kad::RecordKey),
-    RecordStored(PeerId, kad::RecordKey, Vec<u8>),
+    /// Record lookups answered since the last report.
+    RecordsRequested { found: usize, missing: usize },
+    /// Provider lookups answered since the last report.
+    ProvidersRequested(usize),
+    RecordStored(PeerId, kad::RecordKey, Vec<u8>, Option<Instant>, RecordSignature),
+}
+
+/// Every local event is about a record, and the variant names read better saying so.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone)]
+pub enum P2pLocalEvent {
+    RecordStored(kad::Record),
+    RecordRemoved(kad::RecordKey),
+    RecordNotInStore(kad::RecordKey),
+    RecordExpired(kad::RecordKey),
+}
+
+#[derive(Debug, Clone)]
+pub enum P2pError {
+    GetRecordQuorumFailed { key: kad::RecordKey, got: usize, needed: usize },
+    GetRecordTimeout { key: kad::RecordKey },
+    PutRecordQuorumFailed { key: kad::RecordKey, got: usize, needed: usize },
+    PutRecordTimeout { key: kad::RecordKey, got: usize, needed: usize },
+    GetProvidersTimeout { key: kad::RecordKey },
+    GetClosestPeersTimeout { key: kad::RecordKey, peers: Vec<PeerId> },
+    StartProvidingTimeout { key: kad::RecordKey },
+    BootstrapTimeout { num_remaining: Option<u32> },
+    QueryTimedOut { key: kad::RecordKey },
+    Store { key: kad::RecordKey, error: kad::store::Error },
+    Identity { path: PathBuf, reason: String },
+    NoTopicPeers { topic: String },
+    Gossip { topic: String, reason: String },
+    DirectMessageUnsupported { peer_id: PeerId },
+    DirectMessageDialFailed { peer_id: PeerId },
+    DirectMessageTimeout { peer_id: PeerId },
+    DirectMessageConnectionClosed { peer_id: PeerId },
+    DirectMessageIo { peer_id: PeerId, reason: String },
+    FileUnavailable { key: String },
+    FileIo { key: String, path: PathBuf, reason: String },
+    RelayReservationFailed { relay: Multiaddr, reason: String },
+    ListenFailed { address: Multiaddr, reason: String },
+    MetricsUnavailable { address: SocketAddr, reason: String },
+    MdnsUnavailable { reason: String },
+    NotConnected { peer_id: PeerId },
+    InvalidAddress { input: String, reason: String },
+    DialFailed { address: Multiaddr, reason: String },
+    /// The `/dns*` host of a dialed address didn't resolve.
+    ResolveFailed { address: Multiaddr, host: String, reason: String },
+    ConnectionFailed { peer_id: Option<PeerId>, reason: String },
+    ExportFailed { path: PathBuf, reason: String },
+    ImportFailed { path: PathBuf, reason: String },
+    ReadFailed { path: PathBuf, reason: String },
+    /// The node's command channel was full, so a command was dropped instead of waiting.
+    NodeBusy,
+}
+
+impl P2pError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            P2pError::GetRecordTimeout { .. }
+                | P2pError::PutRecordTimeout { .. }
+                | P2pError::GetProvidersTimeout { .. }
+                | P2pError::GetClosestPeersTimeout { .. }
+                | P2pError::StartProvidingTimeout { .. }
+                | P2pError::BootstrapTimeout { .. }
+                | P2pError::QueryTimedOut { .. }
+        )
+    }
+}
+
+impl fmt::Display for P2pError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            P2pError::GetRecordQuorumFailed { key, got, needed } => write!(
+                f,
+                "Only {got} of {needed} required peers returned key {}",
+                format_key(key)
+            ),
+            P2pError::GetRecordTimeout { key } => write!(
+                f,
+                "Timed out looking up key {}",
+                format_key(key)
+            ),
+            P2pError::PutRecordQuorumFailed { key, got, needed } => write!(
+                f,
+                "Only {got} of {needed} required peers stored key {}",
+                format_key(key)
+            ),
+            P2pError::PutRecordTimeout { key, got, needed } => write!(
+                f,
+                "Timed out storing key {} ({got} of {needed} required peers stored it)",
+                format_key(key)
+            ),
+            P2pError::GetProvidersTimeout { key } => write!(
+                f,
+                "Timed out looking up providers for key {}",
+                format_key(key)
+            ),
+            P2pError::GetClosestPeersTimeout { key, peers } => write!(
+                f,
+                "Timed out looking for peers closest to key {}, found {} so far",
+                format_key(key),
+                peers.len()
+            ),
+            P2pError::StartProvidingTimeout { key } => write!(
+                f,
+                "Timed out announcing provider record for key {}",
+                format_key(key)
+            ),
+            P2pError::BootstrapTimeout { num_remaining } => match num_remaining {
+                Some(num_remaining) => {
+                    write!(f, "Bootstrap timed out with {num_remaining} buckets remaining")
+                }
+                None => write!(f, "Bootstrap timed out"),
+            },
+            P2pError::QueryTimedOut { key } => write!(
+                f,
+                "Gave up waiting for query on key {}",
+                format_key(key)
+            ),
+            P2pError::Store { key, error } => write!(
+                f,
+                "Failed to store key {}: {error}",
+                format_key(key)
+            ),
+            P2pError::Identity { path, reason } => write!(
+                f,
+                "Failed to persist identity at {}: {reason}, using a fresh one",
+                path.display()
+            ),
+            P2pError::NoTopicPeers { topic } => write!(
+                f,
+                "Nobody else is subscribed to {topic} yet, message was not sent"
+            ),
+            P2pError::Gossip { topic, reason } => write!(f, "Chat on {topic} failed: {reason}"),
+            P2pError::DirectMessageUnsupported { peer_id } => {
+                write!(f, "Peer {peer_id} does not support direct messages")
+            }
+            P2pError::DirectMessageDialFailed { peer_id } => {
+                write!(f, "Failed to reach {peer_id} to deliver direct message")
+            }
+            P2pError::DirectMessageTimeout { peer_id } => {
+                write!(f, "Direct message to {peer_id} timed out")
+            }
+            P2pError::DirectMessageConnectionClosed { peer_id } => write!(
+                f,
+                "Connection to {peer_id} closed before direct message was acknowledged"
+            ),
+            P2pError::DirectMessageIo { peer_id, reason } => {
+                write!(f, "Failed to send direct message to {peer_id}: {reason}")
+            }
+            P2pError::FileUnavailable { key } => {
+                write!(f, "No provider could deliver file {key}")
+            }
+            P2pError::FileIo { key, path, reason } => {
+                write!(f, "File {key} at {}: {reason}", path.display())
+            }
+            P2pError::RelayReservationFailed { relay, reason } => {
+                write!(f, "Relay reservation on {relay} failed: {reason}")
+            }
+            P2pError::ListenFailed { address, reason } => {
+                write!(f, "Failed to listen on {address}: {reason}")
+            }
+            P2pError::MetricsUnavailable { address, reason } => {
+                write!(f, "Failed to serve metrics on {address}: {reason}")
+            }
+            P2pError::MdnsUnavailable { reason } => write!(f, "Failed to start mDNS: {reason}"),
+            P2pError::NotConnected { peer_id } => write!(f, "Not connected to {peer_id}"),
+            P2pError::InvalidAddress { input, reason } => {
+                write!(f, "Invalid address {input}: {reason}")
+            }
+            P2pError::DialFailed { address, reason } => {
+                write!(f, "Failed to dial {address}: {reason}")
+            }
+            P2pError::ResolveFailed { host, reason, .. } => {
+                write!(f, "Could not resolve host {host}: {reason}")
+            }
+            P2pError::ConnectionFailed { peer_id, reason } => match peer_id {
+                Some(peer_id) => write!(f, "Failed to connect to {peer_id}: {reason}"),
+                None => write!(f, "Failed to connect: {reason}"),
+            },
+            P2pError::ExportFailed { path, reason } => {
+                write!(f, "Failed to export to {}: {reason}", path.display())
+            }
+            P2pError::ImportFailed { path, reason } => {
+                write!(f, "Failed to import {}: {reason}", path.display())
+            }
+            P2pError::ReadFailed { path, reason } => {
+                write!(f, "Failed to read {}: {reason}", path.display())
+            }
+            P2pError::NodeBusy => {
+                write!(f, "Networking is too busy to take more commands, try again")
+            }
+        }
+    }
+}
+
+impl From<kad::PutRecordError> for P2pError {
+    fn from(err: kad::PutRecordError) -> Self {
+        match err {
+            kad::PutRecordError::QuorumFailed {
+                key,
+                success,
+                quorum,
+            } => P2pError::PutRecordQuorumFailed {
+                key,
+                got: success.len(),
+                needed: quorum.get(),
+            },
+            kad::PutRecordError::Timeout {
+                key,
+                success,
+                quorum,
+            } => P2pError::PutRecordTimeout {
+                key,
+                got: success.len(),
+                needed: quorum.get(),
+            },
+        }
+    }
+}
+
+impl From<kad::GetProvidersError> for P2pError {
+    fn from(err: kad::GetProvidersError) -> Self {
+        match err {
+            kad::GetProvidersError::Timeout { key, .. } => P2pError::GetProvidersTimeout { key },
+        }
+    }
+}
+
+impl From<kad::GetClosestPeersError> for P2pError {
+    fn from(err: kad::GetClosestPeersError) -> Self {
+        match err {
+            kad::GetClosestPeersError::Timeout { key, peers } => {
+                P2pError::GetClosestPeersTimeout {
+                    key: key.into(),
+                    peers: peers.into_iter().map(|peer| peer.peer_id).collect(),
+                }
+            }
+        }
+    }
+}
+
+impl From<kad::AddProviderError> for P2pError {
+    fn from(err: kad::AddProviderError) -> Self {
+        match err {
+            kad::AddProviderError::Timeout { key } => P2pError::StartProvidingTimeout { key },
+        }
+    }
+}
+
+impl From<kad::BootstrapError> for P2pError {
+    fn from(err: kad::BootstrapError) -> Self {
+        match err {
+            kad::BootstrapError::Timeout { num_remaining, .. } => {
+                P2pError::BootstrapTimeout { num_remaining }
+            }
+        }
+    }
+}
+
+impl P2pError {
+    fn direct_message(peer_id: PeerId, failure: OutboundFailure) -> Self {
+        match failure {
+            OutboundFailure::UnsupportedProtocols => P2pError::DirectMessageUnsupported { peer_id },
+            OutboundFailure::DialFailure => P2pError::DirectMessageDialFailed { peer_id },
+            OutboundFailure::Timeout => P2pError::DirectMessageTimeout { peer_id },
+            OutboundFailure::ConnectionClosed => {
+                P2pError::DirectMessageConnectionClosed { peer_id }
+            }
+            OutboundFailure::Io(err) => P2pError::DirectMessageIo {
+                peer_id,
+                reason: err.to_string(),
+            },
+        }
+    }
 }
 
 impl fmt::Display for P2pEvent {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
+            P2pEvent::LocalIdentity(peer_id) => write!(f, "Local peer id is {peer_id}"),
             P2pEvent::Bootstrapped(address) => write!(f, "Listen on {address}"),
+            P2pEvent::ListenAddrExpired(address) => write!(f, "Stopped listening on {address}"),
             P2pEvent::PeerDiscovered(peer_id, address) => {
                 write!(f, "Discovered peer {peer_id} at {address}")
             }
             P2pEvent::PeerExpired(peer_id, address) => {
                 write!(f, "Expired peer {peer_id} at {address}")
             }
-            P2pEvent::Error(msg) => write!(f, "Something went wrong: {msg}"),
-            P2pEvent::Outbound(event) => match event {
-                P2pOutboundEvent::RecordFound(key, value) => write!(
+            P2pEvent::Dialing(address) => write!(f, "Dialing {address}"),
+            P2pEvent::DialingBootstrapPeer(address) => write!(f, "Dialing bootstrap peer {address}"),
+            P2pEvent::RememberedPeers(peers) => {
+                write!(f, "Remembered {} peers from earlier sessions", peers.len())
+            }
+            P2pEvent::PeerConnected {
+                peer_id,
+                relayed: true,
+                ..
+            } => write!(
+                f,
+                "Connected to {peer_id} through a relay, a hole punch may upgrade it"
+            ),
+            P2pEvent::PeerConnected {
+                peer_id,
+                address,
+                security: Some(security),
+                num_established: 1,
+                ..
+            } => write!(f, "Connected to peer {peer_id} at {address} over {security}"),
+            P2pEvent::PeerConnected {
+                peer_id,
+                address,
+                num_established: 1,
+                ..
+            } => write!(f, "Connected to peer {peer_id} at {address}"),
+            P2pEvent::PeerConnected {
+                peer_id,
+                address,
+                num_established,
+                ..
+            } => write!(
+                f,
+                "Opened connection {num_established} to peer {peer_id} at {address}"
+            ),
+            P2pEvent::PeerDisconnected {
+                peer_id,
+                num_established: 0,
+            } => write!(f, "Disconnected from peer {peer_id}"),
+            P2pEvent::PeerDisconnected {
+                peer_id,
+                num_established,
+            } => write!(
+                f,
+                "Closed a connection to peer {peer_id}, {num_established} remaining"
+            ),
+            P2pEvent::PeerIdentified {
+                peer_id,
+                agent,
+                protocols,
+            } => write!(
+                f,
+                "Identified peer {peer_id} as {agent} supporting {}",
+                protocols.join(", ")
+            ),
+            P2pEvent::DhtProtocolMissing { peer_id, protocol } => write!(
+                f,
+                "Peer {peer_id} doesn't serve {protocol}; it uses another DHT or is in client mode"
+            ),
+            P2pEvent::PingRtt(peer_id, rtt) => {
+                write!(f, "Ping to {peer_id} took {} ms", rtt.as_millis())
+            }
+            P2pEvent::PeerUnresponsive(peer_id) => write!(f, "Peer {peer_id} stopped responding"),
+            P2pEvent::TopicSubscribed(topic) => write!(f, "Subscribed to topic {topic}"),
+            P2pEvent::TopicUnsubscribed(topic) => write!(f, "Unsubscribed from topic {topic}"),
+            P2pEvent::GossipMessage {
+                topic,
+                source,
+                data,
+            } => match source {
+                Some(source) => write!(
                     f,
-                    "Outbound: Found record value for {key:?}: {}",
-                    String::from_utf8(value.clone()).unwrap()
+                    "Message on {topic} from {source}: {}",
+                    format_record_value(data)
                 ),
-                P2pOutboundEvent::ProvidersFound(key, peer_ids) => {
-                    write!(f, "Outbound: Found providers for {key:?}: {peer_ids:?}")
+                None => write!(f, "Message on {topic}: {}", format_record_value(data)),
+            },
+            P2pEvent::GossipPublished { topic, data } => {
+                write!(f, "Published on {topic}: {}", format_record_value(data))
+            }
+            P2pEvent::DirectMessageReceived(peer_id, text) => {
+                write!(f, "Direct message from {peer_id}: {text}")
+            }
+            P2pEvent::DirectMessageDelivered(peer_id) => {
+                write!(f, "Direct message delivered to {peer_id}")
+            }
+            P2pEvent::FileTransferProgress {
+                key,
+                received,
+                total,
+            } => write!(f, "Fetching {key}: {received} of {total} bytes"),
+            P2pEvent::FileFetched { key, path } => {
+                write!(f, "Fetched file {key} to {}", path.display())
+            }
+            P2pEvent::RelayReservationAccepted { relay, renewal: false } => {
+                write!(f, "Relay {relay} accepted our reservation")
+            }
+            P2pEvent::RelayReservationAccepted { relay, renewal: true } => {
+                write!(f, "Relay {relay} renewed our reservation")
+            }
+            P2pEvent::HolePunch {
+                remote,
+                success: true,
+            } => write!(f, "Hole punch to {remote} succeeded, connection is now direct"),
+            P2pEvent::HolePunch {
+                remote,
+                success: false,
+            } => write!(
+                f,
+                "Hole punch to {remote} failed, keeping the relayed connection"
+            ),
+            P2pEvent::NatStatus(NatStatus::Public(address)) => {
+                write!(f, "Publicly reachable at {address}")
+            }
+            P2pEvent::NatStatus(NatStatus::Private) => write!(f, "Behind NAT, not publicly reachable"),
+            P2pEvent::NatStatus(NatStatus::Unknown) => write!(f, "Reachability is unknown"),
+            P2pEvent::KadModeChanged(mode) => write!(f, "Kademlia switched to {mode} mode"),
+            P2pEvent::RoutingTable(buckets) => write!(
+                f,
+                "Routing table holds {} peers in {} buckets",
+                buckets.iter().map(|bucket| bucket.entries.len()).sum::<usize>(),
+                buckets.len()
+            ),
+            P2pEvent::RoutingUpdated {
+                peer_id,
+                bucket,
+                is_new_peer: true,
+                evicted: Some(evicted),
+            } => write!(f, "Added {peer_id} to bucket {bucket}, evicting {evicted}"),
+            P2pEvent::RoutingUpdated {
+                peer_id,
+                bucket,
+                is_new_peer: true,
+                evicted: None,
+            } => write!(f, "Added {peer_id} to bucket {bucket}"),
+            P2pEvent::RoutingUpdated {
+                peer_id, bucket, ..
+            } => write!(f, "Updated addresses of {peer_id} in bucket {bucket}"),
+            P2pEvent::UnroutablePeer(peer_id) => {
+                write!(f, "{peer_id} connected without a known listen address")
+            }
+            P2pEvent::PeerBlocked(peer_id) => write!(f, "Blocked peer {peer_id}"),
+            P2pEvent::PeerUnblocked(peer_id) => write!(f, "Unblocked peer {peer_id}"),
+            P2pEvent::RelayServerToggled(true) => write!(f, "Acting as a relay for other peers"),
+            P2pEvent::RelayServerToggled(false) => write!(f, "No longer acting as a relay"),
+            P2pEvent::MdnsToggled(true) => write!(f, "Discovering peers on the local network"),
+            P2pEvent::MdnsToggled(false) => write!(f, "Local discovery (mDNS) is off"),
+            P2pEvent::ConnectionDenied { peer, address, reason } => {
+                match (peer, address) {
+                    (Some(peer), _) => write!(f, "Connection with {peer} denied: {reason}"),
+                    (None, Some(address)) => {
+                        write!(f, "Incoming connection from {address} denied: {reason}")
+                    }
+                    (None, None) => write!(f, "Connection denied: {reason}"),
                 }
-                P2pOutboundEvent::RecordPut(key) => {
-                    write!(f, "Outbound: Successfully put record with {key:?}")
+            }
+            P2pEvent::MetricsServing(address) => {
+                write!(f, "Serving metrics on http://{address}/metrics")
+            }
+            P2pEvent::RelayReservationServed { peer_id, renewed: false } => {
+                write!(f, "Accepted relay reservation from {peer_id}")
+            }
+            P2pEvent::RelayReservationServed { peer_id, renewed: true } => {
+                write!(f, "Renewed relay reservation for {peer_id}")
+            }
+            P2pEvent::RelayCircuitOpened { src, dst } => {
+                write!(f, "Relaying circuit from {src} to {dst}")
+            }
+            P2pEvent::RelayCircuitClosed { src, dst } => {
+                write!(f, "Closed relayed circuit from {src} to {dst}")
+            }
+            P2pEvent::StoreRestored { providers } => write!(
+                f,
+                "Restored the record store, providing {} keys again",
+                providers.len()
+            ),
+            P2pEvent::Republished { records, providers } => write!(
+                f,
+                "Republished {records} records and {providers} provider records"
+            ),
+            P2pEvent::BenchmarkFinished(report) => write!(f, "{report}"),
+            P2pEvent::Bandwidth {
+                inbound_bps,
+                outbound_bps,
+                ..
+            } => write!(f, "Receiving {inbound_bps} B/s, sending {outbound_bps} B/s"),
+            P2pEvent::InboundApprovalToggled(true) => {
+                write!(f, "Inbound records now wait for approval")
+            }
+            P2pEvent::InboundApprovalToggled(false) => {
+                write!(f, "Inbound records are stored automatically")
+            }
+            P2pEvent::PeerRateLimited(peer_id) => {
+                write!(f, "Dropping store requests from {peer_id} until it slows down")
+            }
+            P2pEvent::InboundPending {
+                id,
+                source,
+                key,
+                value: Some(value),
+            } => write!(
+                f,
+                "[{id}] {source} wants to store {} with value {}, awaiting approval",
+                format_key(key),
+                format_record_value(value)
+            ),
+            P2pEvent::InboundPending {
+                id,
+                source,
+                key,
+                value: None,
+            } => write!(
+                f,
+                "[{id}] {source} wants to provide {}, awaiting approval",
+                format_key(key)
+            ),
+            P2pEvent::InboundExpired(id) => write!(f, "[{id}] Inbound request expired unanswered"),
+            P2pEvent::InboundRejected { source, key, reason } => {
+                write!(f, "Rejected {} from {source}: {reason}", format_key(key))
+            }
+            P2pEvent::LocalRecords(records) => {
+                write!(f, "Local store holds {} records", records.len())
+            }
+            P2pEvent::Ready(_) => write!(f, "Networking started"),
+            P2pEvent::ShutdownComplete => write!(f, "Networking shut down"),
+            P2pEvent::Error(Some(request_id), err) => write!(f, "[{request_id}] {err}"),
+            P2pEvent::Error(None, err) => write!(f, "Something went wrong: {err}"),
+            P2pEvent::Outbound(request_id, event) => {
+                if let Some(request_id) = request_id {
+                    write!(f, "[{request_id}] ")?;
                 }
-                P2pOutboundEvent::ProviderPut(key) => {
-                    write!(f, "Outbound: Successfully started providing record with {key:?}")
+
+                match event {
+                    P2pOutboundEvent::RecordResolved {
+                        key,
+                        values,
+                        agree: false,
+                    } => write!(
+                        f,
+                        "Outbound: Peers disagree on {}: {} different values from {} peers",
+                        format_key(key),
+                        value_variants(values).len(),
+                        values.len()
+                    ),
+                    P2pOutboundEvent::RecordResolved { key, values, .. } => {
+                        let Some(found) = values.first() else {
+                            return write!(f, "Outbound: No value found for {}", format_key(key));
+                        };
+
+                        write!(
+                            f,
+                            "Outbound: Found record value for {}: {} ({})",
+                            format_key(key),
+                            format_record_value(&found.value),
+                            found.signature
+                        )?;
+
+                        match values.len() {
+                            1 => Ok(()),
+                            count => write!(f, " from {count} peers"),
+                        }
+                    }
+                    P2pOutboundEvent::ProvidersFound(key, peer_ids) => {
+                        write!(f, "Outbound: Found providers for {}: {peer_ids:?}", format_key(key))
+                    }
+                    P2pOutboundEvent::ClosestPeers(key, peer_ids) => write!(
+                        f,
+                        "Outbound: Found {} peers closest to {}",
+                        peer_ids.len(),
+                        format_key(key)
+                    ),
+                    P2pOutboundEvent::RecordPut(key) => {
+                        write!(f, "Outbound: Successfully put record with {}", format_key(key))
+                    }
+                    P2pOutboundEvent::ProviderPut(key) => {
+                        write!(
+                            f,
+                            "Outbound: Successfully started providing record with {}",
+                            format_key(key)
+                        )
+                    }
+                    P2pOutboundEvent::ProviderStopped(key) => {
+                        write!(f, "Outbound: Stopped providing record with {}", format_key(key))
+                    }
+                    P2pOutboundEvent::RecordCached { key, peers } => write!(
+                        f,
+                        "Outbound: Cached record {} at {peers} peers that didn't have it",
+                        format_key(key)
+                    ),
+                    P2pOutboundEvent::BootstrapProgress { num_remaining } => {
+                        write!(f, "Outbound: Bootstrapping, {num_remaining} buckets remaining")
+                    }
+                    P2pOutboundEvent::BootstrapFinished => write!(f, "Outbound: Bootstrap finished"),
+                    P2pOutboundEvent::NoKnownPeers => {
+                        write!(f, "Outbound: Cannot bootstrap, no known peers yet")
+                    }
+                    P2pOutboundEvent::RecordNotFound(key) => write!(
+                        f,
+                        "Outbound: No value stored for key {}",
+                        format_key(key)
+                    ),
+                    P2pOutboundEvent::QueryCancelled(key) => write!(
+                        f,
+                        "Outbound: Cancelled query for key {}",
+                        format_key(key)
+                    ),
+                    P2pOutboundEvent::NotProviding(key) => {
+                        write!(
+                            f,
+                            "Outbound: Not providing record with {}, nothing to stop",
+                            format_key(key)
+                        )
+                    }
                 }
-            },
+            }
             P2pEvent::Inbound(event) => match event {
                 P2pInboundEvent::ProviderAdded(key) => {
-                    write!(f, "Inbound: Received new provider for {key:?}")
+                    write!(f, "Inbound: Received new provider for {}", format_key(key))
                 }
-                P2pInboundEvent::RecordStored(source_id, key, value) => write!(
+                P2pInboundEvent::RecordsRequested { found, missing } => write!(
                     f,
-                    "Inbound: Stored new record from {source_id} with {key:?} and value {}",
-                    String::from_utf8(value.clone()).unwrap()
+                    "Inbound: Answered {} record requests, {found} from the local store",
+                    found + missing
                 ),
+                P2pInboundEvent::ProvidersRequested(count) => {
+                    write!(f, "Inbound: Answered {count} provider lookups")
+                }
+                P2pInboundEvent::RecordStored(source_id, key, value, _, signature) => write!(
+                    f,
+                    "Inbound: Stored new record from {source_id} with {} and value {} ({})",
+                    format_key(key),
+                    format_record_value(value),
+                    signature
+                ),
+            },
+            P2pEvent::Local(event) => match event {
+                P2pLocalEvent::RecordStored(record) => {
+                    write!(f, "Local: Stored record with {} in the store", format_key(&record.key))
+                }
+                P2pLocalEvent::RecordRemoved(key) => {
+                    write!(f, "Local: Removed record with {} from the store", format_key(key))
+                }
+                P2pLocalEvent::RecordNotInStore(key) => {
+                    write!(
+                        f,
+                        "Local: No record with {} in the store, nothing to remove",
+                        format_key(key)
+                    )
+                }
+                P2pLocalEvent::RecordExpired(key) => {
+                    write!(f, "Local: Record with {} expired locally", format_key(key))
+                }
             },
         }
     }
 }
 
-#[derive(NetworkBehaviour)]
-struct CustomBehaviour {
-    kademlia: kad::Behaviour<MemoryStore>,
-    mdns: mdns::tokio::Behaviour,
-}
+const MAX_DISPLAYED_VALUE_CHARS: usize = 256;
+const ENCRYPTED_VALUE_LABEL: &str = "encrypted (wrong passphrase?)";
 
-pub async fn run(mut commands: mpsc::Receiver<P2pCommand>, mut events: mpsc::Sender<P2pEvent>) {
-    let mut kad_config = kad::Config::default();
-    kad_config.set_record_filtering(StoreInserts::FilterBoth);
+/// Renders untrusted record bytes as UTF-8 when possible, hex otherwise, truncated with an ellipsis.
+///
+/// The app decrypts what it can before showing it, so a value still encrypted here was sealed
+/// under a passphrase other than ours.
+pub fn format_record_value(value: &[u8]) -> String {
+    if envelope::is_encrypted(value) {
+        return ENCRYPTED_VALUE_LABEL.to_owned();
+    }
 
-    let mdns_config = mdns::Config {
-        ttl: Duration::from_secs(5),
-        query_interval: Duration::from_secs(4),
-        ..Default::default()
+    let formatted = match std::str::from_utf8(value) {
+        Ok(text) => text.to_owned(),
+        Err(_) => to_hex(value),
     };
 
-    let mut swarm: Swarm<CustomBehaviour> = SwarmBuilder::with_new_identity()
-        .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )
-        .expect("Failed to build tcp config")
-        .with_quic()
-        .with_behaviour(|key| {
-            Ok(CustomBehaviour {
-                kademlia: kad::Behaviour::with_config(
-                    key.public().to_peer_id(),
-                    MemoryStore::new(key.public().to_peer_id()),
-                    kad_config,
-                ),
-                mdns: mdns::tokio::Behaviour::new(
-                    mdns_config,
-                    key.public().to_peer_id(),
-                )
-                .expect("Failed to set up mDNS behaviour"),
-            })
-        })
-        .expect("Failed to build Swarm")
-        .build();
+    if formatted.chars().count() > MAX_DISPLAYED_VALUE_CHARS {
+        let truncated: String = formatted.chars().take(MAX_DISPLAYED_VALUE_CHARS).collect();
+        format!("{truncated}…")
+    } else {
+        formatted
+    }
+}
 
-    swarm.behaviour_mut().kademlia.set_mode(Some(Mode::Server));
+/// Renders a record key as text when it is printable UTF-8, as `0x`-prefixed hex otherwise.
+///
+/// Text that itself looks like hex is rendered as hex too, so [`parse_key`] always round-trips.
+pub fn format_key(key: &kad::RecordKey) -> String {
+    format_bytes(key.as_ref())
+}
 
-    swarm
-        .listen_on(
-            "/ip4/0.0.0.0/tcp/0"
-                .parse()
-                .expect("Failed to parse multiaddress"),
-        )
-        .expect("Failed to start a Swarm");
+/// Turns user input into a record key: `0x`-prefixed hex is taken as the raw key bytes,
+/// anything else is a human key, put in `namespace` and stored under its SHA-256 digest when
+/// `hash_keys` is set. Every way of typing a key goes through here, so none skips the namespace.
+pub fn parse_key(input: &str, namespace: &str, hash_keys: bool) -> kad::RecordKey {
+    let input = namespaced(input, namespace);
 
-    loop {
-        select! {
-            cmd = commands.select_next_some() => handle_command(cmd, &mut swarm).await,
-            event = swarm.select_next_some() => handle_swarm_event(event, &mut swarm, &mut events).await,
+    if hash_keys && !is_hex(&input) {
+        kad::RecordKey::from(Sha256::digest(input.as_bytes()).to_vec())
+    } else {
+        kad::RecordKey::from(parse_bytes(&input))
+    }
+}
+
+/// The key named by [`format_key`] output, taken as is.
+pub fn parse_raw_key(input: &str) -> kad::RecordKey {
+    parse_key(input, "", false)
+}
+
+/// Puts a human key in `namespace`; `0x` hex keys are the raw key and stay as they are.
+pub fn namespaced(input: &str, namespace: &str) -> String {
+    match namespace {
+        "" => input.to_owned(),
+        _ if is_hex(input) => input.to_owned(),
+        namespace => format!("{namespace}/{input}"),
+    }
+}
+
+/// The byte encoding behind [`format_key`], also used for record values in exports.
+pub fn format_bytes(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.starts_with("0x") && !text.chars().any(char::is_control) => {
+            text.to_owned()
         }
+        _ => to_hex(bytes),
     }
 }
 
-async fn handle_command(cmd: P2pCommand, swarm: &mut Swarm<CustomBehaviour>) {
-    match cmd {
-        P2pCommand::GetRecord(key) => {
-            let key = kad::RecordKey::new(&key);
-            swarm.behaviour_mut().kademlia.get_record(key);
+pub fn parse_bytes(input: &str) -> Vec<u8> {
+    input
+        .strip_prefix("0x")
+        .and_then(from_hex)
+        .unwrap_or_else(|| input.as_bytes().to_vec())
+}
+
+/// Whether [`parse_bytes`] takes `input` as `0x`-prefixed hex rather than text.
+pub fn is_hex(input: &str) -> bool {
+    input.strip_prefix("0x").and_then(from_hex).is_some()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("0x{hex}")
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+const RECORD_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often answered GET requests are summed up into one event.
+const SERVED_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+const BANDWIDTH_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+const QUERY_TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_KNOWN_PEER_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+pub const DEFAULT_RECORD_REPUBLISH_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+pub const DEFAULT_PROVIDER_REPUBLISH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const REDIALED_KNOWN_PEERS: usize = 8;
+
+struct PendingQuery {
+    request_id: RequestId,
+    kind: QueryKind,
+    key: kad::RecordKey,
+    started_at: Instant,
+    found: bool,
+    finished: bool,
+    /// Values a record lookup waits for before it's resolved.
+    quorum: NonZeroUsize,
+    values: Vec<FoundValue>,
+    /// The record a lookup may cache at peers that didn't return it: the first one found, as
+    /// long as its signature holds and no peer returned a different value.
+    cacheable: Option<kad::Record>,
+    /// Peers a cache put went to; zero for every other query.
+    cache_peers: usize,
+}
+
+impl PendingQuery {
+    fn new(request_id: RequestId, kind: QueryKind, key: kad::RecordKey) -> Self {
+        Self {
+            request_id,
+            kind,
+            key,
+            started_at: Instant::now(),
+            found: false,
+            finished: false,
+            quorum: NonZeroUsize::MIN,
+            values: Vec::new(),
+            cacheable: None,
+            cache_peers: 0,
         }
-        P2pCommand::GetProviders(key) => {
-            let key = kad::RecordKey::new(&key);
-            swarm.behaviour_mut().kademlia.get_providers(key);
+    }
+
+    /// Hands over the values a record lookup collected.
+    fn resolve(&mut self) -> P2pOutboundEvent {
+        let values = mem::take(&mut self.values);
+        let agree = value_variants(&values).len() <= 1;
+
+        P2pOutboundEvent::RecordResolved {
+            key: self.key.clone(),
+            values,
+            agree,
         }
-        P2pCommand::PutRecord(key, value) => {
-            let key = kad::RecordKey::new(&key);
-            let record = kad::Record::new(key, value);
+    }
+}
 
-            swarm
-                .behaviour_mut()
-                .kademlia
-                .put_record(record, kad::Quorum::One)
-                .expect("Failed to store record");
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayLimits {
+    pub max_reservations: usize,
+    pub max_circuits: usize,
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        let config = relay::Config::default();
+
+        Self {
+            max_reservations: config.max_reservations,
+            max_circuits: config.max_circuits,
         }
-        P2pCommand::PutProvider(key) => {
-            let key = kad::RecordKey::new(&key);
-            swarm
-                .behaviour_mut()
-                .kademlia
-                .start_providing(key)
-                .expect("Failed to start providing key");
+    }
+}
+
+/// Kademlia knobs worth changing on small networks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KadTuning {
+    /// How many peers a record is stored on.
+    pub replication_factor: NonZeroUsize,
+    /// How many peers a query asks at the same time.
+    pub parallelism: NonZeroUsize,
+    pub disjoint_query_paths: bool,
+    /// Store records we looked up at the closest peers that didn't return them.
+    pub cache_records: bool,
+}
+
+impl Default for KadTuning {
+    fn default() -> Self {
+        Self {
+            replication_factor: kad::K_VALUE,
+            parallelism: kad::ALPHA_VALUE,
+            disjoint_query_paths: false,
+            cache_records: false,
         }
     }
 }
 
-async fn handle_swarm_event(
-    event: SwarmEvent<CustomBehaviourEvent>,
-    swarm: &mut Swarm<CustomBehaviour>,
-    sender: &mut mpsc::Sender<P2pEvent>,
-) {
-    match event {
-        SwarmEvent::NewListenAddr { address, .. } => {
-            info!("Listening on {address:?}");
-            sender
-                .send(P2pEvent::Bootstrapped(address))
-                .await
-                .expect("Failed to send");
+/// Caps on connections; `None` leaves a count unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    pub max_established: Option<u32>,
+    pub max_established_per_peer: Option<u32>,
+    pub max_pending_incoming: Option<u32>,
+    pub max_pending_outgoing: Option<u32>,
+    /// How many addresses of a peer are dialed at the same time.
+    pub dial_concurrency: NonZeroU8,
+    /// How long a connection no behaviour needs stays open.
+    pub idle_timeout: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_established: None,
+            max_established_per_peer: None,
+            max_pending_incoming: None,
+            max_pending_outgoing: None,
+            dial_concurrency: NonZeroU8::new(8).expect("8 > 0"),
+            idle_timeout: Duration::from_secs(10),
         }
-        SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
-            for (peer_id, multiaddr) in list {
-                info!("Discovered peer {peer_id} at {multiaddr}");
-                swarm
-                    .behaviour_mut()
-                    .kademlia
-                    .add_address(&peer_id, multiaddr.clone());
-                sender
-                    .send(P2pEvent::PeerDiscovered(peer_id, multiaddr))
-                    .await
-                    .expect("Failed to send");
-            }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DirectMessage {
+    text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Ack;
+
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub listen_addrs: Vec<Multiaddr>,
+    pub bootstrap_peers: Vec<Multiaddr>,
+    pub bootstrap_interval: Duration,
+    pub query_timeout: Duration,
+    pub fresh_identity: bool,
+    pub relay_server: bool,
+    pub relay_limits: RelayLimits,
+    pub connection_limits: ConnectionLimits,
+    pub inbound_approval: bool,
+    pub inbound_limits: InboundLimits,
+    pub record_store: StoreConfig,
+    pub known_peers: Option<PathBuf>,
+    pub known_peer_max_age: Duration,
+    pub mdns: MdnsTiming,
+    pub republish: RepublishIntervals,
+    /// At least one of the two transports has to stay enabled.
+    pub tcp: bool,
+    pub quic: bool,
+    pub websocket: bool,
+    pub security: Security,
+    /// Whether to discover peers on the local network; can be switched while running.
+    pub mdns_enabled: bool,
+    pub kad_mode: Mode,
+    pub kad_protocol: StreamProtocol,
+    pub kad_tuning: KadTuning,
+    /// Whether to wrap the records we put in an envelope signed with our identity key; can be
+    /// switched while running.
+    pub sign_records: bool,
+    pub identity_path: Option<PathBuf>,
+    pub metrics: Option<Arc<Metrics>>,
+    /// Where to serve `/metrics` from; only used together with `metrics`.
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MdnsTiming {
+    pub ttl: Duration,
+    pub query_interval: Duration,
+}
+
+impl Default for MdnsTiming {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(5),
+            query_interval: Duration::from_secs(4),
+        }
+    }
+}
+
+/// How often we put what we published ourselves again, before it expires at the peers holding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepublishIntervals {
+    pub records: Duration,
+    pub providers: Duration,
+}
+
+impl Default for RepublishIntervals {
+    fn default() -> Self {
+        Self {
+            records: DEFAULT_RECORD_REPUBLISH_INTERVAL,
+            providers: DEFAULT_PROVIDER_REPUBLISH_INTERVAL,
+        }
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            listen_addrs: default_listen_addrs(true),
+            bootstrap_peers: Vec::new(),
+            bootstrap_interval: DEFAULT_BOOTSTRAP_INTERVAL,
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+            fresh_identity: false,
+            relay_server: false,
+            relay_limits: RelayLimits::default(),
+            connection_limits: ConnectionLimits::default(),
+            inbound_approval: false,
+            inbound_limits: InboundLimits::default(),
+            record_store: StoreConfig::Memory,
+            known_peers: None,
+            known_peer_max_age: DEFAULT_KNOWN_PEER_MAX_AGE,
+            mdns: MdnsTiming::default(),
+            republish: RepublishIntervals::default(),
+            tcp: true,
+            quic: true,
+            websocket: false,
+            security: Security::Noise,
+            mdns_enabled: true,
+            kad_mode: Mode::Server,
+            kad_protocol: kad::PROTOCOL_NAME,
+            kad_tuning: KadTuning::default(),
+            sign_records: false,
+            identity_path: None,
+            metrics: None,
+            metrics_addr: None,
+        }
+    }
+}
+
+pub fn default_websocket_listen_addr() -> Multiaddr {
+    Multiaddr::empty()
+        .with(Protocol::Ip4(Ipv4Addr::UNSPECIFIED))
+        .with(Protocol::Tcp(0))
+        .with(Protocol::Ws("/".into()))
+}
+
+pub fn default_listen_addrs(ipv6: bool) -> Vec<Multiaddr> {
+    let mut addrs = vec![
+        Multiaddr::empty()
+            .with(Protocol::Ip4(Ipv4Addr::UNSPECIFIED))
+            .with(Protocol::Tcp(0)),
+        Multiaddr::empty()
+            .with(Protocol::Ip4(Ipv4Addr::UNSPECIFIED))
+            .with(Protocol::Udp(0))
+            .with(Protocol::QuicV1),
+    ];
+
+    if ipv6 {
+        addrs.push(
+            Multiaddr::empty()
+                .with(Protocol::Ip6(Ipv6Addr::UNSPECIFIED))
+                .with(Protocol::Tcp(0)),
+        );
+        addrs.push(
+            Multiaddr::empty()
+                .with(Protocol::Ip6(Ipv6Addr::UNSPECIFIED))
+                .with(Protocol::Udp(0))
+                .with(Protocol::QuicV1),
+        );
+    }
+
+    addrs
+}
+
+pub async fn run(
+    mut commands: mpsc::Receiver<P2pCommand>,
+    mut events: mpsc::Sender<P2pEvent>,
+    config: NodeConfig,
+) -> Result<(), String> {
+    if let Some(metrics) = &config.metrics {
+        events = metrics::tap(events, metrics.clone());
+    }
+
+    let keypair = if config.fresh_identity {
+        info!("Using a fresh identity as requested");
+        identity::Keypair::generate_ed25519()
+    } else {
+        load_or_create_identity(config.identity_path.clone(), &mut events).await
+    };
+    let local_peer_id = keypair.public().to_peer_id();
+    info!("Local peer id is {local_peer_id}");
+    events.send(P2pEvent::LocalIdentity(local_peer_id)).await.ok();
+
+    let swarm_config = SwarmConfig {
+        tcp: config.tcp,
+        quic: config.quic,
+        websocket: config.websocket,
+        security: config.security,
+        mdns: config.mdns_enabled.then_some(config.mdns),
+        relay_limits: config.relay_server.then_some(config.relay_limits),
+        connection_limits: config.connection_limits,
+        kad_protocol: config.kad_protocol,
+        kad_tuning: config.kad_tuning,
+        query_timeout: config.query_timeout,
+        record_store: config.record_store.clone(),
+    };
+    let mut signing_key = config.sign_records.then(|| keypair.clone());
+    let (mut swarm, mut bandwidth, negotiated) =
+        build_measured_swarm(&swarm_config, keypair.clone()).map_err(|err| err.to_string())?;
+
+    swarm.behaviour_mut().kademlia.set_mode(Some(config.kad_mode));
+
+    let mut listeners = Vec::new();
+
+    for address in config.listen_addrs {
+        match swarm.listen_on(address.clone()) {
+            Ok(listener_id) => listeners.push(listener_id),
+            Err(err) => {
+                error!("Failed to listen on {address}: {err}");
+                events
+                    .send(P2pEvent::Error(None, P2pError::ListenFailed {
+                        address,
+                        reason: err.to_string(),
+                    }))
+                    .await
+                    .ok();
+            }
+        }
+    }
+
+    republish_restored(&mut swarm, &mut events).await;
+    connect_bootstrap_peers(&mut swarm, &config.bootstrap_peers, &mut events).await;
+
+    let mut known_peers = KnownPeers::open(config.known_peers, config.known_peer_max_age);
+    redial_known_peers(&mut swarm, &known_peers, &mut events).await;
+
+    let metrics_server = match (&config.metrics, config.metrics_addr) {
+        (Some(metrics), Some(address)) => serve_metrics(metrics, address, &mut events).await,
+        _ => None,
+    };
+
+    let mut expiry_ticks = ticks(RECORD_EXPIRY_CHECK_INTERVAL);
+    let mut served_ticks = ticks(SERVED_REPORT_INTERVAL);
+    let mut bandwidth_ticks = ticks(BANDWIDTH_REPORT_INTERVAL);
+    let mut bootstrap_ticks = ticks(config.bootstrap_interval);
+    let mut query_timeout_ticks = ticks(QUERY_TIMEOUT_CHECK_INTERVAL);
+    let mut record_republish_ticks = delayed_ticks(config.republish.records);
+    let mut provider_republish_ticks = delayed_ticks(config.republish.providers);
+    let mut pending_queries = HashMap::new();
+    let mut transfers = FileTransfers::default();
+    let mut relay_listeners = HashMap::new();
+    let mut mdns_peers = MdnsPeers::default();
+    let mut inbound = InboundQueue::default();
+    inbound.approval_required = config.inbound_approval;
+    inbound.limits = config.inbound_limits;
+
+    loop {
+        if events.is_closed() {
+            info!("Nobody is listening for events anymore");
+            break;
+        }
+
+        select! {
+            cmd = commands.next() => match cmd {
+                None | Some(P2pCommand::Shutdown) => break,
+                Some(P2pCommand::SetMdnsEnabled(enabled)) => {
+                    mdns_peers = MdnsPeers::default();
+                    set_mdns(&mut swarm, enabled.then_some(config.mdns), &mut events).await
+                }
+                Some(P2pCommand::SetRecordSigning(enabled)) => {
+                    info!("Record signing {}", if enabled { "enabled" } else { "disabled" });
+                    signing_key = enabled.then(|| keypair.clone());
+                }
+                Some(mut cmd) => {
+                    if let Some(keypair) = &signing_key {
+                        sign_put(&mut cmd, keypair);
+                    }
+
+                    if let Some(metrics) = &config.metrics {
+                        metrics.record_command(&cmd);
+                    }
+
+                    handle_command(
+                        cmd,
+                        &mut swarm,
+                        &mut events,
+                        &mut pending_queries,
+                        &mut transfers,
+                        &mut relay_listeners,
+                        &mut inbound,
+                    )
+                    .await
+                }
+            },
+            mut event = swarm.select_next_some() => {
+                remember_peer(&mut known_peers, &event);
+                mdns_peers.dedup(&mut event);
+
+                if let Some(metrics) = &config.metrics {
+                    metrics.record_swarm_event(&event);
+                }
+
+                handle_swarm_event(
+                    event,
+                    &mut swarm,
+                    &mut events,
+                    &mut pending_queries,
+                    &mut transfers,
+                    (&mut relay_listeners, &negotiated),
+                    &mut inbound,
+                )
+                .await
+            }
+            job = transfers.jobs.select_next_some() => {
+                finish_file_job(job, &mut swarm, &mut events, &mut pending_queries, &mut transfers)
+                    .await
+            }
+            _ = expiry_ticks.select_next_some() => {
+                remove_expired_records(&mut swarm, &mut events).await;
+                inbound.uploads.prune(swarm.behaviour_mut().kademlia.store_mut());
+                expire_pending_inbound(&mut inbound, &mut events).await
+            }
+            _ = served_ticks.select_next_some() => report_served(&mut inbound, &mut events).await,
+            _ = bandwidth_ticks.select_next_some() => {
+                if let Some(event) = bandwidth.read() {
+                    events.send(event).await.ok();
+                }
+            }
+            _ = bootstrap_ticks.select_next_some() => periodic_bootstrap(&mut swarm),
+            _ = record_republish_ticks.select_next_some() => {
+                let records = republish_records(&mut swarm);
+                report_republished(&mut events, records, 0).await
+            }
+            _ = provider_republish_ticks.select_next_some() => {
+                let providers = republish_providers(&mut swarm).len();
+                report_republished(&mut events, 0, providers).await
+            }
+            _ = query_timeout_ticks.select_next_some() => {
+                finish_timed_out_queries(
+                    &mut swarm,
+                    &mut events,
+                    &mut pending_queries,
+                    config.query_timeout,
+                )
+                .await
+            }
+        }
+    }
+
+    info!("Shutting down networking");
+
+    for listener_id in listeners.into_iter().chain(relay_listeners.into_keys()) {
+        swarm.remove_listener(listener_id);
+    }
+
+    if let Some(server) = metrics_server {
+        server.abort();
+    }
+
+    swarm.behaviour_mut().kademlia.store_mut().flush();
+    drop(swarm);
+
+    events.send(P2pEvent::ShutdownComplete).await.ok();
+
+    Ok(())
+}
+
+/// Starts or stops local discovery; peers found so far stay in the routing table.
+async fn set_mdns(
+    swarm: &mut Swarm<CustomBehaviour>,
+    timing: Option<MdnsTiming>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+) {
+    let local_peer_id = *swarm.local_peer_id();
+
+    match mdns_behaviour(local_peer_id, timing) {
+        Ok(mdns) => {
+            swarm.behaviour_mut().mdns = mdns;
+
+            info!("mDNS {}", if timing.is_some() { "enabled" } else { "disabled" });
+            sender.send(P2pEvent::MdnsToggled(timing.is_some())).await.ok();
+        }
+        Err(err) => {
+            error!("Failed to start mDNS: {err}");
+            let reason = err.to_string();
+            sender.send(P2pEvent::Error(None, P2pError::MdnsUnavailable { reason })).await.ok();
+        }
+    }
+}
+
+/// Wraps the value of a put in a signed envelope; a key that can't sign leaves it plain.
+fn sign_put(cmd: &mut P2pCommand, keypair: &identity::Keypair) {
+    let P2pCommand::PutRecord(_, key, value, ..) = cmd else {
+        return;
+    };
+
+    match envelope::seal(keypair, key, value) {
+        Ok(sealed) => *value = sealed,
+        Err(err) => warn!("Failed to sign {}, putting it unsigned: {err}", format_key(key)),
+    }
+}
+
+/// Serves `/metrics` on `address` until the returned task is aborted.
+async fn serve_metrics(
+    metrics: &Arc<Metrics>,
+    address: SocketAddr,
+    sender: &mut mpsc::Sender<P2pEvent>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let listener = match tokio::net::TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to serve metrics on {address}: {err}");
+            let reason = err.to_string();
+            sender
+                .send(P2pEvent::Error(None, P2pError::MetricsUnavailable { address, reason }))
+                .await
+                .ok();
+            return None;
+        }
+    };
+
+    let bound = listener.local_addr().unwrap_or(address);
+    info!("Serving metrics on http://{bound}/metrics");
+    sender.send(P2pEvent::MetricsServing(bound)).await.ok();
+
+    let router = metrics::router(metrics.clone());
+
+    Some(tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, router).await {
+            error!("Metrics server stopped: {err}");
+        }
+    }))
+}
+
+/// Re-announces records and provider records restored from a persistent store so the node
+/// resumes its role in the DHT after a restart.
+async fn republish_restored(swarm: &mut Swarm<CustomBehaviour>, sender: &mut mpsc::Sender<P2pEvent>) {
+    let records = republish_records(swarm);
+    let providers = republish_providers(swarm);
+
+    if records == 0 && providers.is_empty() {
+        return;
+    }
+
+    info!(
+        "Republished {records} records and {} provider records from the store",
+        providers.len()
+    );
+
+    let records = swarm
+        .behaviour_mut()
+        .kademlia
+        .store_mut()
+        .records()
+        .map(|record| (record.key.clone(), record.value.clone(), record.expires))
+        .collect();
+
+    sender.send(P2pEvent::StoreRestored { providers }).await.ok();
+    sender.send(P2pEvent::LocalRecords(records)).await.ok();
+}
+
+/// Puts the records we published ourselves again; ones removed since have left the store, so
+/// they aren't brought back.
+fn republish_records(swarm: &mut Swarm<CustomBehaviour>) -> usize {
+    let local_peer_id = *swarm.local_peer_id();
+    let kademlia = &mut swarm.behaviour_mut().kademlia;
+
+    let records: Vec<kad::Record> = kademlia
+        .store_mut()
+        .records()
+        .filter(|record| record.publisher == Some(local_peer_id))
+        .map(|record| record.into_owned())
+        .collect();
+
+    records
+        .into_iter()
+        .filter(|record| match kademlia.put_record(record.clone(), kad::Quorum::One) {
+            Ok(_) => true,
+            Err(error) => {
+                warn!("Failed to republish {}: {error:?}", format_key(&record.key));
+                false
+            }
+        })
+        .count()
+}
+
+/// Announces the keys we still provide again, returning the ones that went out.
+fn republish_providers(swarm: &mut Swarm<CustomBehaviour>) -> Vec<kad::RecordKey> {
+    let kademlia = &mut swarm.behaviour_mut().kademlia;
+    let keys: Vec<kad::RecordKey> =
+        kademlia.store_mut().provided().map(|record| record.key.clone()).collect();
+
+    keys.into_iter()
+        .filter(|key| match kademlia.start_providing(key.clone()) {
+            Ok(_) => true,
+            Err(error) => {
+                warn!("Failed to resume providing {}: {error:?}", format_key(key));
+                false
+            }
+        })
+        .collect()
+}
+
+async fn report_republished(sender: &mut mpsc::Sender<P2pEvent>, records: usize, providers: usize) {
+    if records == 0 && providers == 0 {
+        return;
+    }
+
+    info!("Republished {records} records and {providers} provider records");
+    sender.send(P2pEvent::Republished { records, providers }).await.ok();
+}
+
+async fn load_or_create_identity(
+    path: Option<PathBuf>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+) -> identity::Keypair {
+    let Some(path) = path.or_else(keypair::default_path) else {
+        warn!("No data directory available, identity will not be persisted");
+        return identity::Keypair::generate_ed25519();
+    };
+
+    if path.exists() {
+        match keypair::load(&path) {
+            Ok(keypair) => {
+                info!("Loaded identity from {}", path.display());
+                return keypair;
+            }
+            Err(err) => {
+                error!("Failed to load identity from {}: {err}", path.display());
+                sender
+                    .send(P2pEvent::Error(
+                        None,
+                        P2pError::Identity {
+                            path,
+                            reason: err.to_string(),
+                        },
+                    ))
+                    .await
+                    .ok();
+                return identity::Keypair::generate_ed25519();
+            }
+        }
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+
+    match keypair::save(&path, &keypair) {
+        Ok(()) => info!("Saved new identity to {}", path.display()),
+        Err(err) => {
+            error!("Failed to save identity to {}: {err}", path.display());
+            sender
+                .send(P2pEvent::Error(
+                    None,
+                    P2pError::Identity {
+                        path,
+                        reason: err.to_string(),
+                    },
+                ))
+                .await
+                .ok();
+        }
+    }
+
+    keypair
+}
+
+fn ticks(period: Duration) -> impl FusedStream<Item = ()> + Unpin {
+    ticks_from(tokio::time::Instant::now(), period)
+}
+
+/// Like [`ticks`], but the first one comes after a whole period instead of right away.
+fn delayed_ticks(period: Duration) -> impl FusedStream<Item = ()> + Unpin {
+    ticks_from(tokio::time::Instant::now() + period, period)
+}
+
+fn ticks_from(
+    start: tokio::time::Instant,
+    period: Duration,
+) -> impl FusedStream<Item = ()> + Unpin {
+    Box::pin(async_stream::stream! {
+        let mut interval = tokio::time::interval_at(start, period);
+
+        loop {
+            interval.tick().await;
+            yield;
+        }
+    })
+    .fuse()
+}
+
+async fn finish_timed_out_queries(
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    pending_queries: &mut HashMap<QueryId, PendingQuery>,
+    timeout: Duration,
+) {
+    let now = Instant::now();
+
+    for (id, query) in pending_queries.iter_mut() {
+        let expired = now.duration_since(query.started_at) >= timeout;
+
+        if query.finished || query.cache_peers > 0 || !expired {
+            continue;
+        }
+
+        warn!(
+            "{} query {} for {} timed out",
+            query.kind,
+            query.request_id,
+            format_key(&query.key)
+        );
+        finish_query(swarm, *id, query);
+
+        let event = if query.values.is_empty() {
+            P2pEvent::Error(
+                Some(query.request_id),
+                P2pError::QueryTimedOut {
+                    key: query.key.clone(),
+                },
+            )
+        } else {
+            P2pEvent::Outbound(Some(query.request_id), query.resolve())
+        };
+        sender.send(event).await.ok();
+    }
+}
+
+fn routing_table(swarm: &mut Swarm<CustomBehaviour>) -> Vec<BucketInfo> {
+    swarm
+        .behaviour_mut()
+        .kademlia
+        .kbuckets()
+        .map(|bucket| BucketInfo {
+            index: bucket.range().1.ilog2().unwrap_or_default(),
+            entries: bucket
+                .iter()
+                .map(|entry| {
+                    (
+                        *entry.node.key.preimage(),
+                        entry.node.value.iter().cloned().collect(),
+                        entry.status,
+                    )
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+fn finish_query(swarm: &mut Swarm<CustomBehaviour>, id: QueryId, query: &mut PendingQuery) {
+    query.finished = true;
+
+    if let Some(mut running) = swarm.behaviour_mut().kademlia.query_mut(&id) {
+        running.finish();
+    }
+
+    // A provide cut short counts as failed, and the UI no longer lists the key as provided.
+    if query.kind == QueryKind::StartProviding {
+        swarm.behaviour_mut().kademlia.stop_providing(&query.key);
+    }
+}
+
+/// A lookup that finds nothing is an answer rather than a failure, so it isn't a [`P2pError`].
+fn get_record_failed(request_id: Option<RequestId>, err: kad::GetRecordError) -> P2pEvent {
+    let error = match err {
+        kad::GetRecordError::NotFound { key, .. } => {
+            info!("No record found for {}", format_key(&key));
+            return P2pEvent::Outbound(request_id, P2pOutboundEvent::RecordNotFound(key));
+        }
+        kad::GetRecordError::QuorumFailed {
+            key,
+            records,
+            quorum,
+        } => P2pError::GetRecordQuorumFailed {
+            key,
+            got: records.len(),
+            needed: quorum.get(),
+        },
+        kad::GetRecordError::Timeout { key } => P2pError::GetRecordTimeout { key },
+    };
+
+    error!("Failed to get record: {error}");
+    P2pEvent::Error(request_id, error)
+}
+
+/// The typed error for a dial that failed because a `/dns*` host didn't resolve.
+fn resolve_failure(error: &DialError) -> Option<P2pError> {
+    let DialError::Transport(attempts) = error else {
+        return None;
+    };
+
+    attempts.iter().find_map(|(address, error)| {
+        let TransportError::Other(error) = error else {
+            return None;
+        };
+        let host = address.iter().find_map(|protocol| match protocol {
+            Protocol::Dns(host)
+            | Protocol::Dns4(host)
+            | Protocol::Dns6(host)
+            | Protocol::Dnsaddr(host) => Some(host.into_owned()),
+            _ => None,
+        })?;
+        let mut chain = iter::successors(Some::<&(dyn Error + 'static)>(error), |error| {
+            (*error).source()
+        });
+        let resolve_error =
+            chain.find_map(|error| error.downcast_ref::<libp2p::dns::ResolveError>())?;
+
+        Some(P2pError::ResolveFailed {
+            address: address.clone(),
+            host,
+            reason: resolve_error.to_string(),
+        })
+    })
+}
+
+/// Seeds Kademlia with the configured peers and dials them. Entries without a `/p2p/` suffix are
+/// dialed too and reach the routing table once identify reports their listen addresses.
+async fn connect_bootstrap_peers(
+    swarm: &mut Swarm<CustomBehaviour>,
+    peers: &[Multiaddr],
+    sender: &mut mpsc::Sender<P2pEvent>,
+) {
+    let mut routable = false;
+
+    for address in peers {
+        let mut transport = address.clone();
+
+        if let Some(Protocol::P2p(peer_id)) = transport.pop() {
+            swarm.behaviour_mut().kademlia.add_address(&peer_id, transport);
+            routable = true;
+        }
+
+        let event = match swarm.dial(address.clone()) {
+            Ok(()) => P2pEvent::DialingBootstrapPeer(address.clone()),
+            Err(err) => {
+                warn!("Failed to dial bootstrap peer {address}: {err}");
+                P2pEvent::Error(None, P2pError::DialFailed {
+                    address: address.clone(),
+                    reason: err.to_string(),
+                })
+            }
+        };
+
+        sender.send(event).await.ok();
+    }
+
+    if routable && let Err(err) = swarm.behaviour_mut().kademlia.bootstrap() {
+        warn!("Failed to bootstrap from the configured peers: {err}");
+    }
+}
+
+/// Adds every remembered peer to Kademlia and dials the ones seen most recently.
+async fn redial_known_peers(
+    swarm: &mut Swarm<CustomBehaviour>,
+    known_peers: &KnownPeers,
+    sender: &mut mpsc::Sender<P2pEvent>,
+) {
+    let peers = known_peers.all();
+
+    if peers.is_empty() {
+        return;
+    }
+
+    for peer in &peers {
+        for address in &peer.addresses {
+            swarm
+                .behaviour_mut()
+                .kademlia
+                .add_address(&peer.peer_id, address.clone());
+        }
+    }
+
+    for peer in peers.iter().take(REDIALED_KNOWN_PEERS) {
+        let opts = DialOpts::peer_id(peer.peer_id)
+            .addresses(peer.addresses.clone())
+            .build();
+
+        if let Err(err) = swarm.dial(opts) {
+            warn!("Failed to dial remembered peer {}: {err}", peer.peer_id);
+        }
+    }
+
+    sender.send(P2pEvent::RememberedPeers(peers)).await.ok();
+}
+
+fn remember_peer(known_peers: &mut KnownPeers, event: &SwarmEvent<CustomBehaviourEvent>) {
+    match event {
+        SwarmEvent::ConnectionEstablished {
+            peer_id, endpoint, ..
+        } => {
+            let address = match endpoint {
+                ConnectedPoint::Dialer { address, .. } => Some(address.clone()),
+                ConnectedPoint::Listener { .. } => None,
+            };
+
+            known_peers.seen(*peer_id, address);
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => known_peers.identified(*peer_id, info.listen_addrs.clone()),
+        _ => {}
+    }
+}
+
+/// mDNS answers already passed on, so a peer re-announcing itself doesn't show up again.
+#[derive(Debug, Default)]
+struct MdnsPeers {
+    last_seen: HashMap<(PeerId, Multiaddr), Instant>,
+}
+
+impl MdnsPeers {
+    /// Drops addresses that were discovered before, or expire without ever being announced.
+    fn dedup(&mut self, event: &mut SwarmEvent<CustomBehaviourEvent>) {
+        match event {
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
+                let now = Instant::now();
+                list.retain(|(peer_id, address)| {
+                    self.last_seen.insert((*peer_id, address.clone()), now).is_none()
+                });
+            }
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
+                list.retain(|(peer_id, address)| {
+                    self.last_seen.remove(&(*peer_id, address.clone())).is_some()
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+fn periodic_bootstrap(swarm: &mut Swarm<CustomBehaviour>) {
+    if let Err(err) = swarm.behaviour_mut().kademlia.bootstrap() {
+        debug!("Skipping periodic bootstrap: {err}");
+    }
+}
+
+async fn remove_expired_records(
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+) {
+    let now = Instant::now();
+    let store = swarm.behaviour_mut().kademlia.store_mut();
+
+    let expired: Vec<kad::RecordKey> = store
+        .records()
+        .filter(|record| record.is_expired(now))
+        .map(|record| record.key.clone())
+        .collect();
+
+    for key in expired {
+        info!(
+            "Record {} expired locally",
+            format_key(&key)
+        );
+        store.remove(&key);
+        sender.send(P2pEvent::Local(P2pLocalEvent::RecordExpired(key))).await.ok();
+    }
+}
+
+async fn expire_pending_inbound(inbound: &mut InboundQueue, sender: &mut mpsc::Sender<P2pEvent>) {
+    inbound.rate.prune(Instant::now());
+
+    for id in inbound.expire(Instant::now()) {
+        info!("Inbound request {id} expired without an answer");
+        sender.send(P2pEvent::InboundExpired(id)).await.ok();
+    }
+}
+
+async fn report_served(inbound: &mut InboundQueue, sender: &mut mpsc::Sender<P2pEvent>) {
+    let Some(served) = inbound.served.take() else {
+        return;
+    };
+
+    if served.records_found + served.records_missing > 0 {
+        let event = P2pInboundEvent::RecordsRequested {
+            found: served.records_found,
+            missing: served.records_missing,
+        };
+        sender.send(P2pEvent::Inbound(event)).await.ok();
+    }
+
+    if served.provider_lookups > 0 {
+        let event = P2pInboundEvent::ProvidersRequested(served.provider_lookups);
+        sender.send(P2pEvent::Inbound(event)).await.ok();
+    }
+}
+
+async fn handle_command(
+    cmd: P2pCommand,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    pending_queries: &mut HashMap<QueryId, PendingQuery>,
+    transfers: &mut FileTransfers,
+    relay_listeners: &mut HashMap<ListenerId, Multiaddr>,
+    inbound: &mut InboundQueue,
+) {
+    match cmd {
+        P2pCommand::GetRecord(request_id, key, quorum) => {
+            let query_id = swarm.behaviour_mut().kademlia.get_record(key.clone());
+            pending_queries.insert(query_id, PendingQuery {
+                quorum,
+                ..PendingQuery::new(request_id, QueryKind::GetRecord, key)
+            });
+        }
+        P2pCommand::GetProviders(request_id, key) => {
+            let query_id = swarm.behaviour_mut().kademlia.get_providers(key.clone());
+            pending_queries.insert(
+                query_id,
+                PendingQuery::new(request_id, QueryKind::GetProviders, key),
+            );
+        }
+        P2pCommand::GetClosestPeers(request_id, key) => {
+            let query_id = swarm.behaviour_mut().kademlia.get_closest_peers(key.to_vec());
+            pending_queries.insert(
+                query_id,
+                PendingQuery::new(request_id, QueryKind::GetClosestPeers, key),
+            );
+        }
+        P2pCommand::PutRecord(request_id, key, value, ttl, quorum) => {
+            let mut record = kad::Record::new(key.clone(), value);
+            record.expires = ttl.map(|ttl| Instant::now() + ttl);
+
+            match swarm.behaviour_mut().kademlia.put_record(record, quorum)
+            {
+                Ok(query_id) => {
+                    let stored = swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .store_mut()
+                        .get(&key)
+                        .map(|record| record.into_owned());
+
+                    pending_queries.insert(
+                        query_id,
+                        PendingQuery::new(request_id, QueryKind::PutRecord, key),
+                    );
+
+                    if let Some(record) = stored {
+                        sender
+                            .send(P2pEvent::Local(P2pLocalEvent::RecordStored(record)))
+                            .await
+                            .ok();
+                    }
+                }
+                Err(error) => {
+                    error!("Failed to store record: {error:?}");
+                    sender
+                        .send(P2pEvent::Error(
+                            Some(request_id),
+                            P2pError::Store { key, error },
+                        ))
+                        .await
+                        .ok();
+                }
+            }
+        }
+        P2pCommand::PutProvider(request_id, key) => {
+            start_providing(request_id, key, swarm, sender, pending_queries).await
+        }
+        P2pCommand::ShareFile(request_id, key, path) => {
+            info!("Sharing {} as {key}", path.display());
+            transfers.share(request_id, key, path);
+        }
+        P2pCommand::FetchFile(key, path) => {
+            if transfers.downloads.contains_key(&key) {
+                warn!("Already fetching {key}");
+                return;
+            }
+
+            match Download::new(path.clone()) {
+                Ok(download) => {
+                    let query_id = swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .get_providers(kad::RecordKey::new(&key));
+                    transfers.lookups.insert(query_id, key.clone());
+                    transfers.downloads.insert(key, download);
+                }
+                Err(err) => {
+                    error!("Failed to create {}: {err}", path.display());
+                    sender
+                        .send(P2pEvent::Error(None, P2pError::FileIo {
+                            key,
+                            path,
+                            reason: err.to_string(),
+                        }))
+                        .await
+                        .ok();
+                }
+            }
+        }
+        P2pCommand::StopProviding(key) => {
+            let kademlia = &mut swarm.behaviour_mut().kademlia;
+
+            let event = if kademlia.store_mut().provided().any(|record| record.key == key) {
+                kademlia.stop_providing(&key);
+                P2pOutboundEvent::ProviderStopped(key)
+            } else {
+                P2pOutboundEvent::NotProviding(key)
+            };
+
+            sender.send(P2pEvent::Outbound(None, event)).await.ok();
+        }
+        P2pCommand::RemoveRecord(key) => {
+            let store = swarm.behaviour_mut().kademlia.store_mut();
+
+            let event = if store.get(&key).is_some() {
+                store.remove(&key);
+                P2pLocalEvent::RecordRemoved(key)
+            } else {
+                P2pLocalEvent::RecordNotInStore(key)
+            };
+
+            sender.send(P2pEvent::Local(event)).await.ok();
+        }
+        P2pCommand::ListRecords => {
+            let records = swarm
+                .behaviour_mut()
+                .kademlia
+                .store_mut()
+                .records()
+                .map(|record| (record.key.clone(), record.value.clone(), record.expires))
+                .collect();
+
+            sender.send(P2pEvent::LocalRecords(records)).await.ok();
+        }
+        P2pCommand::ConnectBootstrapPeers(peers) => {
+            connect_bootstrap_peers(swarm, &peers, sender).await
+        }
+        P2pCommand::Dial(address) => {
+            let event = match swarm.dial(address.clone()) {
+                Ok(()) => P2pEvent::Dialing(address),
+                Err(err) => {
+                    error!("Failed to dial {address}: {err}");
+                    P2pEvent::Error(None, P2pError::DialFailed {
+                        address,
+                        reason: err.to_string(),
+                    })
+                }
+            };
+
+            sender.send(event).await.ok();
+        }
+        P2pCommand::ListenViaRelay(relay) => {
+            let circuit = if relay.iter().any(|protocol| protocol == Protocol::P2pCircuit) {
+                relay.clone()
+            } else {
+                relay.clone().with(Protocol::P2pCircuit)
+            };
+
+            match swarm.listen_on(circuit) {
+                Ok(listener_id) => {
+                    info!("Requesting a reservation on relay {relay}");
+                    relay_listeners.insert(listener_id, relay);
+                }
+                Err(err) => {
+                    error!("Failed to listen via relay {relay}: {err}");
+                    sender
+                        .send(P2pEvent::Error(None, P2pError::RelayReservationFailed {
+                            relay,
+                            reason: err.to_string(),
+                        }))
+                        .await
+                        .ok();
+                }
+            }
+        }
+        P2pCommand::SetRelayServer(limits) => {
+            let local_peer_id = *swarm.local_peer_id();
+            swarm.behaviour_mut().relay_server = relay_server(local_peer_id, limits);
+
+            info!("Relay server mode {}", if limits.is_some() { "enabled" } else { "disabled" });
+            sender.send(P2pEvent::RelayServerToggled(limits.is_some())).await.ok();
+        }
+        P2pCommand::SetInboundApproval(enabled) => {
+            inbound.approval_required = enabled;
+
+            sender.send(P2pEvent::InboundApprovalToggled(enabled)).await.ok();
+        }
+        P2pCommand::DumpRoutingTable => {
+            let buckets = routing_table(swarm);
+            sender.send(P2pEvent::RoutingTable(buckets)).await.ok();
+        }
+        P2pCommand::SetKadMode(mode) => {
+            swarm.behaviour_mut().kademlia.set_mode(Some(mode));
+
+            info!("Kademlia mode set to {mode}");
+            sender.send(P2pEvent::KadModeChanged(mode)).await.ok();
+        }
+        P2pCommand::AcceptInbound(id) => match inbound.take(id) {
+            Some(pending) => {
+                store_inbound(pending.source, pending.record, swarm, sender, &mut inbound.uploads)
+                    .await
+            }
+            None => warn!("Inbound request {id} is no longer pending"),
+        },
+        P2pCommand::RejectInbound(id) => {
+            if let Some(pending) = inbound.take(id) {
+                info!("Rejected {:?} from {}", pending.record.key(), pending.source);
+            }
+        }
+        P2pCommand::DisconnectPeer(peer_id) => {
+            if swarm.disconnect_peer_id(peer_id).is_err() {
+                warn!("Cannot disconnect from {peer_id}, not connected");
+                sender.send(P2pEvent::Error(None, P2pError::NotConnected { peer_id })).await.ok();
+            }
+        }
+        P2pCommand::BlockPeer(peer_id) => {
+            let behaviour = swarm.behaviour_mut();
+            behaviour.blocked.block_peer(peer_id);
+            behaviour.kademlia.remove_peer(&peer_id);
+
+            info!("Blocked peer {peer_id}");
+            sender.send(P2pEvent::PeerBlocked(peer_id)).await.ok();
+        }
+        P2pCommand::UnblockPeer(peer_id) => {
+            swarm.behaviour_mut().blocked.unblock_peer(peer_id);
+
+            info!("Unblocked peer {peer_id}");
+            sender.send(P2pEvent::PeerUnblocked(peer_id)).await.ok();
+        }
+        P2pCommand::CancelQuery(request_id) => {
+            let running = pending_queries
+                .iter_mut()
+                .find(|(_, query)| query.request_id == request_id && !query.finished);
+
+            if let Some((id, query)) = running {
+                info!("Cancelling {} query {request_id}", query.kind);
+                finish_query(swarm, *id, query);
+                sender
+                    .send(P2pEvent::Outbound(
+                        Some(request_id),
+                        P2pOutboundEvent::QueryCancelled(query.key.clone()),
+                    ))
+                    .await
+                    .ok();
+            }
+        }
+        P2pCommand::SubscribeTopic(topic) => {
+            let event = match swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&gossipsub::IdentTopic::new(&topic))
+            {
+                Ok(_) => P2pEvent::TopicSubscribed(topic),
+                Err(err) => {
+                    error!("Failed to subscribe to {topic}: {err}");
+                    P2pEvent::Error(
+                        None,
+                        P2pError::Gossip {
+                            topic,
+                            reason: err.to_string(),
+                        },
+                    )
+                }
+            };
+
+            sender.send(event).await.ok();
+        }
+        P2pCommand::UnsubscribeTopic(topic) => {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .unsubscribe(&gossipsub::IdentTopic::new(&topic));
+            sender.send(P2pEvent::TopicUnsubscribed(topic)).await.ok();
+        }
+        P2pCommand::PublishMessage(topic, data) => {
+            let event = match swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(gossipsub::IdentTopic::new(&topic), data.clone())
+            {
+                Ok(_) => P2pEvent::GossipPublished { topic, data },
+                Err(gossipsub::PublishError::InsufficientPeers) => {
+                    warn!("No peers subscribed to {topic}");
+                    P2pEvent::Error(None, P2pError::NoTopicPeers { topic })
+                }
+                Err(err) => {
+                    error!("Failed to publish to {topic}: {err}");
+                    P2pEvent::Error(
+                        None,
+                        P2pError::Gossip {
+                            topic,
+                            reason: err.to_string(),
+                        },
+                    )
+                }
+            };
+
+            sender.send(event).await.ok();
+        }
+        P2pCommand::SendDirect(peer_id, text) => {
+            swarm
+                .behaviour_mut()
+                .direct_message
+                .send_request(&peer_id, DirectMessage { text });
+        }
+        P2pCommand::Bootstrap => {
+            if let Err(err) = swarm.behaviour_mut().kademlia.bootstrap() {
+                warn!("Failed to bootstrap: {err}");
+                sender.send(P2pEvent::Outbound(None, P2pOutboundEvent::NoKnownPeers)).await.ok();
+            }
+        }
+        P2pCommand::Shutdown => unreachable!("shutdown is handled by the run loop"),
+        P2pCommand::SetMdnsEnabled(_) => unreachable!("mDNS is switched by the run loop"),
+        P2pCommand::SetRecordSigning(_) => unreachable!("signing is switched by the run loop"),
+    }
+}
+
+async fn start_providing(
+    request_id: RequestId,
+    key: kad::RecordKey,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    pending_queries: &mut HashMap<QueryId, PendingQuery>,
+) {
+    match swarm.behaviour_mut().kademlia.start_providing(key.clone()) {
+        Ok(query_id) => {
+            pending_queries.insert(
+                query_id,
+                PendingQuery::new(request_id, QueryKind::StartProviding, key),
+            );
+        }
+        Err(error) => {
+            error!("Failed to start providing key: {error:?}");
+            sender
+                .send(P2pEvent::Error(
+                    Some(request_id),
+                    P2pError::Store { key, error },
+                ))
+                .await
+                .ok();
+        }
+    }
+}
+
+async fn handle_swarm_event(
+    event: SwarmEvent<CustomBehaviourEvent>,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    pending_queries: &mut HashMap<QueryId, PendingQuery>,
+    transfers: &mut FileTransfers,
+    (relay_listeners, negotiated): (&mut HashMap<ListenerId, Multiaddr>, &NegotiatedSecurity),
+    inbound: &mut InboundQueue,
+) {
+    match event {
+        SwarmEvent::ExpiredListenAddr { address, .. } => {
+            info!("No longer listening on {address:?}");
+            sender.send(P2pEvent::ListenAddrExpired(address)).await.ok();
+        }
+        SwarmEvent::NewListenAddr { address, .. } => {
+            info!("Listening on {address:?}");
+            sender.send(P2pEvent::Bootstrapped(address)).await.ok();
+        }
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            endpoint,
+            num_established,
+            ..
+        } => {
+            let relayed = endpoint.is_relayed();
+            let address = endpoint.get_remote_address().clone();
+            let transport = ConnectionTransport::of(&address);
+            let direction = ConnectionDirection::from(&endpoint);
+            let security = match transport {
+                ConnectionTransport::Quic if !relayed => Some(SecurityProtocol::Tls),
+                _ => negotiated.of(&peer_id),
+            };
+
+            info!("Connected to peer {peer_id} at {address} ({num_established} connections)");
+
+            if let ConnectedPoint::Dialer { address, .. } = endpoint {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, address);
+            }
+
+            sender
+                .send(P2pEvent::PeerConnected {
+                    peer_id,
+                    address,
+                    relayed,
+                    transport,
+                    direction,
+                    security,
+                    num_established: num_established.get(),
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            num_established,
+            cause,
+            ..
+        } => {
+            match cause {
+                Some(cause) => info!("Connection to {peer_id} closed: {cause}"),
+                None => info!("Connection to {peer_id} closed"),
+            }
+
+            if num_established == 0 {
+                negotiated.forget(&peer_id);
+            }
+
+            sender
+                .send(P2pEvent::PeerDisconnected {
+                    peer_id,
+                    num_established,
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Autonat(autonat::Event::StatusChanged {
+            old,
+            new,
+        })) => {
+            info!("NAT status changed from {old:?} to {new:?}");
+
+            let mode = match &new {
+                autonat::NatStatus::Public(_) => Some(Mode::Server),
+                autonat::NatStatus::Private => Some(Mode::Client),
+                autonat::NatStatus::Unknown => None,
+            };
+
+            sender.send(P2pEvent::NatStatus(new.into())).await.ok();
+
+            if let Some(mode) = mode.filter(|mode| *mode != swarm.behaviour().kademlia.mode()) {
+                swarm.behaviour_mut().kademlia.set_mode(Some(mode));
+                sender.send(P2pEvent::KadModeChanged(mode)).await.ok();
+            }
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Dcutr(dcutr::Event {
+            remote_peer_id,
+            result,
+        })) => {
+            match &result {
+                Ok(_) => info!("Hole punch to {remote_peer_id} succeeded"),
+                Err(err) => warn!("Hole punch to {remote_peer_id} failed: {err}"),
+            }
+
+            sender
+                .send(P2pEvent::HolePunch {
+                    remote: remote_peer_id,
+                    success: result.is_ok(),
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::ListenerClosed {
+            listener_id,
+            reason,
+            ..
+        } => {
+            let Some(relay) = relay_listeners.remove(&listener_id) else {
+                return;
+            };
+
+            match reason {
+                Ok(()) => info!("Stopped listening via relay {relay}"),
+                Err(err) => {
+                    error!("Relay reservation on {relay} lost: {err}");
+                    sender
+                        .send(P2pEvent::Error(None, P2pError::RelayReservationFailed {
+                            relay,
+                            reason: err.to_string(),
+                        }))
+                        .await
+                        .ok();
+                }
+            }
+        }
+        SwarmEvent::ListenerError { listener_id, error } => {
+            if let Some(relay) = relay_listeners.get(&listener_id) {
+                warn!("Relay listener on {relay} reported an error: {error}");
+                sender
+                    .send(P2pEvent::Error(None, P2pError::RelayReservationFailed {
+                        relay: relay.clone(),
+                        reason: error.to_string(),
+                    }))
+                    .await
+                    .ok();
+            }
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RelayClient(
+            relay::client::Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal,
+                ..
+            },
+        )) => {
+            info!("Reservation on relay {relay_peer_id} accepted (renewal: {renewal})");
+            sender
+                .send(P2pEvent::RelayReservationAccepted {
+                    relay: relay_peer_id,
+                    renewal,
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::RelayServer(event)) => {
+            let event = match event {
+                relay::Event::ReservationReqAccepted {
+                    src_peer_id,
+                    renewed,
+                } => P2pEvent::RelayReservationServed {
+                    peer_id: src_peer_id,
+                    renewed,
+                },
+                relay::Event::CircuitReqAccepted {
+                    src_peer_id,
+                    dst_peer_id,
+                } => P2pEvent::RelayCircuitOpened {
+                    src: src_peer_id,
+                    dst: dst_peer_id,
+                },
+                relay::Event::CircuitClosed {
+                    src_peer_id,
+                    dst_peer_id,
+                    ..
+                } => P2pEvent::RelayCircuitClosed {
+                    src: src_peer_id,
+                    dst: dst_peer_id,
+                },
+                event => {
+                    debug!("Relay server event: {event:?}");
+                    return;
+                }
+            };
+
+            sender.send(event).await.ok();
+        }
+        SwarmEvent::OutgoingConnectionError {
+            peer_id,
+            error: DialError::Denied { cause },
+            ..
+        } if cause.downcast_ref::<connection_limits::Exceeded>().is_some() => {
+            warn!("Outgoing connection to {peer_id:?} denied: {cause}");
+            sender
+                .send(P2pEvent::ConnectionDenied {
+                    peer: peer_id,
+                    address: None,
+                    reason: cause.to_string(),
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::IncomingConnectionError {
+            send_back_addr,
+            error: ListenError::Denied { cause },
+            ..
+        } if cause.downcast_ref::<connection_limits::Exceeded>().is_some() => {
+            warn!("Incoming connection from {send_back_addr} denied: {cause}");
+            sender
+                .send(P2pEvent::ConnectionDenied {
+                    peer: None,
+                    address: Some(send_back_addr),
+                    reason: cause.to_string(),
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::OutgoingConnectionError { error, .. }
+            if let Some(failure) = resolve_failure(&error) =>
+        {
+            error!("{failure}");
+            sender.send(P2pEvent::Error(None, failure)).await.ok();
+        }
+        SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+            error!("Outgoing connection to {peer_id:?} failed: {error}");
+            sender
+                .send(P2pEvent::Error(None, P2pError::ConnectionFailed {
+                    peer_id,
+                    reason: error.to_string(),
+                }))
+                .await
+                .ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => {
+            info!(
+                "Identified peer {peer_id} as {} with {} listen addresses",
+                info.agent_version,
+                info.listen_addrs.len()
+            );
+
+            for address in info.listen_addrs {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, address);
+            }
+
+            swarm.add_external_address(info.observed_addr);
+
+            let dht_protocol = swarm.behaviour().kademlia.protocol_names()[0].clone();
+
+            if !info.protocols.contains(&dht_protocol) {
+                debug!("Peer {peer_id} doesn't serve {dht_protocol}");
+                sender
+                    .send(P2pEvent::DhtProtocolMissing {
+                        peer_id,
+                        protocol: dht_protocol.to_string(),
+                    })
+                    .await
+                    .ok();
+            }
+
+            sender
+                .send(P2pEvent::PeerIdentified {
+                    peer_id,
+                    agent: info.agent_version,
+                    protocols: info.protocols.iter().map(ToString::to_string).collect(),
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message,
+            ..
+        })) => {
+            sender
+                .send(P2pEvent::GossipMessage {
+                    topic: message.topic.into_string(),
+                    source: message.source,
+                    data: message.data,
+                })
+                .await
+                .ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::DirectMessage(
+            request_response::Event::Message { peer, message, .. },
+        )) => {
+            let event = match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    if swarm
+                        .behaviour_mut()
+                        .direct_message
+                        .send_response(channel, Ack)
+                        .is_err()
+                    {
+                        warn!("Failed to acknowledge direct message from {peer}");
+                    }
+
+                    P2pEvent::DirectMessageReceived(peer, request.text)
+                }
+                request_response::Message::Response { .. } => {
+                    P2pEvent::DirectMessageDelivered(peer)
+                }
+            };
+
+            sender.send(event).await.ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::DirectMessage(
+            request_response::Event::OutboundFailure { peer, error, .. },
+        )) => {
+            warn!("Direct message to {peer} failed: {error}");
+            sender.send(P2pEvent::Error(None, P2pError::direct_message(peer, error))).await.ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Ping(ping::Event { peer, result, .. })) => {
+            let event = match result {
+                Ok(rtt) => {
+                    trace!("Ping to {peer} took {rtt:?}");
+                    P2pEvent::PingRtt(peer, rtt)
+                }
+                Err(err) => {
+                    debug!("Ping to {peer} failed: {err}");
+                    P2pEvent::PeerUnresponsive(peer)
+                }
+            };
+
+            sender.send(event).await.ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
+            for (peer_id, multiaddr) in list {
+                if swarm.behaviour().blocked.blocked_peers().contains(&peer_id) {
+                    debug!("Ignoring blocked peer {peer_id} at {multiaddr}");
+                    continue;
+                }
+
+                info!("Discovered peer {peer_id} at {multiaddr}");
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, multiaddr.clone());
+                sender.send(P2pEvent::PeerDiscovered(peer_id, multiaddr)).await.ok();
+            }
         }
         SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
             for (peer_id, multiaddr) in list {
@@ -207,153 +2860,1461 @@ async fn handle_swarm_event(
                     .behaviour_mut()
                     .kademlia
                     .remove_address(&peer_id, &multiaddr);
-                sender
-                    .send(P2pEvent::PeerExpired(peer_id, multiaddr))
-                    .await
-                    .expect("Failed to send");
+                sender.send(P2pEvent::PeerExpired(peer_id, multiaddr)).await.ok();
             }
         }
         SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(
-            kad::Event::OutboundQueryProgressed { result, .. },
-        )) => handle_outbound_query(result, sender).await,
+            kad::Event::OutboundQueryProgressed { id, result, step, .. },
+        )) if transfers.lookups.contains_key(&id) => {
+            handle_file_lookup(id, result, step, swarm, sender, transfers).await
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(
+            kad::Event::OutboundQueryProgressed { id, result, step, .. },
+        )) => handle_outbound_query(id, result, step, swarm, sender, pending_queries).await,
+        SwarmEvent::Behaviour(CustomBehaviourEvent::FileTransfer(event)) => {
+            handle_file_transfer(event, swarm, sender, transfers).await
+        }
         SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(kad::Event::InboundRequest {
             request,
             ..
-        })) => handle_inbound_request(request, swarm, sender).await,
+        })) => handle_inbound_request(request, swarm, sender, inbound).await,
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(kad::Event::RoutingUpdated {
+            peer,
+            is_new_peer,
+            bucket_range: (_, max_distance),
+            old_peer,
+            ..
+        })) => {
+            debug!("Routing table updated with {peer}");
+            sender
+                .send(P2pEvent::RoutingUpdated {
+                    peer_id: peer,
+                    bucket: max_distance.ilog2().unwrap_or_default(),
+                    is_new_peer,
+                    evicted: old_peer,
+                })
+                .await
+                .ok();
+
+            let buckets = routing_table(swarm);
+            sender.send(P2pEvent::RoutingTable(buckets)).await.ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(kad::Event::UnroutablePeer {
+            peer,
+        })) => {
+            debug!("Connected to {peer} without a known listen address");
+            sender.send(P2pEvent::UnroutablePeer(peer)).await.ok();
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(kad::Event::ModeChanged {
+            new_mode,
+        })) => {
+            info!("Kademlia switched to {new_mode} mode");
+            sender.send(P2pEvent::KadModeChanged(new_mode)).await.ok();
+        }
+        _ => {}
+    }
+}
+
+async fn handle_file_lookup(
+    id: QueryId,
+    result: QueryResult,
+    step: kad::ProgressStep,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    transfers: &mut FileTransfers,
+) {
+    let Some(key) = transfers.lookups.get(&id).cloned() else {
+        return;
+    };
+
+    if step.last {
+        transfers.lookups.remove(&id);
+    }
+
+    let Some(download) = transfers.downloads.get_mut(&key) else {
+        return;
+    };
+
+    match result {
+        QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders { providers, .. })) => {
+            let local_peer_id = *swarm.local_peer_id();
+            download.add_providers(providers.into_iter().filter(|peer| *peer != local_peer_id));
+        }
+        QueryResult::GetProviders(Err(err)) => {
+            warn!("Provider lookup for file {key} failed: {err:?}");
+        }
         _ => {}
     }
-}
 
-async fn handle_outbound_query(result: QueryResult, sender: &mut mpsc::Sender<P2pEvent>) {
+    if step.last {
+        download.lookup_finished = true;
+    }
+
+    request_next_chunk(key, swarm, sender, transfers).await
+}
+
+async fn handle_file_transfer(
+    event: request_response::Event<FileRequest, FileResponse>,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    transfers: &mut FileTransfers,
+) {
+    match event {
+        request_response::Event::Message {
+            peer,
+            message: request_response::Message::Request {
+                request, channel, ..
+            },
+            ..
+        } => {
+            let Some(shared) = transfers.shared.get(&request.key).cloned() else {
+                return respond_to_file_request(swarm, peer, channel, FileResponse::NotFound);
+            };
+
+            debug!("Serving {} at offset {} to {peer}", request.key, request.offset);
+            transfers.serve(shared, request.offset, peer, channel);
+        }
+        request_response::Event::Message {
+            peer,
+            message: request_response::Message::Response {
+                request_id,
+                response,
+            },
+            ..
+        } => {
+            let Some(key) = transfers.requests.remove(&request_id) else {
+                return;
+            };
+            let Some(download) = transfers.downloads.get_mut(&key) else {
+                return;
+            };
+
+            let (data, total, hash) = match response {
+                FileResponse::Chunk { data, total, hash } => (data, total, hash),
+                FileResponse::NotFound => {
+                    warn!("Peer {peer} no longer provides file {key}");
+                    download.current = None;
+                    return request_next_chunk(key, swarm, sender, transfers).await;
+                }
+            };
+
+            match download.write_chunk(&data, total, hash) {
+                Ok(()) => {}
+                Err(ChunkError::WrongFile) => {
+                    warn!("Peer {peer} sent a chunk that doesn't continue file {key}");
+                    download.current = None;
+                    return request_next_chunk(key, swarm, sender, transfers).await;
+                }
+                Err(ChunkError::Io(err)) => {
+                    error!("Failed to write {}: {err}", download.destination().display());
+                    return fail_download(key, err.to_string(), sender, transfers).await;
+                }
+            }
+
+            sender
+                .send(P2pEvent::FileTransferProgress {
+                    key: key.clone(),
+                    received: download.received,
+                    total,
+                })
+                .await
+                .ok();
+
+            if download.is_complete() && !download.is_intact() {
+                warn!("File {key} doesn't hash to what its providers announced");
+                let reason = "the fetched contents don't match their hash".to_owned();
+                fail_download(key, reason, sender, transfers).await
+            } else if download.is_complete() {
+                let download = transfers.downloads.remove(&key).expect("download exists");
+                info!("Fetched file {key} from {peer}");
+
+                sender
+                    .send(P2pEvent::FileFetched {
+                        key,
+                        path: download.destination().to_owned(),
+                    })
+                    .await
+                    .ok();
+            } else {
+                let request_id = swarm.behaviour_mut().file_transfer.send_request(
+                    &peer,
+                    FileRequest {
+                        key: key.clone(),
+                        offset: download.received,
+                    },
+                );
+                transfers.requests.insert(request_id, key);
+            }
+        }
+        request_response::Event::OutboundFailure {
+            peer,
+            request_id,
+            error,
+            ..
+        } => {
+            let Some(key) = transfers.requests.remove(&request_id) else {
+                return;
+            };
+
+            warn!("Fetching file {key} from {peer} failed: {error}");
+
+            if let Some(download) = transfers.downloads.get_mut(&key) {
+                download.current = None;
+            }
+
+            request_next_chunk(key, swarm, sender, transfers).await
+        }
+        request_response::Event::InboundFailure { peer, error, .. } => {
+            warn!("Serving file to {peer} failed: {error}");
+        }
+        request_response::Event::ResponseSent { .. } => {}
+    }
+}
+
+fn respond_to_file_request(
+    swarm: &mut Swarm<CustomBehaviour>,
+    peer: PeerId,
+    channel: request_response::ResponseChannel<FileResponse>,
+    response: FileResponse,
+) {
+    if swarm
+        .behaviour_mut()
+        .file_transfer
+        .send_response(channel, response)
+        .is_err()
+    {
+        warn!("Failed to respond to file request from {peer}");
+    }
+}
+
+/// Shares a file once it's hashed, or answers a peer once its chunk is read.
+async fn finish_file_job(
+    job: FileJob,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    pending_queries: &mut HashMap<QueryId, PendingQuery>,
+    transfers: &mut FileTransfers,
+) {
+    match job {
+        FileJob::Hashed {
+            request_id,
+            key,
+            result: Ok(shared),
+            ..
+        } => {
+            transfers.shared.insert(key.clone(), shared);
+            start_providing(request_id, kad::RecordKey::new(&key), swarm, sender, pending_queries)
+                .await
+        }
+        FileJob::Hashed {
+            request_id,
+            key,
+            path,
+            result: Err(err),
+        } => {
+            error!("Failed to read {}: {err}", path.display());
+            sender
+                .send(P2pEvent::Error(Some(request_id), P2pError::FileIo {
+                    key,
+                    path,
+                    reason: err.to_string(),
+                }))
+                .await
+                .ok();
+        }
+        FileJob::Read {
+            peer,
+            channel,
+            response,
+        } => respond_to_file_request(swarm, peer, channel, response),
+    }
+}
+
+/// Gives up on a download, removing what was written of it.
+async fn fail_download(
+    key: String,
+    reason: String,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    transfers: &mut FileTransfers,
+) {
+    let download = transfers.downloads.remove(&key).expect("download exists");
+    let path = download.destination().to_owned();
+    download.discard();
+
+    sender
+        .send(P2pEvent::Error(None, P2pError::FileIo { key, path, reason }))
+        .await
+        .ok();
+}
+
+async fn request_next_chunk(
+    key: String,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    transfers: &mut FileTransfers,
+) {
+    let Some(download) = transfers.downloads.get_mut(&key) else {
+        return;
+    };
+
+    if download.current.is_some() {
+        return;
+    }
+
+    if let Some(peer) = download.next_provider() {
+        info!("Fetching file {key} from {peer} at offset {}", download.received);
+        let request_id = swarm.behaviour_mut().file_transfer.send_request(
+            &peer,
+            FileRequest {
+                key: key.clone(),
+                offset: download.received,
+            },
+        );
+        transfers.requests.insert(request_id, key);
+    } else if download.lookup_finished {
+        warn!("No providers left for file {key}");
+        transfers
+            .downloads
+            .remove(&key)
+            .expect("download exists")
+            .discard();
+
+        sender.send(P2pEvent::Error(None, P2pError::FileUnavailable { key })).await.ok();
+    }
+}
+
+async fn handle_outbound_query(
+    id: QueryId,
+    result: QueryResult,
+    step: kad::ProgressStep,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    pending_queries: &mut HashMap<QueryId, PendingQuery>,
+) {
+    let request_id = pending_queries.get(&id).map(|query| query.request_id);
+
+    // Comes in after a lookup finished early too, which is when it got enough values.
+    if let QueryResult::GetRecord(Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord {
+        cache_candidates,
+    })) = &result
+        && !cache_candidates.is_empty()
+        && let Some(record) = pending_queries.get_mut(&id).and_then(|query| query.cacheable.take())
+    {
+        let peers: Vec<_> = cache_candidates.values().copied().collect();
+        info!("Caching record {} at {} peers", format_key(&record.key), peers.len());
+
+        let query = PendingQuery {
+            cache_peers: peers.len(),
+            ..PendingQuery::new(RequestId::next(), QueryKind::PutRecord, record.key.clone())
+        };
+        let kademlia = &mut swarm.behaviour_mut().kademlia;
+        let cache_id = kademlia.put_record_to(record, peers.into_iter(), kad::Quorum::All);
+        pending_queries.insert(cache_id, query);
+    }
+
+    if let QueryResult::PutRecord(result) = &result
+        && let Some(query) = pending_queries.get(&id)
+        && query.cache_peers > 0
+    {
+        let peers = match result {
+            Ok(_) => query.cache_peers,
+            Err(
+                kad::PutRecordError::QuorumFailed { success, .. }
+                | kad::PutRecordError::Timeout { success, .. },
+            ) => success.len(),
+        };
+        let event = P2pOutboundEvent::RecordCached {
+            key: query.key.clone(),
+            peers,
+        };
+
+        sender.send(P2pEvent::Outbound(None, event)).await.ok();
+        pending_queries.remove(&id);
+        return;
+    }
+
+    if let Some(query) = pending_queries.get(&id) {
+        if query.finished {
+            debug!("Dropping late result for finished query {}", query.request_id);
+
+            if step.last {
+                pending_queries.remove(&id);
+            }
+
+            return;
+        }
+
+        debug!(
+            "Progress for {} {} query {}",
+            query.kind,
+            query.request_id,
+            format_key(&query.key)
+        );
+    }
+
     match result {
         QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders { key, providers })) => {
+            if let Some(query) = pending_queries.get_mut(&id) {
+                query.found = true;
+            }
+
             for peer in &providers {
                 info!(
                     "Peer {peer} provides key {}",
-                    std::str::from_utf8(key.as_ref()).unwrap()
+                    format_key(&key)
                 );
             }
 
             sender
-                .send(P2pEvent::Outbound(P2pOutboundEvent::ProvidersFound(
+                .send(P2pEvent::Outbound(request_id, P2pOutboundEvent::ProvidersFound(
                     key,
                     providers.into_iter().collect(),
                 )))
                 .await
-                .expect("Failed to send");
+                .ok();
+        }
+        QueryResult::GetProviders(Ok(kad::GetProvidersOk::FinishedWithNoAdditionalRecord {
+            ..
+        })) => {
+            if let Some(PendingQuery { key, found: false, .. }) = pending_queries.get(&id) {
+                sender
+                    .send(P2pEvent::Outbound(
+                        request_id,
+                        P2pOutboundEvent::ProvidersFound(key.clone(), Vec::new()),
+                    ))
+                    .await
+                    .ok();
+            }
         }
         QueryResult::GetProviders(Err(err)) => {
             error!("Failed to get providers: {err:?}");
-            sender
-                .send(P2pEvent::Error(format!("{:?}", err)))
-                .await
-                .expect("Failed to send");
+            sender.send(P2pEvent::Error(request_id, err.into())).await.ok();
         }
         QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(kad::PeerRecord {
-            record: kad::Record { key, value, .. },
-            ..
+            peer,
+            record,
         }))) => {
+            let key = record.key.clone();
+            let (value, signature) = envelope::open(&key, &record.value);
+            let value = value.to_vec();
+
             info!(
-                "Got record {} : {}",
-                std::str::from_utf8(key.as_ref()).unwrap(),
-                std::str::from_utf8(&value).unwrap(),
+                "Got record {} from {} : {}",
+                format_key(&key),
+                peer.map_or_else(|| "our store".to_owned(), |peer| peer.to_string()),
+                format_record_value(&value),
             );
 
-            sender
-                .send(P2pEvent::Outbound(P2pOutboundEvent::RecordFound(
-                    key, value,
-                )))
-                .await
-                .expect("Failed to send");
+            if let Some(query) = pending_queries.get_mut(&id) {
+                let trusted = signature.signer.is_none() || signature.verified;
+
+                if query.values.is_empty() {
+                    query.cacheable = trusted.then_some(record);
+                } else if query.values.iter().any(|found| found.value != value) {
+                    query.cacheable = None;
+                }
+
+                query.values.push(FoundValue {
+                    peer,
+                    value,
+                    signature,
+                });
+
+                if query.values.len() >= query.quorum.get() {
+                    finish_query(swarm, id, query);
+                    sender.send(P2pEvent::Outbound(request_id, query.resolve())).await.ok();
+                }
+            }
         }
         QueryResult::GetRecord(Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord { .. })) => {
             debug!("GetRecord outbound query finished with no additional record");
+
+            if let Some(query) = pending_queries.get_mut(&id) {
+                let event = if query.values.is_empty() {
+                    P2pOutboundEvent::RecordNotFound(query.key.clone())
+                } else {
+                    query.resolve()
+                };
+
+                sender.send(P2pEvent::Outbound(request_id, event)).await.ok();
+            }
         }
         QueryResult::GetRecord(Err(err)) => {
-            error!("Failed to get record: {err:?}");
+            let event = match pending_queries.get_mut(&id) {
+                Some(query) if !query.values.is_empty() => {
+                    warn!("Record lookup ended early, keeping what it found: {err:?}");
+                    P2pEvent::Outbound(request_id, query.resolve())
+                }
+                _ => get_record_failed(request_id, err),
+            };
+
+            sender.send(event).await.ok();
+        }
+        QueryResult::GetClosestPeers(Ok(kad::GetClosestPeersOk { key, peers })) => {
+            let key = kad::RecordKey::from(key);
+            info!("Found {} peers closest to {}", peers.len(), format_key(&key));
+
             sender
-                .send(P2pEvent::Error(format!("{:?}", err)))
+                .send(P2pEvent::Outbound(request_id, P2pOutboundEvent::ClosestPeers(
+                    key,
+                    peers.into_iter().map(|peer| peer.peer_id).collect(),
+                )))
                 .await
-                .expect("Failed to send");
+                .ok();
+        }
+        QueryResult::GetClosestPeers(Err(err)) => {
+            warn!("Closest peers query timed out: {err:?}");
+            sender.send(P2pEvent::Error(request_id, err.into())).await.ok();
         }
         QueryResult::PutRecord(Ok(kad::PutRecordOk { key })) => {
             info!(
                 "Successfully put record {}",
-                std::str::from_utf8(key.as_ref()).unwrap()
+                format_key(&key)
             );
 
             sender
-                .send(P2pEvent::Outbound(P2pOutboundEvent::RecordPut(key)))
+                .send(P2pEvent::Outbound(request_id, P2pOutboundEvent::RecordPut(key)))
                 .await
-                .expect("Failed to send");
+                .ok();
         }
         QueryResult::PutRecord(Err(err)) => {
             info!("Failed to put record: {err:?}");
-            sender
-                .send(P2pEvent::Error(format!("{:?}", err)))
-                .await
-                .expect("Failed to send");
+            sender.send(P2pEvent::Error(request_id, err.into())).await.ok();
         }
         QueryResult::StartProviding(Ok(kad::AddProviderOk { key })) => {
             info!(
                 "Successfully put provider record {}",
-                std::str::from_utf8(key.as_ref()).unwrap()
+                format_key(&key)
             );
 
             sender
-                .send(P2pEvent::Outbound(P2pOutboundEvent::ProviderPut(key)))
+                .send(P2pEvent::Outbound(request_id, P2pOutboundEvent::ProviderPut(key)))
                 .await
-                .expect("Failed to send");
+                .ok();
         }
         QueryResult::StartProviding(Err(err)) => {
             error!("Failed to put provider record: {err:?}");
-            sender
-                .send(P2pEvent::Error(format!("{:?}", err)))
-                .await
-                .expect("Failed to send");
+            // The UI drops the key from what it provides, so don't keep announcing it either.
+            swarm.behaviour_mut().kademlia.stop_providing(err.key());
+            sender.send(P2pEvent::Error(request_id, err.into())).await.ok();
+        }
+        QueryResult::Bootstrap(Ok(kad::BootstrapOk { num_remaining, .. })) => {
+            debug!("Bootstrap in progress, {num_remaining} buckets remaining");
+
+            let event = if num_remaining == 0 {
+                P2pOutboundEvent::BootstrapFinished
+            } else {
+                P2pOutboundEvent::BootstrapProgress { num_remaining }
+            };
+
+            sender.send(P2pEvent::Outbound(request_id, event)).await.ok();
+        }
+        QueryResult::Bootstrap(Err(err)) => {
+            error!("Failed to bootstrap: {err:?}");
+            sender.send(P2pEvent::Error(request_id, err.into())).await.ok();
         }
         _ => {}
     }
+
+    if step.last {
+        pending_queries.remove(&id);
+    }
 }
 
 async fn handle_inbound_request(
     request: InboundRequest,
     swarm: &mut Swarm<CustomBehaviour>,
     sender: &mut mpsc::Sender<P2pEvent>,
+    inbound: &mut InboundQueue,
 ) {
-    info!("Inbound request: {request:?}");
-
     match request {
+        InboundRequest::GetRecord { present_locally, .. } => {
+            debug!("Inbound record request, present locally: {present_locally}");
+            inbound.served.record(present_locally);
+            return;
+        }
+        InboundRequest::GetProvider { .. } => {
+            debug!("Inbound provider lookup");
+            inbound.served.provider_lookups += 1;
+            return;
+        }
+        _ => info!("Inbound request: {request:?}"),
+    }
+
+    let (source, record) = match request {
         InboundRequest::AddProvider {
             record: Some(record),
-        } => {
-            let store = swarm.behaviour_mut().kademlia.store_mut();
-            store
-                .add_provider(record.clone())
-                .expect("Failed to store provider record");
+        } => (record.provider, InboundRecord::Provider(record)),
+        InboundRequest::PutRecord {
+            source,
+            record: Some(record),
+            ..
+        } => (source, InboundRecord::Record(record)),
+        _ => return,
+    };
+
+    let per_minute = inbound.limits.max_stores_per_minute;
+
+    match inbound.rate.check(source, per_minute, Instant::now()) {
+        Throttle::Allowed => {}
+        Throttle::Limited => {
+            warn!("{source} sent more than {per_minute} store requests in a minute, dropping");
+            sender.send(P2pEvent::PeerRateLimited(source)).await.ok();
+            return;
+        }
+        Throttle::StillLimited => return,
+    }
+
+    let store = swarm.behaviour_mut().kademlia.store_mut();
+
+    if let Err(reason) = inbound.limits.check(source, &record, store, &inbound.uploads) {
+        reject_inbound(source, record.key().clone(), reason, sender).await;
+        return;
+    }
+
+    if !inbound.approval_required {
+        store_inbound(source, record, swarm, sender, &mut inbound.uploads).await;
+        return;
+    }
+
+    let key = record.key().clone();
+    let value = record.value().map(<[u8]>::to_vec);
+
+    match inbound.park(source, record, Instant::now()) {
+        Some(id) => {
+            sender
+                .send(P2pEvent::InboundPending {
+                    id,
+                    source,
+                    key,
+                    value,
+                })
+                .await
+                .ok();
+        }
+        None => warn!(
+            "Too many inbound requests awaiting approval, dropping {} from {source}",
+            format_key(&key)
+        ),
+    }
+}
+
+async fn store_inbound(
+    source: PeerId,
+    record: InboundRecord,
+    swarm: &mut Swarm<CustomBehaviour>,
+    sender: &mut mpsc::Sender<P2pEvent>,
+    uploads: &mut Uploads,
+) {
+    let store = swarm.behaviour_mut().kademlia.store_mut();
+
+    match record {
+        InboundRecord::Provider(record) => {
+            if let Err(error) = store.add_provider(record.clone()) {
+                reject_inbound(source, record.key, RejectReason::Store(error), sender).await;
+                return;
+            }
+
             sender
                 .send(P2pEvent::Inbound(P2pInboundEvent::ProviderAdded(
                     record.key,
                 )))
                 .await
-                .expect("Failed to send");
+                .ok();
         }
-        InboundRequest::PutRecord {
-            source,
-            record: Some(record),
-            ..
-        } => {
-            let store = swarm.behaviour_mut().kademlia.store_mut();
-            store.put(record.clone()).expect("Failed to store record");
+        InboundRecord::Record(record) => {
+            if let Err(error) = store.put(record.clone()) {
+                reject_inbound(source, record.key, RejectReason::Store(error), sender).await;
+                return;
+            }
+
+            uploads.record(source, record.key.clone());
+            let (value, signature) = envelope::open(&record.key, &record.value);
+
             sender
                 .send(P2pEvent::Inbound(P2pInboundEvent::RecordStored(
                     source,
-                    record.key,
-                    record.value,
+                    record.key.clone(),
+                    value.to_vec(),
+                    record.expires,
+                    signature,
                 )))
                 .await
-                .expect("Failed to send");
+                .ok();
         }
-        _ => {}
+    }
+}
+
+async fn reject_inbound(
+    source: PeerId,
+    key: kad::RecordKey,
+    reason: RejectReason,
+    sender: &mut mpsc::Sender<P2pEvent>,
+) {
+    warn!("Rejected {} from {source}: {reason}", format_key(&key));
+    sender
+        .send(P2pEvent::InboundRejected {
+            source,
+            key,
+            reason,
+        })
+        .await
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swarm::build_swarm;
+
+    #[tokio::test]
+    async fn oversized_put_record_emits_error_event() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let value = vec![0; kad::store::MemoryStoreConfig::default().max_value_bytes + 1];
+
+        handle_command(
+            P2pCommand::PutRecord(
+                RequestId::next(),
+                kad::RecordKey::new(&"key"),
+                value,
+                None,
+                kad::Quorum::One,
+            ),
+            &mut swarm,
+            &mut sender,
+            &mut HashMap::new(),
+            &mut FileTransfers::default(),
+            &mut HashMap::new(),
+            &mut InboundQueue::default(),
+        )
+        .await;
+
+        assert!(matches!(
+            receiver.try_next(),
+            Ok(Some(P2pEvent::Error(_, P2pError::Store {
+                error: kad::store::Error::ValueTooLarge,
+                ..
+            })))
+        ));
+    }
+
+    #[tokio::test]
+    async fn put_record_is_pushed_and_listed() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let mut pending_queries = HashMap::new();
+        let mut transfers = FileTransfers::default();
+        let mut relay_listeners = HashMap::new();
+
+        for cmd in [
+            P2pCommand::PutRecord(
+                RequestId::next(),
+                kad::RecordKey::new(&"key"),
+                b"value".to_vec(),
+                None,
+                kad::Quorum::One,
+            ),
+            P2pCommand::ListRecords,
+        ] {
+            handle_command(
+                cmd,
+                &mut swarm,
+                &mut sender,
+                &mut pending_queries,
+                &mut transfers,
+                &mut relay_listeners,
+                &mut InboundQueue::default(),
+            )
+            .await;
+        }
+
+        let Ok(Some(P2pEvent::Local(P2pLocalEvent::RecordStored(record)))) = receiver.try_next()
+        else {
+            panic!("own put should be pushed as a stored record");
+        };
+        assert_eq!(record.publisher, Some(*swarm.local_peer_id()));
+
+        let Ok(Some(P2pEvent::LocalRecords(records))) = receiver.try_next() else {
+            panic!("listing should reply with the local records");
+        };
+        assert_eq!(records, vec![(kad::RecordKey::new(&"key"), b"value".to_vec(), None)]);
+    }
+
+    #[tokio::test]
+    async fn republishing_skips_what_was_removed_or_no_longer_provided() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, _receiver) = mpsc::channel(20);
+        let mut pending_queries = HashMap::new();
+        let put = |key: &str| {
+            P2pCommand::PutRecord(
+                RequestId::next(),
+                kad::RecordKey::new(&key),
+                b"value".to_vec(),
+                None,
+                kad::Quorum::One,
+            )
+        };
+
+        for cmd in [
+            put("kept"),
+            put("removed"),
+            P2pCommand::PutProvider(RequestId::next(), kad::RecordKey::new(&"provided")),
+            P2pCommand::PutProvider(RequestId::next(), kad::RecordKey::new(&"stopped")),
+            P2pCommand::RemoveRecord(kad::RecordKey::new(&"removed")),
+            P2pCommand::StopProviding(kad::RecordKey::new(&"stopped")),
+        ] {
+            handle_command(
+                cmd,
+                &mut swarm,
+                &mut sender,
+                &mut pending_queries,
+                &mut FileTransfers::default(),
+                &mut HashMap::new(),
+                &mut InboundQueue::default(),
+            )
+            .await;
+        }
+
+        let mut foreign = kad::Record::new(kad::RecordKey::new(&"foreign"), b"value".to_vec());
+        foreign.publisher = Some(PeerId::random());
+        swarm.behaviour_mut().kademlia.store_mut().put(foreign).unwrap();
+
+        assert_eq!(republish_records(&mut swarm), 1);
+        assert_eq!(republish_providers(&mut swarm), vec![kad::RecordKey::new(&"provided")]);
+    }
+
+    #[tokio::test]
+    async fn record_lookups_resolve_once_enough_values_came_in() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let mut pending_queries = HashMap::new();
+        let key = kad::RecordKey::new(&"key");
+        let request_id = RequestId::next();
+
+        handle_command(
+            P2pCommand::GetRecord(request_id, key.clone(), NonZeroUsize::new(2).unwrap()),
+            &mut swarm,
+            &mut sender,
+            &mut pending_queries,
+            &mut FileTransfers::default(),
+            &mut HashMap::new(),
+            &mut InboundQueue::default(),
+        )
+        .await;
+        let query_id = *pending_queries.keys().next().unwrap();
+
+        for value in [b"one", b"two"] {
+            assert!(receiver.try_next().is_err());
+
+            let record = kad::PeerRecord {
+                peer: Some(PeerId::random()),
+                record: kad::Record::new(key.clone(), value.to_vec()),
+            };
+            let result = QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(record)));
+            let step = kad::ProgressStep {
+                count: NonZeroUsize::MIN,
+                last: false,
+            };
+
+            let queries = &mut pending_queries;
+            handle_outbound_query(query_id, result, step, &mut swarm, &mut sender, queries).await;
+        }
+
+        let Ok(Some(P2pEvent::Outbound(Some(id), P2pOutboundEvent::RecordResolved {
+            values,
+            agree,
+            ..
+        }))) = receiver.try_next()
+        else {
+            panic!("the lookup should resolve with both values");
+        };
+        assert_eq!(id, request_id);
+        assert_eq!(value_variants(&values).len(), 2);
+        assert!(!agree);
+        assert!(pending_queries[&query_id].finished);
+    }
+
+    #[tokio::test]
+    async fn only_records_with_a_holding_signature_are_cached() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let mut swarm = build_swarm(&SwarmConfig::default(), keypair.clone()).unwrap();
+        let (mut sender, _receiver) = mpsc::channel(10);
+        let key = kad::RecordKey::new(&"key");
+        let sealed = envelope::seal(&keypair, &key, b"value").unwrap();
+        let mut forged = sealed.clone();
+        *forged.last_mut().unwrap() ^= 1;
+
+        for (value, cached) in [(sealed, true), (forged, false)] {
+            let mut pending_queries = HashMap::new();
+
+            handle_command(
+                P2pCommand::GetRecord(RequestId::next(), key.clone(), NonZeroUsize::MIN),
+                &mut swarm,
+                &mut sender,
+                &mut pending_queries,
+                &mut FileTransfers::default(),
+                &mut HashMap::new(),
+                &mut InboundQueue::default(),
+            )
+            .await;
+            let query_id = *pending_queries.keys().next().unwrap();
+            let candidate = PeerId::random();
+            let target = kad::KBucketKey::new(key.clone());
+            let distance = kad::KBucketKey::from(candidate).distance(&target);
+
+            for (result, last) in [
+                (
+                    kad::GetRecordOk::FoundRecord(kad::PeerRecord {
+                        peer: Some(PeerId::random()),
+                        record: kad::Record::new(key.clone(), value),
+                    }),
+                    false,
+                ),
+                (
+                    kad::GetRecordOk::FinishedWithNoAdditionalRecord {
+                        cache_candidates: [(distance, candidate)].into(),
+                    },
+                    true,
+                ),
+            ] {
+                let step = kad::ProgressStep {
+                    count: NonZeroUsize::MIN,
+                    last,
+                };
+                let result = QueryResult::GetRecord(Ok(result));
+                let queries = &mut pending_queries;
+                handle_outbound_query(query_id, result, step, &mut swarm, &mut sender, queries)
+                    .await;
+            }
+
+            let caching = pending_queries.values().filter(|query| query.cache_peers == 1).count();
+            assert_eq!(caching, usize::from(cached));
+        }
+    }
+
+    #[tokio::test]
+    async fn inbound_record_waits_for_approval() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let mut inbound = InboundQueue::default();
+        inbound.approval_required = true;
+        let key = kad::RecordKey::new(&"key");
+
+        handle_inbound_request(
+            InboundRequest::PutRecord {
+                source: PeerId::random(),
+                connection: libp2p::swarm::ConnectionId::new_unchecked(0),
+                record: Some(kad::Record::new(key.clone(), b"value".to_vec())),
+            },
+            &mut swarm,
+            &mut sender,
+            &mut inbound,
+        )
+        .await;
+
+        let Ok(Some(P2pEvent::InboundPending { id, .. })) = receiver.try_next() else {
+            panic!("inbound record should be parked for approval");
+        };
+        assert!(swarm.behaviour_mut().kademlia.store_mut().get(&key).is_none());
+
+        handle_command(
+            P2pCommand::AcceptInbound(id),
+            &mut swarm,
+            &mut sender,
+            &mut HashMap::new(),
+            &mut FileTransfers::default(),
+            &mut HashMap::new(),
+            &mut inbound,
+        )
+        .await;
+
+        assert!(matches!(
+            receiver.try_next(),
+            Ok(Some(P2pEvent::Inbound(P2pInboundEvent::RecordStored(..))))
+        ));
+        assert!(swarm.behaviour_mut().kademlia.store_mut().get(&key).is_some());
+    }
+
+    fn inbound_put(key: &str, value: &[u8]) -> InboundRequest {
+        InboundRequest::PutRecord {
+            source: PeerId::random(),
+            connection: libp2p::swarm::ConnectionId::new_unchecked(0),
+            record: Some(kad::Record::new(kad::RecordKey::new(&key), value.to_vec())),
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_inbound_records_are_rejected_not_stored() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let mut inbound = InboundQueue::default();
+        inbound.limits.require_utf8 = true;
+
+        let oversized = vec![b'a'; inbound.limits.max_value_bytes + 1];
+        for request in [inbound_put("big", &oversized), inbound_put("binary", &[0xff])] {
+            handle_inbound_request(request, &mut swarm, &mut sender, &mut inbound).await;
+        }
+
+        assert!(matches!(
+            receiver.try_next(),
+            Ok(Some(P2pEvent::InboundRejected {
+                reason: RejectReason::ValueTooLarge { .. },
+                ..
+            }))
+        ));
+        assert!(matches!(
+            receiver.try_next(),
+            Ok(Some(P2pEvent::InboundRejected {
+                reason: RejectReason::NotUtf8,
+                ..
+            }))
+        ));
+        assert_eq!(swarm.behaviour_mut().kademlia.store_mut().records().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn full_store_rejects_inbound_record() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let store = swarm.behaviour_mut().kademlia.store_mut();
+
+        for i in 0..kad::store::MemoryStoreConfig::default().max_records {
+            store
+                .put(kad::Record::new(kad::RecordKey::new(&i.to_string()), vec![]))
+                .unwrap();
+        }
+
+        handle_inbound_request(
+            inbound_put("overflow", b"value"),
+            &mut swarm,
+            &mut sender,
+            &mut InboundQueue::default(),
+        )
+        .await;
+
+        assert!(matches!(
+            receiver.try_next(),
+            Ok(Some(P2pEvent::InboundRejected {
+                reason: RejectReason::Store(kad::store::Error::MaxRecords),
+                ..
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn cancelled_query_is_marked_finished() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let mut pending_queries = HashMap::new();
+        let mut transfers = FileTransfers::default();
+        let mut relay_listeners = HashMap::new();
+        let request_id = RequestId::next();
+
+        handle_command(
+            P2pCommand::GetRecord(request_id, kad::RecordKey::new(&"key"), NonZeroUsize::MIN),
+            &mut swarm,
+            &mut sender,
+            &mut pending_queries,
+            &mut transfers,
+            &mut relay_listeners,
+            &mut InboundQueue::default(),
+        )
+        .await;
+        handle_command(
+            P2pCommand::CancelQuery(request_id),
+            &mut swarm,
+            &mut sender,
+            &mut pending_queries,
+            &mut transfers,
+            &mut relay_listeners,
+            &mut InboundQueue::default(),
+        )
+        .await;
+
+        assert!(matches!(
+            receiver.try_next(),
+            Ok(Some(P2pEvent::Outbound(
+                Some(id),
+                P2pOutboundEvent::QueryCancelled(_)
+            ))) if id == request_id
+        ));
+        assert!(pending_queries.values().all(|query| query.finished));
+    }
+
+    #[tokio::test]
+    async fn cancelled_provide_stops_providing() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, _receiver) = mpsc::channel(10);
+        let mut pending_queries = HashMap::new();
+        let mut transfers = FileTransfers::default();
+        let mut relay_listeners = HashMap::new();
+        let request_id = RequestId::next();
+
+        for command in [
+            P2pCommand::PutProvider(request_id, kad::RecordKey::new(&"key")),
+            P2pCommand::CancelQuery(request_id),
+        ] {
+            handle_command(
+                command,
+                &mut swarm,
+                &mut sender,
+                &mut pending_queries,
+                &mut transfers,
+                &mut relay_listeners,
+                &mut InboundQueue::default(),
+            )
+            .await;
+        }
+
+        assert!(pending_queries.values().all(|query| query.finished));
+        assert_eq!(swarm.behaviour_mut().kademlia.store_mut().provided().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn set_kad_mode_switches_and_confirms() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+
+        handle_command(
+            P2pCommand::SetKadMode(Mode::Client),
+            &mut swarm,
+            &mut sender,
+            &mut HashMap::new(),
+            &mut FileTransfers::default(),
+            &mut HashMap::new(),
+            &mut InboundQueue::default(),
+        )
+        .await;
+
+        assert_eq!(swarm.behaviour().kademlia.mode(), Mode::Client);
+        assert!(matches!(
+            receiver.try_next(),
+            Ok(Some(P2pEvent::KadModeChanged(Mode::Client)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn shutdown_command_stops_the_node() {
+        let (mut commands, command_receiver) = mpsc::channel(10);
+        let (sender, mut receiver) = mpsc::channel(100);
+        let config = NodeConfig {
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            fresh_identity: true,
+            ..NodeConfig::default()
+        };
+
+        commands.send(P2pCommand::Shutdown).await.unwrap();
+        assert_eq!(run(command_receiver, sender, config).await, Ok(()));
+
+        let mut events = Vec::new();
+        while let Ok(Some(event)) = receiver.try_next() {
+            events.push(event);
+        }
+
+        assert!(matches!(events.last(), Some(P2pEvent::ShutdownComplete)));
+    }
+
+    #[tokio::test]
+    async fn dropped_event_receiver_stops_the_node() {
+        let (_commands, command_receiver) = mpsc::channel(10);
+        let (sender, receiver) = mpsc::channel(100);
+        let config = NodeConfig {
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            fresh_identity: true,
+            ..NodeConfig::default()
+        };
+
+        drop(receiver);
+
+        assert_eq!(run(command_receiver, sender, config).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn blocked_peer_rediscovered_via_mdns_is_not_routed() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let mut pending_queries = HashMap::new();
+        let mut transfers = FileTransfers::default();
+        let mut relay_listeners = HashMap::new();
+        let peer_id = PeerId::random();
+        let address: Multiaddr = "/ip4/192.168.1.10/tcp/4001".parse().unwrap();
+
+        handle_command(
+            P2pCommand::BlockPeer(peer_id),
+            &mut swarm,
+            &mut sender,
+            &mut pending_queries,
+            &mut transfers,
+            &mut relay_listeners,
+            &mut InboundQueue::default(),
+        )
+        .await;
+        handle_swarm_event(
+            SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(vec![(
+                peer_id, address,
+            )]))),
+            &mut swarm,
+            &mut sender,
+            &mut pending_queries,
+            &mut transfers,
+            (&mut relay_listeners, &NegotiatedSecurity::default()),
+            &mut InboundQueue::default(),
+        )
+        .await;
+
+        let routed = swarm
+            .behaviour_mut()
+            .kademlia
+            .kbuckets()
+            .any(|bucket| bucket.iter().any(|entry| *entry.node.key.preimage() == peer_id));
+
+        assert!(!routed);
+        assert!(matches!(receiver.try_next(), Ok(Some(P2pEvent::PeerBlocked(id))) if id == peer_id));
+        assert!(receiver.try_next().is_err());
+    }
+
+    #[tokio::test]
+    async fn repeated_mdns_answers_are_announced_once() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let (mut sender, mut receiver) = mpsc::channel(10);
+        let mut mdns_peers = MdnsPeers::default();
+        let peer_id = PeerId::random();
+        let address: Multiaddr = "/ip4/192.168.1.10/tcp/4001".parse().unwrap();
+        let discovered = || mdns::Event::Discovered(vec![(peer_id, address.clone())]);
+        let expired = || mdns::Event::Expired(vec![(peer_id, address.clone())]);
+
+        for event in [discovered(), discovered(), discovered(), expired(), expired()] {
+            let mut event = SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(event));
+            mdns_peers.dedup(&mut event);
+
+            handle_swarm_event(
+                event,
+                &mut swarm,
+                &mut sender,
+                &mut HashMap::new(),
+                &mut FileTransfers::default(),
+                (&mut HashMap::new(), &NegotiatedSecurity::default()),
+                &mut InboundQueue::default(),
+            )
+            .await;
+        }
+
+        assert!(matches!(receiver.try_next(), Ok(Some(P2pEvent::PeerDiscovered(..)))));
+        assert!(matches!(receiver.try_next(), Ok(Some(P2pEvent::PeerExpired(..)))));
+        assert!(receiver.try_next().is_err());
+    }
+
+    #[test]
+    fn binary_keys_are_shown_as_hex_and_parse_back() {
+        let key = kad::RecordKey::from(vec![0xff, 0x00, 0xab]);
+        let lookalike = kad::RecordKey::new(&"0xff");
+
+        assert_eq!(format_key(&key), "0xff00ab");
+        assert_eq!(parse_key(&format_key(&key), "", false), key);
+        assert_eq!(parse_key(&format_key(&lookalike), "", true), lookalike);
+    }
+
+    #[test]
+    fn hashed_key_is_reachable_by_name_and_by_hash() {
+        let hashed = parse_key("hello", "", true);
+
+        assert_eq!(hashed.as_ref(), Sha256::digest(b"hello").as_slice());
+        assert_eq!(parse_key(&format_key(&hashed), "", false), hashed);
+        assert_eq!(parse_key("hello", "", false), kad::RecordKey::new(&"hello"));
+    }
+
+    #[test]
+    fn human_keys_go_in_the_namespace_and_raw_keys_stay_put() {
+        assert_eq!(parse_key("hello", "app", false), kad::RecordKey::new(&"app/hello"));
+        assert_eq!(
+            parse_key("hello", "app", true).as_ref(),
+            Sha256::digest(b"app/hello").as_slice()
+        );
+        assert_eq!(parse_key("0xff00", "app", false), parse_raw_key("0xff00"));
+    }
+
+    /// Listens on `address` and waits for the TCP port it was given.
+    async fn listen_port(swarm: &mut Swarm<CustomBehaviour>, address: &str) -> u16 {
+        swarm.listen_on(address.parse().unwrap()).unwrap();
+
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await
+                && let Some(port) = address.iter().find_map(|protocol| match protocol {
+                    Protocol::Tcp(port) => Some(port),
+                    _ => None,
+                })
+            {
+                return port;
+            }
+        }
+    }
+
+    /// Dials `address` and drives both swarms until the connection is up.
+    async fn connect(
+        dialer: &mut Swarm<CustomBehaviour>,
+        listener: &mut Swarm<CustomBehaviour>,
+        address: Multiaddr,
+    ) -> (PeerId, ConnectedPoint) {
+        dialer.dial(address).unwrap();
+
+        let connected = async {
+            loop {
+                select! {
+                    event = dialer.select_next_some() => {
+                        if let SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } = event {
+                            return (peer_id, endpoint);
+                        }
+                    }
+                    _ = listener.select_next_some() => {}
+                }
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(10), connected).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn dns_addresses_resolve_and_dial() {
+        let mut listener =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let mut dialer =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        let listener_id = *listener.local_peer_id();
+        let port = listen_port(&mut listener, "/ip4/127.0.0.1/tcp/0").await;
+
+        let address = format!("/dns4/localhost/tcp/{port}/p2p/{listener_id}").parse().unwrap();
+        let (peer_id, _) = connect(&mut dialer, &mut listener, address).await;
+
+        assert_eq!(peer_id, listener_id);
+    }
+
+    #[tokio::test]
+    async fn websocket_addresses_dial_over_websocket() {
+        let config = SwarmConfig {
+            websocket: true,
+            ..SwarmConfig::default()
+        };
+        let mut listener = build_swarm(&config, identity::Keypair::generate_ed25519()).unwrap();
+        let mut dialer = build_swarm(&config, identity::Keypair::generate_ed25519()).unwrap();
+        let port = listen_port(&mut listener, "/ip4/127.0.0.1/tcp/0/ws").await;
+
+        let address = format!("/ip4/127.0.0.1/tcp/{port}/ws").parse().unwrap();
+        let (_, endpoint) = connect(&mut dialer, &mut listener, address).await;
+
+        assert_eq!(
+            ConnectionTransport::of(endpoint.get_remote_address()),
+            ConnectionTransport::WebSocket
+        );
+    }
+
+    /// Connects a dialer and a listener secured as given and returns what the dialer negotiated.
+    async fn negotiate(dialer: Security, listener: Security) -> Option<SecurityProtocol> {
+        let build = |security| {
+            let config = SwarmConfig {
+                security,
+                ..SwarmConfig::default()
+            };
+            build_measured_swarm(&config, identity::Keypair::generate_ed25519()).unwrap()
+        };
+        let (mut listener, ..) = build(listener);
+        let (mut dialer, _, negotiated) = build(dialer);
+        let port = listen_port(&mut listener, "/ip4/127.0.0.1/tcp/0").await;
+
+        let address = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+        let (peer_id, _) = connect(&mut dialer, &mut listener, address).await;
+
+        negotiated.of(&peer_id)
+    }
+
+    #[tokio::test]
+    async fn both_interoperates_with_either_security_protocol() {
+        assert_eq!(negotiate(Security::Both, Security::Noise).await, Some(SecurityProtocol::Noise));
+        assert_eq!(negotiate(Security::Noise, Security::Both).await, Some(SecurityProtocol::Noise));
+        assert_eq!(negotiate(Security::Both, Security::Tls).await, Some(SecurityProtocol::Tls));
+        assert_eq!(negotiate(Security::Tls, Security::Both).await, Some(SecurityProtocol::Tls));
+        assert_eq!(negotiate(Security::Both, Security::Both).await, Some(SecurityProtocol::Noise));
+    }
+
+    #[tokio::test]
+    async fn tls_only_peers_connect_over_tls() {
+        assert_eq!(negotiate(Security::Tls, Security::Tls).await, Some(SecurityProtocol::Tls));
+    }
+
+    #[tokio::test]
+    async fn unresolvable_hosts_fail_with_a_resolve_error() {
+        let mut swarm =
+            build_swarm(&SwarmConfig::default(), identity::Keypair::generate_ed25519()).unwrap();
+        swarm.dial("/dns4/nowhere.invalid/tcp/4001".parse::<Multiaddr>().unwrap()).unwrap();
+
+        let failure = loop {
+            let event = swarm.select_next_some().await;
+
+            if let SwarmEvent::OutgoingConnectionError { error, .. } = event {
+                break resolve_failure(&error);
+            }
+        };
+
+        let Some(failure @ P2pError::ResolveFailed { .. }) = failure else {
+            panic!("expected a resolve failure, got {failure:?}");
+        };
+        assert!(failure.to_string().starts_with("Could not resolve host nowhere.invalid"));
+    }
+
+    #[test]
+    fn connections_are_told_apart_by_their_transport() {
+        let tcp: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let quic: Multiaddr = "/ip6/::1/udp/4001/quic-v1".parse().unwrap();
+        let relayed = quic
+            .clone()
+            .with(Protocol::P2p(PeerId::random()))
+            .with(Protocol::P2pCircuit);
+        let memory: Multiaddr = "/memory/1".parse().unwrap();
+        let websocket: Multiaddr = "/dns4/example.com/tcp/443/wss".parse().unwrap();
+
+        assert_eq!(ConnectionTransport::of(&tcp), ConnectionTransport::Tcp);
+        assert_eq!(ConnectionTransport::of(&quic), ConnectionTransport::Quic);
+        assert_eq!(ConnectionTransport::of(&relayed), ConnectionTransport::Quic);
+        assert_eq!(ConnectionTransport::of(&memory), ConnectionTransport::Other);
+        assert_eq!(ConnectionTransport::of(&websocket), ConnectionTransport::WebSocket);
+        assert_eq!(ConnectionTransport::Quic.to_string(), "quic-v1");
+    }
+
+    #[test]
+    fn format_record_value_keeps_valid_utf8() {
+        assert_eq!(format_record_value("hello, мир".as_bytes()), "hello, мир");
+    }
+
+    #[test]
+    fn format_record_value_falls_back_to_hex_for_invalid_utf8() {
+        assert_eq!(format_record_value(&[0xff, 0x00, 0xab]), "0xff00ab");
+    }
+
+    #[test]
+    fn format_record_value_truncates_long_values() {
+        let value = "a".repeat(MAX_DISPLAYED_VALUE_CHARS + 10);
+        let formatted = format_record_value(value.as_bytes());
+
+        assert_eq!(formatted.chars().count(), MAX_DISPLAYED_VALUE_CHARS + 1);
+        assert!(formatted.ends_with('…'));
+    }
+
+    #[test]
+    fn format_record_value_does_not_truncate_at_limit() {
+        let value = "a".repeat(MAX_DISPLAYED_VALUE_CHARS);
+
+        assert_eq!(format_record_value(value.as_bytes()), value);
     }
 }
@@ -0,0 +1,292 @@
+use std::fmt;
+use std::fmt::Formatter;
+use argon2::{Algorithm, Argon2, Block, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use libp2p::{PeerId, identity, kad};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Prefixed to everything we sign, so a record signature can't be passed off as another one.
+const SIGNING_DOMAIN: &[u8] = b"iced-libp2p-sample/record-envelope/1";
+/// Salts the passphrase, so the same one gives a different key in other applications.
+const KEY_SALT: &[u8] = b"iced-libp2p-sample/value-key/1";
+
+/// A signed record value as it travels through the DHT, encoded as CBOR.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Envelope<'a> {
+    #[serde(serialize_with = "as_bytes")]
+    value: &'a [u8],
+    #[serde(serialize_with = "as_bytes")]
+    publisher_pubkey: &'a [u8],
+    #[serde(serialize_with = "as_bytes")]
+    signature: &'a [u8],
+}
+
+/// A record value encrypted under a passphrase; the nonce is random for every value.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Encrypted<'a> {
+    #[serde(serialize_with = "as_bytes")]
+    nonce: &'a [u8],
+    #[serde(serialize_with = "as_bytes")]
+    ciphertext: &'a [u8],
+}
+
+impl<'a> Encrypted<'a> {
+    fn decode(bytes: &'a [u8]) -> Option<Self> {
+        cbor4ii::serde::from_slice::<Encrypted<'a>>(bytes)
+            .ok()
+            .filter(|encrypted| encrypted.nonce.len() == XNonce::default().len())
+    }
+}
+
+/// Who signed a record value and whether the signature holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordSignature {
+    /// The peer whose key is in the envelope, `None` for plain values.
+    pub signer: Option<PeerId>,
+    pub verified: bool,
+}
+
+impl fmt::Display for RecordSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.signer, self.verified) {
+            (Some(signer), true) => write!(f, "signed by {signer}"),
+            (Some(signer), false) => write!(f, "bad signature from {signer}"),
+            (None, _) => write!(f, "unsigned"),
+        }
+    }
+}
+
+/// Wraps `value` in an envelope signed with `keypair`, binding it to `key`.
+pub fn seal(
+    keypair: &identity::Keypair,
+    key: &kad::RecordKey,
+    value: &[u8],
+) -> Result<Vec<u8>, identity::SigningError> {
+    let signature = keypair.sign(&signed_bytes(key, value))?;
+    let publisher_pubkey = keypair.public().encode_protobuf();
+    let envelope = Envelope {
+        value,
+        publisher_pubkey: &publisher_pubkey,
+        signature: &signature,
+    };
+
+    Ok(cbor4ii::serde::to_vec(Vec::new(), &envelope).expect("envelopes are always encodable"))
+}
+
+/// Unwraps a record value stored under `key`.
+///
+/// Bytes that aren't an envelope, or carry a key we can't decode, are returned as they are and
+/// marked unsigned; an envelope is unwrapped whether its signature holds or not, so the caller
+/// can still show the value.
+pub fn open<'a>(key: &kad::RecordKey, bytes: &'a [u8]) -> (&'a [u8], RecordSignature) {
+    let Ok(envelope) = cbor4ii::serde::from_slice::<Envelope<'a>>(bytes) else {
+        return (bytes, RecordSignature::default());
+    };
+
+    let Ok(publisher) = identity::PublicKey::try_decode_protobuf(envelope.publisher_pubkey) else {
+        return (bytes, RecordSignature::default());
+    };
+
+    let signature = RecordSignature {
+        signer: Some(publisher.to_peer_id()),
+        verified: publisher.verify(&signed_bytes(key, envelope.value), envelope.signature),
+    };
+
+    (envelope.value, signature)
+}
+
+/// Whether `bytes` is a value encrypted by a [`ValueCipher`], under whatever passphrase.
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    Encrypted::decode(bytes).is_some()
+}
+
+/// Encrypts record values with XChaCha20-Poly1305 under a key derived from a passphrase that
+/// the peers reading them share out of band. Only values are hidden, keys stay readable.
+pub struct ValueCipher {
+    passphrase: String,
+    cipher: XChaCha20Poly1305,
+}
+
+impl fmt::Debug for ValueCipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueCipher").finish_non_exhaustive()
+    }
+}
+
+impl ValueCipher {
+    /// Derives the key with Argon2id, which is deliberately slow; keep the cipher around.
+    pub fn new(passphrase: &str) -> Self {
+        let params = Params::default();
+        let mut blocks = vec![Block::default(); params.block_count()];
+        let mut key = Key::default();
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into_with_memory(passphrase.as_bytes(), KEY_SALT, &mut key, &mut blocks)
+            .expect("the default Argon2 parameters fit a 32 byte key");
+
+        Self {
+            passphrase: passphrase.to_owned(),
+            cipher: XChaCha20Poly1305::new(&key),
+        }
+    }
+
+    pub fn is_for(&self, passphrase: &str) -> bool {
+        self.passphrase == passphrase
+    }
+
+    pub fn encrypt(&self, value: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(&nonce, value).expect("values fit the cipher");
+        let encrypted = Encrypted {
+            nonce: &nonce,
+            ciphertext: &ciphertext,
+        };
+
+        cbor4ii::serde::to_vec(Vec::new(), &encrypted).expect("encrypted values are encodable")
+    }
+
+    /// The plaintext of an encrypted value, `None` for plain values and for values encrypted
+    /// under a different passphrase or tampered with.
+    pub fn decrypt(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        let encrypted = Encrypted::decode(bytes)?;
+
+        self.cipher
+            .decrypt(XNonce::from_slice(encrypted.nonce), encrypted.ciphertext)
+            .ok()
+    }
+}
+
+fn signed_bytes(key: &kad::RecordKey, value: &[u8]) -> Vec<u8> {
+    let key = key.as_ref();
+    let mut bytes = Vec::with_capacity(SIGNING_DOMAIN.len() + 8 + key.len() + value.len());
+    bytes.extend_from_slice(SIGNING_DOMAIN);
+    bytes.extend_from_slice(&(key.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(key);
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+/// Serde writes `&[u8]` as a list of numbers; CBOR has a proper byte string for it.
+fn as_bytes<S: Serializer>(bytes: &&[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> kad::RecordKey {
+        kad::RecordKey::new(&"greeting")
+    }
+
+    fn encode(envelope: &Envelope<'_>) -> Vec<u8> {
+        cbor4ii::serde::to_vec(Vec::new(), envelope).unwrap()
+    }
+
+    fn encode_encrypted(encrypted: &Encrypted<'_>) -> Vec<u8> {
+        cbor4ii::serde::to_vec(Vec::new(), encrypted).unwrap()
+    }
+
+    #[test]
+    fn sealed_values_open_verified_with_the_signer() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let sealed = seal(&keypair, &key(), b"hello").unwrap();
+
+        let (value, signature) = open(&key(), &sealed);
+
+        assert_eq!(value, b"hello");
+        assert_eq!(signature.signer, Some(keypair.public().to_peer_id()));
+        assert!(signature.verified);
+    }
+
+    #[test]
+    fn tampered_values_and_signatures_are_flagged_but_still_opened() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let signer = Some(keypair.public().to_peer_id());
+        let sealed = seal(&keypair, &key(), b"hello").unwrap();
+        let envelope: Envelope = cbor4ii::serde::from_slice(&sealed).unwrap();
+
+        let tampered_value = encode(&Envelope { value: b"hellO", ..envelope });
+        let (value, signature) = open(&key(), &tampered_value);
+        assert_eq!(value, b"hellO");
+        assert_eq!(signature, RecordSignature { signer, verified: false });
+
+        let mut flipped = envelope.signature.to_vec();
+        flipped[0] ^= 1;
+        let tampered_signature = encode(&Envelope { signature: &flipped, ..envelope });
+        let (_, signature) = open(&key(), &tampered_signature);
+        assert_eq!(signature, RecordSignature { signer, verified: false });
+
+        let (_, moved) = open(&kad::RecordKey::new(&"other"), &sealed);
+        assert!(!moved.verified);
+    }
+
+    #[test]
+    fn swapping_in_another_key_names_that_peer_without_verifying() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let impostor = identity::Keypair::generate_ed25519();
+        let sealed = seal(&keypair, &key(), b"hello").unwrap();
+        let envelope: Envelope = cbor4ii::serde::from_slice(&sealed).unwrap();
+        let impostor_key = impostor.public().encode_protobuf();
+
+        let swapped = encode(&Envelope { publisher_pubkey: &impostor_key, ..envelope });
+        let (_, signature) = open(&key(), &swapped);
+        let impostor = impostor.public().to_peer_id();
+
+        assert_eq!(signature.signer, Some(impostor));
+        assert!(!signature.verified);
+        assert_eq!(signature.to_string(), format!("bad signature from {impostor}"));
+    }
+
+    #[test]
+    fn encrypted_values_round_trip_and_hide_the_plaintext() {
+        let cipher = ValueCipher::new("correct horse");
+        let encrypted = cipher.encrypt(b"hello");
+
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.windows(5).any(|window| window == b"hello"));
+        assert_ne!(cipher.encrypt(b"hello"), encrypted);
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), b"hello");
+
+        let keypair = identity::Keypair::generate_ed25519();
+        let sealed = seal(&keypair, &key(), &encrypted).unwrap();
+        let (value, signature) = open(&key(), &sealed);
+        assert!(signature.verified);
+        assert_eq!(cipher.decrypt(value).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn the_wrong_passphrase_or_a_tampered_value_does_not_decrypt() {
+        let cipher = ValueCipher::new("correct horse");
+        let encrypted = cipher.encrypt(b"hello");
+
+        assert!(ValueCipher::new("battery staple").decrypt(&encrypted).is_none());
+        assert!(cipher.decrypt(b"hello").is_none());
+        assert!(!is_encrypted(b"hello"));
+
+        let parsed = Encrypted::decode(&encrypted).unwrap();
+        let mut flipped = parsed.ciphertext.to_vec();
+        flipped[0] ^= 1;
+        let tampered = encode_encrypted(&Encrypted { ciphertext: &flipped, ..parsed });
+        assert!(is_encrypted(&tampered));
+        assert!(cipher.decrypt(&tampered).is_none());
+    }
+
+    #[test]
+    fn plain_and_broken_values_come_back_unsigned_and_untouched() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let sealed = seal(&keypair, &key(), b"hello").unwrap();
+        let envelope: Envelope = cbor4ii::serde::from_slice(&sealed).unwrap();
+        let garbage_key = encode(&Envelope { publisher_pubkey: b"garbage", ..envelope });
+        let truncated = &sealed[..sealed.len() - 1];
+
+        for bytes in [&b"hello"[..], &[0xc3, 0x28], &[], truncated, &garbage_key] {
+            let (value, signature) = open(&key(), bytes);
+
+            assert_eq!(value, bytes);
+            assert_eq!(signature, RecordSignature::default());
+            assert_eq!(signature.to_string(), "unsigned");
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Whole nodes running in one runtime and talking over `/memory` addresses, driven through the
+//! same command and event channels the app uses.
+
+use std::time::Duration;
+use iced::futures::channel::mpsc;
+use iced::futures::{SinkExt, StreamExt};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use super::{NodeConfig, P2pCommand, P2pEvent, run};
+
+pub(crate) const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Nodes block on a full event channel, so leave room for everything a test doesn't read.
+const EVENT_BUFFER: usize = 1024;
+
+pub(crate) struct TestNode {
+    pub peer_id: PeerId,
+    /// Where the node listens, with its peer id appended.
+    pub address: Multiaddr,
+    commands: mpsc::Sender<P2pCommand>,
+    events: mpsc::Receiver<P2pEvent>,
+}
+
+impl TestNode {
+    pub async fn spawn() -> Self {
+        Self::with_config(NodeConfig::default()).await
+    }
+
+    /// Starts a node with `config`, except that it listens on a fresh `/memory` address, uses a
+    /// fresh identity and doesn't look for peers on its own.
+    pub async fn with_config(config: NodeConfig) -> Self {
+        let (commands, command_receiver) = mpsc::channel(32);
+        let (event_sender, events) = mpsc::channel(EVENT_BUFFER);
+        let config = NodeConfig {
+            listen_addrs: vec![Multiaddr::empty().with(Protocol::Memory(0))],
+            bootstrap_peers: Vec::new(),
+            fresh_identity: true,
+            known_peers: None,
+            mdns_enabled: false,
+            ..config
+        };
+
+        tokio::spawn(run(command_receiver, event_sender, config));
+
+        let mut node = Self {
+            peer_id: PeerId::random(),
+            address: Multiaddr::empty(),
+            commands,
+            events,
+        };
+        let P2pEvent::LocalIdentity(peer_id) = node
+            .wait_for_event(|event| matches!(event, P2pEvent::LocalIdentity(_)), EVENT_TIMEOUT)
+            .await
+        else {
+            unreachable!()
+        };
+        let P2pEvent::Bootstrapped(address) = node
+            .wait_for_event(|event| matches!(event, P2pEvent::Bootstrapped(_)), EVENT_TIMEOUT)
+            .await
+        else {
+            unreachable!()
+        };
+
+        node.peer_id = peer_id;
+        node.address = address.with_p2p(peer_id).unwrap();
+        node
+    }
+
+    pub async fn send(&mut self, cmd: P2pCommand) {
+        self.commands.send(cmd).await.unwrap();
+    }
+
+    /// Skips events until one matches `pred`, panicking if none does within `timeout`.
+    pub async fn wait_for_event(
+        &mut self,
+        mut pred: impl FnMut(&P2pEvent) -> bool,
+        timeout: Duration,
+    ) -> P2pEvent {
+        let wait = async {
+            loop {
+                match self.events.next().await {
+                    Some(event) if pred(&event) => return event,
+                    Some(_) => {}
+                    None => panic!("node stopped before the expected event"),
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, wait)
+            .await
+            .unwrap_or_else(|_| panic!("no matching event within {timeout:?}"))
+    }
+
+    /// Dials `other` and waits until each node has the other in its routing table.
+    pub async fn connect(&mut self, other: &mut TestNode) {
+        let (local, remote) = (self.peer_id, other.peer_id);
+        self.send(P2pCommand::Dial(other.address.clone())).await;
+
+        self.wait_for_event(|event| routed(event, remote), EVENT_TIMEOUT).await;
+        other.wait_for_event(|event| routed(event, local), EVENT_TIMEOUT).await;
+    }
+}
+
+fn routed(event: &P2pEvent, peer: PeerId) -> bool {
+    matches!(event, P2pEvent::RoutingUpdated { peer_id, .. } if *peer_id == peer)
+}
+
+mod tests {
+    use std::num::NonZeroUsize;
+    use libp2p::kad;
+    use super::*;
+    use crate::inbound::{InboundLimits, RejectReason};
+    use crate::p2p::{P2pInboundEvent, P2pOutboundEvent, RequestId};
+
+    fn put(key: &kad::RecordKey, value: &[u8]) -> (RequestId, P2pCommand) {
+        let id = RequestId::next();
+        let cmd = P2pCommand::PutRecord(id, key.clone(), value.to_vec(), None, kad::Quorum::One);
+
+        (id, cmd)
+    }
+
+    #[tokio::test]
+    async fn records_put_on_one_node_can_be_got_from_another() {
+        let mut a = TestNode::spawn().await;
+        let mut b = TestNode::spawn().await;
+        a.connect(&mut b).await;
+        let key = kad::RecordKey::new(&"greeting");
+
+        let (id, cmd) = put(&key, b"hello");
+        a.send(cmd).await;
+        a.wait_for_event(
+            |event| matches!(event, P2pEvent::Outbound(Some(put), _) if *put == id),
+            EVENT_TIMEOUT,
+        )
+        .await;
+
+        let id = RequestId::next();
+        b.send(P2pCommand::GetRecord(id, key, NonZeroUsize::MIN)).await;
+        let P2pEvent::Outbound(_, P2pOutboundEvent::RecordResolved { values, .. }) = b
+            .wait_for_event(
+                |event| matches!(event, P2pEvent::Outbound(Some(get), _) if *get == id),
+                EVENT_TIMEOUT,
+            )
+            .await
+        else {
+            panic!("the lookup didn't resolve");
+        };
+
+        assert!(values.iter().any(|found| found.value == b"hello"));
+    }
+
+    #[tokio::test]
+    async fn providers_are_found_from_another_node() {
+        let mut a = TestNode::spawn().await;
+        let mut b = TestNode::spawn().await;
+        a.connect(&mut b).await;
+        let key = kad::RecordKey::new(&"provided");
+
+        a.send(P2pCommand::PutProvider(RequestId::next(), key.clone())).await;
+        a.wait_for_event(
+            |event| matches!(event, P2pEvent::Outbound(_, P2pOutboundEvent::ProviderPut(_))),
+            EVENT_TIMEOUT,
+        )
+        .await;
+
+        let id = RequestId::next();
+        b.send(P2pCommand::GetProviders(id, key)).await;
+        let P2pEvent::Outbound(_, P2pOutboundEvent::ProvidersFound(_, providers)) = b
+            .wait_for_event(
+                |event| matches!(event, P2pEvent::Outbound(Some(get), _) if *get == id),
+                EVENT_TIMEOUT,
+            )
+            .await
+        else {
+            panic!("no providers were found");
+        };
+
+        assert_eq!(providers, [a.peer_id]);
+    }
+
+    #[tokio::test]
+    async fn inbound_records_over_the_limits_are_rejected() {
+        let mut a = TestNode::spawn().await;
+        let mut b = TestNode::with_config(NodeConfig {
+            inbound_limits: InboundLimits {
+                max_value_bytes: 4,
+                ..InboundLimits::default()
+            },
+            ..NodeConfig::default()
+        })
+        .await;
+        a.connect(&mut b).await;
+
+        let (_, cmd) = put(&kad::RecordKey::new(&"large"), b"far too large");
+        a.send(cmd).await;
+        let P2pEvent::InboundRejected { source, reason, .. } = b
+            .wait_for_event(
+                |event| matches!(event, P2pEvent::InboundRejected { .. }),
+                EVENT_TIMEOUT,
+            )
+            .await
+        else {
+            unreachable!()
+        };
+
+        assert_eq!(source, a.peer_id);
+        assert!(matches!(reason, RejectReason::ValueTooLarge { max: 4, .. }));
+
+        let (_, cmd) = put(&kad::RecordKey::new(&"small"), b"tiny");
+        a.send(cmd).await;
+        b.wait_for_event(
+            |event| matches!(event, P2pEvent::Inbound(P2pInboundEvent::RecordStored(..))),
+            EVENT_TIMEOUT,
+        )
+        .await;
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Formatter;
+use std::sync::{Arc, Mutex};
+use axum::Router;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use iced::futures::channel::mpsc;
+use iced::futures::{SinkExt, StreamExt};
+use libp2p::kad::{self, InboundRequest};
+use libp2p::metrics::Recorder;
+use libp2p::swarm::SwarmEvent;
+use libp2p::mdns;
+use prometheus_client::encoding::text;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::registry::Registry;
+use super::{CustomBehaviourEvent, P2pCommand, P2pEvent, P2pInboundEvent, P2pLocalEvent, P2pOutboundEvent, RequestId};
+
+const METRICS_PREFIX: &str = "iced_libp2p_sample";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// libp2p's own metrics plus counters for what the app does with them, all in one registry.
+///
+/// Shared between the node, which records into it, and whoever serves `/metrics`; it outlives
+/// networking restarts so the counters keep growing.
+pub struct Metrics {
+    registry: Registry,
+    libp2p: libp2p::metrics::Metrics,
+    mdns_discovered: Counter,
+    mdns_expired: Counter,
+    records_stored: Counter,
+    record_bytes_stored: Counter,
+    records_served: Counter,
+    queries_issued: Counter,
+    queries_succeeded: Counter,
+    queries_failed: Counter,
+    queries_not_found: Counter,
+    queries_cancelled: Counter,
+    /// Queries counted as issued whose first result hasn't arrived yet.
+    pending: Mutex<HashSet<RequestId>>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::default();
+        let libp2p = libp2p::metrics::Metrics::new(&mut registry);
+        let app = registry.sub_registry_with_prefix(METRICS_PREFIX);
+
+        let counter = |app: &mut Registry, name: &str, help: &str| {
+            let counter = Counter::default();
+            app.register(name, help, counter.clone());
+            counter
+        };
+
+        Self {
+            mdns_discovered: counter(app, "mdns_discovered", "Peer addresses found through mDNS"),
+            mdns_expired: counter(app, "mdns_expired", "Peer addresses whose mDNS record expired"),
+            records_stored: counter(app, "records_stored", "Records put into the local store"),
+            record_bytes_stored: counter(
+                app,
+                "record_bytes_stored",
+                "Bytes of record values put into the local store",
+            ),
+            records_served: counter(app, "records_served", "Records returned to other peers"),
+            queries_issued: counter(app, "queries_issued", "DHT queries started from the app"),
+            queries_succeeded: counter(app, "queries_succeeded", "Queries that found a result"),
+            queries_failed: counter(app, "queries_failed", "Queries that failed"),
+            queries_not_found: counter(
+                app,
+                "queries_not_found",
+                "Record lookups that finished without finding a value",
+            ),
+            queries_cancelled: counter(app, "queries_cancelled", "Queries cancelled midway"),
+            pending: Mutex::default(),
+            libp2p,
+            registry,
+        }
+    }
+}
+
+impl Metrics {
+    /// The registry in the OpenMetrics text format.
+    pub fn encode(&self) -> String {
+        let mut output = String::new();
+        text::encode(&mut output, &self.registry).expect("writing to a String can't fail");
+        output
+    }
+
+    pub(super) fn record_command(&self, command: &P2pCommand) {
+        if let P2pCommand::GetRecord(request_id, ..)
+        | P2pCommand::GetProviders(request_id, _)
+        | P2pCommand::GetClosestPeers(request_id, _)
+        | P2pCommand::PutRecord(request_id, ..)
+        | P2pCommand::PutProvider(request_id, _) = command
+        {
+            self.queries_issued.inc();
+            self.pending.lock().unwrap().insert(*request_id);
+        }
+    }
+
+    pub(super) fn record_swarm_event(&self, event: &SwarmEvent<CustomBehaviourEvent>) {
+        self.libp2p.record(event);
+
+        let SwarmEvent::Behaviour(event) = event else {
+            return;
+        };
+
+        match event {
+            CustomBehaviourEvent::Kademlia(event) => {
+                self.libp2p.record(event);
+
+                if let kad::Event::InboundRequest {
+                    request: InboundRequest::GetRecord {
+                        present_locally: true,
+                        ..
+                    },
+                } = event
+                {
+                    self.records_served.inc();
+                }
+            }
+            CustomBehaviourEvent::Mdns(mdns::Event::Discovered(list)) => {
+                self.mdns_discovered.inc_by(list.len() as u64);
+            }
+            CustomBehaviourEvent::Mdns(mdns::Event::Expired(list)) => {
+                self.mdns_expired.inc_by(list.len() as u64);
+            }
+            CustomBehaviourEvent::Identify(event) => self.libp2p.record(event),
+            CustomBehaviourEvent::Ping(event) => self.libp2p.record(event),
+            CustomBehaviourEvent::Gossipsub(event) => self.libp2p.record(event),
+            CustomBehaviourEvent::RelayServer(event) => self.libp2p.record(event),
+            CustomBehaviourEvent::Dcutr(event) => self.libp2p.record(event),
+            _ => {}
+        }
+    }
+
+    fn record_event(&self, event: &P2pEvent) {
+        match event {
+            P2pEvent::Local(P2pLocalEvent::RecordStored(kad::Record { value, .. }))
+            | P2pEvent::Inbound(P2pInboundEvent::RecordStored(_, _, value, ..)) => {
+                self.records_stored.inc();
+                self.record_bytes_stored.inc_by(value.len() as u64);
+            }
+            P2pEvent::Outbound(Some(request_id), outcome)
+                if self.pending.lock().unwrap().remove(request_id) =>
+            {
+                match outcome {
+                    P2pOutboundEvent::RecordNotFound(_) => self.queries_not_found.inc(),
+                    P2pOutboundEvent::QueryCancelled(_) => self.queries_cancelled.inc(),
+                    _ => self.queries_succeeded.inc(),
+                };
+            }
+            P2pEvent::Error(Some(request_id), _)
+                if self.pending.lock().unwrap().remove(request_id) =>
+            {
+                self.queries_failed.inc();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Passes events on to `events`, counting the ones the app-level metrics care about.
+///
+/// The returned sender closes once `events` does, so the node still notices when nobody listens.
+pub(super) fn tap(
+    mut events: mpsc::Sender<P2pEvent>,
+    metrics: Arc<Metrics>,
+) -> mpsc::Sender<P2pEvent> {
+    let (sender, mut receiver) = mpsc::channel(100);
+
+    tokio::spawn(async move {
+        while let Some(event) = receiver.next().await {
+            metrics.record_event(&event);
+
+            if events.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    sender
+}
+
+/// Serves `GET /metrics`; merge it into another router to share a listener.
+pub fn router(metrics: Arc<Metrics>) -> Router {
+    Router::new()
+        .route("/metrics", get(scrape))
+        .with_state(metrics)
+}
+
+async fn scrape(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)], metrics.encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+    use libp2p::PeerId;
+    use crate::p2p::envelope::RecordSignature;
+    use super::*;
+
+    fn sample(metrics: &Metrics, name: &str) -> String {
+        metrics
+            .encode()
+            .lines()
+            .find(|line| line.starts_with(&format!("{METRICS_PREFIX}_{name}_total ")))
+            .unwrap_or_else(|| panic!("{name} is not exported"))
+            .to_owned()
+    }
+
+    #[test]
+    fn app_counters_follow_commands_and_events() {
+        let metrics = Metrics::default();
+        let key = kad::RecordKey::new(&"key");
+        let (found, missing) = (RequestId::next(), RequestId::next());
+        let resolved = P2pOutboundEvent::RecordResolved {
+            key: key.clone(),
+            values: Vec::new(),
+            agree: true,
+        };
+
+        metrics.record_command(&P2pCommand::GetRecord(found, key.clone(), NonZeroUsize::MIN));
+        metrics.record_command(&P2pCommand::GetRecord(missing, key.clone(), NonZeroUsize::MIN));
+        metrics.record_event(&P2pEvent::Outbound(Some(found), resolved.clone()));
+        metrics.record_event(&P2pEvent::Outbound(Some(found), resolved));
+        metrics.record_event(&P2pEvent::Outbound(
+            Some(missing),
+            P2pOutboundEvent::RecordNotFound(key.clone()),
+        ));
+        metrics.record_event(&P2pEvent::Inbound(P2pInboundEvent::RecordStored(
+            PeerId::random(),
+            key,
+            vec![0; 42],
+            None,
+            RecordSignature::default(),
+        )));
+
+        assert!(metrics.encode().contains("libp2p_"));
+        assert!(sample(&metrics, "queries_issued").ends_with(" 2"));
+        assert!(sample(&metrics, "queries_succeeded").ends_with(" 1"));
+        assert!(sample(&metrics, "queries_not_found").ends_with(" 1"));
+        assert!(sample(&metrics, "queries_failed").ends_with(" 0"));
+        assert!(sample(&metrics, "records_stored").ends_with(" 1"));
+        assert!(sample(&metrics, "record_bytes_stored").ends_with(" 42"));
+    }
+
+    #[test]
+    fn cancelled_queries_are_not_counted_as_succeeded() {
+        let metrics = Metrics::default();
+        let key = kad::RecordKey::new(&"key");
+        let request_id = RequestId::next();
+
+        metrics.record_command(&P2pCommand::PutProvider(request_id, key.clone()));
+        metrics.record_event(&P2pEvent::Outbound(
+            Some(request_id),
+            P2pOutboundEvent::QueryCancelled(key),
+        ));
+
+        assert!(sample(&metrics, "queries_cancelled").ends_with(" 1"));
+        assert!(sample(&metrics, "queries_succeeded").ends_with(" 0"));
+        assert!(sample(&metrics, "queries_failed").ends_with(" 0"));
+    }
+}
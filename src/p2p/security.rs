@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::io;
+use std::sync::{Arc, Mutex};
+use libp2p::core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo};
+use libp2p::futures::future::{BoxFuture, Either};
+use libp2p::futures::{AsyncRead, AsyncWrite, FutureExt, TryFutureExt};
+use libp2p::{PeerId, identity, noise, tls};
+use serde::{Deserialize, Serialize};
+
+/// The security protocols TCP, WebSocket and relayed connections offer; QUIC always uses TLS.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    #[default]
+    Noise,
+    Tls,
+    /// Offers Noise first and TLS second, so either kind of peer can connect.
+    Both,
+}
+
+impl Security {
+    pub const ALL: [Security; 3] = [Security::Noise, Security::Tls, Security::Both];
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Security::Noise => write!(f, "Noise"),
+            Security::Tls => write!(f, "TLS"),
+            Security::Both => write!(f, "Noise or TLS"),
+        }
+    }
+}
+
+/// The security protocol a connection ended up with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityProtocol {
+    Noise,
+    Tls,
+}
+
+impl fmt::Display for SecurityProtocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SecurityProtocol::Noise => write!(f, "noise"),
+            SecurityProtocol::Tls => write!(f, "tls"),
+        }
+    }
+}
+
+/// What the latest connection to each peer negotiated, filled in by [`SecurityUpgrade`] since
+/// the swarm doesn't report it.
+#[derive(Debug, Clone, Default)]
+pub(super) struct NegotiatedSecurity(Arc<Mutex<HashMap<PeerId, SecurityProtocol>>>);
+
+impl NegotiatedSecurity {
+    fn record(&self, peer_id: PeerId, protocol: SecurityProtocol) {
+        self.0.lock().unwrap().insert(peer_id, protocol);
+    }
+
+    pub(super) fn of(&self, peer_id: &PeerId) -> Option<SecurityProtocol> {
+        self.0.lock().unwrap().get(peer_id).copied()
+    }
+
+    pub(super) fn forget(&self, peer_id: &PeerId) {
+        self.0.lock().unwrap().remove(peer_id);
+    }
+}
+
+/// Offers the configured security protocols on a connection and notes the one it settles on.
+#[derive(Clone)]
+pub(super) struct SecurityUpgrade {
+    noise: Option<noise::Config>,
+    tls: Option<tls::Config>,
+    negotiated: NegotiatedSecurity,
+}
+
+impl SecurityUpgrade {
+    pub(super) fn new(
+        key: &identity::Keypair,
+        security: Security,
+        negotiated: NegotiatedSecurity,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let noise = match security {
+            Security::Tls => None,
+            Security::Noise | Security::Both => Some(noise::Config::new(key)?),
+        };
+        let tls = match security {
+            Security::Noise => None,
+            Security::Tls | Security::Both => Some(tls::Config::new(key)?),
+        };
+
+        Ok(Self {
+            noise,
+            tls,
+            negotiated,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Offer {
+    name: &'static str,
+    protocol: SecurityProtocol,
+}
+
+impl AsRef<str> for Offer {
+    fn as_ref(&self) -> &str {
+        self.name
+    }
+}
+
+impl UpgradeInfo for SecurityUpgrade {
+    type Info = Offer;
+    type InfoIter = Vec<Offer>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let noise = self.noise.iter().flat_map(UpgradeInfo::protocol_info).map(|name| Offer {
+            name,
+            protocol: SecurityProtocol::Noise,
+        });
+        let tls = self.tls.iter().flat_map(UpgradeInfo::protocol_info).map(|name| Offer {
+            name,
+            protocol: SecurityProtocol::Tls,
+        });
+
+        noise.chain(tls).collect()
+    }
+}
+
+type Secured<C> = (PeerId, Either<noise::Output<C>, tls::TlsStream<C>>);
+
+impl<C> InboundConnectionUpgrade<C> for SecurityUpgrade
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = Secured<C>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Secured<C>>>;
+
+    fn upgrade_inbound(self, socket: C, offer: Offer) -> Self::Future {
+        let upgrade = match (offer.protocol, self.noise, self.tls) {
+            (SecurityProtocol::Noise, Some(noise), _) => noise
+                .upgrade_inbound(socket, offer.name)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Left(stream)))
+                .map_err(io::Error::other)
+                .boxed(),
+            (SecurityProtocol::Tls, _, Some(tls)) => tls
+                .upgrade_inbound(socket, offer.name)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Right(stream)))
+                .map_err(io::Error::other)
+                .boxed(),
+            _ => unreachable!("only offered protocols get negotiated"),
+        };
+        let negotiated = self.negotiated;
+
+        upgrade
+            .inspect_ok(move |(peer_id, _)| negotiated.record(*peer_id, offer.protocol))
+            .boxed()
+    }
+}
+
+impl<C> OutboundConnectionUpgrade<C> for SecurityUpgrade
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = Secured<C>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Secured<C>>>;
+
+    fn upgrade_outbound(self, socket: C, offer: Offer) -> Self::Future {
+        let upgrade = match (offer.protocol, self.noise, self.tls) {
+            (SecurityProtocol::Noise, Some(noise), _) => noise
+                .upgrade_outbound(socket, offer.name)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Left(stream)))
+                .map_err(io::Error::other)
+                .boxed(),
+            (SecurityProtocol::Tls, _, Some(tls)) => tls
+                .upgrade_outbound(socket, offer.name)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Right(stream)))
+                .map_err(io::Error::other)
+                .boxed(),
+            _ => unreachable!("only offered protocols get negotiated"),
+        };
+        let negotiated = self.negotiated;
+
+        upgrade
+            .inspect_ok(move |(peer_id, _)| negotiated.record(*peer_id, offer.protocol))
+            .boxed()
+    }
+}
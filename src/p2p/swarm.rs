@@ -0,0 +1,415 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::{Boxed, OptionalTransport};
+#[cfg(test)]
+use libp2p::core::transport::MemoryTransport;
+use libp2p::core::upgrade;
+use libp2p::futures::future::Either;
+use libp2p::kad::StoreInserts;
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::allow_block_list::{self, BlockedPeers};
+#[allow(deprecated)]
+use libp2p::bandwidth::BandwidthSinks;
+use libp2p::{PeerId, StreamProtocol, Swarm, SwarmBuilder, Transport, TransportExt, autonat, connection_limits, dcutr, gossipsub, identify, identity, kad, mdns, ping, quic, relay, tcp, websocket, yamux};
+use crate::file_transfer::{FileRequest, FileResponse};
+use crate::store::{NodeStore, StoreConfig};
+use super::security::{NegotiatedSecurity, Security, SecurityUpgrade};
+use super::{Ack, ConnectionLimits, DirectMessage, KadTuning, MdnsTiming, P2pEvent, RelayLimits, DEFAULT_QUERY_TIMEOUT};
+
+const IDENTIFY_PROTOCOL_VERSION: &str = "/iced-libp2p-sample/1.0.0";
+const AGENT_VERSION: &str = concat!("iced-libp2p-sample/", env!("CARGO_PKG_VERSION"));
+const DIRECT_MESSAGE_PROTOCOL: &str = "/iced-libp2p-sample/direct-message/1.0.0";
+const FILE_TRANSFER_PROTOCOL: &str = "/iced-libp2p-sample/file-transfer/1.0.0";
+/// Closest peers that didn't return a record we looked up and get a copy of it.
+const CACHE_PEERS: u16 = 3;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(NetworkBehaviour)]
+pub(super) struct CustomBehaviour {
+    pub(super) kademlia: kad::Behaviour<NodeStore>,
+    pub(super) mdns: Toggle<mdns::tokio::Behaviour>,
+    pub(super) identify: identify::Behaviour,
+    pub(super) ping: ping::Behaviour,
+    pub(super) gossipsub: gossipsub::Behaviour,
+    pub(super) direct_message: request_response::cbor::Behaviour<DirectMessage, Ack>,
+    pub(super) file_transfer: request_response::cbor::Behaviour<FileRequest, FileResponse>,
+    pub(super) relay_client: relay::client::Behaviour,
+    pub(super) relay_server: Toggle<relay::Behaviour>,
+    pub(super) dcutr: dcutr::Behaviour,
+    pub(super) autonat: autonat::Behaviour,
+    pub(super) blocked: allow_block_list::Behaviour<BlockedPeers>,
+    pub(super) limits: connection_limits::Behaviour,
+}
+
+/// Everything [`build_swarm`] needs besides the identity.
+#[derive(Debug, Clone)]
+pub struct SwarmConfig {
+    pub tcp: bool,
+    pub quic: bool,
+    /// WebSocket over its own TCP transport, for `/ws` addresses.
+    pub websocket: bool,
+    pub security: Security,
+    /// `None` turns local discovery off.
+    pub(super) mdns: Option<MdnsTiming>,
+    pub kad_protocol: StreamProtocol,
+    pub kad_tuning: KadTuning,
+    pub query_timeout: Duration,
+    /// `None` keeps the relay server disabled.
+    pub relay_limits: Option<RelayLimits>,
+    pub connection_limits: ConnectionLimits,
+    pub record_store: StoreConfig,
+}
+
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        Self {
+            tcp: true,
+            quic: true,
+            websocket: false,
+            security: Security::default(),
+            mdns: Some(MdnsTiming::default()),
+            kad_protocol: kad::PROTOCOL_NAME,
+            kad_tuning: KadTuning::default(),
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+            relay_limits: None,
+            connection_limits: ConnectionLimits::default(),
+            record_store: StoreConfig::Memory,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwarmSetupError {
+    Transport(String),
+    Behaviour(String),
+}
+
+impl fmt::Display for SwarmSetupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SwarmSetupError::Transport(reason) => {
+                write!(f, "Failed to set up transports: {reason}")
+            }
+            SwarmSetupError::Behaviour(reason) => {
+                write!(f, "Failed to set up behaviours: {reason}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) fn build_swarm(
+    config: &SwarmConfig,
+    keypair: identity::Keypair,
+) -> Result<Swarm<CustomBehaviour>, SwarmSetupError> {
+    build_measured_swarm(config, keypair).map(|(swarm, ..)| swarm)
+}
+
+/// Builds the swarm, also counting the bytes that go over its connections and noting the
+/// security protocol each one negotiates.
+pub(super) fn build_measured_swarm(
+    config: &SwarmConfig,
+    keypair: identity::Keypair,
+) -> Result<(Swarm<CustomBehaviour>, BandwidthMeter, NegotiatedSecurity), SwarmSetupError> {
+    if !config.tcp && !config.quic {
+        return Err(SwarmSetupError::Transport("enable TCP, QUIC or both".to_owned()));
+    }
+
+    let mut kad_config = kad::Config::new(config.kad_protocol.clone());
+    kad_config.set_record_filtering(StoreInserts::FilterBoth);
+    kad_config.set_periodic_bootstrap_interval(None);
+    // The node republishes what it put or provides itself on its own schedule.
+    kad_config.set_publication_interval(None);
+    kad_config.set_provider_publication_interval(None);
+    kad_config.set_replication_factor(config.kad_tuning.replication_factor);
+    kad_config.set_parallelism(config.kad_tuning.parallelism);
+    kad_config.disjoint_query_paths(config.kad_tuning.disjoint_query_paths);
+    kad_config.set_caching(if config.kad_tuning.cache_records {
+        kad::Caching::Enabled {
+            max_peers: CACHE_PEERS,
+        }
+    } else {
+        kad::Caching::Disabled
+    });
+    kad_config.set_query_timeout(config.query_timeout);
+
+    let mut sinks = None;
+    let negotiated = NegotiatedSecurity::default();
+    let swarm = SwarmBuilder::with_existing_identity(keypair)
+        .with_tokio()
+        .with_other_transport(|key| {
+            #[allow(deprecated)]
+            let (transport, bandwidth) =
+                transport(key, config, &negotiated)?.with_bandwidth_logging();
+            sinks = Some(bandwidth);
+            Ok::<_, BoxError>(transport)
+        })
+        .map_err(|err| SwarmSetupError::Transport(err.to_string()))?
+        .with_dns()
+        .map_err(|err| SwarmSetupError::Transport(format!("no DNS resolver: {err}")))?
+        .with_relay_client(
+            |key: &identity::Keypair| {
+                SecurityUpgrade::new(key, config.security, negotiated.clone())
+            },
+            yamux::Config::default,
+        )
+        .map_err(|err| SwarmSetupError::Transport(err.to_string()))?
+        .with_behaviour(|key, relay_client| {
+            let local_peer_id = key.public().to_peer_id();
+            let mdns = mdns_behaviour(local_peer_id, config.mdns)?;
+
+            Ok(CustomBehaviour {
+                kademlia: kad::Behaviour::with_config(
+                    local_peer_id,
+                    NodeStore::new(local_peer_id, &config.record_store),
+                    kad_config,
+                ),
+                mdns,
+                identify: identify::Behaviour::new(
+                    identify::Config::new(IDENTIFY_PROTOCOL_VERSION.to_owned(), key.public())
+                        .with_agent_version(AGENT_VERSION.to_owned()),
+                ),
+                ping: ping::Behaviour::default(),
+                gossipsub: gossipsub::Behaviour::new(
+                    gossipsub::MessageAuthenticity::Signed(key.clone()),
+                    gossipsub::Config::default(),
+                )?,
+                direct_message: request_response::cbor::Behaviour::new(
+                    [(
+                        StreamProtocol::new(DIRECT_MESSAGE_PROTOCOL),
+                        ProtocolSupport::Full,
+                    )],
+                    request_response::Config::default(),
+                ),
+                file_transfer: request_response::cbor::Behaviour::new(
+                    [(
+                        StreamProtocol::new(FILE_TRANSFER_PROTOCOL),
+                        ProtocolSupport::Full,
+                    )],
+                    request_response::Config::default(),
+                ),
+                relay_client,
+                relay_server: relay_server(local_peer_id, config.relay_limits),
+                dcutr: dcutr::Behaviour::new(local_peer_id),
+                autonat: autonat::Behaviour::new(local_peer_id, autonat::Config::default()),
+                blocked: allow_block_list::Behaviour::default(),
+                limits: connection_limits(&config.connection_limits),
+            })
+        })
+        .map_err(|err| SwarmSetupError::Behaviour(err.to_string()))?
+        .with_swarm_config(|swarm_config| {
+            swarm_config
+                .with_dial_concurrency_factor(config.connection_limits.dial_concurrency)
+                .with_idle_connection_timeout(config.connection_limits.idle_timeout)
+        })
+        .build();
+
+    let meter = BandwidthMeter {
+        sinks: sinks.expect("the transport is built before the swarm"),
+        totals: (0, 0),
+        read_at: Instant::now(),
+        idle: true,
+    };
+
+    Ok((swarm, meter, negotiated))
+}
+
+/// Turns the byte totals of our connections into rates between two reads.
+///
+/// libp2p deprecates these counters in favour of Prometheus metrics, which can't be read back
+/// cheaply every second.
+pub(super) struct BandwidthMeter {
+    #[allow(deprecated)]
+    sinks: Arc<BandwidthSinks>,
+    totals: (u64, u64),
+    read_at: Instant,
+    idle: bool,
+}
+
+impl BandwidthMeter {
+    /// The rates since the last read; `None` while nothing moves, so an idle node stays quiet.
+    pub(super) fn read(&mut self) -> Option<P2pEvent> {
+        let totals = (self.sinks.total_inbound(), self.sinks.total_outbound());
+        let secs = self.read_at.elapsed().as_secs_f64().max(f64::EPSILON);
+        let rate = |now: u64, before: u64| (now.saturating_sub(before) as f64 / secs) as u64;
+        let event = P2pEvent::Bandwidth {
+            inbound_bps: rate(totals.0, self.totals.0),
+            outbound_bps: rate(totals.1, self.totals.1),
+            total_in: totals.0,
+            total_out: totals.1,
+        };
+
+        let was_idle = mem::replace(&mut self.idle, totals == self.totals);
+        self.totals = totals;
+        self.read_at = Instant::now();
+
+        (!was_idle || !self.idle).then_some(event)
+    }
+}
+
+fn connection_limits(limits: &ConnectionLimits) -> connection_limits::Behaviour {
+    connection_limits::Behaviour::new(
+        connection_limits::ConnectionLimits::default()
+            .with_max_established(limits.max_established)
+            .with_max_established_per_peer(limits.max_established_per_peer)
+            .with_max_pending_incoming(limits.max_pending_incoming)
+            .with_max_pending_outgoing(limits.max_pending_outgoing),
+    )
+}
+
+pub(super) fn mdns_behaviour(
+    local_peer_id: PeerId,
+    timing: Option<MdnsTiming>,
+) -> std::io::Result<Toggle<mdns::tokio::Behaviour>> {
+    let mdns = match timing {
+        Some(timing) => Some(mdns::tokio::Behaviour::new(
+            mdns::Config {
+                ttl: timing.ttl,
+                query_interval: timing.query_interval,
+                ..Default::default()
+            },
+            local_peer_id,
+        )?),
+        None => None,
+    };
+
+    Ok(Toggle::from(mdns))
+}
+
+pub(super) fn relay_server(
+    local_peer_id: PeerId,
+    limits: Option<RelayLimits>,
+) -> Toggle<relay::Behaviour> {
+    Toggle::from(limits.map(|limits| {
+        relay::Behaviour::new(
+            local_peer_id,
+            relay::Config {
+                max_reservations: limits.max_reservations,
+                max_circuits: limits.max_circuits,
+                ..Default::default()
+            },
+        )
+    }))
+}
+
+/// TCP, QUIC and WebSocket side by side, each of which can be left out.
+fn transport(
+    key: &identity::Keypair,
+    config: &SwarmConfig,
+    negotiated: &NegotiatedSecurity,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, BoxError> {
+    let security = SecurityUpgrade::new(key, config.security, negotiated.clone())?;
+    let tcp = match config.tcp {
+        true => OptionalTransport::some(
+            tcp::tokio::Transport::new(tcp::Config::default())
+                .upgrade(upgrade::Version::V1Lazy)
+                .authenticate(security.clone())
+                .multiplex(yamux::Config::default()),
+        ),
+        false => OptionalTransport::none(),
+    };
+    let quic = match config.quic {
+        true => OptionalTransport::some(quic::tokio::Transport::new(quic::Config::new(key))),
+        false => OptionalTransport::none(),
+    };
+
+    let websocket = match config.websocket {
+        true => OptionalTransport::some(
+            websocket::Config::new(tcp::tokio::Transport::new(tcp::Config::default()))
+                .upgrade(upgrade::Version::V1Lazy)
+                .authenticate(security.clone())
+                .multiplex(yamux::Config::default()),
+        ),
+        false => OptionalTransport::none(),
+    };
+
+    let transport = websocket
+        .or_transport(tcp)
+        .or_transport(quic)
+        .map(|output, _| match output {
+            Either::Left(Either::Left((peer_id, muxer))) => (peer_id, StreamMuxerBox::new(muxer)),
+            Either::Left(Either::Right((peer_id, muxer))) => (peer_id, StreamMuxerBox::new(muxer)),
+            Either::Right((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+        });
+
+    // Tests also run whole nodes against each other over `/memory` addresses, without sockets.
+    #[cfg(test)]
+    let transport = MemoryTransport::default()
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(security)
+        .multiplex(yamux::Config::default())
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .or_transport(transport)
+        .map(|output, _| output.into_inner());
+
+    Ok(transport.boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swarm(config: &SwarmConfig) -> Swarm<CustomBehaviour> {
+        build_swarm(config, identity::Keypair::generate_ed25519()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn disabled_transports_refuse_to_listen() {
+        let mut tcp_only = swarm(&SwarmConfig {
+            quic: false,
+            ..SwarmConfig::default()
+        });
+        let mut quic_only = swarm(&SwarmConfig {
+            tcp: false,
+            ..SwarmConfig::default()
+        });
+
+        assert!(tcp_only.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).is_ok());
+        assert!(tcp_only.listen_on("/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap()).is_err());
+        assert!(quic_only.listen_on("/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap()).is_ok());
+        assert!(quic_only.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).is_err());
+
+        let neither = SwarmConfig {
+            tcp: false,
+            quic: false,
+            ..SwarmConfig::default()
+        };
+        let keypair = identity::Keypair::generate_ed25519();
+        assert!(matches!(build_swarm(&neither, keypair), Err(SwarmSetupError::Transport(_))));
+    }
+
+    #[tokio::test]
+    async fn websocket_addresses_need_the_websocket_transport() {
+        let address: libp2p::Multiaddr = "/ip4/127.0.0.1/tcp/0/ws".parse().unwrap();
+        let mut with_websocket = swarm(&SwarmConfig {
+            websocket: true,
+            ..SwarmConfig::default()
+        });
+
+        assert!(swarm(&SwarmConfig::default()).listen_on(address.clone()).is_err());
+        assert!(with_websocket.listen_on(address).is_ok());
+        assert!(with_websocket.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn kad_protocol_and_mdns_follow_the_config() {
+        let protocol = StreamProtocol::new("/iced-libp2p-sample/kad/1.0.0");
+        let swarm = swarm(&SwarmConfig {
+            kad_protocol: protocol.clone(),
+            mdns: None,
+            ..SwarmConfig::default()
+        });
+
+        assert_eq!(swarm.behaviour().kademlia.protocol_names(), [protocol]);
+        assert!(!swarm.behaviour().mdns.is_enabled());
+        assert!(self::swarm(&SwarmConfig::default()).behaviour().mdns.is_enabled());
+    }
+}
@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use directories::ProjectDirs;
+use libp2p::kad::store::{self, MemoryStore, RecordStore};
+use libp2p::kad::{ProviderRecord, Record, RecordKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+const STORE_FILE_NAME: &str = "records.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StoreConfig {
+    #[default]
+    Memory,
+    Persistent(PathBuf),
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "iced-libp2p-sample").map(|dirs| dirs.data_dir().join(STORE_FILE_NAME))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    records: Vec<StoredRecord>,
+    providers: Vec<StoredProvider>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<Vec<u8>>,
+    expires_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredProvider {
+    key: Vec<u8>,
+    addresses: Vec<String>,
+    expires_at: Option<u64>,
+}
+
+/// A [`MemoryStore`] that mirrors every change into a JSON snapshot on disk.
+///
+/// Every record is persisted, but of the provider records only those announcing the local
+/// peer, since those are the only ones the node republishes after a restart.
+pub struct PersistentStore {
+    local_peer_id: PeerId,
+    memory: MemoryStore,
+    path: PathBuf,
+}
+
+impl PersistentStore {
+    pub fn open(local_peer_id: PeerId, path: PathBuf) -> Self {
+        let mut memory = MemoryStore::new(local_peer_id);
+
+        match load(&path) {
+            Ok(snapshot) => {
+                let now = (Instant::now(), SystemTime::now());
+                let mut restored = 0;
+
+                for record in snapshot.records.into_iter().filter_map(|record| record.restore(now)) {
+                    if memory.put(record).is_ok() {
+                        restored += 1;
+                    }
+                }
+
+                for provider in snapshot
+                    .providers
+                    .into_iter()
+                    .filter_map(|provider| provider.restore(local_peer_id, now))
+                {
+                    if memory.add_provider(provider).is_ok() {
+                        restored += 1;
+                    }
+                }
+
+                info!("Restored {restored} records from {}", path.display());
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => error!("Ignoring unreadable record store {}: {err}", path.display()),
+        }
+
+        Self {
+            local_peer_id,
+            memory,
+            path,
+        }
+    }
+
+    fn save(&self) {
+        let now = (Instant::now(), SystemTime::now());
+        let snapshot = Snapshot {
+            records: self
+                .memory
+                .records()
+                .map(|record| StoredRecord::new(&record, now))
+                .collect(),
+            providers: self
+                .memory
+                .provided()
+                .map(|provider| StoredProvider::new(&provider, now))
+                .collect(),
+        };
+
+        if let Err(err) = save(&self.path, &snapshot) {
+            error!("Failed to persist record store to {}: {err}", self.path.display());
+        }
+    }
+}
+
+fn load(path: &Path) -> io::Result<Snapshot> {
+    let bytes = fs::read(path)?;
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+fn save(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec(snapshot)?)?;
+    fs::rename(temp_path, path)
+}
+
+fn to_unix(expires: Instant, (now, system_now): (Instant, SystemTime)) -> u64 {
+    let at = system_now + expires.saturating_duration_since(now);
+
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Returns `None` for entries that expired while the node was offline.
+fn from_unix(expires_at: u64, (now, system_now): (Instant, SystemTime)) -> Option<Instant> {
+    let at = UNIX_EPOCH + Duration::from_secs(expires_at);
+
+    at.duration_since(system_now).ok().map(|remaining| now + remaining)
+}
+
+impl StoredRecord {
+    fn new(record: &Record, now: (Instant, SystemTime)) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            value: record.value.clone(),
+            publisher: record.publisher.map(|publisher| publisher.to_bytes()),
+            expires_at: record.expires.map(|expires| to_unix(expires, now)),
+        }
+    }
+
+    fn restore(self, now: (Instant, SystemTime)) -> Option<Record> {
+        let expires = match self.expires_at {
+            Some(expires_at) => Some(from_unix(expires_at, now)?),
+            None => None,
+        };
+
+        Some(Record {
+            key: RecordKey::from(self.key),
+            value: self.value,
+            publisher: self.publisher.and_then(|bytes| PeerId::from_bytes(&bytes).ok()),
+            expires,
+        })
+    }
+}
+
+impl StoredProvider {
+    fn new(provider: &ProviderRecord, now: (Instant, SystemTime)) -> Self {
+        Self {
+            key: provider.key.to_vec(),
+            addresses: provider.addresses.iter().map(ToString::to_string).collect(),
+            expires_at: provider.expires.map(|expires| to_unix(expires, now)),
+        }
+    }
+
+    fn restore(self, local_peer_id: PeerId, now: (Instant, SystemTime)) -> Option<ProviderRecord> {
+        let expires = match self.expires_at {
+            Some(expires_at) => Some(from_unix(expires_at, now)?),
+            None => None,
+        };
+
+        Some(ProviderRecord {
+            key: RecordKey::from(self.key),
+            provider: local_peer_id,
+            expires,
+            addresses: self
+                .addresses
+                .iter()
+                .filter_map(|address| address.parse::<Multiaddr>().ok())
+                .collect(),
+        })
+    }
+}
+
+impl RecordStore for PersistentStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.memory.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        self.memory.put(r)?;
+        self.save();
+
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.memory.remove(k);
+        self.save();
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.memory.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        let local = record.provider == self.local_peer_id;
+        self.memory.add_provider(record)?;
+
+        if local {
+            self.save();
+        }
+
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.memory.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.memory.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.memory.remove_provider(k, p);
+
+        if p == &self.local_peer_id {
+            self.save();
+        }
+    }
+}
+
+/// The record store used by the node, chosen by [`StoreConfig`].
+pub enum NodeStore {
+    Memory(MemoryStore),
+    Persistent(PersistentStore),
+}
+
+impl NodeStore {
+    pub fn new(local_peer_id: PeerId, config: &StoreConfig) -> Self {
+        match config {
+            StoreConfig::Memory => NodeStore::Memory(MemoryStore::new(local_peer_id)),
+            StoreConfig::Persistent(path) => {
+                NodeStore::Persistent(PersistentStore::open(local_peer_id, path.clone()))
+            }
+        }
+    }
+
+    /// Writes the current contents to disk; a no-op for the in-memory store.
+    pub fn flush(&self) {
+        if let NodeStore::Persistent(store) = self {
+            store.save();
+        }
+    }
+}
+
+impl RecordStore for NodeStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        match self {
+            NodeStore::Memory(store) => store.get(k),
+            NodeStore::Persistent(store) => store.get(k),
+        }
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        match self {
+            NodeStore::Memory(store) => store.put(r),
+            NodeStore::Persistent(store) => store.put(r),
+        }
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        match self {
+            NodeStore::Memory(store) => store.remove(k),
+            NodeStore::Persistent(store) => store.remove(k),
+        }
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        match self {
+            NodeStore::Memory(store) => store.records(),
+            NodeStore::Persistent(store) => store.records(),
+        }
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        match self {
+            NodeStore::Memory(store) => store.add_provider(record),
+            NodeStore::Persistent(store) => store.add_provider(record),
+        }
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        match self {
+            NodeStore::Memory(store) => store.providers(key),
+            NodeStore::Persistent(store) => store.providers(key),
+        }
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        match self {
+            NodeStore::Memory(store) => store.provided(),
+            NodeStore::Persistent(store) => store.provided(),
+        }
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        match self {
+            NodeStore::Memory(store) => store.remove_provider(k, p),
+            NodeStore::Persistent(store) => store.remove_provider(k, p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn records_and_own_providers_survive_reopening() {
+        let path = temp_path("store-roundtrip").join(STORE_FILE_NAME);
+        let local_peer_id = PeerId::random();
+        let key = RecordKey::new(&"key");
+
+        let mut store = PersistentStore::open(local_peer_id, path.clone());
+        let mut record = Record::new(key.clone(), b"value".to_vec());
+        record.publisher = Some(local_peer_id);
+        store.put(record).unwrap();
+        store
+            .add_provider(ProviderRecord::new(key.clone(), local_peer_id, Vec::new()))
+            .unwrap();
+        store
+            .add_provider(ProviderRecord::new(key.clone(), PeerId::random(), Vec::new()))
+            .unwrap();
+
+        let reopened = PersistentStore::open(local_peer_id, path.clone());
+
+        let restored = reopened.get(&key).unwrap();
+        assert_eq!(restored.value, b"value");
+        assert_eq!(restored.publisher, Some(local_peer_id));
+        assert_eq!(reopened.provided().count(), 1);
+        assert_eq!(reopened.providers(&key).len(), 1);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupted_store_file_starts_empty() {
+        let path = temp_path("store-corrupted").join(STORE_FILE_NAME);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"{ not json").unwrap();
+
+        let store = PersistentStore::open(PeerId::random(), path.clone());
+
+        assert_eq!(store.records().count(), 0);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
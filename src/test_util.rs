@@ -0,0 +1,8 @@
+//! Helpers shared by the tests of several modules.
+
+use std::path::PathBuf;
+
+/// A path in the temp dir that no other test run uses, for tests that touch the disk.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("iced-libp2p-sample-{}-{name}", std::process::id()))
+}
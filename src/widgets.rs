@@ -1,53 +1,2558 @@
-use crate::app::Message;
-use crate::p2p::P2pEvent;
-use iced::widget::{button, center, column, row, scrollable, text, text_input};
-use iced::{Center, Element, Fill, color};
+use std::borrow::Cow;
+use std::time::{Duration, Instant, SystemTime};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::Path;
+use crate::app::{
+    ChatMessage, ClosestPeers, EventLog, FileTransfer, LogCategory, LogEntry, LogFilter, Message, PeerInfo, PendingQuery, QueryRecord, QueryStatus, RecordEntry,
+    PendingApproval, RecordImport, RecordLookup, Tab, TransferStatus, Draft, SettingsForm,
+    QueryLatencies, Bandwidth, InputField, ValueSource, ValueView, editor_text, Toast, ToastKind,
+    clock_time, full_time,
+};
+use crate::bench::{
+    self, BenchPhase, Benchmark, BenchmarkReport, DEFAULT_BENCH_COUNT, DEFAULT_BENCH_VALUE_SIZE,
+};
+use crate::config::{
+    AppConfig, KadMode, MAX_LOG_CAPACITY, MIN_LOG_CAPACITY, SettingsField, ThemePreference,
+    MAX_FONT_SIZE, MAX_UI_SCALE, MIN_FONT_SIZE, MIN_UI_SCALE, UI_SCALE_STEP,
+};
+use crate::diagnostics::{TraceLevel, TraceLine};
+use crate::export;
+use crate::file_log;
+use crate::known_peers::KnownPeer;
+use crate::format::{self, ValueDisplay};
+use crate::p2p::{QueryKind, format_key, format_record_value, is_websocket, to_hex, value_variants};
+use crate::p2p::{BucketInfo, NatStatus, P2pEvent, P2pInboundEvent, P2pOutboundEvent, RelayLimits};
+use crate::p2p::envelope::RecordSignature;
+use crate::p2p::security::Security;
+use iced::widget::{
+    Button, button, center, checkbox, column, combo_box, container, opaque, pick_list, progress_bar,
+    row, scrollable, slider, text, text_editor, text_input, toggler, tooltip, mouse_area, Text,
+};
+use iced::keyboard::{self, key};
+use iced::mouse;
+use iced::{Center, Color, Element, Fill, Font, Theme, border, color};
+use libp2p::multiaddr::Protocol;
+use tracing::Level;
+use libp2p::{Multiaddr, PeerId, kad};
 
-pub fn network_status<'a>(peer_count: usize) -> Element<'a, Message> {
-    let connected_peers = text(format!("Connected peers: {peer_count}"));
+const PEER_COLUMN_WIDTH: f32 = 200.0;
+const STATUS_COLUMN_WIDTH: f32 = 140.0;
+const AGE_COLUMN_WIDTH: f32 = 60.0;
+const KEY_COLUMN_WIDTH: f32 = 160.0;
+const MAX_VALUE_PREVIEW_CHARS: usize = 48;
+const VALUE_EDITOR_MAX_HEIGHT: f32 = 160.0;
+const EVENT_DETAIL_WIDTH: f32 = 420.0;
+const DETAIL_LABEL_WIDTH: f32 = 70.0;
+const MAX_LISTED_REPEATS: usize = 10;
 
-    row![connected_peers].spacing(10).padding(10).into()
+pub fn network_status<'a>(
+    local_peer_id: Option<PeerId>,
+    listen_addrs: &[Multiaddr],
+    show_listen_addrs: bool,
+    (connected_count, discovered_count): (usize, usize),
+    relay_circuits: Option<usize>,
+    (nat_status, kad_mode, mdns_enabled, theme): (&NatStatus, KadMode, bool, ThemePreference),
+    (served_gets, served_provider_lookups, latencies, bandwidth): (
+        usize,
+        usize,
+        &QueryLatencies,
+        Bandwidth,
+    ),
+) -> Element<'a, Message> {
+    let identity: Element<'a, Message> = match local_peer_id {
+        Some(peer_id) => row![
+            text(format!("Peer: {}", truncate_peer_id(&peer_id))),
+            copy_button(peer_id.to_string())
+        ]
+        .spacing(5)
+        .align_y(Center)
+        .into(),
+        None => text("Peer: starting…").into(),
+    };
+
+    let connected_peers = text(format!(
+        "Connected: {connected_count} · Discovered: {discovered_count}"
+    ));
+    let bootstrap_button = button(text("Bootstrap")).on_press(Message::Bootstrap);
+
+    let toggle_label = if show_listen_addrs { "▾" } else { "▸" };
+    let listen_addrs_toggle = button(text(format!(
+        "{toggle_label} Listen addresses ({})",
+        listen_addrs.len()
+    )))
+    .style(button::text)
+    .on_press(Message::ToggleListenAddrs);
+
+    let relay_status = match relay_circuits {
+        Some(circuits) => text(format!("Relaying {circuits} circuits")),
+        None => text(""),
+    };
+
+    let nat_badge = match nat_status {
+        NatStatus::Public(address) => {
+            text(format!("Publicly reachable ({address})")).color(color!(0x55b055))
+        }
+        NatStatus::Private => text("Behind NAT").color(color!(0xd0a030)),
+        NatStatus::Unknown => text("Unknown").color(color!(0x888888)),
+    };
+
+    let kad_badge = match kad_mode {
+        KadMode::Server => "DHT server",
+        KadMode::Client => "DHT client",
+    };
+
+    let served = (served_gets + served_provider_lookups > 0).then(|| {
+        text(format!("Served {served_gets} gets · {served_provider_lookups} provider lookups"))
+            .size(14)
+    });
+    let mdns_badge = (!mdns_enabled).then(|| text("mDNS off").size(14).color(color!(0x888888)));
+    let latency = latency_status(latencies);
+    let traffic = tooltip(
+        text(format!(
+            "↓ {}/s ↑ {}/s",
+            format_size(bandwidth.inbound_bps),
+            format_size(bandwidth.outbound_bps)
+        ))
+        .size(14),
+        container(
+            text(format!(
+                "Received {} · Sent {}",
+                format_size(bandwidth.total_in),
+                format_size(bandwidth.total_out)
+            ))
+            .size(12),
+        )
+        .padding(5)
+        .style(container::rounded_box),
+        tooltip::Position::Bottom,
+    );
+
+    let status_bar = row![
+        identity,
+        nat_badge.size(14),
+        text(kad_badge).size(14),
+        connected_peers,
+        bootstrap_button,
+        listen_addrs_toggle,
+        traffic,
+        relay_status
+    ]
+    .push_maybe(served)
+    .push_maybe(latency)
+    .push_maybe(mdns_badge)
+    .push(theme_toggle(theme))
+    .spacing(20)
+    .align_y(Center);
+
+    let mut content = column![status_bar].spacing(5).padding(10);
+
+    content = content.extend(
+        listen_addrs
+            .iter()
+            .filter(|address| address.iter().any(|protocol| protocol == Protocol::P2pCircuit))
+            .map(|address| {
+                let address = shareable_addr(address, local_peer_id);
+
+                row![
+                    text(format!("Relay: {address}")).size(14),
+                    copy_button(address.to_string())
+                ]
+                .spacing(5)
+                .align_y(Center)
+                .into()
+            }),
+    );
+
+    if show_listen_addrs {
+        content = content.extend(listen_addrs.iter().map(|address| {
+            let websocket = is_websocket(address)
+                .then(|| text("WebSocket").size(12).color(color!(0xd0a030)));
+            let address = shareable_addr(address, local_peer_id);
+
+            row![text(address.to_string()).size(14)]
+                .push_maybe(websocket)
+                .push(copy_button(address.to_string()))
+                .spacing(5)
+                .align_y(Center)
+                .into()
+        }));
+    }
+
+    content.into()
 }
 
-pub fn event_log(events: &[P2pEvent]) -> Element<Message> {
-    if events.is_empty() {
-        center(text("Events will appear here...").color(color!(0x888888))).into()
+/// Switches to the next theme preference, the same one the settings change.
+fn theme_toggle<'a>(theme: ThemePreference) -> Element<'a, Message> {
+    let icon = match theme {
+        ThemePreference::System => "◐",
+        ThemePreference::Light => "☀",
+        ThemePreference::Dark => "☾",
+    };
+
+    tooltip(
+        button(text(format!("{icon} {theme}")).size(12))
+            .style(button::secondary)
+            .padding([2, 10])
+            .on_press(Message::SettingsChanged(SettingsField::Theme(theme.next()))),
+        container(text(format!("Theme: {theme}, click for {}", theme.next())).size(12))
+            .padding(5)
+            .style(container::rounded_box),
+        tooltip::Position::Bottom,
+    )
+    .into()
+}
+
+/// Median and worst latency of the recent gets and puts, with a sparkline of them.
+fn latency_status<'a>(latencies: &QueryLatencies) -> Option<Element<'a, Message>> {
+    let mut summary = match (latencies.median(), latencies.worst()) {
+        (Some(median), Some(worst)) => format!(
+            "Queries: median {}ms · worst {}ms",
+            median.as_millis(),
+            worst.as_millis()
+        ),
+        _ if latencies.samples().is_empty() => return None,
+        _ => "Queries:".to_owned(),
+    };
+
+    let timed_out = latencies.timed_out();
+
+    if timed_out > 0 {
+        summary.push_str(&format!(" · {timed_out} timed out"));
+    }
+
+    let status = row![
+        text(summary).size(14),
+        text(sparkline(latencies)).size(14).font(Font::MONOSPACE),
+    ]
+    .spacing(10)
+    .align_y(Center);
+
+    Some(status.into())
+}
+
+/// One block per query, scaled to the slowest; timed out queries show as a cross.
+fn sparkline(latencies: &QueryLatencies) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let worst = latencies.worst().unwrap_or_default();
+
+    latencies
+        .samples()
+        .iter()
+        .map(|sample| match sample {
+            None => '×',
+            Some(_) if worst.is_zero() => BLOCKS[0],
+            Some(latency) => {
+                let level = latency.div_duration_f64(worst) * (BLOCKS.len() - 1) as f64;
+                BLOCKS[level.round() as usize]
+            }
+        })
+        .collect()
+}
+
+/// Byte counts in binary units, with one decimal from KiB up.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// The address with our peer id appended, so others can dial it as it is.
+fn shareable_addr(address: &Multiaddr, local_peer_id: Option<PeerId>) -> Multiaddr {
+    match local_peer_id {
+        Some(peer_id) => address.clone().with_p2p(peer_id).unwrap_or_else(|address| address),
+        None => address.clone(),
+    }
+}
+
+fn copy_button<'a>(data: String) -> Element<'a, Message> {
+    button(text("Copy").size(12))
+        .padding([2, 10])
+        .on_press(Message::CopyToClipboard(data))
+        .into()
+}
+
+fn truncate_peer_id(peer_id: &PeerId) -> String {
+    let peer_id = peer_id.to_string();
+
+    if peer_id.len() > 16 {
+        format!("{}…{}", &peer_id[..8], &peer_id[peer_id.len() - 6..])
     } else {
-        let events_elements = events
-            .iter()
-            .map(|m| format!("{m}"))
-            .map(text)
-            .map(Element::from);
+        peer_id
+    }
+}
+
+pub fn stopped_banner(reason: &str) -> Element<'_, Message> {
+    container(
+        row![
+            text(format!("Networking stopped: {reason}")).width(Fill),
+            button(text("Restart"))
+                .style(button::danger)
+                .on_press(Message::RestartNetworking),
+        ]
+        .spacing(10)
+        .align_y(Center),
+    )
+    .padding(10)
+    .style(|theme: &Theme| {
+        let danger = theme.extended_palette().danger.weak;
+
+        container::Style {
+            background: Some(danger.color.into()),
+            text_color: Some(danger.text),
+            border: border::rounded(4),
+            ..container::Style::default()
+        }
+    })
+    .into()
+}
+
+const TOAST_WIDTH: f32 = 300.0;
+
+/// Toasts stacked in the top right corner, each dismissed by clicking it.
+pub fn toast_layer(toasts: &[Toast]) -> Element<'_, Message> {
+    let toasts = toasts.iter().map(|toast| {
+        let label = if toast.count > 1 {
+            format!("{} ×{}", toast.text, toast.count)
+        } else {
+            toast.text.clone()
+        };
+        let kind = toast.kind;
+
+        mouse_area(
+            container(text(label).size(14))
+                .padding(10)
+                .width(Fill)
+                .style(move |theme: &Theme| {
+                    let palette = theme.extended_palette();
+                    let pair = match kind {
+                        ToastKind::Success => palette.success.weak,
+                        ToastKind::Info => palette.background.strong,
+                        ToastKind::Error => palette.danger.weak,
+                    };
+
+                    container::Style {
+                        background: Some(pair.color.into()),
+                        text_color: Some(pair.text),
+                        border: border::rounded(4),
+                        ..container::Style::default()
+                    }
+                }),
+        )
+        .on_press(Message::DismissToast(toast.id))
+        .interaction(mouse::Interaction::Pointer)
+        .into()
+    });
+
+    container(column(toasts).spacing(5).width(TOAST_WIDTH))
+        .align_right(Fill)
+        .padding(20)
+        .into()
+}
+
+const HELP_WIDTH: f32 = 380.0;
+const HELP_KEYS_WIDTH: f32 = 140.0;
 
-        scrollable(column(events_elements).spacing(10))
-            .height(Fill)
+/// Every keyboard shortcut, over a dimmed backdrop that closes it when clicked.
+pub fn help_overlay<'a>() -> Element<'a, Message> {
+    let tabs = format!("Ctrl+1…{}", Tab::ALL.len());
+    let bindings = [
+        ("Tab", "Focus the next input"),
+        ("Esc", "Clear the focused input, or close this"),
+        ("Ctrl+K", "Focus the key"),
+        ("Ctrl+Enter", "Put the value, or get the key without one"),
+        ("Ctrl+L", "Clear the log"),
+        (tabs.as_str(), "Switch tabs"),
+        ("Ctrl+C", "Copy the selected log entry"),
+        ("Ctrl+Plus / Minus", "Zoom in or out"),
+        ("Ctrl+0", "Reset the zoom"),
+        ("F1", "Show or hide this"),
+    ];
+    let rows = bindings.into_iter().map(|(keys, action)| {
+        row![
+            text(keys.to_owned()).font(Font::MONOSPACE).width(HELP_KEYS_WIDTH),
+            text(action),
+        ]
+        .into()
+    });
+
+    let panel = container(
+        column![
+            row![
+                text("Keyboard shortcuts").size(16).width(Fill),
+                button(text("Close").size(12))
+                    .style(button::secondary)
+                    .padding([2, 10])
+                    .on_press(Message::CloseHelp),
+            ]
+            .align_y(Center),
+            column(rows).spacing(5),
+        ]
+        .spacing(10),
+    )
+    .padding(15)
+    .width(HELP_WIDTH)
+    .style(container::bordered_box);
+
+    opaque(
+        mouse_area(center(opaque(panel)).style(|_| {
+            container::Style::default().background(Color { a: 0.6, ..Color::BLACK })
+        }))
+        .on_press(Message::CloseHelp),
+    )
+}
+
+pub fn tab_bar<'a>(active: Tab) -> Element<'a, Message> {
+    let tabs = Tab::ALL.into_iter().map(|tab| {
+        let style = if tab == active {
+            button::primary
+        } else {
+            button::secondary
+        };
+
+        button(text(tab.title()))
+            .style(style)
+            .on_press(Message::TabSelected(tab))
+            .into()
+    });
+
+    row(tabs).spacing(5).into()
+}
+
+pub fn settings_view<'a>(
+    relay_server: bool,
+    relay_limits: RelayLimits,
+    mdns_enabled: bool,
+    inbound_approval: bool,
+    hash_keys: bool,
+    peer_grace: Duration,
+    query_timeout: Duration,
+) -> Element<'a, Message> {
+    let relay_toggle = toggler(relay_server)
+        .label("Act as relay for other peers")
+        .on_toggle(Message::ToggleRelayServer);
+
+    let restart_button = button(text("Restart networking").size(12))
+        .style(button::secondary)
+        .padding([2, 10])
+        .on_press(Message::RestartNetworking);
+
+    column![
+        row![text("Networking").size(18).width(Fill), restart_button].align_y(Center),
+        relay_toggle,
+        text(format!(
+            "Relay limits: {} reservations, {} circuits",
+            relay_limits.max_reservations, relay_limits.max_circuits
+        ))
+        .size(14)
+        .color(color!(0x888888)),
+        toggler(mdns_enabled)
+            .label("Discover peers on the local network (mDNS)")
+            .on_toggle(Message::ToggleMdns),
+        toggler(inbound_approval)
+            .label("Ask before storing records from other peers")
+            .on_toggle(Message::ToggleInboundApproval),
+        toggler(hash_keys)
+            .label("Store records under the SHA-256 of their key")
+            .on_toggle(Message::ToggleHashKeys),
+        text(format!("Expired peers stay listed for {}s", peer_grace.as_secs())).size(14),
+        text(format!("Queries time out after {}s", query_timeout.as_secs())).size(14),
+    ]
+    .spacing(10)
+    .padding(10)
+    .into()
+}
+
+pub fn kad_mode_section<'a>(kad_mode: KadMode, providing: usize) -> Element<'a, Message> {
+    let warning = (kad_mode == KadMode::Client && providing > 0).then(|| {
+        text(format!(
+            "In client mode the {providing} keys you provide are no longer served to other peers"
+        ))
+        .size(14)
+        .color(color!(0xd0a030))
+    });
+
+    column![
+        text("Kademlia").size(18),
+        toggler(kad_mode == KadMode::Server)
+            .label("Answer DHT queries from other peers (server mode)")
+            .on_toggle(Message::ToggleKadServer),
+    ]
+    .push_maybe(warning)
+    .spacing(10)
+    .padding(10)
+    .into()
+}
+
+pub fn config_section<'a>(
+    config: &AppConfig,
+    form: &'a SettingsForm,
+    restart_required: bool,
+    metrics_address: Option<SocketAddr>,
+    log_file_dir: Option<&Path>,
+) -> Element<'a, Message> {
+    let restart_hint = restart_required.then(|| {
+        row![
+            text("Restart networking to apply the changed networking settings")
+                .size(14)
+                .color(color!(0xd0a030))
+                .width(Fill),
+            button(text("Restart now").size(12))
+                .padding([2, 10])
+                .on_press(Message::RestartNetworking),
+        ]
+        .spacing(10)
+        .align_y(Center)
+    });
+    let transport_error = form
+        .transport_error
+        .as_ref()
+        .map(|error| text(error).size(12).color(color!(0xe05555)));
+    let save_error = form.save_error.as_ref().map(|error| {
+        text(format!("Settings could not be saved: {error}"))
+            .size(14)
+            .color(color!(0xe05555))
+    });
+
+    let node = column![
+        text("Node").size(18),
+        setting_input(
+            "Listen addresses",
+            "Comma separated multiaddrs",
+            &form.listen_addrs,
+            SettingsField::ListenAddrs,
+        ),
+        bootstrap_peers_editor(&config.bootstrap_peers, &form.bootstrap_peer),
+        column![row![
+            toggler(config.enable_tcp)
+                .label("TCP")
+                .on_toggle(|enabled| Message::SettingsChanged(SettingsField::EnableTcp(enabled))),
+            toggler(config.enable_quic)
+                .label("QUIC")
+                .on_toggle(|enabled| Message::SettingsChanged(SettingsField::EnableQuic(enabled))),
+            toggler(config.enable_websocket).label("WebSocket").on_toggle(|enabled| {
+                Message::SettingsChanged(SettingsField::EnableWebsocket(enabled))
+            }),
+        ]
+        .spacing(20)]
+        .push_maybe(transport_error)
+        .spacing(5),
+        setting_row(
+            "Connection security",
+            pick_list(Security::ALL, Some(config.security), |security| {
+                Message::SettingsChanged(SettingsField::Security(security))
+            }),
+        ),
+        setting_input(
+            "Forget peers unseen for (days)",
+            "7",
+            &form.known_peer_max_age,
+            SettingsField::KnownPeerMaxAge,
+        ),
+        setting_input("mDNS record TTL (s)", "5", &form.mdns_ttl, SettingsField::MdnsTtl),
+        setting_input(
+            "mDNS query interval (s)",
+            "4",
+            &form.mdns_query_interval,
+            SettingsField::MdnsQueryInterval,
+        ),
+        setting_row(
+            "Initial Kademlia mode",
+            pick_list(KadMode::ALL, Some(config.kad_mode), |mode| {
+                Message::SettingsChanged(SettingsField::KadMode(mode))
+            }),
+        ),
+        setting_input(
+            "DHT protocol",
+            "/ipfs/kad/1.0.0",
+            &form.dht_protocol,
+            SettingsField::DhtProtocol,
+        ),
+        setting_input(
+            "Identity file",
+            "Default location in the data directory",
+            &form.identity_path,
+            SettingsField::IdentityPath,
+        ),
+    ]
+    .push_maybe(restart_hint)
+    .spacing(10);
+
+    let dht = column![
+        text("DHT queries").size(18),
+        setting_input(
+            "Replication factor",
+            "20",
+            &form.replication_factor,
+            SettingsField::ReplicationFactor,
+        ),
+        setting_input(
+            "Query parallelism (alpha)",
+            "3",
+            &form.query_parallelism,
+            SettingsField::QueryParallelism,
+        ),
+        setting_input("Query timeout (s)", "30", &form.query_timeout, SettingsField::QueryTimeout),
+        toggler(config.disjoint_query_paths)
+            .label("Use disjoint query paths")
+            .on_toggle(|enabled| {
+                Message::SettingsChanged(SettingsField::DisjointQueryPaths(enabled))
+            }),
+        toggler(config.cache_records)
+            .label("Cache records we look up at close peers that lack them")
+            .on_toggle(|enabled| Message::SettingsChanged(SettingsField::CacheRecords(enabled))),
+        setting_input(
+            "Republish own records every (s)",
+            "43200",
+            &form.record_republish,
+            SettingsField::RecordRepublishInterval,
+        ),
+        setting_input(
+            "Republish provider records every (s)",
+            "3600",
+            &form.provider_republish,
+            SettingsField::ProviderRepublishInterval,
+        ),
+    ]
+    .spacing(10);
+
+    let connections = column![
+        text("Connections").size(18),
+        setting_input(
+            "Max connections",
+            "Unlimited",
+            &form.max_connections,
+            SettingsField::MaxConnections,
+        ),
+        setting_input(
+            "Max connections per peer",
+            "Unlimited",
+            &form.max_connections_per_peer,
+            SettingsField::MaxConnectionsPerPeer,
+        ),
+        setting_input(
+            "Max pending incoming",
+            "Unlimited",
+            &form.max_pending_incoming,
+            SettingsField::MaxPendingIncoming,
+        ),
+        setting_input(
+            "Max pending outgoing",
+            "Unlimited",
+            &form.max_pending_outgoing,
+            SettingsField::MaxPendingOutgoing,
+        ),
+        setting_input(
+            "Concurrent dials per peer",
+            "8",
+            &form.dial_concurrency,
+            SettingsField::DialConcurrency,
+        ),
+        setting_input(
+            "Close idle connections after (s)",
+            "10",
+            &form.idle_connection_timeout,
+            SettingsField::IdleConnectionTimeout,
+        ),
+    ]
+    .spacing(10);
+
+    let records = column![
+        text("Records").size(18),
+        setting_input(
+            "Default record TTL (s)",
+            "Kademlia default",
+            &form.record_ttl,
+            SettingsField::RecordTtl,
+        ),
+        setting_input("Put quorum (peers)", "1", &form.put_quorum, SettingsField::PutQuorum),
+        setting_input("Get quorum (values)", "3", &form.get_quorum, SettingsField::GetQuorum),
+        toggler(config.sign_records)
+            .label("Sign the records we put with our identity key")
+            .on_toggle(Message::ToggleRecordSigning),
+        setting_row(
+            "Value passphrase",
+            text_input("Values stay readable", &form.value_passphrase.text)
+                .secure(true)
+                .on_input(|input| Message::SettingsChanged(SettingsField::ValuePassphrase(input)))
+                .padding(5)
+                .width(300),
+        ),
+        toggler(config.save_passphrase)
+            .label("Keep the passphrase in the settings file, unencrypted")
+            .on_toggle(|enabled| Message::SettingsChanged(SettingsField::SavePassphrase(enabled))),
+        setting_input(
+            "Key namespace",
+            "None, keys are used as typed",
+            &form.key_namespace,
+            SettingsField::KeyNamespace,
+        ),
+    ]
+    .spacing(10);
+
+    let interface = column![
+        text("Interface").size(18),
+        setting_row(
+            &format!("Keep the last {} log events", config.log_capacity),
+            slider(
+                MIN_LOG_CAPACITY as u32..=MAX_LOG_CAPACITY as u32,
+                config.log_capacity as u32,
+                |capacity| Message::SettingsChanged(SettingsField::LogCapacity(capacity as usize)),
+            )
+            .step(100u32)
+            .width(300),
+        ),
+        setting_row(
+            "Theme",
+            pick_list(ThemePreference::ALL, Some(config.theme), |theme| {
+                Message::SettingsChanged(SettingsField::Theme(theme))
+            }),
+        ),
+        setting_row(
+            &format!("Scale the interface to {}% (Ctrl+Plus, Ctrl+Minus, Ctrl+0)", config.ui_scale),
+            slider(MIN_UI_SCALE..=MAX_UI_SCALE, config.ui_scale, |scale| {
+                Message::SettingsChanged(SettingsField::UiScale(scale))
+            })
+            .step(UI_SCALE_STEP)
+            .width(300),
+        ),
+        setting_row(
+            &format!("Text size of {} px, from the next start", config.font_size),
+            slider(MIN_FONT_SIZE..=MAX_FONT_SIZE, config.font_size, |size| {
+                Message::SettingsChanged(SettingsField::FontSize(size))
+            })
+            .width(300),
+        ),
+    ]
+    .spacing(10);
+
+    let metrics_status = match metrics_address {
+        Some(address) => text(format!("Serving http://{address}/metrics")).color(color!(0x55b055)),
+        None => text("Not serving metrics").color(color!(0x888888)),
+    };
+
+    let metrics = column![
+        text("Metrics").size(18),
+        toggler(config.metrics_enabled)
+            .label("Serve Prometheus metrics")
+            .on_toggle(|enabled| Message::SettingsChanged(SettingsField::MetricsEnabled(enabled))),
+        setting_input(
+            "Metrics address",
+            "127.0.0.1:9464",
+            &form.metrics_addr,
+            SettingsField::MetricsAddr,
+        ),
+        metrics_status.size(14),
+    ]
+    .spacing(10);
+
+    let log_file_status: Element<'a, Message> = match log_file_dir {
+        Some(dir) => row![
+            text(format!("Logging to {}", file_log::current_file(dir).display()))
+                .size(14)
+                .color(color!(0x888888))
+                .width(Fill),
+            button(text("Open folder").size(12))
+                .style(button::secondary)
+                .padding([2, 10])
+                .on_press(Message::OpenLogFolder),
+        ]
+        .spacing(10)
+        .align_y(Center)
+        .into(),
+        None => text("Not logging to a file").size(14).color(color!(0x888888)).into(),
+    };
+
+    let logging = column![
+        text("Logging").size(18),
+        toggler(config.log_to_file)
+            .label("Write logs to a file (applies on the next start)")
+            .on_toggle(|enabled| Message::SettingsChanged(SettingsField::LogToFile(enabled))),
+        setting_input(
+            "Log folder",
+            "Default location in the data directory",
+            &form.log_dir,
+            SettingsField::LogDir,
+        ),
+        setting_input("Log files to keep", "7", &form.log_retention, SettingsField::LogRetention),
+        log_file_status,
+    ]
+    .spacing(10);
+
+    column![node, dht, connections, records, metrics, logging, interface]
+        .push_maybe(save_error)
+        .spacing(10)
+        .padding(10)
+        .into()
+}
+
+fn bootstrap_peers_editor<'a>(peers: &[Multiaddr], draft: &'a Draft) -> Element<'a, Message> {
+    let entries = peers.iter().map(|address| {
+        let remove_button = button(text("Remove").size(12))
+            .style(button::secondary)
+            .padding([2, 10])
+            .on_press(Message::SettingsChanged(SettingsField::RemoveBootstrapPeer(
+                address.clone(),
+            )));
+
+        row![text(address.to_string()).size(14).width(Fill), remove_button]
+            .spacing(10)
+            .align_y(Center)
             .into()
+    });
+
+    let input = text_input("/ip4/…/tcp/…/p2p/<peer id>", &draft.text)
+        .on_input(Message::BootstrapPeerInputChanged)
+        .on_submit(Message::SettingsChanged(SettingsField::AddBootstrapPeer(
+            draft.text.clone(),
+        )))
+        .padding(5);
+    let add_button = button(text("Add").size(12))
+        .padding([2, 10])
+        .on_press_maybe((!draft.text.trim().is_empty()).then(|| {
+            Message::SettingsChanged(SettingsField::AddBootstrapPeer(draft.text.clone()))
+        }));
+    let mut reconnect_button = button(text("Reconnect bootstrap").size(12))
+        .style(button::secondary)
+        .padding([2, 10]);
+
+    if !peers.is_empty() {
+        reconnect_button = reconnect_button.on_press(Message::ReconnectBootstrap);
     }
+
+    let error = draft
+        .error
+        .as_ref()
+        .map(|error| text(error).size(12).color(color!(0xe05555)));
+
+    column![
+        row![text("Bootstrap peers").width(Fill), reconnect_button].align_y(Center),
+        column(entries).spacing(5),
+        row![input, add_button].spacing(10).align_y(Center),
+    ]
+    .push_maybe(error)
+    .spacing(5)
+    .into()
 }
 
-pub fn input_section<'a>(current_key: &str, current_value: &str) -> Element<'a, Message> {
-    let key_input = text_input("Key", current_key)
-        .on_input(Message::KeyTextChanged)
+fn setting_row<'a>(label: &str, control: impl Into<Element<'a, Message>>) -> Element<'a, Message> {
+    row![text(label.to_owned()).width(Fill), control.into()]
+        .spacing(10)
+        .align_y(Center)
+        .into()
+}
+
+fn setting_input<'a>(
+    label: &str,
+    placeholder: &str,
+    draft: &'a Draft,
+    field: fn(String) -> SettingsField,
+) -> Element<'a, Message> {
+    let input = text_input(placeholder, &draft.text)
+        .on_input(move |input| Message::SettingsChanged(field(input)))
+        .padding(5)
+        .width(300);
+    let error = draft
+        .error
+        .as_ref()
+        .map(|error| text(error).size(12).color(color!(0xe05555)));
+
+    column![setting_row(label, input)]
+        .push_maybe(error)
+        .spacing(2)
+        .into()
+}
+
+pub fn chat_view<'a>(
+    topic: &str,
+    input: &str,
+    subscribed_topics: &[String],
+    messages: &'a [ChatMessage],
+) -> Element<'a, Message> {
+    let topic_input = text_input("Topic", topic)
+        .on_input(Message::ChatTopicChanged)
+        .padding(10);
+
+    let is_subscribed = subscribed_topics.iter().any(|known| known == topic);
+
+    let mut subscription_button = if is_subscribed {
+        button(text("Unsubscribe").height(40).align_y(Center)).padding([0, 20])
+    } else {
+        button(text("Subscribe").height(40).align_y(Center)).padding([0, 20])
+    };
+
+    if !topic.trim().is_empty() {
+        subscription_button = subscription_button.on_press(if is_subscribed {
+            Message::UnsubscribeTopic(topic.to_owned())
+        } else {
+            Message::SubscribeTopic(topic.to_owned())
+        });
+    }
+
+    let subscriptions = if subscribed_topics.is_empty() {
+        text("Not subscribed to any topics").color(color!(0x888888))
+    } else {
+        text(format!("Subscribed: {}", subscribed_topics.join(", ")))
+    };
+
+    let message_list: Element<'a, Message> = if messages.is_empty() {
+        center(text("Messages will appear here...").color(color!(0x888888))).into()
+    } else {
+        let entries = messages.iter().map(|message| {
+            let author = match message.source {
+                Some(source) => truncate_peer_id(&source),
+                None => "anonymous".to_owned(),
+            };
+
+            text(format!("[{}] {author}: {}", message.topic, message.text)).into()
+        });
+
+        scrollable(column(entries).spacing(5)).height(Fill).into()
+    };
+
+    let message_input = text_input("Message", input)
+        .on_input(Message::ChatInputChanged)
         .padding(10);
 
-    let value_input = text_input("Value", current_value)
-        .on_input(Message::ValueTextChanged)
+    let mut send_button = button(text("Send").height(40).align_y(Center)).padding([0, 20]);
+
+    if is_subscribed && !input.is_empty() {
+        send_button = send_button.on_press(Message::SendChat(topic.to_owned(), input.to_owned()));
+    }
+
+    column![
+        row![topic_input, subscription_button].spacing(10),
+        subscriptions,
+        message_list,
+        row![message_input, send_button].spacing(10)
+    ]
+    .spacing(10)
+    .padding(10)
+    .height(Fill)
+    .into()
+}
+
+pub fn dial_section<'a>(dial_address: &str) -> Element<'a, Message> {
+    let placeholder = "/ip4/192.168.1.10/tcp/43211 or /dns4/example.com/tcp/4001";
+    let address_input = text_input(placeholder, dial_address)
+        .on_input(Message::DialAddressChanged)
         .padding(10);
 
-    let mut put_button = button(text("Put").height(40).align_y(Center)).padding([0, 20]);
-    let mut get_button = button(text("Get").height(40).align_y(Center)).padding([0, 20]);
+    let mut dial_button = button(text("Dial").height(40).align_y(Center)).padding([0, 20]);
+    let mut relay_button =
+        button(text("Listen via relay").height(40).align_y(Center)).padding([0, 20]);
+
+    if !dial_address.trim().is_empty() {
+        dial_button = dial_button.on_press(Message::Dial(dial_address.to_owned()));
+        relay_button = relay_button.on_press(Message::ListenViaRelay(dial_address.to_owned()));
+    }
+
+    row![address_input, dial_button, relay_button]
+        .spacing(10)
+        .padding(10)
+        .into()
+}
+
+pub fn peer_list<'a>(
+    peers: &'a HashMap<PeerId, PeerInfo>,
+    connected_peers: &HashSet<PeerId>,
+    direct_target: Option<PeerId>,
+    direct_input: &str,
+    now: Instant,
+) -> Element<'a, Message> {
+    if peers.is_empty() {
+        return row![].into();
+    }
+
+    let mut sorted: Vec<_> = peers.iter().collect();
+    sorted.sort_by_key(|(peer_id, info)| (info.discovered_at, **peer_id));
+
+    let header = row![
+        text("Peer").width(PEER_COLUMN_WIDTH),
+        text("Addresses").width(Fill),
+        text("Status").width(STATUS_COLUMN_WIDTH),
+        text("Age").width(AGE_COLUMN_WIDTH),
+    ]
+    .spacing(10);
+
+    let entries = sorted.into_iter().map(|(peer_id, info)| {
+        let connected = connected_peers.contains(peer_id);
+        let dimmed = info.expired_at.is_some().then_some(color!(0x888888));
+
+        let agent = info.agent.as_deref().unwrap_or("unknown agent");
+        let protocols = if info.protocols.is_empty() {
+            "protocols unknown".to_owned()
+        } else {
+            info.protocols.join(", ")
+        };
+
+        let addresses = if info.addresses.is_empty() {
+            "no known addresses".to_owned()
+        } else {
+            info.addresses
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        let status = if connected {
+            "connected"
+        } else if info.expired_at.is_some() {
+            "expired"
+        } else {
+            "discovered"
+        };
+
+        let age = info
+            .discovered_at
+            .map(|discovered_at| format_age(now.duration_since(discovered_at)))
+            .unwrap_or_default();
+
+        let message_button = button(text("Message").size(12))
+            .padding([2, 10])
+            .on_press(Message::ComposeDirect(*peer_id));
+
+        let mut disconnect_button = button(text("Disconnect").size(12))
+            .style(button::secondary)
+            .padding([2, 10]);
+
+        if connected {
+            disconnect_button = disconnect_button.on_press(Message::DisconnectPeer(*peer_id));
+        }
+
+        let block_button = button(text("Block").size(12))
+            .style(button::danger)
+            .padding([2, 10])
+            .on_press(Message::BlockPeer(*peer_id));
+
+        let entry = column![
+            row![
+                row![
+                    text(truncate_peer_id(peer_id)).color_maybe(dimmed),
+                    copy_button(peer_id.to_string())
+                ]
+                .spacing(5)
+                .align_y(Center)
+                .width(PEER_COLUMN_WIDTH),
+                text(addresses).size(12).color_maybe(dimmed).width(Fill),
+                row![text(status).size(14).color_maybe(dimmed), connection_kind(info)]
+                    .spacing(5)
+                    .width(STATUS_COLUMN_WIDTH),
+                text(age).size(14).color_maybe(dimmed).width(AGE_COLUMN_WIDTH),
+            ]
+            .spacing(10)
+            .align_y(Center),
+            row![
+                text(format!("{agent} · {protocols}")).size(12).color(color!(0x888888)),
+                peer_health(info),
+                message_button,
+                disconnect_button,
+                block_button
+            ]
+            .spacing(10)
+            .align_y(Center)
+        ]
+        .spacing(2);
+
+        if direct_target == Some(*peer_id) {
+            let mut send_button = button(text("Send").size(12)).padding([2, 10]);
+
+            if !direct_input.is_empty() {
+                send_button =
+                    send_button.on_press(Message::SendDirect(*peer_id, direct_input.to_owned()));
+            }
+
+            let cancel_button = button(text("Cancel").size(12))
+                .style(button::secondary)
+                .padding([2, 10])
+                .on_press(Message::CancelDirect);
+
+            entry
+                .push(
+                    row![
+                        text_input("Direct message", direct_input)
+                            .on_input(Message::DirectInputChanged)
+                            .size(12),
+                        send_button,
+                        cancel_button
+                    ]
+                    .spacing(5)
+                    .align_y(Center),
+                )
+                .into()
+        } else {
+            entry.into()
+        }
+    });
+
+    column![
+        text("Peers:"),
+        header,
+        scrollable(column(entries).spacing(8)).height(Fill)
+    ]
+    .spacing(5)
+    .padding(10)
+    .into()
+}
+
+fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        secs @ 0..60 => format!("{secs}s"),
+        secs @ 60..3600 => format!("{}m", secs / 60),
+        secs @ 3600..86400 => format!("{}h", secs / 3600),
+        secs => format!("{}d", secs / 86400),
+    }
+}
+
+fn connection_kind<'a>(info: &PeerInfo) -> Element<'a, Message> {
+    let relayed = match info.relayed {
+        Some(true) => text("relayed").size(12).color(color!(0xd0a030)),
+        Some(false) => text("direct").size(12).color(color!(0x888888)),
+        None => text(""),
+    };
+    let transport = info.connection.map(|(transport, direction)| {
+        text(format!("{transport} · {direction}")).size(12).color(color!(0x888888))
+    });
+    let security = info
+        .security
+        .map(|security| text(format!("🔒 {security}")).size(12).color(color!(0x55b055)));
+
+    row![relayed].push_maybe(transport).push_maybe(security).spacing(5).into()
+}
+
+fn peer_health<'a>(info: &PeerInfo) -> Element<'a, Message> {
+    if info.unresponsive {
+        return text("unresponsive").color(color!(0xe05555)).into();
+    }
+
+    match info.rtt {
+        Some(rtt) => {
+            let rtt_color = match rtt.as_millis() {
+                0..50 => color!(0x55b055),
+                50..200 => color!(0xd0a030),
+                _ => color!(0xe05555),
+            };
+
+            text(format!("{} ms", rtt.as_millis())).color(rtt_color).into()
+        }
+        None => text("").into(),
+    }
+}
+
+pub fn file_section<'a>(current_key: &str, transfers: &'a [FileTransfer]) -> Element<'a, Message> {
+    let share_button =
+        button(text("Share file").height(40).align_y(Center)).padding([0, 20]).on_press(Message::ShareFile);
+
+    let mut fetch_button = button(text("Fetch file").height(40).align_y(Center)).padding([0, 20]);
+
+    if !current_key.is_empty() {
+        fetch_button = fetch_button.on_press(Message::FetchFile(current_key.to_owned()));
+    }
+
+    let entries = transfers.iter().map(|transfer| {
+        let total = transfer.total.unwrap_or(0).max(1) as f32;
+
+        let status = match transfer.status {
+            TransferStatus::Running => match transfer.total {
+                Some(total) => text(format!("{} / {total} bytes", transfer.received)),
+                None => text("looking for providers").color(color!(0x888888)),
+            },
+            TransferStatus::Done => text("done").color(color!(0x55b055)),
+            TransferStatus::Failed => text("failed").color(color!(0xe05555)),
+        };
+
+        row![
+            text(&transfer.key),
+            progress_bar(0.0..=total, transfer.received as f32).height(10),
+            status.size(12)
+        ]
+        .spacing(10)
+        .align_y(Center)
+        .into()
+    });
+
+    column![row![share_button, fetch_button].spacing(10), column(entries).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+/// Peers remembered from earlier sessions that have not shown up in this one yet.
+pub fn remembered_list<'a>(
+    remembered: &'a [KnownPeer],
+    live: &HashMap<PeerId, PeerInfo>,
+    now: SystemTime,
+) -> Element<'a, Message> {
+    let offline: Vec<_> = remembered
+        .iter()
+        .filter(|known| !live.contains_key(&known.peer_id))
+        .collect();
+
+    if offline.is_empty() {
+        return row![].into();
+    }
+
+    let entries = offline.into_iter().map(|known| {
+        let addresses = known
+            .addresses
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let last_seen = now
+            .duration_since(known.last_seen)
+            .map(|age| format!("seen {} ago", format_age(age)))
+            .unwrap_or_default();
+        let dial_address = known
+            .addresses
+            .first()
+            .and_then(|address| address.clone().with_p2p(known.peer_id).ok());
+        let dial_button = button(text("Dial").size(12))
+            .padding([2, 10])
+            .on_press_maybe(dial_address.map(|address| Message::Dial(address.to_string())));
+
+        row![
+            row![
+                text(truncate_peer_id(&known.peer_id)).color(color!(0x888888)),
+                copy_button(known.peer_id.to_string())
+            ]
+            .spacing(5)
+            .align_y(Center)
+            .width(PEER_COLUMN_WIDTH),
+            text(addresses).size(12).color(color!(0x888888)).width(Fill),
+            text("remembered").size(14).color(color!(0x888888)).width(STATUS_COLUMN_WIDTH),
+            text(last_seen).size(14).color(color!(0x888888)).width(AGE_COLUMN_WIDTH),
+            dial_button
+        ]
+        .spacing(10)
+        .align_y(Center)
+        .into()
+    });
+
+    column![text("Remembered:"), column(entries).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+pub fn blocked_list(peers: &[PeerId]) -> Element<'_, Message> {
+    if peers.is_empty() {
+        return row![].into();
+    }
+
+    let entries = peers.iter().map(|peer_id| {
+        let unblock_button = button(text("Unblock").size(12))
+            .padding([2, 10])
+            .on_press(Message::UnblockPeer(*peer_id));
+
+        row![text(peer_id.to_string()), unblock_button]
+            .spacing(10)
+            .align_y(Center)
+            .into()
+    });
 
-    if !current_key.is_empty() && !current_value.is_empty() {
-        put_button = put_button.on_press(Message::PutRecord(
-            current_key.to_owned(),
-            current_value.to_owned(),
+    column![text("Blocked:"), column(entries).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+pub fn routing_table<'a>(
+    buckets: &'a [BucketInfo],
+    expanded: &HashSet<u32>,
+    changed: Option<u32>,
+) -> Element<'a, Message> {
+    let refresh_button = button(text("Refresh").size(12))
+        .style(button::secondary)
+        .padding([2, 10])
+        .on_press(Message::RefreshRoutingTable);
+    let total: usize = buckets.iter().map(|bucket| bucket.entries.len()).sum();
+    let header = row![
+        text(format!("{total} peers in {} buckets", buckets.len())).width(Fill),
+        refresh_button
+    ]
+    .align_y(Center);
+
+    if buckets.is_empty() {
+        return column![header, text("The routing table is empty").color(color!(0x888888))]
+            .spacing(10)
+            .into();
+    }
+
+    let rows = buckets.iter().map(|bucket| {
+        let is_expanded = expanded.contains(&bucket.index);
+        let toggle_label = if is_expanded { "▾" } else { "▸" };
+        let mut title = text(format!(
+            "{toggle_label} Bucket {} · {} peers",
+            bucket.index,
+            bucket.entries.len()
         ));
-    } else if !current_key.is_empty() && current_value.is_empty() {
-        get_button = get_button.on_press(Message::GetRecord(current_key.to_owned()));
+
+        if changed == Some(bucket.index) {
+            title = title.color(color!(0x55b055));
+        }
+
+        let toggle = button(title)
+            .style(button::text)
+            .on_press(Message::ToggleBucket(bucket.index));
+
+        let entries = is_expanded.then(|| {
+            column(bucket.entries.iter().map(|(peer_id, addresses, status)| {
+                let status = match status {
+                    kad::NodeStatus::Connected => text("connected"),
+                    kad::NodeStatus::Disconnected => text("disconnected").color(color!(0x888888)),
+                };
+                let addresses = addresses
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                row![
+                    text(truncate_peer_id(peer_id)).width(PEER_COLUMN_WIDTH),
+                    text(addresses).size(14).width(Fill),
+                    status.size(14).width(STATUS_COLUMN_WIDTH),
+                ]
+                .spacing(10)
+                .into()
+            }))
+            .spacing(5)
+            .padding([0, 20])
+        });
+
+        column![toggle].push_maybe(entries).spacing(5).into()
+    });
+
+    column![header, scrollable(column(rows).spacing(5)).height(Fill)]
+        .spacing(10)
+        .into()
+}
+
+pub fn pending_inbound_list(pending: &[PendingApproval]) -> Element<'_, Message> {
+    if pending.is_empty() {
+        return row![].into();
+    }
+
+    let entries = pending.iter().map(|pending| {
+        let description = match &pending.value {
+            Some(value) => format!(
+                "{} wants to store {} = {}",
+                truncate_peer_id(&pending.source),
+                pending.key,
+                value_preview(value)
+            ),
+            None => format!(
+                "{} wants to provide {}",
+                truncate_peer_id(&pending.source),
+                pending.key
+            ),
+        };
+
+        let accept_button = button(text("Accept").size(12))
+            .padding([2, 10])
+            .on_press(Message::AcceptInbound(pending.id));
+
+        let reject_button = button(text("Reject").size(12))
+            .style(button::danger)
+            .padding([2, 10])
+            .on_press(Message::RejectInbound(pending.id));
+
+        row![text(description).width(Fill), accept_button, reject_button]
+            .spacing(10)
+            .align_y(Center)
+            .into()
+    });
+
+    column![text("Awaiting approval:"), column(entries).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+pub fn records_table<'a>(
+    records: &'a BTreeMap<String, RecordEntry>,
+    value_views: &HashMap<ValueSource, ValueView>,
+    import: Option<&'a RecordImport>,
+    now: Instant,
+) -> Element<'a, Message> {
+    let refresh_button = button(text("Refresh").size(12))
+        .style(button::secondary)
+        .padding([2, 10])
+        .on_press(Message::RefreshRecords);
+    let import_button = button(text("Import").size(12))
+        .style(button::secondary)
+        .padding([2, 10])
+        .on_press(Message::ImportRecords);
+    let export_button = button(text("Export").size(12))
+        .style(button::secondary)
+        .padding([2, 10])
+        .on_press(Message::ExportRecords);
+
+    let title = row![text("Local records:"), refresh_button, import_button, export_button]
+        .spacing(10)
+        .align_y(Center);
+    let title = column![title].push_maybe(import.map(import_progress)).spacing(5);
+
+    if records.is_empty() {
+        return column![title, text("No records stored locally").color(color!(0x888888))]
+            .spacing(5)
+            .padding(10)
+            .into();
+    }
+
+    let header = row![
+        text("Key").width(KEY_COLUMN_WIDTH),
+        text("Value").width(Fill),
+        text("Publisher").width(PEER_COLUMN_WIDTH),
+        text("Expires").width(AGE_COLUMN_WIDTH),
+        text("").width(AGE_COLUMN_WIDTH),
+    ]
+    .spacing(10);
+
+    let entries = records.iter().map(|(key, entry)| {
+        let publisher = entry
+            .publisher
+            .as_ref()
+            .map(truncate_peer_id)
+            .unwrap_or_else(|| "unknown".to_owned());
+
+        let expires = match entry.expires {
+            Some(expires) if expires <= now => "expired".to_owned(),
+            Some(expires) => format_age(expires - now),
+            None => "never".to_owned(),
+        };
+
+        let delete_button = button(text("Delete").size(12))
+            .style(button::danger)
+            .padding([2, 10])
+            .on_press(Message::RemoveRecord(key.to_owned()));
+
+        let source = ValueSource::Record(key.to_owned());
+        let view = value_views.get(&source).copied();
+
+        let value = row![
+            value_toggle(source.clone(), &entry.value, view.is_some()),
+            text(value_preview(&entry.value)).width(Fill),
+        ]
+        .push_maybe(signature_badge(&entry.signature))
+        .spacing(5)
+        .align_y(Center)
+        .width(Fill);
+
+        let raw_key = tooltip(
+            text(key).width(KEY_COLUMN_WIDTH),
+            container(text(format!("Stored as {}", format_key(&entry.key))).size(12))
+                .padding(5)
+                .style(container::rounded_box),
+            tooltip::Position::Bottom,
+        );
+
+        let row = row![
+            raw_key,
+            value,
+            text(publisher).width(PEER_COLUMN_WIDTH),
+            text(expires).width(AGE_COLUMN_WIDTH),
+            delete_button,
+        ]
+        .spacing(10)
+        .align_y(Center);
+
+        column![row]
+            .push_maybe(view.map(|view| value_detail(source, &[&entry.value], view)))
+            .spacing(5)
+            .into()
+    });
+
+    column![title, header, column(entries).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+/// Opens or closes the detail view of a value, first showing it the way that suits it.
+fn value_toggle<'a>(source: ValueSource, value: &[u8], open: bool) -> Element<'a, Message> {
+    button(text(if open { "▾" } else { "▸" }).size(12))
+        .style(button::text)
+        .padding([2, 5])
+        .on_press(Message::ToggleValueView(source, ValueDisplay::detect(value)))
+        .into()
+}
+
+/// The values in full, or up to the cut, as text, a hex dump or Base64.
+fn value_detail<'a>(
+    source: ValueSource,
+    values: &[&[u8]],
+    view: ValueView,
+) -> Element<'a, Message> {
+    let modes = ValueDisplay::ALL.into_iter().map(|display| {
+        let style = if display == view.display {
+            button::primary
+        } else {
+            button::secondary
+        };
+
+        button(text(display.to_string()).size(12))
+            .style(style)
+            .padding([2, 10])
+            .on_press(Message::ValueDisplayChanged(source.clone(), display))
+            .into()
+    });
+
+    let rendered: Vec<_> = values
+        .iter()
+        .map(|value| format::render(value, view.display, view.whole))
+        .collect();
+    let show_all = rendered.iter().any(|rendered| rendered.truncated).then(|| {
+        let size = values.iter().map(|value| value.len()).max().unwrap_or_default();
+
+        button(text(format!("Show all {}", format_size(size as u64))).size(12))
+            .style(button::secondary)
+            .padding([2, 10])
+            .on_press(Message::ShowWholeValue(source.clone()))
+    });
+    let values = rendered.into_iter().map(|rendered| {
+        container(text(rendered.text).font(Font::MONOSPACE).size(12))
+            .padding(5)
+            .style(container::rounded_box)
+            .width(Fill)
+            .into()
+    });
+
+    column![row(modes).spacing(5), column(values).spacing(5)]
+        .push_maybe(show_all)
+        .spacing(5)
+        .padding([0, 20])
+        .into()
+}
+
+/// How far an import got, then what went wrong with it once it's done.
+fn import_progress(import: &RecordImport) -> Element<'_, Message> {
+    if !import.is_finished() {
+        return text(format!("Importing {}/{}", import.done, import.total))
+            .size(12)
+            .into();
+    }
+
+    let summary = format!(
+        "Imported {}/{} records",
+        import.done - import.failures.len(),
+        import.total
+    );
+    let dismiss_button = button(text("Dismiss").size(12))
+        .style(button::secondary)
+        .padding([2, 10])
+        .on_press(Message::DismissImport);
+
+    let failures = import
+        .failures
+        .iter()
+        .map(|failure| text(failure).size(12).color(color!(0xe05555)).into());
+    let skipped = import
+        .skipped
+        .iter()
+        .map(|row| text(format!("Skipped {row}")).size(12).color(color!(0xd0a030)).into());
+
+    column![row![text(summary).size(12), dismiss_button].spacing(10).align_y(Center)]
+        .extend(failures.chain(skipped))
+        .spacing(2)
+        .into()
+}
+
+fn value_preview(value: &[u8]) -> String {
+    let formatted = format_record_value(value);
+
+    if formatted.chars().count() > MAX_VALUE_PREVIEW_CHARS {
+        let truncated: String = formatted.chars().take(MAX_VALUE_PREVIEW_CHARS).collect();
+        format!("{truncated}…")
+    } else {
+        formatted
+    }
+}
+
+/// The values a lookup found, grouped so peers that disagree stand out.
+pub fn record_lookup_view(lookup: Option<&RecordLookup>) -> Element<'_, Message> {
+    let Some(lookup) = lookup else {
+        return row![].into();
+    };
+
+    let title = if lookup.agree {
+        text(format!("Value of {} from {} peers:", lookup.key, lookup.values.len()))
+    } else {
+        text(format!("Peers disagree on {}:", lookup.key)).color(color!(0xd0a030))
+    };
+
+    let variants = value_variants(&lookup.values).into_iter().map(|variant| {
+        let peers = variant
+            .iter()
+            .map(|found| found.peer.as_ref().map_or_else(|| "us".to_owned(), truncate_peer_id))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let value = &variant[0].value;
+        let save = str::from_utf8(value).is_err().then(|| {
+            button(text("Save as…").size(12))
+                .style(button::secondary)
+                .padding([2, 10])
+                .on_press(Message::SaveValue(value.clone()))
+        });
+
+        row![text(value_preview(value)).width(Fill)]
+            .push_maybe(save)
+            .push_maybe(signature_badge(&variant[0].signature))
+            .push(text(peers).size(12).color(color!(0x888888)).width(Fill))
+            .spacing(10)
+            .align_y(Center)
+            .into()
+    });
+
+    column![title, column(variants).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+pub fn closest_peers_list<'a>(
+    closest: Option<&'a ClosestPeers>,
+    connected_peers: &HashSet<PeerId>,
+) -> Element<'a, Message> {
+    let Some(closest) = closest else {
+        return row![].into();
+    };
+
+    let title = if closest.complete {
+        format!("Peers closest to {}:", closest.key)
+    } else {
+        format!("Peers closest to {} (timed out, partial result):", closest.key)
+    };
+
+    if closest.peers.is_empty() {
+        return column![text(title), text("No peers found").color(color!(0x888888))]
+            .spacing(5)
+            .padding(10)
+            .into();
     }
 
-    row![key_input, value_input, put_button, get_button]
+    let target = kad::KBucketKey::new(closest.record_key.clone());
+    let entries = closest.peers.iter().enumerate().map(|(rank, peer_id)| {
+        let distance = kad::KBucketKey::from(*peer_id)
+            .distance(&target)
+            .ilog2()
+            .map_or_else(|| "0".to_owned(), |log2| format!("2^{log2}"));
+        let status = if connected_peers.contains(peer_id) {
+            text("connected").size(14)
+        } else {
+            text("not connected").size(14).color(color!(0x888888))
+        };
+
+        row![
+            text(format!("{}.", rank + 1)).width(30),
+            text(truncate_peer_id(peer_id)).width(PEER_COLUMN_WIDTH),
+            text(format!("distance ≈ {distance}")).size(14).width(Fill),
+            status.width(STATUS_COLUMN_WIDTH),
+            copy_button(peer_id.to_string()),
+        ]
         .spacing(10)
+        .align_y(Center)
+        .into()
+    });
+
+    column![text(title), column(entries).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+pub fn providing_list(keys: &[String]) -> Element<'_, Message> {
+    if keys.is_empty() {
+        return row![text("Not providing any keys").color(color!(0x888888))]
+            .padding(10)
+            .into();
+    }
+
+    let entries = keys.iter().map(|key| {
+        let stop_button = button(text("Stop").size(12))
+            .padding([2, 10])
+            .on_press(Message::StopProviding(key.to_owned()));
+
+        row![text(key), stop_button]
+            .spacing(10)
+            .align_y(Center)
+            .into()
+    });
+
+    column![text("Providing:"), column(entries).spacing(5)]
+        .spacing(5)
+        .padding(10)
+        .into()
+}
+
+pub fn log_scroll_id() -> scrollable::Id {
+    scrollable::Id::new("event-log")
+}
+
+pub fn input_id(field: InputField) -> text_input::Id {
+    match field {
+        InputField::Key => text_input::Id::new("key-input"),
+        InputField::Ttl => text_input::Id::new("ttl-input"),
+    }
+}
+
+/// Everything about the log entry numbered `number`, or a note once it has left the log.
+pub fn event_detail(number: usize, entry: Option<&LogEntry>) -> Element<'_, Message> {
+    let header = row![
+        text(format!("Event #{number}")).size(16).width(Fill),
+        button(text("Close").size(12))
+            .style(button::secondary)
+            .padding([2, 10])
+            .on_press(Message::CloseEventDetail),
+    ]
+    .align_y(Center);
+
+    let Some(entry) = entry else {
+        return container(
+            column![
+                header,
+                text("This event is no longer in the log").color(color!(0x888888)),
+            ]
+            .spacing(10),
+        )
         .padding(10)
+        .width(EVENT_DETAIL_WIDTH)
+        .style(container::bordered_box)
+        .into();
+    };
+
+    let (peer, address, key) = export::subjects(&entry.event);
+    let category = LogCategory::of(&entry.event);
+    let mut fields = vec![
+        detail_field("Time", entry.full_timestamp(), false),
+        detail_field("Kind", export::event_kind(&entry.event).to_owned(), false),
+        detail_field(
+            "Category",
+            format!("{} {}", category_icon(category), category.title()),
+            false,
+        ),
+        detail_field("Summary", entry.event.to_string(), true),
+    ];
+    if entry.repeat > 1 {
+        let times: Vec<_> = entry.occurrences().skip(1).map(full_time).collect();
+        let kept = if times.len() + 1 < entry.repeat as usize {
+            format!(", the latest {} at:", times.len())
+        } else {
+            " at:".to_owned()
+        };
+
+        fields.push(detail_field(
+            "Repeated",
+            format!("{} times, earlier{kept}\n{}", entry.repeat, times.join("\n")),
+            false,
+        ));
+    }
+    if let Some(key) = key {
+        fields.push(detail_field("Key", format_key(key), true));
+        fields.push(detail_field("Key bytes", to_hex(key.as_ref()), true));
+    }
+    if let Some(address) = address {
+        fields.push(detail_field("Address", address.to_string(), true));
+    }
+    fields.extend(
+        peer.copied()
+            .into_iter()
+            .chain(listed_peers(&entry.event))
+            .map(|peer| detail_field("Peer", peer.to_string(), true)),
+    );
+
+    let debug = container(text(format!("{:#?}", entry.event)).font(Font::MONOSPACE).size(12))
+        .padding(5)
+        .style(container::rounded_box)
+        .width(Fill);
+
+    container(
+        scrollable(
+            column![header, column(fields).spacing(5), debug]
+                .spacing(10)
+                .padding([0, 10]),
+        )
+        .height(Fill),
+    )
+    .padding([10, 0])
+    .width(EVENT_DETAIL_WIDTH)
+    .height(Fill)
+    .style(container::bordered_box)
+    .into()
+}
+
+fn detail_field<'a>(label: &'a str, value: String, copyable: bool) -> Element<'a, Message> {
+    let copy = copyable.then(|| copy_button(value.clone()));
+
+    row![
+        text(label).size(12).color(color!(0x888888)).width(DETAIL_LABEL_WIDTH),
+        text(value).size(14).width(Fill),
+    ]
+    .push_maybe(copy)
+    .spacing(10)
+    .align_y(Center)
+    .into()
+}
+
+/// "×N" on an event that happened N times in a row, listing when on hover.
+fn repeat_badge(entry: &LogEntry) -> Option<Element<'_, Message>> {
+    if entry.repeat < 2 {
+        return None;
+    }
+
+    let mut times: Vec<_> = entry.occurrences().take(MAX_LISTED_REPEATS).map(clock_time).collect();
+    let unlisted = entry.repeat as usize - times.len();
+    if unlisted > 0 {
+        times.push(format!("and {unlisted} earlier"));
+    }
+
+    let badge = tooltip(
+        text(format!("×{}", entry.repeat)).size(12).color(color!(0x888888)),
+        container(text(times.join("\n")).size(12))
+            .padding(5)
+            .style(container::rounded_box),
+        tooltip::Position::Bottom,
+    );
+
+    Some(badge.into())
+}
+
+/// Peers an event lists beyond the one it's about.
+fn listed_peers(event: &P2pEvent) -> Vec<PeerId> {
+    match event {
+        P2pEvent::Outbound(_, P2pOutboundEvent::ProvidersFound(_, peers))
+        | P2pEvent::Outbound(_, P2pOutboundEvent::ClosestPeers(_, peers)) => peers.clone(),
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordResolved { values, .. }) => {
+            values.iter().filter_map(|found| found.peer).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+pub fn event_log<'a>(
+    events: &'a EventLog,
+    value_views: &HashMap<ValueSource, ValueView>,
+    filter: &LogFilter,
+    paused: bool,
+    export_records: bool,
+    selected: Option<usize>,
+    now: SystemTime,
+) -> Element<'a, Message> {
+    if events.is_empty() {
+        return center(text("Events will appear here...").color(color!(0x888888))).into();
+    }
+
+    let chips = LogCategory::ALL.into_iter().map(|category| {
+        let style = if filter.hidden.contains(&category) {
+            button::secondary
+        } else {
+            button::primary
+        };
+
+        button(text(format!("{} {}", category_icon(category), category.title())).size(12))
+            .style(style)
+            .padding([2, 10])
+            .on_press(Message::ToggleLogCategory(category))
+            .into()
+    });
+
+    let matching: Vec<(usize, &LogEntry)> = events
+        .numbered()
+        .filter(|(_, entry)| filter.matches(&entry.event))
+        .collect();
+
+    let filter_bar = row![
+        row(chips).spacing(5),
+        text_input("Search events", &filter.search)
+            .on_input(Message::LogSearchChanged)
+            .padding(5),
+        text(format!("{} of {} events", matching.len(), events.len()))
+            .size(14)
+            .color(color!(0x888888)),
+        button(text("Copy all").size(12))
+            .style(button::secondary)
+            .padding([2, 10])
+            .on_press(Message::CopyLog),
+        checkbox("Include records", export_records)
+            .on_toggle(Message::ToggleExportRecords)
+            .text_size(12),
+        button(text("Export").size(12))
+            .style(button::secondary)
+            .padding([2, 10])
+            .on_press(Message::ExportLog),
+        button(text("Clear").size(12))
+            .style(button::danger)
+            .padding([2, 10])
+            .on_press(Message::ClearLog),
+    ]
+    .spacing(10)
+    .align_y(Center);
+
+    let events_elements = matching.into_iter().map(|(number, entry)| {
+        let age = now.duration_since(entry.at).unwrap_or_default();
+        let source = ValueSource::LogEntry(number);
+        let view = value_views.get(&source).copied();
+        let values = match &entry.event {
+            P2pEvent::Outbound(_, P2pOutboundEvent::RecordResolved { values, .. }) => {
+                value_variants(values)
+                    .into_iter()
+                    .map(|variant| variant[0].value.as_slice())
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let toggle = values
+            .first()
+            .map(|value| value_toggle(source.clone(), value, view.is_some()));
+
+        let row = row![
+            tooltip(
+                text(entry.timestamp()).size(14).color(color!(0x888888)),
+                container(text(format!("{} ago", format_age(age))).size(12))
+                    .padding(5)
+                    .style(container::rounded_box),
+                tooltip::Position::Right,
+            ),
+            event_text(category_icon(LogCategory::of(&entry.event)), &entry.event),
+            mouse_area(event_text(entry.event.to_string(), &entry.event).width(Fill))
+            .on_press(Message::EventSelected(number))
+            .interaction(mouse::Interaction::Pointer),
+        ]
+        .push_maybe(repeat_badge(entry))
+        .push_maybe(toggle)
+        .push_maybe(event_signature(&entry.event).and_then(signature_badge))
+        .push(copy_button(entry.to_string()))
+        .spacing(10)
+        .align_y(Center);
+        let row = if selected == Some(number) {
+            container(row).style(container::rounded_box)
+        } else {
+            container(row)
+        };
+
+        column![row]
+            .push_maybe(view.map(|view| value_detail(source, &values, view)))
+            .spacing(5)
+            .into()
+    });
+
+    let discarded = (events.discarded() > 0).then(|| {
+        text(format!("{} older events discarded", events.discarded()))
+            .size(14)
+            .color(color!(0x888888))
+    });
+
+    let log = scrollable(column![].push_maybe(discarded).extend(events_elements).spacing(10))
+        .id(log_scroll_id())
+        .on_scroll(Message::LogScrolled)
+        .height(Fill);
+
+    let jump_to_latest = paused.then(|| {
+        container(
+            button(text("Jump to latest").size(12))
+                .padding([4, 12])
+                .on_press(Message::JumpToLatest),
+        )
+        .center_x(Fill)
+    });
+
+    column![filter_bar, log]
+        .push_maybe(jump_to_latest)
+        .spacing(10)
+        .into()
+}
+
+pub fn query_history(queries: &[QueryRecord]) -> Element<'_, Message> {
+    if queries.is_empty() {
+        return row![].into();
+    }
+
+    let entries = queries.iter().rev().map(|query| {
+        let (status, status_color) = match query.status {
+            QueryStatus::Queued => ("queued (waiting for peers)", color!(0xd0a030)),
+            QueryStatus::Pending => ("pending", color!(0x888888)),
+            QueryStatus::Succeeded => ("succeeded", color!(0x55b055)),
+            QueryStatus::NotFound => ("not found", color!(0x888888)),
+            QueryStatus::Failed => ("failed", color!(0xe05555)),
+            QueryStatus::TimedOut => ("timed out", color!(0xd0a030)),
+            QueryStatus::Cancelled => ("cancelled", color!(0x888888)),
+        };
+
+        let mut entry = row![
+            text(format!("{} {} {}", query.request_id, query.kind, query.key)),
+            text(status).color(status_color)
+        ]
+        .spacing(10)
+        .align_y(Center);
+
+        if matches!(query.status, QueryStatus::Queued | QueryStatus::Pending) {
+            entry = entry.push(
+                button(text("Cancel").size(12))
+                    .padding([2, 10])
+                    .on_press(Message::CancelQuery(query.request_id)),
+            );
+        }
+
+        entry.into()
+    });
+
+    column![
+        text("Queries:"),
+        scrollable(column(entries).spacing(5)).height(100)
+    ]
+    .spacing(5)
+    .padding(10)
+    .into()
+}
+
+pub fn diagnostics_panel(
+    lines: &VecDeque<TraceLine>,
+    level: TraceLevel,
+    expanded: bool,
+) -> Element<'_, Message> {
+    let toggle = button(text(if expanded { "▾ Diagnostics" } else { "▸ Diagnostics" }).size(14))
+        .style(button::text)
+        .padding([2, 0])
+        .on_press(Message::ToggleDiagnostics);
+
+    if !expanded {
+        return toggle.into();
+    }
+
+    let matching: Vec<&TraceLine> = lines
+        .iter()
+        .filter(|line| level.includes(&line.level))
+        .collect();
+
+    let header = row![
+        toggle,
+        pick_list(TraceLevel::ALL, Some(level), Message::TraceLevelSelected).text_size(12),
+        text(format!("{} of {} lines", matching.len(), lines.len()))
+            .size(14)
+            .color(color!(0x888888))
+            .width(Fill),
+        button(text("Copy diagnostics").size(12))
+            .style(button::secondary)
+            .padding([2, 10])
+            .on_press(Message::CopyDiagnostics),
+    ]
+    .spacing(10)
+    .align_y(Center);
+
+    let entries = matching.into_iter().map(|line| {
+        text(line.to_string())
+            .size(12)
+            .font(Font::MONOSPACE)
+            .color_maybe(trace_color(&line.level))
+            .into()
+    });
+
+    column![
+        header,
+        scrollable(column(entries).spacing(2)).anchor_bottom().height(200),
+    ]
+    .spacing(5)
+    .into()
+}
+
+/// Puts and gets a batch of random records through the node, with the last results below.
+pub fn benchmark_section<'a>(
+    count: &str,
+    value_size: &str,
+    benchmark: Option<&Benchmark>,
+    report: Option<&BenchmarkReport>,
+) -> Element<'a, Message> {
+    let params = bench::parse_params(count, value_size);
+
+    let count_input = text_input(&DEFAULT_BENCH_COUNT.to_string(), count)
+        .on_input(Message::BenchCountChanged)
+        .size(12)
+        .width(70);
+    let size_input = text_input(&DEFAULT_BENCH_VALUE_SIZE.to_string(), value_size)
+        .on_input(Message::BenchValueSizeChanged)
+        .size(12)
+        .width(70);
+
+    let action: Element<'a, Message> = match benchmark {
+        Some(benchmark) => {
+            let (phase, done, total) = benchmark.progress();
+            let verb = match phase {
+                BenchPhase::Put => "Putting",
+                BenchPhase::Get => "Getting",
+                BenchPhase::Remove => "Removing",
+            };
+
+            row![
+                text(format!("{verb} {done}/{total}")).size(12),
+                button(text("Cancel").size(12))
+                    .style(button::secondary)
+                    .padding([2, 10])
+                    .on_press(Message::CancelBenchmark),
+            ]
+            .spacing(10)
+            .align_y(Center)
+            .into()
+        }
+        None => button(text("Run benchmark").size(12))
+            .padding([2, 10])
+            .on_press_maybe(params.is_ok().then_some(Message::RunBenchmark))
+            .into(),
+    };
+
+    let controls = row![
+        text("Records").size(12),
+        count_input,
+        text("Value bytes").size(12),
+        size_input,
+        action,
+    ]
+    .spacing(10)
+    .align_y(Center);
+
+    let error = params
+        .err()
+        .map(|message| text(message).size(12).color(color!(0xe05555)));
+
+    column![controls]
+        .push_maybe(error)
+        .push_maybe(report.map(benchmark_card))
+        .spacing(5)
         .into()
 }
+
+fn benchmark_card<'a>(report: &BenchmarkReport) -> Element<'a, Message> {
+    let title = text(format!("{} records of {} bytes", report.count, report.value_size)).size(14);
+    let cancelled = report
+        .cancelled
+        .then(|| text("Cancelled").size(12).color(color!(0xd0a030)));
+
+    container(
+        column![
+            row![title].push_maybe(cancelled).spacing(10).align_y(Center),
+            text(format!("Puts: {}", report.puts)).size(12),
+            text(format!("Gets: {}", report.gets)).size(12),
+        ]
+        .spacing(2),
+    )
+    .padding(10)
+    .style(container::rounded_box)
+    .into()
+}
+
+fn trace_color(level: &Level) -> Option<Color> {
+    match *level {
+        Level::ERROR => Some(color!(0xe05555)),
+        Level::WARN => Some(color!(0xd0a030)),
+        Level::DEBUG | Level::TRACE => Some(color!(0x888888)),
+        _ => None,
+    }
+}
+
+fn event_signature(event: &P2pEvent) -> Option<&RecordSignature> {
+    match event {
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordResolved { values, agree: true, .. }) => {
+            values.first().map(|found| &found.signature)
+        }
+        P2pEvent::Inbound(P2pInboundEvent::RecordStored(.., signature)) => Some(signature),
+        _ => None,
+    }
+}
+
+/// Flags a record value that isn't signed, or whose signature doesn't hold.
+fn signature_badge<'a>(signature: &RecordSignature) -> Option<Element<'a, Message>> {
+    if signature.verified {
+        return None;
+    }
+
+    let (label, badge_color) = match signature.signer {
+        Some(_) => ("Bad signature", color!(0xe05555)),
+        None => ("Unsigned", color!(0xd0a030)),
+    };
+
+    let badge = tooltip(
+        text(label).size(12).color(badge_color),
+        container(text(signature.to_string()).size(12))
+            .padding(5)
+            .style(container::rounded_box),
+        tooltip::Position::Left,
+    );
+
+    Some(badge.into())
+}
+
+fn category_icon(category: LogCategory) -> &'static str {
+    match category {
+        LogCategory::Peers => "⇄",
+        LogCategory::Inbound => "↓",
+        LogCategory::Outbound => "↑",
+        LogCategory::Errors => "⚠",
+        LogCategory::System => "•",
+    }
+}
+
+/// A category's colour in `theme`; system events keep the default text colour.
+fn category_color(category: LogCategory, theme: &Theme) -> Option<Color> {
+    let palette = theme.extended_palette();
+    let text = palette.background.base.text;
+
+    match category {
+        LogCategory::Peers => Some(dimmed(theme)),
+        LogCategory::Inbound => Some(readable(palette.primary.base.color, text)),
+        LogCategory::Outbound => Some(readable(palette.success.base.color, text)),
+        LogCategory::Errors => Some(readable(palette.danger.base.color, text)),
+        LogCategory::System => None,
+    }
+}
+
+/// A log entry's category colour, except that a record not being found is dimmed rather than
+/// shown as a success.
+fn event_color(event: &P2pEvent, theme: &Theme) -> Option<Color> {
+    match event {
+        P2pEvent::Outbound(_, P2pOutboundEvent::RecordNotFound(_)) => Some(dimmed(theme)),
+        _ => category_color(LogCategory::of(event), theme),
+    }
+}
+
+/// Text coloured like `event` in whichever theme is active when it's drawn.
+fn event_text<'a>(content: impl text::IntoFragment<'a>, event: &'a P2pEvent) -> Text<'a> {
+    text(content).style(move |theme: &Theme| text::Style {
+        color: event_color(event, theme),
+    })
+}
+
+fn dimmed(theme: &Theme) -> Color {
+    theme.extended_palette().background.base.text.scale_alpha(0.55)
+}
+
+/// Pulls a palette colour a third of the way towards the text colour, so that dark accents stay
+/// legible on dark backgrounds and light ones on light backgrounds.
+fn readable(color: Color, text: Color) -> Color {
+    let mix = |from: f32, to: f32| from + (to - from) / 3.0;
+
+    Color::from_rgb(mix(color.r, text.r), mix(color.g, text.g), mix(color.b, text.b))
+}
+
+/// Where the value to put comes from.
+pub enum ValueInput<'a> {
+    Text(&'a text_editor::Content),
+    File { name: &'a str, bytes: &'a [u8] },
+}
+
+pub fn input_section<'a>(
+    current_key: &str,
+    value: ValueInput<'a>,
+    current_ttl: &str,
+    recent_keys: &'a combo_box::State<String>,
+    pending_queries: &[PendingQuery],
+    max_value_bytes: usize,
+) -> Element<'a, Message> {
+    let is_key_pending = |kind| is_pending(pending_queries, kind, current_key);
+    let current_value = match value {
+        ValueInput::Text(content) => Cow::Owned(editor_text(content).into_bytes()),
+        ValueInput::File { bytes, .. } => Cow::Borrowed(bytes),
+    };
+
+    let mut key_input = text_input("Key", current_key)
+        .id(input_id(InputField::Key))
+        .on_input(Message::KeyTextChanged)
+        .padding(10);
+
+    let recent_keys = combo_box(recent_keys, "Recent keys", None, Message::RecentKeySelected)
+        .padding(10)
+        .width(140);
+
+    let mut ttl_input = text_input("TTL (s)", current_ttl)
+        .id(input_id(InputField::Ttl))
+        .on_input(Message::TtlTextChanged)
+        .padding(10)
+        .width(80);
+
+    let put =
+        put_action(current_key, &current_value, current_ttl, max_value_bytes, pending_queries);
+    let get = get_action(current_key, pending_queries);
+    let mut provide_button = button(text("Provide").height(40).align_y(Center)).padding([0, 20]);
+    let mut delete_button = button(text("Delete").height(40).align_y(Center)).padding([0, 20]);
+    let mut providers_button =
+        button(text("Providers").height(40).align_y(Center)).padding([0, 20]);
+    let mut closest_button = button(text("Closest").height(40).align_y(Center)).padding([0, 20]);
+
+    if !current_key.is_empty() && current_value.is_empty() {
+        if !is_key_pending(QueryKind::StartProviding) {
+            provide_button =
+                provide_button.on_press(Message::StartProviding(current_key.to_owned()));
+        }
+        delete_button = delete_button.on_press(Message::RemoveRecord(current_key.to_owned()));
+    }
+
+    if !current_key.is_empty() && !is_key_pending(QueryKind::GetProviders) {
+        providers_button = providers_button.on_press(Message::GetProviders(current_key.to_owned()));
+    }
+
+    if !current_key.is_empty() && !is_key_pending(QueryKind::GetClosestPeers) {
+        closest_button = closest_button.on_press(Message::GetClosestPeers(current_key.to_owned()));
+    }
+
+    // Enter does whatever the Put or Get button would, and nothing they wouldn't
+    let submit = if current_key.is_empty() {
+        Some(Message::FocusInput(InputField::Key))
+    } else if current_value.is_empty() {
+        get.clone().ok()
+    } else {
+        put.clone().ok()
+    };
+
+    if let Some(submit) = &submit {
+        key_input = key_input.on_submit(submit.clone());
+        ttl_input = ttl_input.on_submit(submit.clone());
+    }
+
+    let value_size = text(format!(
+        "{} / {}",
+        format_size(current_value.len() as u64),
+        format_size(max_value_bytes as u64)
+    ))
+    .size(12)
+    .color(if current_value.len() > max_value_bytes {
+        color!(0xe05555)
+    } else {
+        color!(0x888888)
+    });
+
+    let put_button = explained(button(text("Put").height(40).align_y(Center)), put);
+    let get_button = explained(button(text("Get").height(40).align_y(Center)), get);
+    let clear_button = button(text("Clear").height(40).align_y(Center))
+        .style(button::secondary)
+        .padding([0, 20])
+        .on_press_maybe(
+            (!current_key.is_empty() || !current_value.is_empty())
+                .then_some(Message::ClearRecordInputs),
+        );
+
+    let pending_indicator = if pending_queries.is_empty() {
+        text("")
+    } else {
+        text(format!("… {}", pending_queries.len())).color(color!(0x888888))
+    };
+
+    let actions = row![
+        key_input,
+        recent_keys,
+        ttl_input,
+        put_button,
+        get_button,
+        provide_button,
+        providers_button,
+        tooltip(closest_button, "Who is closest to this key?", tooltip::Position::Bottom),
+        delete_button,
+        clear_button,
+        pending_indicator
+    ]
+    .spacing(10)
+    .align_y(Center);
+
+    let value: Element<'a, Message> = match value {
+        ValueInput::Text(content) => row![
+            value_editor(content, submit),
+            tooltip(
+                button(text("📎")).style(button::secondary).on_press(Message::AttachValueFile),
+                container(text("Put a file's contents instead").size(12))
+                    .padding(5)
+                    .style(container::rounded_box),
+                tooltip::Position::Bottom,
+            ),
+        ]
+        .spacing(10)
+        .into(),
+        ValueInput::File { name, .. } => row![
+            text(format!("📎 {name}")).width(Fill),
+            button(text("Remove").size(12))
+                .style(button::secondary)
+                .padding([2, 10])
+                .on_press(Message::DetachValueFile),
+        ]
+        .spacing(10)
+        .align_y(Center)
+        .into(),
+    };
+    let value = row![value, value_size].spacing(10);
+
+    column![actions, value].spacing(10).padding(10).into()
+}
+
+/// Enter breaks the line here, so submitting takes Ctrl+Enter; Esc clears the value.
+fn value_editor(content: &text_editor::Content, submit: Option<Message>) -> Element<'_, Message> {
+    let editor = text_editor(content)
+        .placeholder("Value")
+        .on_action(Message::ValueEdited)
+        .padding(10)
+        .key_binding(move |key_press| {
+            if key_press.status != text_editor::Status::Focused {
+                return None;
+            }
+
+            match key_press.key.as_ref() {
+                keyboard::Key::Named(key::Named::Escape) => Some(text_editor::Binding::Sequence(
+                    vec![
+                        text_editor::Binding::SelectAll,
+                        text_editor::Binding::Delete,
+                        text_editor::Binding::Unfocus,
+                    ],
+                )),
+                keyboard::Key::Named(key::Named::Enter) if key_press.modifiers.command() => {
+                    submit.clone().map(text_editor::Binding::Custom)
+                }
+                _ => text_editor::Binding::from_key_press(key_press),
+            }
+        });
+
+    container(editor).max_height(VALUE_EDITOR_MAX_HEIGHT).into()
+}
+
+fn is_pending(pending_queries: &[PendingQuery], kind: QueryKind, key: &str) -> bool {
+    pending_queries.iter().any(|query| query.kind == kind && query.key == key)
+}
+
+/// What the Put button sends, or what's missing before it can.
+pub fn put_action(
+    key: &str,
+    value: &[u8],
+    ttl: &str,
+    max_value_bytes: usize,
+    pending_queries: &[PendingQuery],
+) -> Result<Message, &'static str> {
+    if key.is_empty() {
+        return Err("Enter a key to put");
+    }
+    if value.is_empty() {
+        return Err("Enter a value to put");
+    }
+    if value.len() > max_value_bytes {
+        return Err("The value is larger than stores accept");
+    }
+
+    let ttl = if ttl.trim().is_empty() {
+        None
+    } else {
+        let secs = ttl.trim().parse().map_err(|_| "The TTL must be a whole number of seconds")?;
+        Some(Duration::from_secs(secs))
+    };
+
+    if is_pending(pending_queries, QueryKind::PutRecord, key) {
+        return Err("A put for this key is still running");
+    }
+
+    Ok(Message::PutRecord(key.to_owned(), value.to_vec(), ttl))
+}
+
+/// What the Get button sends, or what's missing before it can.
+pub fn get_action(key: &str, pending_queries: &[PendingQuery]) -> Result<Message, &'static str> {
+    if key.is_empty() {
+        return Err("Enter a key to get");
+    }
+    if is_pending(pending_queries, QueryKind::GetRecord, key) {
+        return Err("A get for this key is still running");
+    }
+
+    Ok(Message::GetRecord(key.to_owned()))
+}
+
+/// Enables `button` with the action, or disables it with a tooltip saying why.
+fn explained<'a>(
+    button: Button<'a, Message>,
+    action: Result<Message, &'static str>,
+) -> Element<'a, Message> {
+    let button = button.padding([0, 20]);
+
+    match action {
+        Ok(message) => button.on_press(message).into(),
+        Err(missing) => tooltip(
+            button,
+            container(text(missing).size(12))
+                .padding(5)
+                .style(container::rounded_box),
+            tooltip::Position::Bottom,
+        )
+        .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::RequestId;
+
+    const MAX: usize = 16;
+
+    fn pending(kind: QueryKind, key: &str) -> PendingQuery {
+        PendingQuery {
+            request_id: RequestId::next(),
+            kind,
+            key: key.to_owned(),
+            started_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn put_needs_a_key_a_value_and_a_valid_ttl() {
+        assert!(put_action("", b"", "", MAX, &[]).is_err());
+        assert!(put_action("key", b"", "", MAX, &[]).is_err());
+        assert!(put_action("", b"value", "", MAX, &[]).is_err());
+        assert!(put_action("key", b"value", "soon", MAX, &[]).is_err());
+        assert!(put_action("key", &[b'v'; MAX + 1], "", MAX, &[]).is_err());
+        assert!(matches!(
+            put_action("key", b"value", "", MAX, &[]),
+            Ok(Message::PutRecord(key, value, None)) if key == "key" && value == b"value"
+        ));
+        assert!(matches!(
+            put_action("key", b"value", " 60 ", MAX, &[]),
+            Ok(Message::PutRecord(_, _, Some(ttl))) if ttl == Duration::from_secs(60)
+        ));
+    }
+
+    #[test]
+    fn get_only_needs_a_key() {
+        assert!(get_action("", &[]).is_err());
+        assert!(matches!(get_action("key", &[]), Ok(Message::GetRecord(key)) if key == "key"));
+    }
+
+    #[test]
+    fn running_queries_disable_only_their_own_key_and_kind() {
+        let running = [pending(QueryKind::PutRecord, "key"), pending(QueryKind::GetRecord, "key")];
+
+        assert!(put_action("key", b"value", "", MAX, &running).is_err());
+        assert!(get_action("key", &running).is_err());
+        assert!(put_action("other", b"value", "", MAX, &running).is_ok());
+        assert!(get_action("other", &running).is_ok());
+        assert!(get_action("key", &running[..1]).is_ok());
+    }
+
+    #[test]
+    fn log_colours_stand_out_from_the_background_in_any_theme() {
+        let luminance = |color: Color| 0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b;
+
+        for theme in [Theme::Light, Theme::Dark] {
+            let background = luminance(theme.palette().background);
+
+            for category in LogCategory::ALL {
+                if let Some(color) = category_color(category, &theme) {
+                    let contrast = (luminance(color) - background).abs();
+                    assert!(contrast > 0.2, "{} in {theme}: {contrast}", category.title());
+                }
+            }
+        }
+    }
+}